@@ -0,0 +1,66 @@
+use std::{hint::black_box, io};
+
+use bf_rust::bf::{bf_machine::BfMachine, compiled::CompiledProgram, program::Program};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const PI: &str = include_str!("../tests/programs/pi.b");
+
+fn machine() -> BfMachine<io::Empty, io::Sink> {
+    BfMachine::new(30000, io::empty(), io::sink())
+}
+
+fn bench_pi(c: &mut Criterion) {
+    let program = Program::parse_optimized(PI).unwrap();
+    let compiled = CompiledProgram::compile(&program);
+    let mut group = c.benchmark_group("pi");
+
+    group.bench_function("run", |b| {
+        b.iter(|| machine().run(black_box(&program)).unwrap())
+    });
+    group.bench_function("run_fast", |b| {
+        b.iter(|| machine().run_fast(black_box(&program)).unwrap())
+    });
+    group.bench_function("run_fast_protected", |b| {
+        b.iter(|| {
+            let mut machine = machine();
+            machine.protect_range(29_000..30_000);
+            machine.run_fast(black_box(&program)).unwrap()
+        })
+    });
+    group.bench_function("run_compiled", |b| {
+        b.iter(|| machine().run_compiled(black_box(&compiled)).unwrap())
+    });
+
+    group.finish();
+}
+
+/// Resetting after touching a few cells should cost far less than after
+/// touching all of them, and both less than building a new machine.
+fn bench_reuse(c: &mut Criterion) {
+    let few = Program::parse(&"+>".repeat(40)).unwrap();
+    let all = Program::parse("+[>+]").unwrap();
+    let mut group = c.benchmark_group("reuse");
+
+    group.bench_function("new", |b| {
+        b.iter(|| machine().run_fast(black_box(&few)).unwrap())
+    });
+    group.bench_function("reset_40_cells", |b| {
+        let mut machine = machine();
+        b.iter(|| {
+            machine.run_fast(black_box(&few)).unwrap();
+            machine.reset();
+        })
+    });
+    group.bench_function("reset_30000_cells", |b| {
+        let mut machine = machine();
+        b.iter(|| {
+            machine.run_fast(black_box(&all)).unwrap();
+            machine.reset();
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_pi, bench_reuse);
+criterion_main!(benches);
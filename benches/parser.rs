@@ -0,0 +1,54 @@
+use std::hint::black_box;
+
+use bf_rust::bf::{bf_parser::BfParser, bf_token::BfToken, program::Program};
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const HELLO_WORLD: &str = include_str!("../tests/programs/hello_world.b");
+
+/// About 5MB of commands, comments and nested loops.
+fn synthetic_program() -> String {
+    let body = format!("[{HELLO_WORLD}] some comment text\n");
+    body.repeat(5_000_000 / body.len())
+}
+
+/// The char-by-char tokenizer followed by a separate `loop_matching` pass,
+/// which is what `BfParser::parse` used to do.
+fn char_parse(code: &str) -> Vec<BfToken> {
+    let tokens: Vec<_> = code
+        .chars()
+        .map(|ch| match ch {
+            '+' => BfToken::Increment(1),
+            '-' => BfToken::Decrement(1),
+            '<' => BfToken::CursorLeft(1),
+            '>' => BfToken::CursorRight(1),
+            '[' => BfToken::LoopStart,
+            ']' => BfToken::LoopEnd,
+            ',' => BfToken::InputChar,
+            '.' => BfToken::PrintChar,
+            _ => BfToken::NotCommand(ch),
+        })
+        .collect();
+    BfParser::loop_matching(&tokens).unwrap();
+    tokens
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let code = synthetic_program();
+    assert_eq!(BfParser::parse(&code).unwrap(), char_parse(&code));
+
+    let mut group = c.benchmark_group("parse_5mb");
+    group.sample_size(20);
+
+    group.bench_function("parse", |b| {
+        b.iter(|| BfParser::parse(black_box(&code)).unwrap())
+    });
+    group.bench_function("char_parse", |b| b.iter(|| char_parse(black_box(&code))));
+    group.bench_function("program", |b| {
+        b.iter(|| Program::parse(black_box(&code)).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
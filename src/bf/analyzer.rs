@@ -0,0 +1,488 @@
+use super::{
+    ast::{self, BfAst},
+    bf_parser::BfParser,
+    diagnostics::Diagnostic,
+};
+
+pub const UNMATCHED_BRACKET: &str = "BF001";
+pub const TAPE_UNDERFLOW: &str = "BF002";
+pub const EMPTY_LOOP: &str = "BF003";
+pub const DEAD_LOOP: &str = "BF004";
+pub const INFINITE_LOOP: &str = "BF005";
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+struct Interval {
+    lo: Option<i64>,
+    hi: Option<i64>,
+}
+
+impl Interval {
+    const ZERO: Self = Self {
+        lo: Some(0),
+        hi: Some(0),
+    };
+
+    fn shift(self, offset: i64) -> Self {
+        Self {
+            lo: self.lo.map(|lo| lo + offset),
+            hi: self.hi.map(|hi| hi + offset),
+        }
+    }
+
+    fn may_be_negative(&self) -> bool {
+        self.lo.is_none_or(|lo| lo < 0)
+    }
+
+    // A loop body runs 0..N times, so any possible drift in one direction
+    // makes the pointer unbounded in that direction.
+    fn widen(self, delta: Self) -> Self {
+        Self {
+            lo: if delta.may_be_negative() {
+                None
+            } else {
+                self.lo
+            },
+            hi: if delta.hi.is_none_or(|hi| hi > 0) {
+                None
+            } else {
+                self.hi
+            },
+        }
+    }
+}
+
+fn displacement(ast: &[BfAst]) -> Interval {
+    let mut position = Interval::ZERO;
+
+    for node in ast {
+        match node {
+            BfAst::Left(val) => position = position.shift(-(*val as i64)),
+            BfAst::Right(val) => position = position.shift(*val as i64),
+            BfAst::Loop(body) | BfAst::Transfer { body, .. } => {
+                position = position.widen(displacement(body))
+            }
+            _ => {}
+        }
+    }
+
+    position
+}
+
+struct UnderflowChecker {
+    index: usize,
+    straight_line: bool,
+    diagnostic: Option<Diagnostic>,
+}
+
+impl UnderflowChecker {
+    fn block(&mut self, ast: &[BfAst], mut position: Interval) -> Interval {
+        for node in ast {
+            match node {
+                BfAst::Left(val) => {
+                    position = position.shift(-(*val as i64));
+                    if position.may_be_negative() {
+                        self.report();
+                    }
+                }
+                BfAst::Right(val) => position = position.shift(*val as i64),
+                BfAst::Loop(body) | BfAst::Transfer { body, .. } => {
+                    self.straight_line = false;
+                    self.index += 1;
+                    position = position.widen(displacement(body));
+                    self.block(body, position);
+                }
+                _ => {}
+            }
+            self.index += 1;
+        }
+
+        position
+    }
+
+    fn report(&mut self) {
+        if self.diagnostic.is_some() {
+            return;
+        }
+
+        let message = if self.straight_line {
+            "tape pointer does underflow past the first cell"
+        } else {
+            "tape pointer may underflow past the first cell"
+        };
+        self.diagnostic = Some(Diagnostic::warning(TAPE_UNDERFLOW, self.index, message));
+    }
+}
+
+pub fn check_tape_underflow(ast: &[BfAst]) -> Vec<Diagnostic> {
+    let mut checker = UnderflowChecker {
+        index: 0,
+        straight_line: true,
+        diagnostic: None,
+    };
+    checker.block(ast, Interval::ZERO);
+
+    checker.diagnostic.into_iter().collect()
+}
+
+pub fn check_brackets(code: &str) -> Vec<Diagnostic> {
+    let mut open = vec![];
+    let mut diagnostics = vec![];
+
+    for (index, ch) in code.chars().enumerate() {
+        match ch {
+            '[' => open.push(index),
+            ']' if open.pop().is_none() => {
+                diagnostics.push(Diagnostic::error(
+                    UNMATCHED_BRACKET,
+                    index,
+                    "unmatched ']' has no opening bracket",
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    diagnostics.extend(open.into_iter().map(|index| {
+        Diagnostic::error(
+            UNMATCHED_BRACKET,
+            index,
+            "unclosed '[' has no closing bracket",
+        )
+    }));
+    diagnostics.sort_by_key(|diagnostic| diagnostic.index);
+    diagnostics
+}
+
+pub fn check_empty_loops(ast: &[BfAst]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for_each_loop(ast, 0, &mut |index, body| {
+        if cancels_out(body) {
+            diagnostics.push(Diagnostic::warning(
+                EMPTY_LOOP,
+                index,
+                "empty loop either does nothing or never terminates",
+            ));
+        }
+    });
+    diagnostics
+}
+
+pub fn check_dead_loops(ast: &[BfAst]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for_each_block(ast, 0, true, &mut |index, block, at_start| {
+        let mut cell_is_zero = at_start;
+        let mut index = index;
+
+        for node in block {
+            match node {
+                BfAst::Loop(_) | BfAst::Transfer { .. } => {
+                    if cell_is_zero {
+                        diagnostics.push(Diagnostic::warning(
+                            DEAD_LOOP,
+                            index,
+                            "loop is never entered because the current cell is always zero",
+                        ));
+                    }
+                    cell_is_zero = true;
+                }
+                BfAst::Comment(_) => {}
+                _ => cell_is_zero = false,
+            }
+            index += token_len(node);
+        }
+    });
+    diagnostics.sort_by_key(|diagnostic| diagnostic.index);
+    diagnostics
+}
+
+pub fn check_infinite_loops(ast: &[BfAst]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    for_each_loop(ast, 0, &mut |index, body| {
+        if !cancels_out(body) && never_terminates(body) {
+            diagnostics.push(Diagnostic::warning(
+                INFINITE_LOOP,
+                index,
+                "loop never terminates once entered",
+            ));
+        }
+    });
+    diagnostics
+}
+
+pub fn lint(code: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = check_brackets(code);
+    let (_, warnings) = BfParser::parse_with_warnings(code);
+
+    let balanced = code
+        .chars()
+        .enumerate()
+        .map(|(index, ch)| {
+            if diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.index == index)
+            {
+                ' '
+            } else {
+                ch
+            }
+        })
+        .collect::<String>();
+    let tokens = BfParser::parse(&balanced).expect("unmatched brackets were removed");
+    let ast = ast::from_tokens(&tokens).expect("unmatched brackets were removed");
+
+    diagnostics.extend(warnings.into_iter().map(Diagnostic::from));
+    diagnostics.extend(check_tape_underflow(&ast));
+    diagnostics.extend(check_empty_loops(&ast));
+    diagnostics.extend(check_dead_loops(&ast));
+    diagnostics.extend(check_infinite_loops(&ast));
+    diagnostics.sort_by_key(|diagnostic| diagnostic.index);
+
+    // Every check points at a single command or at the '[' of a loop, so the
+    // end is the next character or one past the matching ']'.
+    let chars = balanced.chars().collect::<Vec<_>>();
+    for diagnostic in &mut diagnostics {
+        diagnostic.end = diagnostic
+            .end
+            .or_else(|| Some(loop_end(&chars, diagnostic.index)));
+    }
+    diagnostics
+}
+
+fn loop_end(chars: &[char], index: usize) -> usize {
+    if chars.get(index) != Some(&'[') {
+        return index + 1;
+    }
+
+    let mut depth = 0;
+    for (offset, ch) in chars[index..].iter().enumerate() {
+        match ch {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    return index + offset + 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    index + 1
+}
+
+fn token_len(node: &BfAst) -> usize {
+    match node {
+        BfAst::Loop(body) | BfAst::Transfer { body, .. } => {
+            body.iter().map(token_len).sum::<usize>() + 2
+        }
+        _ => 1,
+    }
+}
+
+/// Whether `ast` does nothing once comments are dropped and opposite moves and
+/// arithmetic cancel each other out, like `[+-]` or `[<>]`.
+fn cancels_out(ast: &[BfAst]) -> bool {
+    let mut pending: Vec<(bool, i64)> = vec![];
+
+    for node in ast {
+        let (is_move, amount) = match node {
+            BfAst::Inc(val) => (false, *val as i64),
+            BfAst::Dec(val) => (false, -(*val as i64)),
+            BfAst::Right(val) => (true, *val as i64),
+            BfAst::Left(val) => (true, -(*val as i64)),
+            BfAst::Comment(_) => continue,
+            _ => return false,
+        };
+        match pending.last_mut() {
+            Some((last_is_move, total)) if *last_is_move == is_move => {
+                *total += amount;
+                if *total == 0 {
+                    pending.pop();
+                }
+            }
+            _ => pending.push((is_move, amount)),
+        }
+    }
+
+    pending.is_empty()
+}
+
+fn never_terminates(body: &[BfAst]) -> bool {
+    let mut offset = 0i64;
+    let mut delta = 0u8;
+
+    for node in body {
+        match node {
+            BfAst::Inc(val) if offset == 0 => delta = delta.wrapping_add(*val),
+            BfAst::Dec(val) if offset == 0 => delta = delta.wrapping_sub(*val),
+            BfAst::Left(val) => offset -= *val as i64,
+            BfAst::Right(val) => offset += *val as i64,
+            BfAst::Input
+            | BfAst::Extension(_)
+            | BfAst::SwitchTape
+            | BfAst::Fork
+            | BfAst::Loop(_)
+            | BfAst::Transfer { .. } => return false,
+            _ => {}
+        }
+    }
+
+    offset == 0 && delta == 0
+}
+
+fn for_each_loop(ast: &[BfAst], mut index: usize, f: &mut impl FnMut(usize, &[BfAst])) {
+    for node in ast {
+        if let BfAst::Loop(body) | BfAst::Transfer { body, .. } = node {
+            f(index, body);
+            for_each_loop(body, index + 1, f);
+        }
+        index += token_len(node);
+    }
+}
+
+fn for_each_block(
+    ast: &[BfAst],
+    index: usize,
+    at_start: bool,
+    f: &mut impl FnMut(usize, &[BfAst], bool),
+) {
+    f(index, ast, at_start);
+    for_each_loop(ast, index, &mut |index, body| f(index + 1, body, false));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bf::{ast, bf_parser::BfParser};
+
+    use super::*;
+
+    fn underflow(code: &str) -> Vec<Diagnostic> {
+        check_tape_underflow(&ast::from_tokens(&BfParser::parse(code).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn definite_underflow() {
+        assert_eq!(
+            underflow("<+"),
+            vec![Diagnostic::warning(
+                TAPE_UNDERFLOW,
+                0,
+                "tape pointer does underflow past the first cell"
+            )]
+        );
+        assert_eq!(underflow("> +\n<<")[0].index, 5);
+    }
+
+    #[test]
+    fn possible_underflow() {
+        assert_eq!(
+            underflow("+[<]"),
+            vec![Diagnostic::warning(
+                TAPE_UNDERFLOW,
+                2,
+                "tape pointer may underflow past the first cell"
+            )]
+        );
+        assert_eq!(
+            underflow("+[>]<<")[0].message,
+            "tape pointer may underflow past the first cell"
+        );
+        assert_eq!(underflow(">[>[-]]<<")[0].index, 8);
+    }
+
+    #[test]
+    fn no_underflow() {
+        assert!(underflow(">><<").is_empty());
+        assert!(underflow("").is_empty());
+        assert!(underflow(">+[->+<]>[-<+>]").is_empty());
+        assert!(underflow("+[>+]").is_empty());
+        assert!(underflow(
+            "++++++++++[>+++++++>++++++++++>+++>+<<<<-]>++.>+.+++++++..+++.>++.<<+++++++++++++++.>.+++.------.--------.>+.>."
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn reports_first_position_only() {
+        let diagnostics = underflow("<<<");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].index, 0);
+    }
+
+    fn codes(code: &str) -> Vec<(&'static str, usize)> {
+        lint(code)
+            .into_iter()
+            .map(|diagnostic| (diagnostic.code, diagnostic.index))
+            .collect()
+    }
+
+    #[test]
+    fn unmatched_brackets() {
+        assert_eq!(
+            codes("+]["),
+            vec![(UNMATCHED_BRACKET, 1), (UNMATCHED_BRACKET, 2)]
+        );
+        assert_eq!(codes("+[[-]"), vec![(UNMATCHED_BRACKET, 1)]);
+        assert_eq!(lint("+[[-]")[0].end, Some(2));
+        assert!(check_brackets("+[>[-]<]").is_empty());
+    }
+
+    #[test]
+    fn empty_loops() {
+        assert_eq!(codes("+[]"), vec![(EMPTY_LOOP, 1)]);
+        assert_eq!(codes("+[ comment ]"), vec![(EMPTY_LOOP, 1)]);
+        assert_eq!(lint("+[ + - ]")[0].end, Some(8));
+        assert!(codes("+[-]").is_empty());
+
+        let empty = |code| {
+            let ast = ast::from_tokens(&BfParser::parse(code).unwrap()).unwrap();
+            check_empty_loops(&ast)
+                .into_iter()
+                .map(|diagnostic| diagnostic.index)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(empty("+[+-]"), vec![1]);
+        assert_eq!(empty("+[<>]"), vec![1]);
+        assert_eq!(empty("+[>+-<]>[<++-->]"), vec![1, 8]);
+        assert!(empty("+[>+<]").is_empty());
+    }
+
+    #[test]
+    fn dead_loops() {
+        assert_eq!(codes("[-]+"), vec![(DEAD_LOOP, 0)]);
+        assert_eq!(codes("+[-] [>+<-]"), vec![(DEAD_LOOP, 5)]);
+        assert_eq!(codes("+[>+[-][<+>-]<-]"), vec![(DEAD_LOOP, 7)]);
+        assert!(codes("+[-]+[-]").is_empty());
+    }
+
+    #[test]
+    fn infinite_loops() {
+        assert_eq!(codes("+[>+<]"), vec![(INFINITE_LOOP, 1)]);
+        assert_eq!(
+            codes("+[+-]"),
+            vec![
+                (EMPTY_LOOP, 1),
+                (crate::bf::bf_parser::REDUNDANT_OPERATIONS, 2)
+            ]
+        );
+        assert!(codes("+[>+<-]").is_empty());
+        assert!(codes("+[,]").is_empty());
+        assert!(codes("+[>]").is_empty());
+        assert!(codes("+[[-]]").is_empty());
+    }
+
+    #[test]
+    fn lint_includes_parse_warnings() {
+        assert_eq!(
+            codes("+[-]>+-<"),
+            vec![(crate::bf::bf_parser::REDUNDANT_OPERATIONS, 5)]
+        );
+    }
+
+    #[test]
+    fn lint_continues_past_bracket_errors() {
+        assert_eq!(
+            codes("]<[]"),
+            vec![(UNMATCHED_BRACKET, 0), (TAPE_UNDERFLOW, 1), (EMPTY_LOOP, 2)]
+        );
+    }
+}
@@ -0,0 +1,476 @@
+use super::{
+    bf_parser::{BfParserError, DEFAULT_MAX_NESTING_DEPTH},
+    bf_token::BfToken,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BfAst {
+    Inc(u8),
+    Dec(u8),
+    Left(usize),
+    Right(usize),
+    Input,
+    Output,
+    Loop(Vec<BfAst>),
+    /// A loop [`TransferFinder`] found to add its counter, times each factor,
+    /// to the cell at each offset and leave the counter at zero; `[-]` is one
+    /// with no factors. `body` is the loop as it was. [`from_tokens`] never
+    /// builds one.
+    ///
+    /// [`TransferFinder`]: super::visitor::TransferFinder
+    Transfer {
+        factors: Vec<(isize, u8)>,
+        body: Vec<BfAst>,
+    },
+    Comment(char),
+    Extension(u8),
+    SwitchTape,
+    Fork,
+}
+
+/// Builds the tree of `tokens`. Offset `+` and `-` have no node of their own
+/// and become a move to their cell, the `+` or `-` and a move back.
+pub fn from_tokens(tokens: &[BfToken]) -> Result<Vec<BfAst>, BfParserError> {
+    from_tokens_with_limit(tokens, DEFAULT_MAX_NESTING_DEPTH)
+}
+
+/// Like [`from_tokens`], refusing loops nested more than `max_depth` deep as
+/// the parser does.
+pub fn from_tokens_with_limit(
+    tokens: &[BfToken],
+    max_depth: usize,
+) -> Result<Vec<BfAst>, BfParserError> {
+    let mut stack: Vec<(usize, Vec<BfAst>)> = vec![];
+    let mut current = vec![];
+
+    for (index, token) in tokens.iter().enumerate() {
+        match *token {
+            BfToken::NotCommand(ch) => current.push(BfAst::Comment(ch)),
+            BfToken::Increment(val) => current.push(BfAst::Inc(val)),
+            BfToken::Decrement(val) => current.push(BfAst::Dec(val)),
+            BfToken::IncrementAt(offset, val) | BfToken::DecrementAt(offset, val) => {
+                let distance = offset.unsigned_abs();
+                let (there, back) = if offset < 0 {
+                    (BfAst::Left(distance), BfAst::Right(distance))
+                } else {
+                    (BfAst::Right(distance), BfAst::Left(distance))
+                };
+                let node = match token {
+                    BfToken::IncrementAt(..) => BfAst::Inc(val),
+                    _ => BfAst::Dec(val),
+                };
+                current.extend([there, node, back]);
+            }
+            BfToken::CursorLeft(val) => current.push(BfAst::Left(val)),
+            BfToken::CursorRight(val) => current.push(BfAst::Right(val)),
+            BfToken::PrintChar => current.push(BfAst::Output),
+            BfToken::PrintCharN(count) => {
+                current.extend(std::iter::repeat_n(BfAst::Output, count));
+            }
+            BfToken::InputChar => current.push(BfAst::Input),
+            BfToken::Extension(id) => current.push(BfAst::Extension(id)),
+            BfToken::SwitchTape => current.push(BfAst::SwitchTape),
+            BfToken::Fork => current.push(BfAst::Fork),
+            BfToken::LoopStart => {
+                if stack.len() == max_depth {
+                    return Err(BfParserError::NestingTooDeep {
+                        index,
+                        depth: max_depth + 1,
+                    });
+                }
+                stack.push((index, current));
+                current = vec![];
+            }
+            BfToken::LoopEnd => {
+                let (_, mut parent) = stack.pop().ok_or(BfParserError::LoopNotClosed(index))?;
+                parent.push(BfAst::Loop(current));
+                current = parent;
+            }
+        }
+    }
+
+    if let Some((start, _)) = stack.pop() {
+        return Err(BfParserError::LoopNotClosed(start));
+    }
+
+    Ok(current)
+}
+
+pub fn to_tokens(ast: &[BfAst]) -> Vec<BfToken> {
+    let mut tokens = vec![];
+    // The bodies being walked, innermost last, so depth costs heap, not stack.
+    let mut stack = vec![ast.iter()];
+
+    while let Some(nodes) = stack.last_mut() {
+        let Some(node) = nodes.next() else {
+            stack.pop();
+            if !stack.is_empty() {
+                tokens.push(BfToken::LoopEnd);
+            }
+            continue;
+        };
+        match node {
+            BfAst::Inc(val) => tokens.push(BfToken::Increment(*val)),
+            BfAst::Dec(val) => tokens.push(BfToken::Decrement(*val)),
+            BfAst::Left(val) => tokens.push(BfToken::CursorLeft(*val)),
+            BfAst::Right(val) => tokens.push(BfToken::CursorRight(*val)),
+            BfAst::Input => tokens.push(BfToken::InputChar),
+            BfAst::Output => tokens.push(BfToken::PrintChar),
+            BfAst::Comment(ch) => tokens.push(BfToken::NotCommand(*ch)),
+            BfAst::Extension(id) => tokens.push(BfToken::Extension(*id)),
+            BfAst::SwitchTape => tokens.push(BfToken::SwitchTape),
+            BfAst::Fork => tokens.push(BfToken::Fork),
+            BfAst::Loop(body) | BfAst::Transfer { body, .. } => {
+                tokens.push(BfToken::LoopStart);
+                stack.push(body.iter());
+            }
+        }
+    }
+    tokens
+}
+
+pub fn max_depth(ast: &[BfAst]) -> usize {
+    let mut deepest = 0;
+    let mut stack = vec![(ast, 0)];
+    while let Some((nodes, depth)) = stack.pop() {
+        deepest = deepest.max(depth);
+        for node in nodes {
+            if let BfAst::Loop(body) | BfAst::Transfer { body, .. } = node {
+                stack.push((body, depth + 1));
+            }
+        }
+    }
+    deepest
+}
+
+fn token_len(ast: &[BfAst]) -> usize {
+    ast.iter()
+        .map(|node| match node {
+            BfAst::Loop(body) | BfAst::Transfer { body, .. } => token_len(body) + 2,
+            _ => 1,
+        })
+        .sum()
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CommentStyle {
+    Strip,
+    Dim,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct PrettyOptions {
+    pub indent: usize,
+    pub annotate_ranges: bool,
+    pub comments: CommentStyle,
+}
+
+impl Default for PrettyOptions {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            annotate_ranges: false,
+            comments: CommentStyle::Strip,
+        }
+    }
+}
+
+pub fn pretty(ast: &[BfAst], options: PrettyOptions) -> String {
+    let mut printer = PrettyPrinter {
+        options,
+        output: String::new(),
+        line: vec![],
+        run: None,
+        comment: String::new(),
+        token_index: 0,
+    };
+    printer.print_block(ast, 0);
+    printer.flush_line(0);
+    printer.output
+}
+
+struct PrettyPrinter {
+    options: PrettyOptions,
+    output: String,
+    line: Vec<String>,
+    run: Option<(char, usize)>,
+    comment: String,
+    token_index: usize,
+}
+
+impl PrettyPrinter {
+    fn print_block(&mut self, ast: &[BfAst], depth: usize) {
+        for node in ast {
+            match node {
+                BfAst::Inc(val) => self.push_run('+', *val as usize),
+                BfAst::Dec(val) => self.push_run('-', *val as usize),
+                BfAst::Left(val) => self.push_run('<', *val),
+                BfAst::Right(val) => self.push_run('>', *val),
+                BfAst::Input => self.push_run(',', 1),
+                BfAst::Output => self.push_run('.', 1),
+                BfAst::SwitchTape => self.push_run('^', 1),
+                BfAst::Fork => self.push_run('Y', 1),
+                BfAst::Extension(id) => {
+                    self.flush_run();
+                    self.flush_comment();
+                    self.line.push(format!("ext({id})"));
+                }
+                BfAst::Comment(ch) => {
+                    if self.options.comments == CommentStyle::Dim {
+                        self.flush_run();
+                        self.comment.push(*ch);
+                    }
+                }
+                BfAst::Loop(body) | BfAst::Transfer { body, .. } => {
+                    self.flush_line(depth);
+                    let start = self.token_index;
+                    self.token_index += 1;
+
+                    let mut open = String::from("[");
+                    if self.options.annotate_ranges {
+                        let end = start + token_len(body) + 1;
+                        open.push_str(&format!("  ; tokens {start}..={end}"));
+                    }
+                    self.push_line(depth, &open);
+
+                    self.print_block(body, depth + 1);
+                    self.flush_line(depth + 1);
+
+                    self.token_index += 1;
+                    self.push_line(depth, "]");
+                }
+            }
+
+            if !matches!(node, BfAst::Loop(_) | BfAst::Transfer { .. }) {
+                self.token_index += 1;
+            }
+        }
+    }
+
+    fn push_run(&mut self, symbol: char, count: usize) {
+        self.flush_comment();
+        match &mut self.run {
+            Some((last, total)) if *last == symbol => *total += count,
+            _ => {
+                self.flush_run();
+                self.run = Some((symbol, count));
+            }
+        }
+    }
+
+    fn flush_run(&mut self) {
+        if let Some((symbol, count)) = self.run.take() {
+            if count == 1 {
+                self.line.push(symbol.to_string());
+            } else {
+                self.line.push(format!("{symbol} x{count}"));
+            }
+        }
+    }
+
+    fn flush_comment(&mut self) {
+        let text = self
+            .comment
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.comment.clear();
+        if !text.is_empty() {
+            self.line.push(format!("\x1b[2m{text}\x1b[0m"));
+        }
+    }
+
+    fn flush_line(&mut self, depth: usize) {
+        self.flush_run();
+        self.flush_comment();
+        if !self.line.is_empty() {
+            let line = self.line.join(" ");
+            self.line.clear();
+            self.push_line(depth, &line);
+        }
+    }
+
+    fn push_line(&mut self, depth: usize, line: &str) {
+        self.output
+            .push_str(&" ".repeat(depth * self.options.indent));
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bf::bf_parser::BfParser;
+
+    use super::*;
+
+    const HELLO_WORLD: &str = "++++++++++[>+++++++>++++++++++>+++>+<<<<-]
+    >++.>+.+++++++..+++.>++.<<+++++++++++++++.
+    >.+++.------.--------.>+.>.";
+
+    const QUINE: &str = "-->+++>+>+>+>+++++>++>++>->+++>++>+>>>>>>>>>>>>>>>>->++++>>>>->+++>+++>+++>+++>+++>+++>+>+>>>->->>++++>+>>>>->>++++>+>+>>->->++>++>++>++++>+>++>->++>++++>+>+>++>++>->->++>++>++++>+>+>>>>>->>->>++++>++>++>++++>>>>>->>>>>+++>->++++>->->->+++>>>+>+>+++>+>++++>>+++>->>>>>->>>++++>++>++>+>+++>->++++>>->->+++>+>+++>+>++++>>>+++>->++++>>->->++>++++>++>++++>>++[-[->>+[>]++[<]<]>>+[>]<--[++>++++>]+[<]<<++]>>>[>]++++>++++[--[+>+>++++<<[-->>--<<[->-<[--->>+<<[+>+++<[+>>++<<]]]]]]>+++[>+++++++++++++++<-]>--.<<<]";
+
+    // The AST has no repeated-output node, so `PrintCharN` comes back as single prints.
+    fn expand_output(tokens: Vec<BfToken>) -> Vec<BfToken> {
+        tokens
+            .into_iter()
+            .flat_map(|token| match token {
+                BfToken::PrintCharN(count) => vec![BfToken::PrintChar; count],
+                token => vec![token],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hello_world_round_trip() {
+        let tokens = BfParser::parse(HELLO_WORLD).unwrap();
+        let ast = from_tokens(&tokens).unwrap();
+        assert_eq!(to_tokens(&ast), tokens);
+
+        let tokens = BfParser::parse_compress(HELLO_WORLD).unwrap();
+        let ast = from_tokens(&tokens).unwrap();
+        assert_eq!(to_tokens(&ast), expand_output(tokens));
+    }
+
+    #[test]
+    fn quine_round_trip() {
+        let tokens = BfParser::parse(QUINE).unwrap();
+        let ast = from_tokens(&tokens).unwrap();
+        assert_eq!(to_tokens(&ast), tokens);
+
+        let tokens = BfParser::parse_compress(QUINE).unwrap();
+        let ast = from_tokens(&tokens).unwrap();
+        assert_eq!(to_tokens(&ast), expand_output(tokens));
+    }
+
+    #[test]
+    fn nested_structure() {
+        let tokens = BfParser::parse("+[>[-]<]").unwrap();
+        let ast = from_tokens(&tokens).unwrap();
+
+        assert_eq!(
+            ast,
+            vec![
+                BfAst::Inc(1),
+                BfAst::Loop(vec![
+                    BfAst::Right(1),
+                    BfAst::Loop(vec![BfAst::Dec(1)]),
+                    BfAst::Left(1),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn unbalanced_tokens() {
+        let tokens = [BfToken::LoopStart, BfToken::Increment(1)];
+        assert_eq!(from_tokens(&tokens), Err(BfParserError::LoopNotClosed(0)));
+
+        let tokens = [BfToken::Increment(1), BfToken::LoopEnd];
+        assert_eq!(from_tokens(&tokens), Err(BfParserError::LoopNotClosed(1)));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        let tokens = BfParser::parse("[[[-]]]").unwrap();
+        assert_eq!(max_depth(&from_tokens_with_limit(&tokens, 3).unwrap()), 3);
+        assert_eq!(
+            from_tokens_with_limit(&tokens, 2),
+            Err(BfParserError::NestingTooDeep { index: 2, depth: 3 })
+        );
+
+        let depth = 100_000;
+        let mut tokens = vec![BfToken::LoopStart; depth];
+        tokens.extend(vec![BfToken::LoopEnd; depth]);
+        assert_eq!(
+            from_tokens(&tokens),
+            Err(BfParserError::NestingTooDeep {
+                index: DEFAULT_MAX_NESTING_DEPTH,
+                depth: DEFAULT_MAX_NESTING_DEPTH + 1
+            })
+        );
+    }
+
+    #[test]
+    fn nesting_depth() {
+        assert_eq!(max_depth(&[]), 0);
+        assert_eq!(
+            max_depth(&from_tokens(&BfParser::parse("+-.,").unwrap()).unwrap()),
+            0
+        );
+        assert_eq!(
+            max_depth(&from_tokens(&BfParser::parse(HELLO_WORLD).unwrap()).unwrap()),
+            1
+        );
+
+        let depth = 500;
+        let code = format!("{}+{}[[-]]", "[>".repeat(depth), "<]".repeat(depth));
+        let ast = from_tokens(&BfParser::parse(&code).unwrap()).unwrap();
+        assert_eq!(max_depth(&ast), depth);
+        assert_eq!(to_tokens(&ast), BfParser::parse(&code).unwrap());
+    }
+
+    #[test]
+    fn pretty_hello_world() {
+        let ast = from_tokens(&BfParser::parse(HELLO_WORLD).unwrap()).unwrap();
+
+        assert_eq!(
+            pretty(&ast, PrettyOptions::default()),
+            "+ x10
+[
+    > + x7 > + x10 > + x3 > + < x4 -
+]
+> + x2 . > + . + x7 . x2 + x3 . > + x2 . < x2 + x15 . > . + x3 . - x6 . - x8 . > + . > .
+"
+        );
+    }
+
+    #[test]
+    fn pretty_options() {
+        let ast = from_tokens(&BfParser::parse("+ add [-[> move\n<]]").unwrap()).unwrap();
+
+        let options = PrettyOptions {
+            indent: 2,
+            annotate_ranges: true,
+            comments: CommentStyle::Dim,
+        };
+        assert_eq!(
+            pretty(&ast, options),
+            "+ \x1b[2madd\x1b[0m
+[  ; tokens 6..=18
+  -
+  [  ; tokens 8..=17
+    > \x1b[2mmove\x1b[0m <
+  ]
+]
+"
+        );
+    }
+
+    #[test]
+    fn pretty_ranges_match_tokens() {
+        let tokens = BfParser::parse(HELLO_WORLD).unwrap();
+        let ast = from_tokens(&tokens).unwrap();
+        let options = PrettyOptions {
+            annotate_ranges: true,
+            ..Default::default()
+        };
+
+        assert!(pretty(&ast, options).contains("[  ; tokens 10..=41"));
+        assert_eq!(tokens[10], BfToken::LoopStart);
+        assert_eq!(BfParser::loop_matching(&tokens).unwrap()[10], 41);
+    }
+
+    #[test]
+    fn pretty_output_is_not_brainfuck() {
+        let tokens = BfParser::parse_compress(HELLO_WORLD).unwrap();
+        let printed = pretty(&from_tokens(&tokens).unwrap(), PrettyOptions::default());
+
+        let reparsed = BfParser::parse_compress(&printed).unwrap();
+        assert_ne!(reparsed, tokens);
+        assert_ne!(
+            pretty(&from_tokens(&reparsed).unwrap(), PrettyOptions::default()),
+            printed
+        );
+    }
+}
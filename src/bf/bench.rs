@@ -0,0 +1,154 @@
+use std::{
+    io::{self, Cursor},
+    time::{Duration, Instant},
+};
+
+use super::{
+    bf_machine::{BfMachine, MachineConfig},
+    bf_parser::ParseOptions,
+    error::BfError,
+    events::ExecEvent,
+    program::{OptLevel, Program},
+};
+
+/// How a program fared at one [`OptLevel`] in [`bench_levels`].
+#[derive(Debug, PartialEq, Clone)]
+pub struct LevelBench {
+    pub level: OptLevel,
+    pub tokens: usize,
+    /// Instructions one run dispatched.
+    pub steps: usize,
+    /// The wall time of each run.
+    pub times: Vec<Duration>,
+    /// What one run wrote.
+    pub output: Vec<u8>,
+}
+
+impl LevelBench {
+    pub fn mean(&self) -> Duration {
+        match self.times.len() {
+            0 => Duration::ZERO,
+            runs => self.times.iter().sum::<Duration>() / runs as u32,
+        }
+    }
+
+    /// The standard deviation of the run times, 0 for fewer than two runs.
+    pub fn std_dev(&self) -> Duration {
+        let runs = self.times.len();
+        if runs < 2 {
+            return Duration::ZERO;
+        }
+        let mean = self.mean().as_secs_f64();
+        let variance = self
+            .times
+            .iter()
+            .map(|time| (time.as_secs_f64() - mean).powi(2))
+            .sum::<f64>()
+            / (runs - 1) as f64;
+        Duration::from_secs_f64(variance.sqrt())
+    }
+
+    /// How many times faster than `baseline` a run is on average.
+    pub fn speedup(&self, baseline: &LevelBench) -> f64 {
+        baseline.mean().as_secs_f64() / self.mean().as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+/// Parses `code` at every [`OptLevel`] and runs each `runs` times on a
+/// default machine reading `input`, timing runs that write to a sink. Every
+/// run gets a new machine, so O2 is unrolled for one as well. An
+/// extra untimed run per level counts its steps and keeps its output, for
+/// [`first_divergence`] to compare.
+pub fn bench_levels(
+    code: &str,
+    options: &ParseOptions,
+    input: &[u8],
+    runs: usize,
+) -> Result<Vec<LevelBench>, BfError> {
+    let mut results = vec![];
+    for level in OptLevel::ALL {
+        let program = Program::parse_at(code, options, level)?;
+        let program = match level {
+            OptLevel::O2 => program.unrolled_for_fresh_tape(&MachineConfig::default()),
+            _ => program,
+        };
+
+        let mut output = vec![];
+        let mut steps = 0;
+        BfMachine::with_config(MachineConfig::default(), Cursor::new(input), &mut output)
+            .run_with_events(&program, |event| {
+                if let ExecEvent::Halt { steps: halted } = event {
+                    steps = halted;
+                }
+            })?;
+
+        let mut times = Vec::with_capacity(runs);
+        for _ in 0..runs {
+            let mut machine =
+                BfMachine::with_config(MachineConfig::default(), Cursor::new(input), io::sink());
+            let started = Instant::now();
+            machine.run(&program)?;
+            times.push(started.elapsed());
+        }
+
+        results.push(LevelBench {
+            level,
+            tokens: program.tokens().len(),
+            steps,
+            times,
+            output,
+        });
+    }
+    Ok(results)
+}
+
+/// The first result whose output differs from that of the first.
+pub fn first_divergence(results: &[LevelBench]) -> Option<&LevelBench> {
+    let (first, rest) = results.split_first()?;
+    rest.iter().find(|result| result.output != first.output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_agree_and_shrink() {
+        let code = include_str!("../../tests/programs/hello_world.b");
+        let results = bench_levels(code, &ParseOptions::default(), b"", 3).unwrap();
+
+        let levels: Vec<_> = results.iter().map(|result| result.level).collect();
+        assert_eq!(levels, OptLevel::ALL);
+        assert!(results.iter().all(|result| result.times.len() == 3));
+        #[cfg(feature = "optimizer")]
+        assert!(results[2].tokens < results[1].tokens);
+        assert!(results[1].tokens < results[0].tokens);
+        assert!(results[2].steps < results[0].steps);
+        assert_eq!(results[0].output, b"Hello World!\n");
+        assert!(first_divergence(&results).is_none());
+
+        let mut diverged = results.clone();
+        diverged[1].output.push(b'!');
+        assert_eq!(first_divergence(&diverged).unwrap().level, OptLevel::O1);
+    }
+
+    #[test]
+    fn spread_of_run_times() {
+        let bench = LevelBench {
+            level: OptLevel::O0,
+            tokens: 0,
+            steps: 0,
+            times: [2, 4, 4, 4, 5, 5, 7, 9].map(Duration::from_secs).to_vec(),
+            output: vec![],
+        };
+        assert_eq!(bench.mean(), Duration::from_secs(5));
+        assert!((bench.std_dev().as_secs_f64() - 2.138).abs() < 0.001);
+
+        let faster = LevelBench {
+            times: vec![Duration::from_millis(2500)],
+            ..bench.clone()
+        };
+        assert_eq!(faster.std_dev(), Duration::ZERO);
+        assert_eq!(faster.speedup(&bench), 2.0);
+    }
+}
@@ -1,108 +1,1614 @@
 use std::{
+    collections::{BTreeSet, HashMap},
     error::Error,
-    fmt::Debug,
-    io::{stdin, stdout, Read, Stdin, Stdout, Write},
+    fmt::{Debug, Display},
+    io::{self, stdin, stdout, ErrorKind, Read, Stdin, Stdout, Write},
+    mem,
+    ops::Range,
+    time::Instant,
 };
 
-use super::bf_token::BfToken;
+#[cfg(feature = "mmap")]
+use std::path::Path;
+
+use log::debug;
+
+use super::{
+    bf_token::BfToken,
+    cell::Cell,
+    compiled::{CompiledProgram, OpCode},
+    coverage::Coverage,
+    handler::{ControlFlow, HostFn, InstructionHandler, MachineContext, Rng},
+    io::{InputTranslation, OutputTranslation, TranslatingReader, TranslatingWriter},
+    profile::{Profile, Timings},
+    program::Program,
+    tape::Tape,
+    trace::TraceRecord,
+};
 
-pub struct BfMachine<R, W>
+/// Runs programs on a tape of `C` cells, bytes unless given another
+/// [`Cell`] type.
+pub struct BfMachine<R, W, C = u8>
 where
     R: Read,
     W: Write,
+    C: Cell,
 {
     cursor: usize,
-    memory: Vec<u8>,
+    memory: Tape<C>,
+    /// One past the last cell written since the machine was built or reset,
+    /// always past the cursor; [`BfMachine::reset`] zeroes no more.
+    touched: usize,
+    max_steps: usize,
+    max_output: usize,
+    eof_mode: EofMode,
+    /// Whether a `,` whose input would block pauses a run that can pause.
+    nonblocking_input: bool,
+    tape_policy: TapePolicy,
+    max_memory: usize,
+    bytes_read: usize,
+    bytes_written: usize,
     input: R,
     output: W,
+    handler: Option<Box<dyn InstructionHandler<C>>>,
+    rng: Rng,
+    host_fns: HashMap<u8, HostFn<C>>,
+    coverage: Option<Coverage>,
+    profile: Option<Profile>,
+    timings: Option<Timings>,
+    protection: Protection,
+    breakpoints: BTreeSet<usize>,
+    /// The pass each loop, by the pc of its `[`, is on, for the loop trace of
+    /// a failed run.
+    loop_iterations: Vec<usize>,
+    /// The tape `BfToken::SwitchTape` switched away from, once it ran.
+    other_tape: Option<OtherTape<C>>,
+    /// 0 or 1, the tape that `memory` and `cursor` are.
+    active_tape: usize,
+}
+
+/// The tape of a multi-tape machine that commands do not act on right now.
+#[derive(Clone)]
+struct OtherTape<C: Cell> {
+    memory: Tape<C>,
+    cursor: usize,
+    touched: usize,
+}
+
+/// A thread of a Brainfork run while another one runs: what would be the
+/// machine's tapes and cursors. [`BfMachine::swap_thread`] trades it for the
+/// running thread.
+pub(crate) struct ThreadState<C: Cell = u8> {
+    tape: OtherTape<C>,
+    other_tape: Option<OtherTape<C>>,
+    active_tape: usize,
+}
+
+impl<C: Cell> ThreadState<C> {
+    /// The cells the thread holds on to, across both its tapes.
+    pub(crate) fn cells(&self) -> usize {
+        self.tape.memory.len()
+            + self
+                .other_tape
+                .as_ref()
+                .map_or(0, |other| other.memory.len())
+    }
+}
+
+/// How a run that honors breakpoints ended.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RunOutcome {
+    /// The program ran to its end or an extension halted it.
+    Completed,
+    /// The run stopped before executing the instruction at `pc`.
+    Breakpoint { pc: usize },
+    /// The `,` at `pc` found no input yet; resuming at `pc` reads it again.
+    NeedsInput { pc: usize },
+}
+
+/// What `,` does once the input is exhausted.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum EofMode {
+    #[default]
+    Error,
+    Zero,
+    Unchanged,
+    /// Store -1, the `EOF` of C's `getchar`, which is 255 in a byte cell.
+    MinusOne,
+}
+
+/// What moving the cursor past an end of the tape does.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum TapePolicy {
+    /// The tape is a ring: the last cell is left of the first.
+    #[default]
+    Wrap,
+    /// The tape starts at cell 0 and grows to the right on demand, up to the
+    /// machine's memory limit. Moving left of cell 0 is an error, which only
+    /// an unoptimized program reliably runs into; see
+    /// [`OptLevel::highest_for`](super::program::OptLevel::highest_for).
+    Grow,
+}
+
+/// The most cells a growing tape may reach unless
+/// [`BfMachine::with_memory_limit`] says otherwise: 64 MiB, far beyond what
+/// well-behaved programs use, but small enough that a runaway `[>+]` fails
+/// fast instead of exhausting the host.
+pub const DEFAULT_MAX_MEMORY_CELLS: usize = 64 << 20;
+
+/// The tape and cursor of a machine, without its I/O handles.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(deserialize = "C: serde::Deserialize<'de>"))
+)]
+pub struct BfSnapshot<C = u8> {
+    /// The cursor on the active tape.
+    pub cursor: usize,
+    /// The active tape.
+    pub memory: Vec<C>,
+    /// The tape a multi-tape program switched away from, if it switched.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub other_tape: Option<TapeSnapshot<C>>,
+}
+
+/// The inactive tape in a [`BfSnapshot`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TapeSnapshot<C = u8> {
+    /// Which tape it is, 0 or 1; the active tape is the other one.
+    pub tape: usize,
+    pub cursor: usize,
+    pub memory: Vec<C>,
+}
+
+impl<C> BfSnapshot<C> {
+    /// The tape `memory` is, 0 unless a program switched to tape 1.
+    pub fn active_tape(&self) -> usize {
+        self.other_tape.as_ref().map_or(0, |other| 1 - other.tape)
+    }
+}
+
+/// The settings of a machine, for building several machines alike.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MachineConfig {
+    pub memory_size: usize,
+    pub max_steps: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+    pub eof_mode: EofMode,
+    pub tape_policy: TapePolicy,
+    pub max_memory_cells: usize,
+}
+
+/// Data given to [`BfMachine::load_tape`] that runs past the end of the tape.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TapeOverflow {
+    pub end: usize,
+    pub tape_len: usize,
+}
+
+#[derive(Debug)]
+pub enum BfRuntimeError {
+    UnexpectedEof {
+        pc: usize,
+    },
+    Io {
+        pc: usize,
+        source: io::Error,
+    },
+    StepLimitExceeded {
+        pc: usize,
+    },
+    OutputLimitExceeded {
+        pc: usize,
+        bytes: usize,
+    },
+    MemoryLimitExceeded {
+        pc: usize,
+        requested: usize,
+        limit: usize,
+    },
+    CursorUnderflow {
+        pc: usize,
+    },
+    UnhandledExtension {
+        pc: usize,
+        id: u8,
+    },
+    HostError {
+        pc: usize,
+        message: String,
+    },
+    WriteProtected {
+        pc: usize,
+        cell: usize,
+    },
+    /// A `BfToken::Fork` ran outside [`run_threads`], which alone can fork.
+    ///
+    /// [`run_threads`]: super::fork::run_threads
+    UnhandledFork {
+        pc: usize,
+    },
+    /// `error` happened inside `loops`, innermost first.
+    InLoops {
+        error: Box<BfRuntimeError>,
+        loops: Vec<LoopFrame>,
+    },
+}
+
+/// A loop that was running when a run failed.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct LoopFrame {
+    /// The pc of the loop's `[`.
+    pub pc: usize,
+    /// Where the `[` is in the source, as a char index.
+    pub span: Option<usize>,
+    /// The pass through the body that was running, counted from 1.
+    pub iteration: usize,
+}
+
+/// The cells marked read-only by [`BfMachine::protect_range`], kept sorted and
+/// merged.
+#[derive(Debug, Default, Clone)]
+struct Protection {
+    ranges: Vec<Range<usize>>,
+    /// From the first protected cell to past the last, and empty without any,
+    /// so a write anywhere else costs one bounds check.
+    bounds: Range<usize>,
+}
+
+impl<R, W> BfMachine<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    pub fn new(memory_size: usize, input: R, output: W) -> Self {
+        let config = MachineConfig {
+            memory_size,
+            ..Default::default()
+        };
+        Self::with_cells(config, input, output)
+    }
+
+    pub fn with_config(config: MachineConfig, input: R, output: W) -> Self {
+        Self::with_cells(config, input, output)
+    }
+
+    /// Runs a [`CompiledProgram`], whose `+` and `-` are one wrapping byte
+    /// add, which is why only byte tapes run one.
+    pub fn run_compiled(&mut self, program: &CompiledProgram) -> Result<(), BfRuntimeError> {
+        self.log_run("run_compiled", program.len(), 0);
+        let mut pc = 0;
+        let mut steps = 0;
+
+        while pc < program.ops.len() {
+            if steps == self.max_steps {
+                return Err(BfRuntimeError::StepLimitExceeded {
+                    pc: program.pcs[pc],
+                });
+            }
+            steps += 1;
+
+            let op = program.ops[pc];
+            let cursor = self.cursor;
+            let len = self.memory.len();
+
+            match op.code {
+                OpCode::Add => {
+                    self.protection.check(cursor, program.pcs[pc])?;
+                    self.memory[cursor] = self.memory[cursor].wrapping_add(op.operand as u8);
+                }
+                OpCode::AddAt => {
+                    let at =
+                        self.move_cursor(op.offset < 0, op.offset.unsigned_abs(), program.pcs[pc])?;
+                    self.protection.check(at, program.pcs[pc])?;
+                    self.memory[at] = self.memory[at].wrapping_add(op.operand as u8);
+                }
+                OpCode::Left if op.operand <= cursor => self.cursor -= op.operand,
+                OpCode::Right if op.operand < len - cursor => {
+                    self.cursor += op.operand;
+                    self.touched = self.touched.max(self.cursor + 1);
+                }
+                OpCode::Left | OpCode::Right => {
+                    let left = op.code == OpCode::Left;
+                    self.cursor = self.move_cursor(left, op.operand, program.pcs[pc])?;
+                }
+                OpCode::JumpIfZero => {
+                    if self.memory[cursor] == 0 {
+                        pc = op.operand;
+                    }
+                }
+                OpCode::JumpIfNonZero => {
+                    if self.memory[cursor] != 0 {
+                        pc = op.operand;
+                    }
+                }
+                OpCode::Output => {
+                    let pc = program.pcs[pc];
+                    self.write_output(self.memory[cursor], op.operand, pc)?;
+                }
+                OpCode::Input => {
+                    self.protection.check(cursor, program.pcs[pc])?;
+                    let cell = &mut self.memory[cursor];
+                    *cell = read_byte(
+                        &mut self.input,
+                        self.eof_mode,
+                        &mut self.bytes_read,
+                        *cell,
+                        program.pcs[pc],
+                    )?;
+                }
+                OpCode::SwitchTape => self.switch_tape(),
+                OpCode::Fork => {
+                    return Err(BfRuntimeError::UnhandledFork {
+                        pc: program.pcs[pc],
+                    })
+                }
+                OpCode::Extension => {
+                    let mut ctx = MachineContext::new(
+                        program.pcs[pc],
+                        &mut self.cursor,
+                        &mut self.memory,
+                        &mut self.input,
+                        &mut self.output,
+                        &mut self.rng,
+                        &mut self.host_fns,
+                    );
+                    let token = BfToken::Extension(op.operand as u8);
+                    let flow = extension(self.handler.as_deref_mut(), token, &mut ctx);
+                    self.touched = self.memory.len();
+                    if flow? == ControlFlow::Halt {
+                        return Ok(());
+                    }
+                }
+            }
+
+            pc += 1;
+        }
+
+        Ok(())
+    }
+}
+
+impl<R, W, C> BfMachine<R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    /// Like [`BfMachine::with_config`], for a tape of any [`Cell`] type, as in
+    /// `BfMachine::<_, _, i32>::with_cells(config, input, output)`.
+    pub fn with_cells(config: MachineConfig, input: R, output: W) -> Self {
+        assert!(config.memory_size > 0);
+
+        let memory = Tape::Heap(vec![C::default(); config.memory_size]);
+        let mut machine = Self {
+            cursor: 0,
+            memory,
+            touched: 1,
+            max_steps: usize::MAX,
+            max_output: usize::MAX,
+            eof_mode: EofMode::default(),
+            nonblocking_input: false,
+            tape_policy: TapePolicy::default(),
+            max_memory: DEFAULT_MAX_MEMORY_CELLS,
+            bytes_read: 0,
+            bytes_written: 0,
+            input,
+            output,
+            handler: None,
+            rng: Rng::from_time(),
+            host_fns: HashMap::new(),
+            coverage: None,
+            profile: None,
+            timings: None,
+            protection: Protection::default(),
+            breakpoints: BTreeSet::new(),
+            loop_iterations: vec![],
+            other_tape: None,
+            active_tape: 0,
+        }
+        .with_eof_mode(config.eof_mode)
+        .with_tape_policy(config.tape_policy)
+        .with_memory_limit(config.max_memory_cells);
+        if let Some(max_steps) = config.max_steps {
+            machine.set_step_limit(max_steps);
+        }
+        if let Some(max_bytes) = config.max_output_bytes {
+            machine.set_output_limit(max_bytes);
+        }
+        machine
+    }
+
+    /// Limits every run to `max_steps` dispatched instructions, after which it
+    /// stops with `BfRuntimeError::StepLimitExceeded`.
+    pub fn with_step_limit(mut self, max_steps: usize) -> Self {
+        self.set_step_limit(max_steps);
+        self
+    }
+
+    pub fn set_step_limit(&mut self, max_steps: usize) {
+        self.max_steps = max_steps;
+    }
+
+    /// Limits the bytes the machine writes over its lifetime, counted like
+    /// [`BfMachine::bytes_written`]. The write that would pass the limit is cut
+    /// short there and the run stops with
+    /// `BfRuntimeError::OutputLimitExceeded`.
+    pub fn with_output_limit(mut self, max_bytes: usize) -> Self {
+        self.set_output_limit(max_bytes);
+        self
+    }
+
+    pub fn set_output_limit(&mut self, max_bytes: usize) {
+        self.max_output = max_bytes;
+    }
+
+    pub fn with_eof_mode(mut self, eof_mode: EofMode) -> Self {
+        self.eof_mode = eof_mode;
+        self
+    }
+
+    /// Makes [`BfMachine::run_until_break`] and
+    /// [`BfMachine::resume_until_break`] return [`RunOutcome::NeedsInput`]
+    /// when the input fails with `WouldBlock`, as a non-blocking reader does
+    /// when it has no data yet, instead of stopping with an I/O error. Other
+    /// runs still fail, having no way to be resumed.
+    pub fn with_nonblocking_input(mut self) -> Self {
+        self.nonblocking_input = true;
+        self
+    }
+
+    pub fn with_tape_policy(mut self, tape_policy: TapePolicy) -> Self {
+        self.tape_policy = tape_policy;
+        self
+    }
+
+    /// Rewrites the line endings of the input before `,` sees them.
+    pub fn with_input_translation(
+        self,
+        translation: InputTranslation,
+    ) -> BfMachine<TranslatingReader<R>, W, C> {
+        self.map_io(
+            |input| TranslatingReader::new(input, translation),
+            |output| output,
+        )
+    }
+
+    /// Rewrites the line endings the program writes before they reach the
+    /// output.
+    pub fn with_output_translation(
+        self,
+        translation: OutputTranslation,
+    ) -> BfMachine<R, TranslatingWriter<W>, C> {
+        self.map_io(
+            |input| input,
+            |output| TranslatingWriter::new(output, translation),
+        )
+    }
+
+    /// Caps how many cells a [`TapePolicy::Grow`] tape may reach; a move
+    /// beyond it stops the run with `BfRuntimeError::MemoryLimitExceeded`
+    /// instead of allocating. Wrapping tapes never grow, but the tapes of
+    /// Brainfork threads count toward it either way.
+    pub fn with_memory_limit(mut self, max_cells: usize) -> Self {
+        self.set_memory_limit(max_cells);
+        self
+    }
+
+    pub fn set_memory_limit(&mut self, max_cells: usize) {
+        self.max_memory = max_cells;
+    }
+
+    /// Keeps the tape in the file at `path`, mapped into memory, so cells are
+    /// read and written in the file and outlive the machine. The file is
+    /// created or zero-extended to at least `len` cells, and the tape is as
+    /// long as the file; it does not grow. Changes reach the file on drop or
+    /// [`BfMachine::sync`]. Nothing else may change the file while it is
+    /// mapped.
+    #[cfg(feature = "mmap")]
+    pub fn with_mmap_tape(mut self, path: impl AsRef<Path>, len: usize) -> io::Result<Self> {
+        self.memory = Tape::map(path.as_ref(), len)?;
+        self.touched = self.memory.len();
+        if self.cursor >= self.memory.len() {
+            self.cursor = 0;
+        }
+        Ok(self)
+    }
+
+    /// Writes a mapped tape out to its file; other tapes have nothing to do.
+    pub fn sync(&self) -> io::Result<()> {
+        if let Some(other) = &self.other_tape {
+            other.memory.sync()?;
+        }
+        self.memory.sync()
+    }
+
+    pub fn with_handler(mut self, handler: impl InstructionHandler<C> + 'static) -> Self {
+        self.set_handler(handler);
+        self
+    }
+
+    pub fn set_handler(&mut self, handler: impl InstructionHandler<C> + 'static) {
+        self.handler = Some(Box::new(handler));
+    }
+
+    /// Makes `f` callable from the program through `HostCall`, which passes
+    /// the current cell as `selector`. Errors end the run as
+    /// `BfRuntimeError::HostError`.
+    pub fn register_host_fn(
+        &mut self,
+        selector: u8,
+        f: impl FnMut(&mut [C], usize) -> Result<(), String> + 'static,
+    ) {
+        self.host_fns.insert(selector, Box::new(f));
+    }
+
+    /// Seeds the generator behind `MachineContext::random_byte`; unseeded
+    /// machines start from the current time.
+    pub fn with_rng_seed(mut self, seed: u64) -> Self {
+        self.set_rng_seed(seed);
+        self
+    }
+
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = Rng::from_seed(seed);
+    }
+
+    /// Records which instructions `run` and `run_from` execute, see
+    /// [`BfMachine::coverage`]. The other run methods do not record coverage.
+    pub fn with_coverage(mut self) -> Self {
+        self.enable_coverage();
+        self
+    }
+
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::default);
+    }
+
+    /// What ran so far, indexed by pc, or `None` unless coverage is enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Counts how often `run` and `run_from` execute each instruction, see
+    /// [`BfMachine::profile`]. Like coverage, other run methods do not count.
+    pub fn with_profiling(mut self) -> Self {
+        self.enable_profiling();
+        self
+    }
+
+    pub fn enable_profiling(&mut self) {
+        self.profile.get_or_insert_with(Profile::default);
+    }
+
+    /// What ran so far, or `None` unless profiling is enabled.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Estimates where `run` spends its time by timing about one instruction
+    /// in `interval`; see [`Timings`] for how exact that is.
+    pub fn with_timings(mut self, interval: u32) -> Self {
+        self.enable_timings(interval);
+        self
+    }
+
+    pub fn enable_timings(&mut self, interval: u32) {
+        self.timings.get_or_insert_with(|| Timings::new(interval));
+    }
+
+    pub fn timings(&self) -> Option<&Timings> {
+        self.timings.as_ref()
+    }
+
+    /// Copies `data` onto the tape starting at cell `offset`, a byte a cell,
+    /// e.g. to stage input a program expects in memory. Later loads overwrite
+    /// earlier ones. A growing tape grows to fit the data, up to the memory
+    /// limit.
+    pub fn load_tape(&mut self, offset: usize, data: &[u8]) -> Result<(), TapeOverflow> {
+        let end = offset.saturating_add(data.len());
+        if self.tape_policy == TapePolicy::Grow && end <= self.max_memory {
+            self.memory.resize(end.max(self.memory.len()));
+        }
+        if end > self.memory.len() {
+            return Err(TapeOverflow {
+                end,
+                tape_len: self.memory.len(),
+            });
+        }
+        for (cell, &byte) in self.memory[offset..end].iter_mut().zip(data) {
+            *cell = C::from_byte(byte);
+        }
+        self.touched = self.touched.max(end);
+        Ok(())
+    }
+
+    /// Like [`BfMachine::load_tape`], for host data that a program transforms
+    /// in place and [`BfMachine::read_memory`] reads back. Loaded cells are
+    /// part of snapshots like any other, and [`BfMachine::reset`] zeroes them.
+    pub fn load_memory(&mut self, offset: usize, data: &[u8]) -> Result<(), TapeOverflow> {
+        self.load_tape(offset, data)
+    }
+
+    /// Copies out the `len` cells from `offset`. A growing tape reads the
+    /// cells it has not grown to yet as 0, up to the memory limit.
+    pub fn read_memory(&self, offset: usize, len: usize) -> Result<Vec<C>, TapeOverflow> {
+        let end = offset.saturating_add(len);
+        let tape_len = match self.tape_policy {
+            TapePolicy::Grow if self.memory.is_growable() => self.max_memory,
+            _ => self.memory.len(),
+        };
+        if end > tape_len {
+            return Err(TapeOverflow { end, tape_len });
+        }
+
+        let mut cells = vec![C::default(); len];
+        let present = end.min(self.memory.len());
+        if offset < present {
+            cells[..present - offset].copy_from_slice(&self.memory[offset..present]);
+        }
+        Ok(cells)
+    }
+
+    /// Makes the cells in `range` read-only: a program that changes one with
+    /// `+`, `-` or `,` stops with `BfRuntimeError::WriteProtected` before the
+    /// write. Output, loop tests, extensions and `load_tape` are unaffected.
+    pub fn protect_range(&mut self, range: Range<usize>) {
+        self.protection.protect(range);
+    }
+
+    /// Makes the cells in `range` writable again, splitting any protected
+    /// range that covers only part of it.
+    pub fn unprotect_range(&mut self, range: Range<usize>) {
+        self.protection.unprotect(range);
+    }
+
+    /// Stops [`BfMachine::run_until_break`] and
+    /// [`BfMachine::resume_until_break`] before the instruction at `pc`. Other
+    /// runs ignore breakpoints.
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    /// Returns whether there was a breakpoint at `pc`.
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn tape(&self) -> &[C] {
+        &self.memory
+    }
+
+    /// The tape the other commands act on: 0, or 1 after an odd number of
+    /// `BfToken::SwitchTape`. [`BfMachine::cursor`] and [`BfMachine::tape`]
+    /// are this tape's.
+    pub fn active_tape(&self) -> usize {
+        self.active_tape
+    }
+
+    /// The cursor and cells of the tape that is not active, once a program
+    /// switched tapes.
+    pub fn other_tape(&self) -> Option<(usize, &[C])> {
+        let other = self.other_tape.as_ref()?;
+        Some((other.cursor, &other.memory))
+    }
+
+    pub fn snapshot(&self) -> BfSnapshot<C> {
+        BfSnapshot {
+            cursor: self.cursor,
+            memory: self.memory.to_vec(),
+            other_tape: self.other_tape.as_ref().map(|other| TapeSnapshot {
+                tape: 1 - self.active_tape,
+                cursor: other.cursor,
+                memory: other.memory.to_vec(),
+            }),
+        }
+    }
+
+    pub fn restore(&mut self, snapshot: BfSnapshot<C>) {
+        assert!(snapshot.cursor < snapshot.memory.len());
+
+        if snapshot.active_tape() != self.active_tape {
+            self.switch_tape();
+        }
+        self.cursor = snapshot.cursor;
+        self.memory.replace(snapshot.memory);
+        self.touched = self.memory.len();
+        match snapshot.other_tape {
+            Some(tape) => {
+                assert!(tape.cursor < tape.memory.len());
+                self.other_tape = Some(OtherTape {
+                    touched: tape.memory.len(),
+                    memory: Tape::Heap(tape.memory),
+                    cursor: tape.cursor,
+                });
+            }
+            None => self.other_tape = None,
+        }
+    }
+
+    /// Makes the other tape the active one, for `BfToken::SwitchTape`. Tape 1
+    /// starts out blank and as long as tape 0 is when it is first switched to.
+    pub(crate) fn switch_tape(&mut self) {
+        let len = self.memory.len();
+        let other = self.other_tape.get_or_insert_with(|| OtherTape {
+            memory: Tape::Heap(vec![C::default(); len]),
+            cursor: 0,
+            touched: 1,
+        });
+        mem::swap(&mut self.memory, &mut other.memory);
+        mem::swap(&mut self.cursor, &mut other.cursor);
+        mem::swap(&mut self.touched, &mut other.touched);
+        self.active_tape = 1 - self.active_tape;
+    }
+
+    /// Trades the running thread's tapes and cursors for `thread`'s.
+    pub(crate) fn swap_thread(&mut self, thread: &mut ThreadState<C>) {
+        mem::swap(&mut self.memory, &mut thread.tape.memory);
+        mem::swap(&mut self.cursor, &mut thread.tape.cursor);
+        mem::swap(&mut self.touched, &mut thread.tape.touched);
+        mem::swap(&mut self.other_tape, &mut thread.other_tape);
+        mem::swap(&mut self.active_tape, &mut thread.active_tape);
+    }
+
+    /// Forks the running thread at the `BfToken::Fork` at `pc` and returns the
+    /// child, as [`run_threads`] describes. `held` is the cells other threads
+    /// hold, which count toward the memory limit with both copies of these
+    /// tapes.
+    ///
+    /// [`run_threads`]: super::fork::run_threads
+    pub(crate) fn fork(
+        &mut self,
+        pc: usize,
+        held: usize,
+    ) -> Result<ThreadState<C>, BfRuntimeError> {
+        let cells = self.memory.len()
+            + self
+                .other_tape
+                .as_ref()
+                .map_or(0, |other| other.memory.len());
+        let requested = held.saturating_add(cells.saturating_mul(2));
+        if requested > self.max_memory {
+            return Err(BfRuntimeError::MemoryLimitExceeded {
+                pc,
+                requested,
+                limit: self.max_memory,
+            });
+        }
+        self.protection.check(self.cursor, pc)?;
+
+        let mut child = ThreadState {
+            tape: OtherTape {
+                memory: self.memory.clone(),
+                cursor: self.cursor,
+                touched: self.touched,
+            },
+            other_tape: self.other_tape.clone(),
+            active_tape: self.active_tape,
+        };
+        self.swap_thread(&mut child);
+        let moved = self.move_cursor(false, 1, pc).and_then(|cursor| {
+            self.protection.check(cursor, pc)?;
+            Ok(cursor)
+        });
+        self.swap_thread(&mut child);
+        child.tape.cursor = moved?;
+        child.tape.memory[child.tape.cursor] = C::default().add(1);
+        child.tape.touched = child.tape.touched.max(child.tape.cursor + 1);
+        self.memory[self.cursor] = C::default();
+        Ok(child)
+    }
+
+    pub fn run(&mut self, program: &Program) -> Result<(), BfRuntimeError> {
+        self.run_from(program, 0)
+    }
+
+    /// Continues `program` at `pc`, e.g. the one reported by
+    /// `BfRuntimeError::StepLimitExceeded`, which has not been executed yet.
+    pub fn run_from(&mut self, program: &Program, pc: usize) -> Result<(), BfRuntimeError> {
+        self.execute(program, pc, None, None).map(|_| ())
+    }
+
+    /// Like [`BfMachine::run`], stopping at the first breakpoint reached,
+    /// including one on the first instruction.
+    pub fn run_until_break(&mut self, program: &Program) -> Result<RunOutcome, BfRuntimeError> {
+        if self.breakpoints.contains(&0) && !program.tokens().is_empty() {
+            return Ok(RunOutcome::Breakpoint { pc: 0 });
+        }
+        self.resume_until_break(program, 0)
+    }
+
+    /// Continues at `pc` until the next breakpoint. A breakpoint at `pc`
+    /// itself is where the last run stopped, so it does not stop this one
+    /// before it has moved on.
+    pub fn resume_until_break(
+        &mut self,
+        program: &Program,
+        pc: usize,
+    ) -> Result<RunOutcome, BfRuntimeError> {
+        let breakpoints = mem::take(&mut self.breakpoints);
+        let result = self.execute(program, pc, None, Some(&breakpoints));
+        self.breakpoints = breakpoints;
+        result
+    }
+
+    /// The interpreter behind `run_from`, calling `trace` after each step and
+    /// stopping before any of `breakpoints` but the first instruction. A run
+    /// given `breakpoints` also stops at a `,` whose input would block, if
+    /// the machine allows it.
+    ///
+    /// Errors come wrapped in the loops that were running. Which loops those
+    /// are follows from the jump table once the error is known, so the loop
+    /// only keeps what it cannot: the pass each loop is on, updated when a
+    /// loop is entered or jumps back and never on other instructions. A step
+    /// limit is where a run is paused and resumed, so it stays bare.
+    pub(crate) fn execute(
+        &mut self,
+        program: &Program,
+        pc: usize,
+        trace: Option<&mut dyn FnMut(TraceRecord<C>) -> io::Result<()>>,
+        breakpoints: Option<&BTreeSet<usize>>,
+    ) -> Result<RunOutcome, BfRuntimeError> {
+        if self.loop_iterations.len() < program.tokens().len() {
+            self.loop_iterations.resize(program.tokens().len(), 0);
+        }
+        self.interpret(program, pc, trace, breakpoints)
+            .map_err(|err| match err {
+                BfRuntimeError::StepLimitExceeded { .. } => err,
+                err => self.in_loops(program, err),
+            })
+    }
+
+    /// `err` with the loops around its pc, if there are any.
+    fn in_loops(&self, program: &Program, err: BfRuntimeError) -> BfRuntimeError {
+        let loops: Vec<_> = program
+            .enclosing_loops(err.pc())
+            .map(|pc| LoopFrame {
+                pc,
+                span: program.span(pc),
+                iteration: self.loop_iterations[pc],
+            })
+            .collect();
+        if loops.is_empty() {
+            return err;
+        }
+        BfRuntimeError::InLoops {
+            error: Box::new(err),
+            loops,
+        }
+    }
+
+    /// Moves the cursor back to `cursor` and puts the old value back in
+    /// `cell`, if any, to undo one instruction.
+    pub(crate) fn rewind(&mut self, cursor: usize, cell: Option<(usize, C)>) {
+        if let Some((index, value)) = cell {
+            self.memory[index] = value;
+        }
+        self.cursor = cursor;
+    }
+
+    /// Puts `byte` in the current cell as if the `,` at `pc` had read it.
+    pub(crate) fn store_input(
+        &mut self,
+        program: &Program,
+        pc: usize,
+        byte: u8,
+    ) -> Result<(), BfRuntimeError> {
+        if let Err(err) = self.protection.check(self.cursor, pc) {
+            return Err(self.in_loops(program, err));
+        }
+        self.memory[self.cursor] = C::from_byte(byte);
+        self.bytes_read += 1;
+        Ok(())
+    }
+
+    fn interpret(
+        &mut self,
+        program: &Program,
+        mut pc: usize,
+        mut trace: Option<&mut dyn FnMut(TraceRecord<C>) -> io::Result<()>>,
+        breakpoints: Option<&BTreeSet<usize>>,
+    ) -> Result<RunOutcome, BfRuntimeError> {
+        let commands = program.tokens();
+        let jump_table = program.jump_table();
+        let mut steps = 0;
+        self.log_run("run", commands.len(), pc);
+        if let Some(coverage) = &mut self.coverage {
+            coverage.grow(commands.len());
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.grow(commands.len());
+        }
+
+        while pc < commands.len() {
+            if steps > 0 && breakpoints.is_some_and(|breakpoints| breakpoints.contains(&pc)) {
+                return Ok(RunOutcome::Breakpoint { pc });
+            }
+            if steps == self.max_steps {
+                return Err(BfRuntimeError::StepLimitExceeded { pc });
+            }
+            steps += 1;
+            if let Some(coverage) = &mut self.coverage {
+                coverage.mark(pc);
+            }
+            if let Some(profile) = &mut self.profile {
+                profile.record(pc);
+            }
+            #[cfg(feature = "trace-execution")]
+            log::trace!(
+                "pc {pc}: {:?} at cell {} = {}",
+                commands[pc],
+                self.cursor,
+                self.memory[self.cursor]
+            );
+            let (current, token, bytes_read) = (pc, commands[pc], self.bytes_read);
+            let sample = self
+                .timings
+                .as_mut()
+                .is_some_and(Timings::due)
+                .then(Instant::now);
+
+            match token {
+                BfToken::NotCommand(_) => {}
+                BfToken::Increment(val) => {
+                    self.protection.check(self.cursor, pc)?;
+                    self.memory[self.cursor] = self.memory[self.cursor].add(val);
+                }
+                BfToken::Decrement(val) => {
+                    self.protection.check(self.cursor, pc)?;
+                    self.memory[self.cursor] = self.memory[self.cursor].sub(val);
+                }
+                BfToken::IncrementAt(offset, val) => {
+                    let at = self.move_cursor(offset < 0, offset.unsigned_abs(), pc)?;
+                    self.protection.check(at, pc)?;
+                    self.memory[at] = self.memory[at].add(val);
+                }
+                BfToken::DecrementAt(offset, val) => {
+                    let at = self.move_cursor(offset < 0, offset.unsigned_abs(), pc)?;
+                    self.protection.check(at, pc)?;
+                    self.memory[at] = self.memory[at].sub(val);
+                }
+                BfToken::CursorLeft(val) => self.cursor = self.move_cursor(true, val, pc)?,
+                BfToken::CursorRight(val) => self.cursor = self.move_cursor(false, val, pc)?,
+                BfToken::LoopStart => {
+                    if self.memory[self.cursor] == C::default() {
+                        if let Some(profile) = &mut self.profile {
+                            profile.record_jump(pc);
+                        }
+                        pc = jump_table[pc];
+                    } else {
+                        self.loop_iterations[pc] = 1;
+                    }
+                }
+                BfToken::LoopEnd => {
+                    if self.memory[self.cursor] != C::default() {
+                        if let Some(profile) = &mut self.profile {
+                            profile.record_jump(pc);
+                        }
+                        pc = jump_table[pc];
+                        self.loop_iterations[pc] += 1;
+                    }
+                }
+                BfToken::PrintChar => {
+                    self.write_output(self.memory[self.cursor].to_byte(), 1, pc)?
+                }
+                BfToken::PrintCharN(count) => {
+                    self.write_output(self.memory[self.cursor].to_byte(), count, pc)?;
+                }
+                BfToken::InputChar => {
+                    self.protection.check(self.cursor, pc)?;
+                    let cell = &mut self.memory[self.cursor];
+                    match read_byte(
+                        &mut self.input,
+                        self.eof_mode,
+                        &mut self.bytes_read,
+                        *cell,
+                        pc,
+                    ) {
+                        Ok(byte) => *cell = byte,
+                        Err(BfRuntimeError::Io { source, .. })
+                            if source.kind() == ErrorKind::WouldBlock
+                                && self.nonblocking_input
+                                && breakpoints.is_some() =>
+                        {
+                            return Ok(RunOutcome::NeedsInput { pc });
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+                BfToken::SwitchTape => self.switch_tape(),
+                BfToken::Fork => return Err(BfRuntimeError::UnhandledFork { pc }),
+                token @ BfToken::Extension(_) => {
+                    let mut ctx = MachineContext::new(
+                        pc,
+                        &mut self.cursor,
+                        &mut self.memory,
+                        &mut self.input,
+                        &mut self.output,
+                        &mut self.rng,
+                        &mut self.host_fns,
+                    );
+                    let flow = extension(self.handler.as_deref_mut(), token, &mut ctx);
+                    // Handlers and host functions may write anywhere.
+                    self.touched = self.memory.len();
+                    if flow? == ControlFlow::Halt {
+                        return Ok(RunOutcome::Completed);
+                    }
+                }
+            }
+
+            if let (Some(start), Some(timings)) = (sample, &mut self.timings) {
+                timings.record(&token, start.elapsed());
+            }
+            if let Some(trace) = trace.as_mut() {
+                let cell = self.memory[self.cursor];
+                let record = TraceRecord {
+                    step: steps,
+                    pc: current,
+                    token,
+                    cursor: self.cursor,
+                    cell,
+                    output: matches!(token, BfToken::PrintChar | BfToken::PrintCharN(_))
+                        .then_some(cell.to_byte()),
+                    input: (self.bytes_read > bytes_read).then_some(cell.to_byte()),
+                };
+                trace(record).map_err(|source| BfRuntimeError::Io {
+                    pc: current,
+                    source,
+                })?;
+            }
+            pc += 1;
+        }
+
+        Ok(RunOutcome::Completed)
+    }
+
+    /// Runs a validated program without bounds checks in the dispatch loop.
+    ///
+    /// This is sound because a `Program` always gets its jump table from
+    /// `BfParser::loop_matching`, which makes it as long as the tokens with
+    /// every entry in range, and because every cursor move, and every cell an
+    /// offset token addresses, either stays inside `0..len` by the guard on its
+    /// arm or goes through `move_cursor`, which wraps it modulo `len` or grows
+    /// the tape to hold it. A grown or switched
+    /// tape is borrowed again right away, so `memory` and `len` always
+    /// describe it.
+    pub fn run_fast(&mut self, program: &Program) -> Result<(), BfRuntimeError> {
+        if self.protection.ranges.is_empty() {
+            self.run_fast_with::<false>(program)
+        } else {
+            self.run_fast_with::<true>(program)
+        }
+    }
+
+    /// The loop of `run_fast`, compiled once without the write protection
+    /// checks so that machines without protected cells do not pay for them.
+    fn run_fast_with<const PROTECTED: bool>(
+        &mut self,
+        program: &Program,
+    ) -> Result<(), BfRuntimeError> {
+        let tokens = program.tokens();
+        let jump_table = program.jump_table();
+        self.log_run("run_fast", tokens.len(), 0);
+        let mut memory = &mut self.memory[..];
+        let mut len = memory.len();
+        let mut cursor = self.cursor;
+        let mut touched = self.touched;
+        let mut pc = 0;
+        let mut steps = 0;
+
+        let result = loop {
+            if pc >= tokens.len() {
+                break Ok(());
+            }
+            if steps == self.max_steps {
+                break Err(BfRuntimeError::StepLimitExceeded { pc });
+            }
+            steps += 1;
+
+            // SAFETY: `pc < tokens.len()` was just checked.
+            let token = unsafe { *tokens.get_unchecked(pc) };
+            // SAFETY: `cursor < len` always holds, see above.
+            let cell = unsafe { memory.get_unchecked_mut(cursor) };
+
+            match token {
+                BfToken::NotCommand(_) => {}
+                BfToken::Increment(_) | BfToken::Decrement(_) | BfToken::InputChar
+                    if PROTECTED && self.protection.guards(cursor) =>
+                {
+                    break Err(BfRuntimeError::WriteProtected { pc, cell: cursor });
+                }
+                BfToken::Increment(val) => *cell = cell.add(val),
+                BfToken::Decrement(val) => *cell = cell.sub(val),
+                BfToken::IncrementAt(offset, val) | BfToken::DecrementAt(offset, val) => {
+                    let at = match cursor.checked_add_signed(offset) {
+                        Some(at) if at < len => at,
+                        _ => match move_cursor(
+                            &mut self.memory,
+                            self.tape_policy,
+                            self.max_memory,
+                            cursor,
+                            offset < 0,
+                            offset.unsigned_abs(),
+                            pc,
+                        ) {
+                            Ok(at) => {
+                                memory = &mut self.memory[..];
+                                len = memory.len();
+                                at
+                            }
+                            Err(err) => break Err(err),
+                        },
+                    };
+                    if PROTECTED && self.protection.guards(at) {
+                        break Err(BfRuntimeError::WriteProtected { pc, cell: at });
+                    }
+                    touched = touched.max(at + 1);
+                    // SAFETY: `at < len`, by the guard or as `move_cursor`
+                    // keeps it.
+                    let cell = unsafe { memory.get_unchecked_mut(at) };
+                    *cell = match token {
+                        BfToken::IncrementAt(..) => cell.add(val),
+                        _ => cell.sub(val),
+                    };
+                }
+                BfToken::CursorLeft(val) if val <= cursor => cursor -= val,
+                BfToken::CursorRight(val) if val < len - cursor => {
+                    cursor += val;
+                    touched = touched.max(cursor + 1);
+                }
+                BfToken::CursorLeft(val) | BfToken::CursorRight(val) => {
+                    let left = matches!(token, BfToken::CursorLeft(_));
+                    cursor = match move_cursor(
+                        &mut self.memory,
+                        self.tape_policy,
+                        self.max_memory,
+                        cursor,
+                        left,
+                        val,
+                        pc,
+                    ) {
+                        Ok(cursor) => cursor,
+                        Err(err) => break Err(err),
+                    };
+                    touched = touched.max(cursor + 1);
+                    memory = &mut self.memory[..];
+                    len = memory.len();
+                }
+                BfToken::LoopStart => {
+                    if *cell == C::default() {
+                        // SAFETY: the jump table is as long as the tokens and
+                        // every entry is in range by the `Program` invariant.
+                        pc = unsafe { *jump_table.get_unchecked(pc) };
+                    }
+                }
+                BfToken::LoopEnd => {
+                    if *cell != C::default() {
+                        // SAFETY: the jump table is as long as the tokens and
+                        // every entry is in range by the `Program` invariant.
+                        pc = unsafe { *jump_table.get_unchecked(pc) };
+                    }
+                }
+                BfToken::PrintChar => {
+                    if let Err(err) = write_limited(
+                        &mut self.output,
+                        cell.to_byte(),
+                        1,
+                        pc,
+                        &mut self.bytes_written,
+                        self.max_output,
+                    ) {
+                        break Err(err);
+                    }
+                }
+                BfToken::PrintCharN(count) => {
+                    if let Err(err) = write_limited(
+                        &mut self.output,
+                        cell.to_byte(),
+                        count,
+                        pc,
+                        &mut self.bytes_written,
+                        self.max_output,
+                    ) {
+                        break Err(err);
+                    }
+                }
+                BfToken::InputChar => match read_byte(
+                    &mut self.input,
+                    self.eof_mode,
+                    &mut self.bytes_read,
+                    *cell,
+                    pc,
+                ) {
+                    Ok(byte) => *cell = byte,
+                    Err(err) => break Err(err),
+                },
+                BfToken::SwitchTape => {
+                    self.cursor = cursor;
+                    self.touched = touched;
+                    self.switch_tape();
+                    cursor = self.cursor;
+                    touched = self.touched;
+                    memory = &mut self.memory[..];
+                    len = memory.len();
+                }
+                BfToken::Fork => break Err(BfRuntimeError::UnhandledFork { pc }),
+                token @ BfToken::Extension(_) => {
+                    // The context only hands out the cursor through
+                    // `set_cursor`, which keeps it below `len`.
+                    let mut ctx = MachineContext::new(
+                        pc,
+                        &mut cursor,
+                        memory,
+                        &mut self.input,
+                        &mut self.output,
+                        &mut self.rng,
+                        &mut self.host_fns,
+                    );
+                    let flow = extension(self.handler.as_deref_mut(), token, &mut ctx);
+                    touched = len;
+                    match flow {
+                        Ok(ControlFlow::Continue) => {}
+                        Ok(ControlFlow::Halt) => break Ok(()),
+                        Err(err) => break Err(err),
+                    }
+                }
+            }
+
+            pc += 1;
+        };
+
+        self.cursor = cursor;
+        self.touched = touched;
+        result
+    }
+
+    /// Compares the tapes and cursors only, whatever I/O either machine uses.
+    pub fn state_eq<R2: Read, W2: Write>(&self, other: &BfMachine<R2, W2, C>) -> bool {
+        self.cursor == other.cursor
+            && self.memory == other.memory
+            && self.active_tape == other.active_tape
+            && self.other_tape() == other.other_tape()
+    }
+
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+
+    pub fn bytes_written(&self) -> usize {
+        self.bytes_written
+    }
+
+    /// The same machine with its I/O handles replaced by `input(self.input)`
+    /// and `output(self.output)`.
+    fn map_io<R2: Read, W2: Write>(
+        self,
+        input: impl FnOnce(R) -> R2,
+        output: impl FnOnce(W) -> W2,
+    ) -> BfMachine<R2, W2, C> {
+        BfMachine {
+            cursor: self.cursor,
+            memory: self.memory,
+            touched: self.touched,
+            max_steps: self.max_steps,
+            max_output: self.max_output,
+            eof_mode: self.eof_mode,
+            nonblocking_input: self.nonblocking_input,
+            tape_policy: self.tape_policy,
+            max_memory: self.max_memory,
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
+            input: input(self.input),
+            output: output(self.output),
+            handler: self.handler,
+            rng: self.rng,
+            host_fns: self.host_fns,
+            coverage: self.coverage,
+            profile: self.profile,
+            timings: self.timings,
+            protection: self.protection,
+            breakpoints: self.breakpoints,
+            loop_iterations: self.loop_iterations,
+            other_tape: self.other_tape,
+            active_tape: self.active_tape,
+        }
+    }
+
+    /// Zeroes the tape, moves the cursor back to cell 0 and clears the I/O
+    /// counts, keeping the settings and what coverage, profiling and timings
+    /// recorded. Only the cells up to the furthest one written are zeroed, so
+    /// a reset costs what the last run touched rather than the whole tape.
+    /// A multi-tape machine drops tape 1 and is back on tape 0.
+    pub fn reset(&mut self) {
+        if self.active_tape == 1 {
+            self.switch_tape();
+        }
+        self.other_tape = None;
+        let touched = self.touched.min(self.memory.len());
+        self.memory[..touched].fill(C::default());
+        self.touched = 1;
+        self.cursor = 0;
+        self.bytes_read = 0;
+        self.bytes_written = 0;
+    }
+
+    /// Resets the machine and hands its tape and settings to a machine with
+    /// new I/O handles, without allocating another tape.
+    pub fn recycle<R2: Read, W2: Write>(mut self, input: R2, output: W2) -> BfMachine<R2, W2, C> {
+        self.reset();
+        self.map_io(|_| input, |_| output)
+    }
+
+    /// Takes the machine apart into its tape, cursor, input and output. A
+    /// multi-tape machine gives tape 0.
+    pub fn into_parts(mut self) -> (Vec<C>, usize, R, W) {
+        if self.active_tape == 1 {
+            self.switch_tape();
+        }
+        (self.memory.into_vec(), self.cursor, self.input, self.output)
+    }
+
+    fn write_output(&mut self, byte: u8, count: usize, pc: usize) -> Result<(), BfRuntimeError> {
+        write_limited(
+            &mut self.output,
+            byte,
+            count,
+            pc,
+            &mut self.bytes_written,
+            self.max_output,
+        )
+    }
+
+    fn log_run(&self, mode: &str, len: usize, pc: usize) {
+        debug!(
+            "{mode}: {len} instructions from pc {pc} on {} cells, step limit {}, eof mode {:?}",
+            self.memory.len(),
+            match self.max_steps {
+                usize::MAX => "none".to_string(),
+                max_steps => max_steps.to_string(),
+            },
+            self.eof_mode
+        );
+    }
+
+    fn move_cursor(
+        &mut self,
+        left: bool,
+        offset: usize,
+        pc: usize,
+    ) -> Result<usize, BfRuntimeError> {
+        let cursor = move_cursor(
+            &mut self.memory,
+            self.tape_policy,
+            self.max_memory,
+            self.cursor,
+            left,
+            offset,
+            pc,
+        )?;
+        self.touched = self.touched.max(cursor + 1);
+        Ok(cursor)
+    }
+}
+
+impl<R, C> BfMachine<R, Vec<u8>, C>
+where
+    R: Read,
+    C: Cell,
+{
+    /// Runs `program` and returns exactly the bytes it wrote, decoded as lossy UTF-8.
+    pub fn run_capture(&mut self, program: &Program) -> Result<String, BfRuntimeError> {
+        let start = self.output.len();
+        self.run(program)?;
+        Ok(String::from_utf8_lossy(&self.output[start..]).into_owned())
+    }
+
+    /// Returns everything written so far and starts over with an empty buffer.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        mem::take(&mut self.output)
+    }
+}
+
+/// Where `cursor` lands after moving `offset` cells, wrapping around the tape
+/// or growing it as `tape_policy` says. A grown tape at least doubles, capped
+/// at `max_memory` cells; a mapped tape cannot grow past its file.
+fn move_cursor<C: Cell>(
+    memory: &mut Tape<C>,
+    tape_policy: TapePolicy,
+    max_memory: usize,
+    cursor: usize,
+    left: bool,
+    offset: usize,
+    pc: usize,
+) -> Result<usize, BfRuntimeError> {
+    let len = memory.len();
+    match (tape_policy, left) {
+        (TapePolicy::Wrap, true) if offset > cursor => Ok((len - (offset % len) + cursor) % len),
+        (TapePolicy::Wrap, true) => Ok(cursor - offset),
+        (TapePolicy::Wrap, false) => Ok((cursor + offset % len) % len),
+        (TapePolicy::Grow, true) => cursor
+            .checked_sub(offset)
+            .ok_or(BfRuntimeError::CursorUnderflow { pc }),
+        (TapePolicy::Grow, false) => {
+            let target = cursor.saturating_add(offset);
+            if target >= len {
+                let requested = target.saturating_add(1);
+                let limit = if memory.is_growable() {
+                    max_memory
+                } else {
+                    len
+                };
+                if requested > limit {
+                    return Err(BfRuntimeError::MemoryLimitExceeded {
+                        pc,
+                        requested,
+                        limit,
+                    });
+                }
+                memory.resize(requested.max(len.saturating_mul(2)).min(max_memory));
+            }
+            Ok(target)
+        }
+    }
 }
 
-pub struct BfState {
-    commands: Vec<BfToken>,
-    program_counter: usize,
+/// Writes `count` copies of `byte`, or as many as `max_output` still allows,
+/// flushing before it reports the limit.
+pub(crate) fn write_limited(
+    output: &mut impl Write,
+    byte: u8,
+    count: usize,
+    pc: usize,
+    bytes_written: &mut usize,
+    max_output: usize,
+) -> Result<(), BfRuntimeError> {
+    let allowed = count.min(max_output.saturating_sub(*bytes_written));
+    write_repeated(output, byte, allowed, pc)?;
+    *bytes_written += allowed;
+    if allowed < count {
+        output
+            .flush()
+            .map_err(|source| BfRuntimeError::Io { pc, source })?;
+        return Err(BfRuntimeError::OutputLimitExceeded {
+            pc,
+            bytes: *bytes_written,
+        });
+    }
+    Ok(())
 }
 
-impl<R, W> BfMachine<R, W>
-where
-    R: Read,
-    W: Write,
-{
-    pub fn new(memory_size: usize, input: R, output: W) -> Self {
-        assert!(memory_size > 0);
+impl Protection {
+    fn protect(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.ranges.push(range);
+        self.ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(self.ranges.len());
+        for range in self.ranges.drain(..) {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        self.ranges = merged;
+        self.update_bounds();
+    }
 
-        let memory = vec![0; memory_size];
-        Self {
-            cursor: 0,
-            memory,
-            input,
-            output,
+    fn unprotect(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
         }
+        self.ranges = self
+            .ranges
+            .drain(..)
+            .flat_map(|protected| {
+                [
+                    protected.start..protected.end.min(range.start),
+                    protected.start.max(range.end)..protected.end,
+                ]
+            })
+            .filter(|part| !part.is_empty())
+            .collect();
+        self.update_bounds();
     }
 
-    pub fn run(&mut self, commands: &[BfToken]) -> Result<(), Box<dyn Error>> {
-        let mut state = BfState {
-            commands: commands.to_vec(),
-            program_counter: 0,
+    fn update_bounds(&mut self) {
+        self.bounds = match (self.ranges.first(), self.ranges.last()) {
+            (Some(first), Some(last)) => first.start..last.end,
+            _ => 0..0,
         };
+    }
 
-        while state.program_counter < state.commands.len() {
-            match state.commands[state.program_counter] {
-                BfToken::NotCommand(_) => {}
-                BfToken::Increment(val) => {
-                    self.memory[self.cursor] = self.memory[self.cursor].wrapping_add(val);
-                }
-                BfToken::Decrement(val) => {
-                    self.memory[self.cursor] = self.memory[self.cursor].wrapping_sub(val);
-                }
-                BfToken::CursorLeft(val) => {
-                    self.cursor = Self::wrapped_cursor(self.cursor, true, val, self.memory.len());
-                }
-                BfToken::CursorRight(val) => {
-                    self.cursor = Self::wrapped_cursor(self.cursor, false, val, self.memory.len());
-                }
-                BfToken::LoopStart(to_end) => {
-                    if self.memory[self.cursor] == 0 {
-                        state.program_counter = to_end;
-                    }
-                }
-                BfToken::LoopEnd(to_start) => {
-                    if self.memory[self.cursor] != 0 {
-                        state.program_counter = to_start;
-                    }
-                }
-                BfToken::PrintChar => {
-                    self.output.write(&vec![self.memory[self.cursor]])?;
-                }
-                BfToken::InputChar => {
-                    let mut input = [0; 1];
-                    self.input.read_exact(&mut input)?;
-                    self.memory[self.cursor] = input[0];
-                }
-            }
+    #[inline]
+    fn guards(&self, cell: usize) -> bool {
+        self.bounds.contains(&cell) && self.ranges.iter().any(|range| range.contains(&cell))
+    }
 
-            state.program_counter += 1;
+    #[inline]
+    fn check(&self, cell: usize, pc: usize) -> Result<(), BfRuntimeError> {
+        if self.guards(cell) {
+            return Err(BfRuntimeError::WriteProtected { pc, cell });
         }
-
         Ok(())
     }
+}
 
-    fn wrapped_cursor(cursor: usize, sign: bool, offset: usize, bound: usize) -> usize {
-        if sign {
-            if offset > cursor {
-                (bound - (offset % bound) + cursor) % bound
-            } else {
-                (cursor - offset) % bound
-            }
-        } else {
-            (cursor + offset) % bound
+fn write_repeated(
+    output: &mut impl Write,
+    byte: u8,
+    count: usize,
+    pc: usize,
+) -> Result<(), BfRuntimeError> {
+    const CHUNK: usize = 64;
+
+    let buffer = [byte; CHUNK];
+    let mut remaining = count;
+    while remaining > 0 {
+        let len = remaining.min(CHUNK);
+        output
+            .write_all(&buffer[..len])
+            .map_err(|source| BfRuntimeError::Io { pc, source })?;
+        remaining -= len;
+    }
+    Ok(())
+}
+
+fn read_byte<C: Cell>(
+    input: &mut impl Read,
+    eof_mode: EofMode,
+    bytes_read: &mut usize,
+    current: C,
+    pc: usize,
+) -> Result<C, BfRuntimeError> {
+    let mut byte = [0; 1];
+    match input.read_exact(&mut byte) {
+        Ok(()) => {
+            *bytes_read += 1;
+            Ok(C::from_byte(byte[0]))
+        }
+        Err(source) if source.kind() == ErrorKind::UnexpectedEof => match eof_mode {
+            EofMode::Error => Err(BfRuntimeError::UnexpectedEof { pc }),
+            EofMode::Zero => Ok(C::default()),
+            EofMode::Unchanged => Ok(current),
+            EofMode::MinusOne => Ok(C::MINUS_ONE),
+        },
+        Err(source) => Err(BfRuntimeError::Io { pc, source }),
+    }
+}
+
+fn extension<C: Cell>(
+    handler: Option<&mut (dyn InstructionHandler<C> + 'static)>,
+    token: BfToken,
+    ctx: &mut MachineContext<C>,
+) -> Result<ControlFlow, BfRuntimeError> {
+    match (handler, token) {
+        (Some(handler), _) => handler.handle(&token, ctx),
+        (None, BfToken::Extension(id)) => {
+            Err(BfRuntimeError::UnhandledExtension { pc: ctx.pc(), id })
         }
+        (None, _) => unreachable!("only extensions are dispatched to handlers"),
     }
 }
 
-impl<R, W> Debug for BfMachine<R, W>
+impl<R, W, C> Debug for BfMachine<R, W, C>
 where
     R: Read + Debug,
     W: Write + Debug,
+    C: Cell,
 {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("BfMachine")
@@ -114,6 +1620,270 @@ where
     }
 }
 
+/// A one-line summary of the cursor, the cells around it and the I/O so far,
+/// e.g. `cursor 2, cells 0..6: 0 1 [72] 0 0 0, 0 bytes read, 3 written`.
+impl<R, W, C> Display for BfMachine<R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.other_tape() {
+            None => write_cells(f, &self.memory, self.cursor)?,
+            Some((other_cursor, other)) => {
+                let mut tapes = [(&self.memory[..], self.cursor), (other, other_cursor)];
+                if self.active_tape == 1 {
+                    tapes.swap(0, 1);
+                }
+                for (tape, (memory, cursor)) in tapes.into_iter().enumerate() {
+                    let active = if tape == self.active_tape {
+                        " (active)"
+                    } else {
+                        ""
+                    };
+                    write!(
+                        f,
+                        "{}tape {tape}{active}: ",
+                        if tape > 0 { "; " } else { "" }
+                    )?;
+                    write_cells(f, memory, cursor)?;
+                }
+            }
+        }
+        write!(
+            f,
+            ", {} bytes read, {} written",
+            self.bytes_read, self.bytes_written
+        )
+    }
+}
+
+/// Writes the cells around `cursor` with the cursor's in brackets.
+fn write_cells(
+    f: &mut std::fmt::Formatter<'_>,
+    memory: &[impl Display],
+    cursor: usize,
+) -> std::fmt::Result {
+    const CONTEXT: usize = 3;
+
+    let start = cursor.saturating_sub(CONTEXT);
+    let end = cursor.saturating_add(CONTEXT + 1).min(memory.len());
+    write!(f, "cursor {cursor}, cells {start}..{end}:")?;
+    for (cell, value) in memory.iter().enumerate().take(end).skip(start) {
+        if cell == cursor {
+            write!(f, " [{value}]")?;
+        } else {
+            write!(f, " {value}")?;
+        }
+    }
+    Ok(())
+}
+
+/// Clones the tape, settings and I/O handles. The program counter is not part
+/// of the machine, so a clone taken mid-run resumes only through
+/// [`BfMachine::run_from`] with the pc the original stopped at. The
+/// instruction handler and host functions are not cloned either.
+impl<R, W, C> Clone for BfMachine<R, W, C>
+where
+    R: Read + Clone,
+    W: Write + Clone,
+    C: Cell,
+{
+    fn clone(&self) -> Self {
+        Self {
+            cursor: self.cursor,
+            memory: self.memory.clone(),
+            touched: self.touched,
+            max_steps: self.max_steps,
+            max_output: self.max_output,
+            eof_mode: self.eof_mode,
+            nonblocking_input: self.nonblocking_input,
+            tape_policy: self.tape_policy,
+            max_memory: self.max_memory,
+            bytes_read: self.bytes_read,
+            bytes_written: self.bytes_written,
+            input: self.input.clone(),
+            output: self.output.clone(),
+            handler: None,
+            rng: self.rng,
+            host_fns: HashMap::new(),
+            coverage: self.coverage.clone(),
+            profile: self.profile.clone(),
+            timings: self.timings.clone(),
+            protection: self.protection.clone(),
+            breakpoints: self.breakpoints.clone(),
+            loop_iterations: self.loop_iterations.clone(),
+            other_tape: self.other_tape.clone(),
+            active_tape: self.active_tape,
+        }
+    }
+}
+
+/// Machines are equal when their tapes and cursors are; I/O is not compared.
+impl<R, W, C> PartialEq for BfMachine<R, W, C>
+where
+    R: Read,
+    W: Write,
+    C: Cell,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.state_eq(other)
+    }
+}
+
+impl BfRuntimeError {
+    pub fn pc(&self) -> usize {
+        match self {
+            Self::UnexpectedEof { pc }
+            | Self::Io { pc, .. }
+            | Self::StepLimitExceeded { pc }
+            | Self::OutputLimitExceeded { pc, .. }
+            | Self::MemoryLimitExceeded { pc, .. }
+            | Self::CursorUnderflow { pc }
+            | Self::UnhandledExtension { pc, .. }
+            | Self::HostError { pc, .. }
+            | Self::WriteProtected { pc, .. }
+            | Self::UnhandledFork { pc } => *pc,
+            Self::InLoops { error, .. } => error.pc(),
+        }
+    }
+
+    pub fn map_pc(mut self, f: impl Fn(usize) -> usize) -> Self {
+        self.map_pc_in_place(&f);
+        self
+    }
+
+    fn map_pc_in_place(&mut self, f: &impl Fn(usize) -> usize) {
+        let pc = match self {
+            Self::UnexpectedEof { pc }
+            | Self::Io { pc, .. }
+            | Self::StepLimitExceeded { pc }
+            | Self::OutputLimitExceeded { pc, .. }
+            | Self::MemoryLimitExceeded { pc, .. }
+            | Self::CursorUnderflow { pc }
+            | Self::UnhandledExtension { pc, .. }
+            | Self::HostError { pc, .. }
+            | Self::WriteProtected { pc, .. }
+            | Self::UnhandledFork { pc } => pc,
+            Self::InLoops { error, loops } => {
+                for frame in loops {
+                    frame.pc = f(frame.pc);
+                }
+                return error.map_pc_in_place(f);
+            }
+        };
+        *pc = f(*pc);
+    }
+
+    /// The error itself, without the loops it happened in.
+    pub fn root_cause(&self) -> &Self {
+        match self {
+            Self::InLoops { error, .. } => error.root_cause(),
+            err => err,
+        }
+    }
+}
+
+impl Display for BfRuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let message = match self {
+            Self::UnexpectedEof { pc } => {
+                format!("The error occurred at instruction {pc} due to the end of input.")
+            }
+            Self::Io { pc, source } => {
+                format!("The error occurred at instruction {pc} due to an I/O error: {source}")
+            }
+            Self::StepLimitExceeded { pc } => {
+                format!("The error occurred at instruction {pc} due to the step limit.")
+            }
+            Self::OutputLimitExceeded { pc, bytes } => {
+                format!(
+                    "The error occurred at instruction {pc} due to the output limit of {bytes} bytes."
+                )
+            }
+            Self::MemoryLimitExceeded {
+                pc,
+                requested,
+                limit,
+            } => {
+                format!(
+                    "The error occurred at instruction {pc} due to the memory limit: \
+                     {requested} cells needed, {limit} allowed."
+                )
+            }
+            Self::CursorUnderflow { pc } => {
+                format!("The error occurred at instruction {pc} due to moving left of cell 0.")
+            }
+            Self::UnhandledExtension { pc, id } => {
+                format!(
+                    "The error occurred at instruction {pc} due to extension {id} having no handler."
+                )
+            }
+            Self::HostError { pc, message } => {
+                format!("The error occurred at instruction {pc} due to a host function: {message}")
+            }
+            Self::WriteProtected { pc, cell } => {
+                format!(
+                    "The error occurred at instruction {pc} due to cell {cell} being read-only."
+                )
+            }
+            Self::UnhandledFork { pc } => {
+                format!(
+                    "The error occurred at instruction {pc} due to a fork outside a threaded run."
+                )
+            }
+            Self::InLoops { error, loops } => {
+                write!(f, "{error}")?;
+                for frame in loops {
+                    write!(
+                        f,
+                        "\n  in the loop at instruction {}, iteration {}",
+                        frame.pc, frame.iteration
+                    )?;
+                }
+                return Ok(());
+            }
+        };
+        write!(f, "{message}")
+    }
+}
+
+impl Display for TapeOverflow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "the data ends at cell {} but the tape has only {} cells",
+            self.end, self.tape_len
+        )
+    }
+}
+
+impl Error for TapeOverflow {}
+
+impl Error for BfRuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::InLoops { error, .. } => error.source(),
+            _ => None,
+        }
+    }
+}
+
+impl Default for MachineConfig {
+    fn default() -> Self {
+        Self {
+            memory_size: 30_000,
+            max_steps: None,
+            max_output_bytes: None,
+            eof_mode: EofMode::default(),
+            tape_policy: TapePolicy::default(),
+            max_memory_cells: DEFAULT_MAX_MEMORY_CELLS,
+        }
+    }
+}
+
 impl Default for BfMachine<Stdin, Stdout> {
     fn default() -> Self {
         Self::new(30_000, stdin(), stdout())
@@ -124,46 +1894,64 @@ impl Default for BfMachine<Stdin, Stdout> {
 mod tests {
     use std::io::Cursor;
 
-    use crate::bf::bf_parser::BfParser;
+    use crate::bf::{
+        bf_parser::{BfParser, ParseOptions},
+        program::OptLevel,
+    };
 
     use super::*;
 
-    fn create_test_machine(input: &[u8]) -> BfMachine<std::io::Cursor<Vec<u8>>, Vec<u8>> {
-        BfMachine::new(30000, Cursor::new(input.to_owned()), vec![0; 30000])
-    }
+    const HELLO_WORLD: &str = include_str!("../../tests/programs/hello_world.b");
+    const PI: &str = include_str!("../../tests/programs/pi.b");
+    const QUINE: &str = include_str!("../../tests/programs/quine.b");
 
-    #[test]
-    fn hello_world() {
-        let mut machine = create_test_machine(&[]);
+    type TestMachine = BfMachine<Cursor<Vec<u8>>, Vec<u8>>;
 
-        let commands = BfParser::parse(
-            "++++++++++[>+++++++>++++++++++>+++>+<<<<-]
-    >++.>+.+++++++..+++.>++.<<+++++++++++++++.
-    >.+++.------.--------.>+.>.",
-        )
-        .unwrap();
-        machine.run(&commands).unwrap();
+    fn create_test_machine(input: &[u8]) -> TestMachine {
+        BfMachine::new(30000, Cursor::new(input.to_owned()), vec![0; 30000])
+    }
 
-        let mut result = vec![0; 30000];
-        result.write("Hello World!\n".as_bytes()).unwrap();
+    fn run_checked(machine: &mut TestMachine, commands: &[BfToken]) -> Result<(), BfRuntimeError> {
+        let program = Program::from_tokens(commands.to_vec()).unwrap();
+        let compiled = CompiledProgram::compile(&program);
+        let mut fast = machine.clone();
+        let mut compiled_machine = machine.clone();
+
+        let result = machine.run(&program);
+        let pc = result.as_ref().map_err(BfRuntimeError::pc);
+
+        assert_eq!(
+            fast.run_fast(&program).as_ref().map_err(BfRuntimeError::pc),
+            pc
+        );
+        assert_eq!(
+            compiled_machine
+                .run_compiled(&compiled)
+                .as_ref()
+                .map_err(BfRuntimeError::pc),
+            pc
+        );
+        for other in [fast, compiled_machine] {
+            assert_eq!(other.cursor, machine.cursor);
+            assert_eq!(other.memory, machine.memory);
+            assert_eq!(other.input, machine.input);
+            assert_eq!(other.output, machine.output);
+            assert_eq!(other.bytes_read, machine.bytes_read);
+            assert_eq!(other.bytes_written, machine.bytes_written);
+        }
 
-        assert_eq!(machine.output, result);
+        result
     }
 
     #[test]
     fn fast_hello_world() {
         let mut machine = create_test_machine(&[]);
 
-        let commands = BfParser::parse_compress(
-            "++++++++++[>+++++++>++++++++++>+++>+<<<<-]
-    >++.>+.+++++++..+++.>++.<<+++++++++++++++.
-    >.+++.------.--------.>+.>.",
-        )
-        .unwrap();
-        machine.run(&commands).unwrap();
+        let commands = BfParser::parse_compress(HELLO_WORLD).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
 
         let mut result = vec![0; 30000];
-        result.write("Hello World!\n".as_bytes()).unwrap();
+        result.write_all("Hello World!\n".as_bytes()).unwrap();
 
         assert_eq!(machine.output, result);
     }
@@ -177,22 +1965,22 @@ mod tests {
 
         assert_eq!(machine.memory[0], 10);
 
-        machine.run(&commands).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
 
         assert_eq!(machine.memory[0], 0);
     }
 
     #[test]
     fn input_and_output() {
-        let mut machine = create_test_machine(&['t' as u8]);
-
-        let commands = BfParser::parse(",.").unwrap();
-        machine.run(&commands).unwrap();
+        let mut machine = create_test_machine(b"t");
 
-        let mut result = vec![0; 30000];
-        result.write("t".as_bytes()).unwrap();
+        let program = Program::parse(",.").unwrap();
 
-        assert_eq!(machine.output, result);
+        assert_eq!(machine.run_capture(&program).unwrap(), "t");
+        assert_eq!(
+            machine.run_capture(&Program::parse("+.").unwrap()).unwrap(),
+            "u"
+        );
     }
 
     #[test]
@@ -203,14 +1991,14 @@ mod tests {
         let move_cell_0_to_cell_1 = BfParser::parse("[>+ <-]").unwrap();
         let clear_cell_1 = BfParser::parse(">[-]").unwrap();
 
-        machine.run(&assign_cell_0_to_10).unwrap();
+        run_checked(&mut machine, &assign_cell_0_to_10).unwrap();
         assert_eq!(machine.memory[0], 10);
 
-        machine.run(&move_cell_0_to_cell_1).unwrap();
+        run_checked(&mut machine, &move_cell_0_to_cell_1).unwrap();
         assert_eq!(machine.memory[0], 0);
         assert_eq!(machine.memory[1], 10);
 
-        machine.run(&clear_cell_1).unwrap();
+        run_checked(&mut machine, &clear_cell_1).unwrap();
         assert_eq!(machine.memory[1], 0);
     }
 
@@ -222,7 +2010,7 @@ mod tests {
             "++++>++++>[-]>[-]>[-]<<<<[->[->+>+<<]>>[-<<+>>]>+<<<<]>>>>[-<<<<+>>>>]<<<<",
         )
         .unwrap();
-        machine.run(&commands).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
 
         assert_eq!(machine.memory[0], 4);
         assert_eq!(machine.memory[1], 4);
@@ -233,46 +2021,23 @@ mod tests {
     fn pi() {
         let mut machine = create_test_machine(&[]);
 
-        let commands = BfParser::parse(
-            ">+++++++++++++++[<+>>>>>>>>++++++++++<<<<<<<-]>+++++[<+++++++++>-]+>>>>>>+[<<+++
-            [>>[-<]<[>]<-]>>[>+>]<[<]>]>[[->>>>+<<<<]>>>+++>-]<[<<<<]<<<<<<<<+[->>>>>>>>>>>>
-            [<+[->>>>+<<<<]>>>>>]<<<<[>>>>>[<<<<+>>>>-]<<<<<-[<<++++++++++>>-]>>>[<<[<+<<+>>
-            >-]<[>+<-]<++<<+>>>>>>-]<<[-]<<-<[->>+<-[>>>]>[[<+>-]>+>>]<<<<<]>[-]>+<<<-[>>+<<
-            -]<]<<<<+>>>>>>>>[-]>[<<<+>>>-]<<++++++++++<[->>+<-[>>>]>[[<+>-]>+>>]<<<<<]>[-]>
-            +>[<<+<+>>>-]<<<<+<+>>[-[-[-[-[-[-[-[-[-<->[-<+<->>]]]]]]]]]]<[+++++[<<<++++++++
-            <++++++++>>>>-]<<<<+<->>>>[>+<<<+++++++++<->>>-]<<<<<[>>+<<-]+<[->-<]>[>>.<<<<[+
-            .[-]]>>-]>[>>.<<-]>[-]>[-]>>>[>>[<<<<<<<<+>>>>>>>>-]<<-]]>>[-]<<<[-]<<<<<<<<]+++
-            +++++++.",
-        )
-        .unwrap();
-        machine.run(&commands).unwrap();
-
-        let mut result = vec![0; 30000];
-        result.write("3.14070455282885\n".as_bytes()).unwrap();
+        let program = Program::parse(PI).unwrap();
 
-        assert_eq!(machine.output, result);
+        assert_eq!(machine.run_capture(&program).unwrap(), "3.14070455282885\n");
     }
 
     #[test]
     fn fast_pi() {
         let mut machine = create_test_machine(&[]);
 
-        let commands = BfParser::parse_compress(
-            ">+++++++++++++++[<+>>>>>>>>++++++++++<<<<<<<-]>+++++[<+++++++++>-]+>>>>>>+[<<+++
-            [>>[-<]<[>]<-]>>[>+>]<[<]>]>[[->>>>+<<<<]>>>+++>-]<[<<<<]<<<<<<<<+[->>>>>>>>>>>>
-            [<+[->>>>+<<<<]>>>>>]<<<<[>>>>>[<<<<+>>>>-]<<<<<-[<<++++++++++>>-]>>>[<<[<+<<+>>
-            >-]<[>+<-]<++<<+>>>>>>-]<<[-]<<-<[->>+<-[>>>]>[[<+>-]>+>>]<<<<<]>[-]>+<<<-[>>+<<
-            -]<]<<<<+>>>>>>>>[-]>[<<<+>>>-]<<++++++++++<[->>+<-[>>>]>[[<+>-]>+>>]<<<<<]>[-]>
-            +>[<<+<+>>>-]<<<<+<+>>[-[-[-[-[-[-[-[-[-<->[-<+<->>]]]]]]]]]]<[+++++[<<<++++++++
-            <++++++++>>>>-]<<<<+<->>>>[>+<<<+++++++++<->>>-]<<<<<[>>+<<-]+<[->-<]>[>>.<<<<[+
-            .[-]]>>-]>[>>.<<-]>[-]>[-]>>>[>>[<<<<<<<<+>>>>>>>>-]<<-]]>>[-]<<<[-]<<<<<<<<]+++
-            +++++++.",
-        )
-        .unwrap();
-        machine.run(&commands).unwrap();
+        let commands = BfParser::parse_compress(PI).unwrap();
+        assert!(!commands
+            .iter()
+            .any(|token| matches!(token, BfToken::NotCommand(_))));
+        run_checked(&mut machine, &commands).unwrap();
 
         let mut result = vec![0; 30000];
-        result.write("3.14070455282885\n".as_bytes()).unwrap();
+        result.write_all("3.14070455282885\n".as_bytes()).unwrap();
 
         assert_eq!(machine.output, result);
     }
@@ -281,13 +2046,11 @@ mod tests {
     fn quine() {
         let mut machine = create_test_machine(&[]);
 
-        let commands_literally =
-            "-->+++>+>+>+>+++++>++>++>->+++>++>+>>>>>>>>>>>>>>>>->++++>>>>->+++>+++>+++>+++>+++>+++>+>+>>>->->>++++>+>>>>->>++++>+>+>>->->++>++>++>++++>+>++>->++>++++>+>+>++>++>->->++>++>++++>+>+>>>>>->>->>++++>++>++>++++>>>>>->>>>>+++>->++++>->->->+++>>>+>+>+++>+>++++>>+++>->>>>>->>>++++>++>++>+>+++>->++++>>->->+++>+>+++>+>++++>>>+++>->++++>>->->++>++++>++>++++>>++[-[->>+[>]++[<]<]>>+[>]<--[++>++++>]+[<]<<++]>>>[>]++++>++++[--[+>+>++++<<[-->>--<<[->-<[--->>+<<[+>+++<[+>>++<<]]]]]]>+++[>+++++++++++++++<-]>--.<<<]";
-        let commands = BfParser::parse(commands_literally).unwrap();
-        machine.run(&commands).unwrap();
+        let commands = BfParser::parse(QUINE).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
 
         let mut result = vec![0; 30000];
-        result.write(commands_literally.as_bytes()).unwrap();
+        result.write_all(QUINE.as_bytes()).unwrap();
 
         assert_eq!(machine.output, result);
     }
@@ -296,13 +2059,11 @@ mod tests {
     fn fast_quine() {
         let mut machine = create_test_machine(&[]);
 
-        let commands_literally =
-            "-->+++>+>+>+>+++++>++>++>->+++>++>+>>>>>>>>>>>>>>>>->++++>>>>->+++>+++>+++>+++>+++>+++>+>+>>>->->>++++>+>>>>->>++++>+>+>>->->++>++>++>++++>+>++>->++>++++>+>+>++>++>->->++>++>++++>+>+>>>>>->>->>++++>++>++>++++>>>>>->>>>>+++>->++++>->->->+++>>>+>+>+++>+>++++>>+++>->>>>>->>>++++>++>++>+>+++>->++++>>->->+++>+>+++>+>++++>>>+++>->++++>>->->++>++++>++>++++>>++[-[->>+[>]++[<]<]>>+[>]<--[++>++++>]+[<]<<++]>>>[>]++++>++++[--[+>+>++++<<[-->>--<<[->-<[--->>+<<[+>+++<[+>>++<<]]]]]]>+++[>+++++++++++++++<-]>--.<<<]";
-        let commands = BfParser::parse_compress(commands_literally).unwrap();
-        machine.run(&commands).unwrap();
+        let commands = BfParser::parse_compress(QUINE).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
 
         let mut result = vec![0; 30000];
-        result.write(commands_literally.as_bytes()).unwrap();
+        result.write_all(QUINE.as_bytes()).unwrap();
 
         assert_eq!(machine.output, result);
     }
@@ -312,11 +2073,11 @@ mod tests {
         let mut machine = create_test_machine(&[]);
 
         let commands = BfParser::parse(".+[.+]").unwrap();
-        machine.run(&commands).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
 
         let mut result = vec![0; 30000];
         for i in 0..256 {
-            result.write(&[i as u8]).unwrap();
+            result.write_all(&[i as u8]).unwrap();
         }
 
         assert_eq!(machine.output, result);
@@ -330,15 +2091,600 @@ mod tests {
         let equal_code = format!("{}+>", ">".repeat(machine.memory.len() - 1));
         let overflow_code = ">".repeat(machine.memory.len() * 2);
 
-        let commands = BfParser::parse(&code).unwrap();
+        let commands = BfParser::parse(code).unwrap();
         let equal_commands = BfParser::parse_compress(&equal_code).unwrap();
         let overflow_commands = BfParser::parse_compress(&overflow_code).unwrap();
 
-        machine.run(&commands).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
         assert_eq!(machine.memory[machine.memory.len() - 1], 1);
-        machine.run(&equal_commands).unwrap();
+        run_checked(&mut machine, &equal_commands).unwrap();
         assert_eq!(machine.memory[machine.memory.len() - 1], 2);
-        machine.run(&overflow_commands).unwrap();
+        run_checked(&mut machine, &overflow_commands).unwrap();
         assert_eq!(machine.cursor, 0);
     }
+
+    #[test]
+    fn unexpected_end_of_input() {
+        let mut machine = create_test_machine(b"a");
+
+        let commands = BfParser::parse("+,,").unwrap();
+        let err = run_checked(&mut machine, &commands).unwrap_err();
+
+        assert!(matches!(err, BfRuntimeError::UnexpectedEof { pc: 2 }));
+        assert_eq!(err.pc(), 2);
+    }
+
+    #[test]
+    fn errors_list_their_loops() {
+        // The first pass of the outer loop reads three bytes, so the fourth
+        // runs out on the second pass of both loops.
+        let program = Program::parse("++[>+++[>,<-]<-]").unwrap();
+        let mut machine = create_test_machine(b"abcd");
+        let err = machine.run(&program).unwrap_err();
+
+        assert!(matches!(
+            err.root_cause(),
+            BfRuntimeError::UnexpectedEof { pc: 9 }
+        ));
+        assert_eq!(err.pc(), 9);
+        assert_eq!(
+            err.to_string(),
+            "The error occurred at instruction 9 due to the end of input.\n  \
+             in the loop at instruction 7, iteration 2\n  \
+             in the loop at instruction 2, iteration 2"
+        );
+
+        let BfRuntimeError::InLoops { loops, .. } = err.map_pc(|pc| pc + 10) else {
+            panic!("no loops");
+        };
+        assert_eq!(
+            loops.iter().map(|frame| frame.pc).collect::<Vec<_>>(),
+            [17, 12]
+        );
+        assert_eq!(loops[0].span, Some(7));
+
+        // Outside of any loop the error is bare.
+        let err = machine.run(&Program::parse(",").unwrap()).unwrap_err();
+        assert!(matches!(err, BfRuntimeError::UnexpectedEof { pc: 0 }));
+    }
+
+    #[test]
+    fn step_limit() {
+        let mut machine = create_test_machine(&[]).with_step_limit(100);
+
+        let commands = BfParser::parse("+[]").unwrap();
+        let err = run_checked(&mut machine, &commands).unwrap_err();
+        assert!(matches!(err, BfRuntimeError::StepLimitExceeded { .. }));
+
+        let commands = BfParser::parse(&"+".repeat(100)).unwrap();
+        run_checked(&mut machine, &commands).unwrap();
+    }
+
+    #[test]
+    fn repeated_output_single_write() {
+        struct WriteLog(Vec<Vec<u8>>);
+
+        impl Write for WriteLog {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.push(buf.to_vec());
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let commands = BfParser::parse_compress("+.....").unwrap();
+        assert_eq!(
+            commands,
+            vec![BfToken::Increment(1), BfToken::PrintCharN(5)]
+        );
+
+        let mut machine = BfMachine::new(10, Cursor::new(vec![]), WriteLog(vec![]));
+        machine
+            .run(&Program::from_tokens(commands.clone()).unwrap())
+            .unwrap();
+        assert_eq!(machine.output.0, vec![vec![1; 5]]);
+
+        let mut machine = create_test_machine(&[]);
+        run_checked(&mut machine, &commands).unwrap();
+        let compressed = machine.output;
+        let mut machine = create_test_machine(&[]);
+        run_checked(&mut machine, &BfParser::parse("+.....").unwrap()).unwrap();
+        assert_eq!(compressed, machine.output);
+    }
+
+    #[test]
+    fn eof_modes() {
+        let commands = BfParser::parse("+++,>,").unwrap();
+
+        let mut machine = create_test_machine(b"a").with_eof_mode(EofMode::Zero);
+        run_checked(&mut machine, &commands).unwrap();
+        assert_eq!(machine.memory[..2], [b'a', 0]);
+
+        let mut machine = create_test_machine(&[]).with_eof_mode(EofMode::Unchanged);
+        run_checked(&mut machine, &commands).unwrap();
+        assert_eq!(machine.memory[..2], [3, 0]);
+
+        let mut machine = create_test_machine(b"a").with_eof_mode(EofMode::MinusOne);
+        run_checked(&mut machine, &commands).unwrap();
+        assert_eq!(machine.memory[..2], [b'a', 255]);
+
+        let mut machine = create_test_machine(b"a");
+        let err = run_checked(&mut machine, &commands).unwrap_err();
+        assert!(matches!(err, BfRuntimeError::UnexpectedEof { pc: 5 }));
+    }
+
+    #[test]
+    fn resume_after_step_limit() {
+        let program = Program::parse(",[.-]").unwrap();
+
+        let mut machine = create_test_machine(b"\x05").with_step_limit(7);
+        let pc = match machine.run(&program).unwrap_err() {
+            BfRuntimeError::StepLimitExceeded { pc } => pc,
+            err => panic!("unexpected error: {err}"),
+        };
+        assert_eq!(machine.bytes_read(), 1);
+
+        let mut resumed = create_test_machine(&[]);
+        resumed.restore(machine.snapshot());
+        resumed.run_from(&program, pc).unwrap();
+
+        let mut whole = create_test_machine(b"\x05");
+        let output = whole.run_capture(&program).unwrap();
+        assert_eq!(
+            [&machine.output[30000..], &resumed.output[30000..]].concat(),
+            output.as_bytes()
+        );
+        assert_eq!(resumed.snapshot(), whole.snapshot());
+    }
+
+    #[test]
+    fn cloned_machines_compare_state() {
+        let mut machine = create_test_machine(b"");
+        machine.memory[..3].copy_from_slice(&[1, 2, 3]);
+        let mut copy = machine.clone();
+        assert_eq!(copy, machine);
+
+        copy.memory[1] += 1;
+        assert_ne!(copy, machine);
+
+        let program = Program::parse("[->+<]>>+").unwrap();
+        let mut left = machine.clone();
+        let mut right = BfMachine::new(30000, Cursor::new(b"unused"), io::sink());
+        right.restore(machine.snapshot());
+        left.run(&program).unwrap();
+        right.run(&program).unwrap();
+        assert!(left.state_eq(&right));
+        assert!(!left.state_eq(&machine));
+    }
+
+    #[test]
+    fn load_tape_regions() {
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+        machine.load_tape(2, b"abcd").unwrap();
+        machine.load_tape(4, b"XY").unwrap();
+        machine.load_tape(8, b"").unwrap();
+        assert_eq!(machine.tape(), b"\0\0abXY\0\0");
+
+        assert_eq!(
+            machine.load_tape(6, b"xyz"),
+            Err(TapeOverflow {
+                end: 9,
+                tape_len: 8
+            })
+        );
+        assert_eq!(machine.memory[6..], [0, 0]);
+    }
+
+    #[test]
+    fn protected_ranges() {
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+        machine.protect_range(0..2);
+        machine.protect_range(4..6);
+        machine.protect_range(1..3);
+        machine.protect_range(7..7);
+        assert_eq!(machine.protection.ranges, [0..3, 4..6]);
+        assert_eq!(machine.protection.bounds, 0..6);
+
+        machine.unprotect_range(1..5);
+        assert_eq!(machine.protection.ranges, [0..1, 5..6]);
+        machine.unprotect_range(0..8);
+        assert!(machine.protection.ranges.is_empty());
+        assert!(!machine.protection.guards(0));
+    }
+
+    #[test]
+    fn write_protected_cells() {
+        let reads = Program::parse_optimized("[.>]>>.<<[-]").unwrap();
+        let writes = Program::parse_optimized(">>.>+<,").unwrap();
+        let protected = || {
+            let mut machine = BfMachine::new(8, Cursor::new(b"x"), vec![]);
+            machine.load_tape(0, b"\0\0ab").unwrap();
+            machine.protect_range(1..4);
+            machine
+        };
+
+        let mut machine = protected();
+        machine.run(&reads).unwrap();
+        assert_eq!(machine.output, b"a");
+
+        // The pcs of the `+` and the `,`: the `>+<` is a single token once
+        // the optimizer sinks its moves.
+        #[cfg(feature = "optimizer")]
+        let (add_pc, input_pc) = (2, 3);
+        #[cfg(not(feature = "optimizer"))]
+        let (add_pc, input_pc) = (3, 5);
+        let expected = format!("Err(WriteProtected {{ pc: {add_pc}, cell: 3 }})");
+        assert_eq!(format!("{:?}", protected().run(&writes)), expected);
+        assert_eq!(format!("{:?}", protected().run_fast(&writes)), expected);
+        let compiled = CompiledProgram::compile(&writes);
+        assert_eq!(
+            format!("{:?}", protected().run_compiled(&compiled)),
+            expected
+        );
+
+        let mut machine = protected();
+        machine.unprotect_range(3..4);
+        assert!(matches!(
+            machine.run(&writes),
+            Err(BfRuntimeError::WriteProtected { pc, cell: 2 }) if pc == input_pc
+        ));
+        assert_eq!(machine.memory[..4], *b"\0\0ac");
+    }
+
+    #[test]
+    fn breakpoints_in_a_loop() {
+        let program = Program::parse("+++[>+.<-]>.").unwrap();
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+        machine.add_breakpoint(5);
+        machine.add_breakpoint(8);
+        machine.add_breakpoint(0);
+        assert!(machine.remove_breakpoint(0));
+        assert!(!machine.remove_breakpoint(0));
+        assert_eq!(machine.breakpoints().collect::<Vec<_>>(), [5, 8]);
+
+        let mut hits = vec![];
+        let mut outcome = machine.run_until_break(&program).unwrap();
+        while let RunOutcome::Breakpoint { pc } = outcome {
+            hits.push((pc, machine.memory[1]));
+            outcome = machine.resume_until_break(&program, pc).unwrap();
+        }
+        assert_eq!(hits, [(5, 0), (8, 1), (5, 1), (8, 2), (5, 2), (8, 3)]);
+        assert_eq!(machine.output, b"\x01\x02\x03\x03");
+
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+        machine.add_breakpoint(0);
+        assert_eq!(
+            machine.run_until_break(&program).unwrap(),
+            RunOutcome::Breakpoint { pc: 0 }
+        );
+        machine.run(&program).unwrap();
+        assert_eq!(
+            machine.to_string(),
+            "cursor 1, cells 0..5: 0 [3] 0 0 0, 0 bytes read, 4 written"
+        );
+    }
+
+    /// Has no data the first `stalls` times it is read.
+    struct SlowReader {
+        stalls: usize,
+        data: Cursor<&'static [u8]>,
+    }
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.stalls > 0 {
+                self.stalls -= 1;
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn input_that_would_block_pauses() {
+        let program = Program::parse(",.").unwrap();
+        let reader = SlowReader {
+            stalls: 2,
+            data: Cursor::new(b"z"),
+        };
+        let mut machine = BfMachine::new(4, reader, vec![]).with_nonblocking_input();
+
+        let mut outcomes = vec![machine.run_until_break(&program).unwrap()];
+        while let Some(&RunOutcome::NeedsInput { pc }) = outcomes.last() {
+            outcomes.push(machine.resume_until_break(&program, pc).unwrap());
+        }
+        assert_eq!(
+            outcomes,
+            [
+                RunOutcome::NeedsInput { pc: 0 },
+                RunOutcome::NeedsInput { pc: 0 },
+                RunOutcome::Completed,
+            ]
+        );
+        assert_eq!(machine.output, b"z");
+        assert_eq!(machine.bytes_read(), 1);
+
+        // Inside a loop, the read resumes on the same pass.
+        let program = Program::parse("+++[>,.<-]").unwrap();
+        let reader = SlowReader {
+            stalls: 1,
+            data: Cursor::new(b"abc"),
+        };
+        let mut machine = BfMachine::new(4, reader, vec![]).with_nonblocking_input();
+        let outcome = machine.run_until_break(&program).unwrap();
+        assert_eq!(outcome, RunOutcome::NeedsInput { pc: 5 });
+        assert_eq!(
+            machine.resume_until_break(&program, 5).unwrap(),
+            RunOutcome::Completed
+        );
+        assert_eq!(machine.output, b"abc");
+
+        // A run that cannot pause fails as before.
+        let reader = SlowReader {
+            stalls: 1,
+            data: Cursor::new(b"z"),
+        };
+        let mut machine = BfMachine::new(4, reader, vec![]).with_nonblocking_input();
+        assert!(matches!(
+            machine.run(&program).unwrap_err().root_cause(),
+            BfRuntimeError::Io { pc: 5, source } if source.kind() == ErrorKind::WouldBlock
+        ));
+    }
+
+    #[test]
+    fn output_limit() {
+        let program = Program::parse(".+[.+]").unwrap();
+        let compressed = Program::parse_optimized("+[..]").unwrap();
+        let compiled = CompiledProgram::compile(&compressed);
+
+        for run in 0..4 {
+            let mut output = vec![];
+            let mut machine =
+                BfMachine::new(10, Cursor::new(b""), &mut output).with_output_limit(100);
+            let err = match run {
+                0 => machine.run(&program),
+                1 => machine.run_fast(&program),
+                2 => machine.run_fast(&compressed),
+                _ => machine.run_compiled(&compiled),
+            }
+            .unwrap_err();
+            assert!(
+                matches!(
+                    err.root_cause(),
+                    BfRuntimeError::OutputLimitExceeded { bytes: 100, .. }
+                ),
+                "{err}"
+            );
+            drop(machine);
+            assert_eq!(output.len(), 100);
+        }
+
+        let mut machine = BfMachine::new(10, Cursor::new(b""), vec![]);
+        machine.run(&program).unwrap();
+        assert_eq!(machine.take_output().len(), 256);
+    }
+
+    #[test]
+    fn memory_limit() {
+        let program = Program::parse("+[>+]").unwrap();
+        let compressed = Program::parse_optimized("+[>+]").unwrap();
+        let compiled = CompiledProgram::compile(&compressed);
+
+        for run in 0..4 {
+            let mut machine = BfMachine::new(100, Cursor::new(b""), io::sink())
+                .with_tape_policy(TapePolicy::Grow)
+                .with_memory_limit(10_000);
+            let err = match run {
+                0 => machine.run(&program),
+                1 => machine.run_fast(&program),
+                2 => machine.run_fast(&compressed),
+                _ => machine.run_compiled(&compiled),
+            }
+            .unwrap_err();
+            assert!(
+                matches!(
+                    err.root_cause(),
+                    BfRuntimeError::MemoryLimitExceeded {
+                        requested: 10_001,
+                        limit: 10_000,
+                        ..
+                    }
+                ),
+                "{err}"
+            );
+            assert_eq!(machine.memory.len(), 10_000);
+            assert_eq!(machine.cursor, 9_999);
+        }
+    }
+
+    #[test]
+    fn growing_tape() {
+        let mut machine = BfMachine::new(2, Cursor::new(b""), vec![])
+            .with_tape_policy(TapePolicy::Grow)
+            .with_memory_limit(10_000);
+        let output = machine.run_capture(&Program::parse(HELLO_WORLD).unwrap());
+        assert_eq!(output.unwrap(), "Hello World!\n");
+        assert!(machine.memory.len() < 100);
+
+        let err = machine
+            .run(&Program::parse("<<<<<<<<<<").unwrap())
+            .unwrap_err();
+        assert!(
+            matches!(err, BfRuntimeError::CursorUnderflow { .. }),
+            "{err}"
+        );
+
+        machine.load_tape(200, b"ab").unwrap();
+        assert_eq!(machine.memory.len(), 202);
+    }
+
+    #[test]
+    fn transforms_host_memory_in_place() {
+        let data: Vec<u8> = (0..16).map(|i| i * 10).collect();
+        let program = Program::parse(&format!("{}{}", ">".repeat(100), "+>".repeat(16))).unwrap();
+        let mut machine = BfMachine::new(200, Cursor::new(b""), vec![]);
+        machine.load_memory(100, &data).unwrap();
+        machine.run(&program).unwrap();
+
+        let expected: Vec<u8> = data.iter().map(|cell| cell + 1).collect();
+        assert_eq!(machine.read_memory(100, 16).unwrap(), expected);
+        assert_eq!(machine.read_memory(99, 1).unwrap(), [0]);
+        assert_eq!(
+            machine.read_memory(190, 16),
+            Err(TapeOverflow {
+                end: 206,
+                tape_len: 200
+            })
+        );
+        assert_eq!(machine.load_memory(199, b"ab").unwrap_err().end, 201);
+
+        machine.reset();
+        assert_eq!(machine.read_memory(100, 16).unwrap(), [0; 16]);
+    }
+
+    #[test]
+    fn reads_past_a_growing_tape_as_zero() {
+        let mut machine = BfMachine::new(4, Cursor::new(b""), vec![])
+            .with_tape_policy(TapePolicy::Grow)
+            .with_memory_limit(64);
+        machine.load_memory(2, b"ab").unwrap();
+        assert_eq!(machine.read_memory(2, 4).unwrap(), b"ab\0\0");
+        assert_eq!(machine.read_memory(60, 4).unwrap(), [0; 4]);
+        assert_eq!(machine.read_memory(62, 4).unwrap_err().tape_len, 64);
+        assert_eq!(machine.tape().len(), 4);
+    }
+
+    #[test]
+    fn reset_zeroes_what_was_touched() {
+        let mut machine = BfMachine::new(100, Cursor::new(b""), vec![]);
+        machine.run(&Program::parse("+>>>++>+<<").unwrap()).unwrap();
+        assert_eq!(machine.touched, 5);
+        machine.reset();
+        assert_eq!((machine.cursor, machine.touched), (0, 1));
+        assert!(machine.tape().iter().all(|&cell| cell == 0));
+
+        // A wrap to the last cell counts the whole tape as touched.
+        machine.run_fast(&Program::parse("<+").unwrap()).unwrap();
+        assert_eq!(machine.touched, 100);
+        machine.reset();
+        assert!(machine.tape().iter().all(|&cell| cell == 0));
+
+        machine.load_tape(50, b"ab").unwrap();
+        assert_eq!(machine.touched, 52);
+        machine.reset();
+        assert!(machine.tape().iter().all(|&cell| cell == 0));
+    }
+
+    #[test]
+    fn switching_tapes() {
+        let options = ParseOptions {
+            multi_tape: true,
+            ..Default::default()
+        };
+        let program = Program::parse_at("+++++^+++++++^.^>", &options, OptLevel::O0).unwrap();
+        let compiled = CompiledProgram::compile(&program);
+
+        let mut machine = BfMachine::new(4, Cursor::new(b""), vec![]);
+        let mut fast = machine.clone();
+        let mut compiled_machine = machine.clone();
+        machine.run(&program).unwrap();
+        fast.run_fast(&program).unwrap();
+        compiled_machine.run_compiled(&compiled).unwrap();
+        assert!(machine.state_eq(&fast) && machine.state_eq(&compiled_machine));
+
+        assert_eq!(machine.output, [5]);
+        assert_eq!(machine.active_tape(), 1);
+        assert_eq!((machine.cursor(), machine.tape()), (1, &[7, 0, 0, 0][..]));
+        assert_eq!(machine.other_tape(), Some((0, &[5, 0, 0, 0][..])));
+        assert_eq!(
+            machine.to_string(),
+            "tape 0: cursor 0, cells 0..4: [5] 0 0 0; \
+             tape 1 (active): cursor 1, cells 0..4: 7 [0] 0 0, 0 bytes read, 1 written"
+        );
+
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.active_tape(), 1);
+        let mut restored = BfMachine::new(4, Cursor::new(b""), vec![]);
+        restored.restore(snapshot.clone());
+        assert_eq!(restored.snapshot(), snapshot);
+
+        // Without the option `^` is a comment.
+        let program = Program::parse_at("+^+.", &ParseOptions::default(), OptLevel::O0).unwrap();
+        let mut machine = BfMachine::new(4, Cursor::new(b""), vec![]);
+        machine.run(&program).unwrap();
+        assert_eq!(machine.output, [2]);
+        assert_eq!(machine.snapshot().other_tape, None);
+
+        restored.reset();
+        assert_eq!((restored.active_tape(), restored.other_tape()), (0, None));
+    }
+
+    #[test]
+    fn recycled_machines_start_clean() {
+        let long = Program::parse(&format!("{}.", "+>".repeat(40))).unwrap();
+        let short = Program::parse("+>+>,.").unwrap();
+
+        let mut machine = BfMachine::new(64, Cursor::new(b"a".to_vec()), vec![]);
+        machine.run_fast(&long).unwrap();
+        assert_eq!(machine.bytes_written(), 1);
+        let compiled = CompiledProgram::compile(&short);
+        let mut recycled = machine.recycle(Cursor::new(b"b".to_vec()), vec![]);
+        assert_eq!(recycled.bytes_written(), 0);
+        recycled.run_compiled(&compiled).unwrap();
+
+        assert_eq!(recycled.tape()[..3], [1, 1, b'b']);
+        assert!(recycled.tape()[3..].iter().all(|&cell| cell == 0));
+        assert_eq!(recycled.bytes_read(), 1);
+        assert_eq!(recycled.into_parts().3, b"b");
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn mmap_tape_persists() {
+        let path = std::env::temp_dir().join(format!("bf-rust-{}.tape", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut machine = BfMachine::new(30_000, Cursor::new(b""), vec![])
+            .with_mmap_tape(&path, 16)
+            .unwrap();
+        machine.run(&Program::parse("+++>++<<-").unwrap()).unwrap();
+        assert_eq!(machine.tape().len(), 16);
+        assert_eq!(machine.cursor(), 15);
+        assert_eq!(machine.snapshot().memory[..2], [3, 2]);
+        assert_eq!(
+            machine.to_string(),
+            "cursor 15, cells 12..16: 0 0 0 [255], 0 bytes read, 0 written"
+        );
+        drop(machine);
+
+        let mut reopened = BfMachine::new(1, Cursor::new(b""), vec![])
+            .with_mmap_tape(&path, 4)
+            .unwrap()
+            .with_tape_policy(TapePolicy::Grow);
+        assert_eq!(reopened.tape()[..2], [3, 2]);
+        assert_eq!(reopened.tape()[15], 255);
+        reopened.run(&Program::parse("[-]").unwrap()).unwrap();
+        reopened.sync().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[..2], [0, 2]);
+
+        let err = reopened
+            .run(&Program::parse(">>>>>>>>>>>>>>>>").unwrap())
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BfRuntimeError::MemoryLimitExceeded {
+                    requested: 17,
+                    limit: 16,
+                    ..
+                }
+            ),
+            "{err}"
+        );
+        drop(reopened);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,23 +1,73 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use super::source_map::SourceMap;
+
 pub struct BfCodeOptimizer;
 
+fn log_pass(pass: &str, before: usize, after: usize) {
+    debug!("pass {pass}: {before} -> {after} tokens");
+}
+
+/// Each round can only shrink the code, so this is never reached in practice;
+/// it just bounds the work if a future pass stops guaranteeing that.
+const MAX_ROUNDS: usize = 16;
+
 impl BfCodeOptimizer {
     pub fn optimize(code: &str) -> String {
-        let code = Self::remove_not_command(code);
-        let code = Self::remove_unnecessary_relative_operate(&code);
-        code
+        Self::optimize_with_map(code).0
     }
 
-    fn remove_not_command(code: &str) -> String {
+    pub fn optimize_with_map(code: &str) -> (String, SourceMap) {
+        Self::optimize_with_extensions(code, &HashMap::new())
+    }
+
+    /// Like `optimize_with_map`, but keeps the registered extension characters
+    /// and treats them as observable, like `.` and `,`.
+    pub fn optimize_with_extensions(
+        code: &str,
+        extensions: &HashMap<char, u8>,
+    ) -> (String, SourceMap) {
+        let chars = code.chars().count();
+        let mut code = Self::remove_not_command(code, extensions);
+        log_pass("remove_not_command", chars, code.len());
+        for round in 1..=MAX_ROUNDS {
+            let len = code.len();
+            code = Self::remove_unnecessary_relative_operate(&code);
+            log_pass("remove_unnecessary_relative_operate", len, code.len());
+            let cancelled = code.len();
+            code = Self::remove_trailing_dead_code(code, extensions);
+            log_pass("remove_trailing_dead_code", cancelled, code.len());
+            if code.len() == len {
+                debug!("optimizer reached a fixpoint after {round} round(s)");
+                break;
+            }
+        }
+
+        let optimized = code.iter().map(|(ch, _)| ch).collect();
+        let map = SourceMap::new(code.into_iter().map(|(_, index)| index).collect());
+        (optimized, map)
+    }
+
+    fn remove_not_command(code: &str, extensions: &HashMap<char, u8>) -> Vec<(char, usize)> {
         code.chars()
-            .filter(|c| matches!(c, '+' | '-' | ',' | '.' | '[' | ']' | '<' | '>'))
-            .collect::<String>()
+            .enumerate()
+            .filter(|(_, c)| {
+                matches!(c, '+' | '-' | ',' | '.' | '[' | ']' | '<' | '>')
+                    || extensions.contains_key(c)
+            })
+            .map(|(index, c)| (c, index))
+            .collect()
     }
 
-    fn remove_unnecessary_relative_operate(code: &str) -> String {
-        let mut result = String::new();
+    /// Cancels opposite pairs with a stack, so pairs that only become adjacent
+    /// once the ones between them are gone (`+><-`) cancel in the same pass.
+    fn remove_unnecessary_relative_operate(code: &[(char, usize)]) -> Vec<(char, usize)> {
+        let mut result: Vec<(char, usize)> = vec![];
 
-        for ch in code.chars() {
-            let last_char = result.chars().last().unwrap_or('\0');
+        for &(ch, index) in code {
+            let last_char = result.last().map_or('\0', |(last, _)| *last);
             if ch == '+' && last_char == '-'
                 || ch == '-' && last_char == '+'
                 || ch == '>' && last_char == '<'
@@ -27,21 +77,102 @@ impl BfCodeOptimizer {
                 continue;
             }
 
-            result.push(ch);
+            result.push((ch, index));
         }
 
         result
     }
+
+    /// Drops everything after the last `.`/`,` that cannot be observed: moves,
+    /// arithmetic, and loops that are either dead (entered on a cell known to be
+    /// zero) or plain `[-]`/`[+]` clears. Programs without any I/O are left
+    /// alone, as their final tape is the only result a caller can inspect.
+    fn remove_trailing_dead_code(
+        mut code: Vec<(char, usize)>,
+        extensions: &HashMap<char, u8>,
+    ) -> Vec<(char, usize)> {
+        let Some(matches) = Self::match_brackets(&code) else {
+            return code;
+        };
+        let Some(last_io) = code
+            .iter()
+            .rposition(|(ch, _)| matches!(ch, '.' | ',') || extensions.contains_key(ch))
+        else {
+            return code;
+        };
+
+        let mut depth = 0;
+        let mut outermost = None;
+        for (index, (ch, _)) in code[..last_io].iter().enumerate() {
+            match ch {
+                '[' => {
+                    if depth == 0 {
+                        outermost = Some(index);
+                    }
+                    depth += 1;
+                }
+                ']' => depth -= 1,
+                _ => {}
+            }
+        }
+        let tail_start = match outermost {
+            Some(start) if depth > 0 => matches[start] + 1,
+            _ => last_io + 1,
+        };
+
+        let mut keep = tail_start;
+        let mut zero_cell = code[tail_start - 1].0 == ']';
+        let mut index = tail_start;
+        while index < code.len() {
+            if code[index].0 == '[' {
+                let end = matches[index];
+                let body: String = code[index + 1..end].iter().map(|(ch, _)| ch).collect();
+                if !zero_cell && body != "-" && body != "+" {
+                    keep = end + 1;
+                }
+                zero_cell = true;
+                index = end + 1;
+            } else {
+                zero_cell = false;
+                index += 1;
+            }
+        }
+
+        code.truncate(keep);
+        code
+    }
+
+    fn match_brackets(code: &[(char, usize)]) -> Option<Vec<usize>> {
+        let mut matches = vec![0; code.len()];
+        let mut open = vec![];
+
+        for (index, (ch, _)) in code.iter().enumerate() {
+            match ch {
+                '[' => open.push(index),
+                ']' => {
+                    let start = open.pop()?;
+                    matches[start] = index;
+                    matches[index] = start;
+                }
+                _ => {}
+            }
+        }
+
+        open.is_empty().then_some(matches)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::bf::bf_optimizer::BfCodeOptimizer;
+    use std::collections::HashMap;
+
+    use crate::bf::{bf_optimizer::BfCodeOptimizer, program::Program};
 
     #[test]
     fn clear_not_command() {
-        let code = BfCodeOptimizer::optimize("the quick brown fox jumps over the lazy dog-[],.<+>");
-        assert_eq!(code, "-[],.<+>");
+        let code =
+            BfCodeOptimizer::optimize("the quick brown fox jumps over the lazy dog-[],.<+>.");
+        assert_eq!(code, "-[],.<+>.");
     }
 
     #[test]
@@ -57,4 +188,57 @@ mod tests {
         let code = BfCodeOptimizer::optimize(">>+++--<<");
         assert_eq!(code, ">>+<<".to_string());
     }
+
+    #[test]
+    fn nested_cancellation() {
+        assert_eq!(BfCodeOptimizer::optimize("+><-"), "");
+        assert_eq!(BfCodeOptimizer::optimize("+>+<>-<-."), ".");
+        assert_eq!(BfCodeOptimizer::optimize(".+[-]>><<"), ".");
+
+        let program = Program::parse_optimized(">+<>-<").unwrap();
+        assert!(program.tokens().is_empty());
+    }
+
+    #[test]
+    fn pathological_inputs() {
+        assert_eq!(BfCodeOptimizer::optimize(&"+-".repeat(50_000)), "");
+        assert_eq!(BfCodeOptimizer::optimize(&"><".repeat(50_000)), "");
+        assert_eq!(
+            BfCodeOptimizer::optimize(&format!("{}{}", ">+".repeat(50_000), "-<".repeat(50_000))),
+            ""
+        );
+        let code = "+>".repeat(50_000);
+        assert_eq!(BfCodeOptimizer::optimize(&code), code);
+    }
+
+    #[test]
+    fn trailing_dead_code() {
+        assert_eq!(BfCodeOptimizer::optimize("+.>>>[-]<<<"), "+.");
+        assert_eq!(BfCodeOptimizer::optimize("+.[,]"), "+.[,]");
+        assert_eq!(BfCodeOptimizer::optimize("+[.>][>+<]<<[-]>"), "+[.>]");
+        assert_eq!(BfCodeOptimizer::optimize("+.<[-<]>"), "+.<[-<]");
+        assert_eq!(BfCodeOptimizer::optimize("+.>[>]<"), "+.>[>]");
+        assert_eq!(BfCodeOptimizer::optimize("+[>+<-]>"), "+[>+<-]>");
+        assert_eq!(BfCodeOptimizer::optimize("+.]>"), "+.]>");
+    }
+
+    #[test]
+    fn keep_extensions() {
+        let extensions = HashMap::from([('?', 0)]);
+        let (code, map) = BfCodeOptimizer::optimize_with_extensions("+x?>[-]<!", &extensions);
+
+        assert_eq!(code, "+?");
+        assert_eq!(map.original(1), 2);
+        assert_eq!(BfCodeOptimizer::optimize("+x?>[-]<"), "+>[-]<");
+    }
+
+    #[test]
+    fn map_to_original_positions() {
+        let (code, map) = BfCodeOptimizer::optimize_with_map("a+\nb>+-<<c.");
+
+        assert_eq!(code, "+<.");
+        assert_eq!(map.original(0), 1);
+        assert_eq!(map.original(1), 8);
+        assert_eq!(map.original(2), 10);
+    }
 }
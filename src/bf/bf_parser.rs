@@ -1,50 +1,360 @@
-use std::{error::Error, fmt::Display};
+use std::{
+    borrow::Cow, collections::HashMap, error::Error, fmt::Display, ops::Range, time::Instant,
+};
 
-use super::bf_token::BfToken;
+use log::debug;
+
+use super::{bf_token::BfToken, directives};
+
+pub const REDUNDANT_OPERATIONS: &str = "BF006";
+pub const DEEP_NESTING: &str = "BF007";
+
+const SUGGESTED_MAX_DEPTH: usize = 64;
+
+/// How deeply loops may nest unless [`ParseOptions::max_nesting_depth`] says
+/// otherwise. Far more than any hand-written program needs, but it keeps
+/// generated programs from building pathologically deep structures.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 10_000;
 
 pub struct BfParser;
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseWarning {
+    pub span: Range<usize>,
+    pub code: &'static str,
+    pub message: String,
+}
+
+const CORE_COMMANDS: [char; 8] = ['+', '-', '<', '>', '[', ']', ',', '.'];
+
+/// The command that switches tapes with [`ParseOptions::multi_tape`].
+pub const SWITCH_TAPE: char = '^';
+
+/// The command that forks a thread with [`ParseOptions::brainfork`].
+pub const FORK: char = 'Y';
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseOptions {
+    /// Treat `;` as the start of a comment running to the end of the line.
+    pub line_comments: bool,
+    /// Treat everything after the first `!` as input for the program.
+    pub bang_input: bool,
+    /// Characters parsed as `BfToken::Extension(id)` instead of comments.
+    pub extensions: HashMap<char, u8>,
+    /// The most loops that may be open at once.
+    pub max_nesting_depth: usize,
+    /// Parse [`SWITCH_TAPE`] as `BfToken::SwitchTape`, for programs that use
+    /// a second tape.
+    pub multi_tape: bool,
+    /// Parse [`FORK`] as `BfToken::Fork`, for Brainfork programs.
+    pub brainfork: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            line_comments: false,
+            bang_input: false,
+            extensions: HashMap::new(),
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            multi_tape: false,
+            brainfork: false,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum BfParserError {
     LoopNotClosed(usize),
+    /// One of the eight core commands, `^` with `multi_tape` or `Y` with
+    /// `brainfork` was registered as an extension. It is not tied to a position in the code,
+    /// so its index is always 0.
+    ReservedExtension(char),
+    /// The `[` at `index` opens a loop `depth` levels deep, past the limit.
+    NestingTooDeep {
+        index: usize,
+        depth: usize,
+    },
+}
+
+/// A broken invariant of a token stream or of the tables built for it,
+/// reported by [`BfParser::validate`] and [`BfParser::validate_jump_table`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ValidationError {
+    /// A `LoopEnd` with no `LoopStart` before it.
+    UnmatchedLoopEnd(usize),
+    /// A `LoopStart` with no `LoopEnd` after it.
+    UnclosedLoop(usize),
+    /// A cursor move or repeated print by zero. `Increment(0)` and
+    /// `Decrement(0)` are fine, since 256 `+` compress to them.
+    ZeroOperand(usize),
+    /// A table with one entry per token has `len` entries instead.
+    TableLength {
+        tokens: usize,
+        len: usize,
+    },
+    JumpOutOfRange {
+        index: usize,
+        target: usize,
+    },
+    /// A bracket's entry points at something other than the opposite bracket.
+    JumpToNonBracket {
+        index: usize,
+        target: usize,
+    },
+    /// A bracket's entry points at an opposite bracket, but not its match.
+    MismatchedJump {
+        index: usize,
+        target: usize,
+        expected: usize,
+    },
+    /// The span of this token comes before the span of the previous one.
+    SpanOutOfOrder(usize),
 }
 
 impl BfParser {
     pub fn parse(code: &str) -> Result<Vec<BfToken>, BfParserError> {
-        let mut tokens = vec![];
+        let (tokens, matched) = Self::scan(code, &ParseOptions::default(), None);
+        matched.map(|()| tokens)
+    }
+
+    /// `parse` plus the jump table `loop_matching` would build, in one pass.
+    pub(crate) fn parse_with_jump_table(
+        code: &str,
+    ) -> Result<(Vec<BfToken>, Vec<usize>), BfParserError> {
+        let mut jump_table = vec![];
+        let (tokens, matched) = Self::scan(code, &ParseOptions::default(), Some(&mut jump_table));
+        matched.map(|()| (tokens, jump_table))
+    }
+
+    pub fn parse_with_options(
+        code: &str,
+        options: &ParseOptions,
+    ) -> Result<Vec<BfToken>, BfParserError> {
+        options.check_extensions()?;
+        let (tokens, matched) = Self::scan(&options.strip_comments(code), options, None);
+        matched?;
+        Ok(code
+            .chars()
+            .zip(tokens)
+            .map(|(ch, token)| match token {
+                BfToken::NotCommand(_) => BfToken::NotCommand(ch),
+                token => token,
+            })
+            .collect())
+    }
+
+    pub fn parse_with_warnings(
+        code: &str,
+    ) -> (Result<Vec<BfToken>, BfParserError>, Vec<ParseWarning>) {
+        let (tokens, matched) = Self::scan(code, &ParseOptions::default(), None);
+        let warnings = Self::warnings(&tokens);
+        (matched.map(|()| tokens), warnings)
+    }
 
-        for ch in code.chars() {
-            match ch {
-                '+' => tokens.push(BfToken::Increment(1)),
-                '-' => tokens.push(BfToken::Decrement(1)),
-                '<' => tokens.push(BfToken::CursorLeft(1)),
-                '>' => tokens.push(BfToken::CursorRight(1)),
-                '[' => tokens.push(BfToken::LoopStart(0)),
-                ']' => tokens.push(BfToken::LoopEnd(0)),
-                ',' => tokens.push(BfToken::InputChar),
-                '.' => tokens.push(BfToken::PrintChar),
-                _ => tokens.push(BfToken::NotCommand(ch)),
+    /// Every char of `code` as a token with its byte offset, one at a time.
+    ///
+    /// The output is unvalidated: brackets are not matched, so an unbalanced
+    /// program yields its tokens all the same. Use it for single scans that
+    /// need no jump table, and [`BfParser::parse`] for anything that runs.
+    pub fn tokens(code: &str) -> impl Iterator<Item = (usize, BfToken)> + '_ {
+        Self::lex(code, None)
+    }
+
+    /// Without `options`, only the eight core commands are commands.
+    fn lex<'a>(
+        code: &'a str,
+        options: Option<&'a ParseOptions>,
+    ) -> impl Iterator<Item = (usize, BfToken)> + 'a {
+        let bytes = code.as_bytes();
+        let header = header_len(code);
+        let mut offset = 0;
+        std::iter::from_fn(move || {
+            let start = offset;
+            let byte = *bytes.get(start)?;
+            let command = match byte {
+                _ if start < header => None,
+                b'+' => Some(BfToken::Increment(1)),
+                b'-' => Some(BfToken::Decrement(1)),
+                b'<' => Some(BfToken::CursorLeft(1)),
+                b'>' => Some(BfToken::CursorRight(1)),
+                b'[' => Some(BfToken::LoopStart),
+                b']' => Some(BfToken::LoopEnd),
+                b',' => Some(BfToken::InputChar),
+                b'.' => Some(BfToken::PrintChar),
+                b'^' if options.is_some_and(|options| options.multi_tape) => {
+                    Some(BfToken::SwitchTape)
+                }
+                b'Y' if options.is_some_and(|options| options.brainfork) => Some(BfToken::Fork),
+                _ => None,
+            };
+            let token = command.unwrap_or_else(|| {
+                let ch = if byte.is_ascii() {
+                    byte as char
+                } else {
+                    let ch = code[start..]
+                        .chars()
+                        .next()
+                        .expect("offset is a char boundary");
+                    offset += ch.len_utf8() - 1;
+                    ch
+                };
+                match options.and_then(|options| options.extensions.get(&ch)) {
+                    Some(&id) if start >= header => BfToken::Extension(id),
+                    _ => BfToken::NotCommand(ch),
+                }
+            });
+            offset += 1;
+            Some((start, token))
+        })
+    }
+
+    /// Tokenizes `code` and matches brackets on the way, filling `jump_table`
+    /// when one is given. Every char becomes exactly one token, so a non-ASCII
+    /// char is a single `NotCommand` and token indices stay char indices. The
+    /// tokens are returned even when the brackets do not match.
+    fn scan(
+        code: &str,
+        options: &ParseOptions,
+        mut jump_table: Option<&mut Vec<usize>>,
+    ) -> (Vec<BfToken>, Result<(), BfParserError>) {
+        let started = Instant::now();
+        let mut tokens = Vec::with_capacity(code.len());
+        if let Some(table) = jump_table.as_deref_mut() {
+            table.clear();
+            table.reserve(code.len());
+        }
+        let mut brackets = Brackets::new(options.max_nesting_depth);
+
+        for (_, token) in Self::lex(code, Some(options)) {
+            let index = tokens.len();
+            let mut target = index;
+            match token {
+                BfToken::LoopStart => brackets.open(index),
+                BfToken::LoopEnd => {
+                    if let Some(start) = brackets.close(index) {
+                        if let Some(table) = jump_table.as_deref_mut() {
+                            table[start] = index;
+                        }
+                        target = start;
+                    }
+                }
+                _ => {}
+            }
+            tokens.push(token);
+            if let Some(table) = jump_table.as_deref_mut() {
+                table.push(target);
             }
         }
 
-        Self::loop_matching(&mut tokens)?;
+        debug!(
+            "scanned {} bytes into {} tokens in {:?}",
+            code.len(),
+            tokens.len(),
+            started.elapsed()
+        );
+        (tokens, brackets.finish())
+    }
+
+    fn warnings(tokens: &[BfToken]) -> Vec<ParseWarning> {
+        let mut warnings = vec![];
+        let mut previous: Option<usize> = None;
+        let mut depth = 0;
+        let mut warned_depth = false;
+
+        for (index, token) in tokens.iter().enumerate() {
+            if let BfToken::NotCommand(_) = token {
+                continue;
+            }
+
+            if let Some(last) = previous {
+                if Self::cancels(&tokens[last], token) {
+                    warnings.push(ParseWarning {
+                        span: last..index + 1,
+                        code: REDUNDANT_OPERATIONS,
+                        message: "adjacent operations cancel each other out".to_string(),
+                    });
+                    previous = None;
+                    continue;
+                }
+            }
+            previous = Some(index);
+
+            match token {
+                BfToken::LoopStart => {
+                    depth += 1;
+                    if depth > SUGGESTED_MAX_DEPTH && !warned_depth {
+                        warned_depth = true;
+                        warnings.push(ParseWarning {
+                            span: index..index + 1,
+                            code: DEEP_NESTING,
+                            message: format!(
+                                "loops are nested more than {SUGGESTED_MAX_DEPTH} levels deep"
+                            ),
+                        });
+                    }
+                }
+                BfToken::LoopEnd => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
 
-        Ok(tokens)
+        warnings
+    }
+
+    fn cancels(first: &BfToken, second: &BfToken) -> bool {
+        matches!(
+            (first, second),
+            (BfToken::Increment(_), BfToken::Decrement(_))
+                | (BfToken::Decrement(_), BfToken::Increment(_))
+                | (BfToken::CursorLeft(_), BfToken::CursorRight(_))
+                | (BfToken::CursorRight(_), BfToken::CursorLeft(_))
+        )
     }
 
     pub fn parse_compress(code: &str) -> Result<Vec<BfToken>, BfParserError> {
-        let uncompress_tokens = Self::parse(code)?;
+        Ok(Self::parse_compress_with_spans(code)?.0)
+    }
+
+    pub fn parse_compress_with_spans(
+        code: &str,
+    ) -> Result<(Vec<BfToken>, Vec<usize>), BfParserError> {
+        Self::parse_compress_with_options(code, &ParseOptions::default())
+    }
+
+    pub fn parse_compress_with_options(
+        code: &str,
+        options: &ParseOptions,
+    ) -> Result<(Vec<BfToken>, Vec<usize>), BfParserError> {
+        options.check_extensions()?;
+        let code = options.strip_comments(code);
+        let mut brackets = Brackets::new(options.max_nesting_depth);
+        let mut len = 0;
         let mut tokens = vec![];
+        let mut spans = vec![];
         let mut sum = 0i32;
+        let mut sum_start = 0;
         let mut cursor_move = 0i32;
+        let mut cursor_move_start = 0;
+
+        for (index, (_, token)) in Self::lex(&code, Some(options)).enumerate() {
+            len += 1;
+            match token {
+                BfToken::NotCommand(_) => continue,
+                BfToken::LoopStart => brackets.open(index),
+                BfToken::LoopEnd => {
+                    brackets.close(index);
+                }
+                _ => {}
+            }
 
-        for token in uncompress_tokens.into_iter() {
             if !matches!(token, BfToken::Increment(_) | BfToken::Decrement(_)) && sum != 0 {
                 if sum > 0 {
                     tokens.push(BfToken::Increment(sum as u8));
                 } else if sum < 0 {
                     tokens.push(BfToken::Decrement(-sum as u8))
                 }
+                spans.push(sum_start);
                 sum = 0;
             }
             if !matches!(token, BfToken::CursorLeft(_) | BfToken::CursorRight(_))
@@ -55,15 +365,35 @@ impl BfParser {
                 } else if cursor_move < 0 {
                     tokens.push(BfToken::CursorLeft(-cursor_move as usize))
                 }
+                spans.push(cursor_move_start);
                 cursor_move = 0;
             }
 
+            match token {
+                BfToken::Increment(_) | BfToken::Decrement(_) if sum == 0 => sum_start = index,
+                BfToken::CursorLeft(_) | BfToken::CursorRight(_) if cursor_move == 0 => {
+                    cursor_move_start = index
+                }
+                _ => {}
+            }
+
             match token {
                 BfToken::Increment(_) => sum += 1,
                 BfToken::Decrement(_) => sum -= 1,
                 BfToken::CursorLeft(_) => cursor_move -= 1,
                 BfToken::CursorRight(_) => cursor_move += 1,
-                _ => tokens.push(token),
+                BfToken::PrintChar => match tokens.last_mut() {
+                    Some(last @ BfToken::PrintChar) => *last = BfToken::PrintCharN(2),
+                    Some(BfToken::PrintCharN(count)) => *count += 1,
+                    _ => {
+                        tokens.push(token);
+                        spans.push(index);
+                    }
+                },
+                _ => {
+                    tokens.push(token);
+                    spans.push(index);
+                }
             }
         }
 
@@ -73,6 +403,7 @@ impl BfParser {
             } else if sum < 0 {
                 tokens.push(BfToken::Decrement(-sum as u8))
             }
+            spans.push(sum_start);
         }
 
         if cursor_move != 0 {
@@ -81,37 +412,435 @@ impl BfParser {
             } else if cursor_move < 0 {
                 tokens.push(BfToken::CursorLeft(-cursor_move as usize))
             }
+            spans.push(cursor_move_start);
         }
 
-        Self::loop_matching(&mut tokens)?;
+        brackets.finish()?;
 
-        Ok(tokens)
+        debug!("compressed {len} tokens into {}", tokens.len());
+        Ok((tokens, spans))
     }
 
-    fn loop_matching(tokens: &mut [BfToken]) -> Result<(), BfParserError> {
+    pub fn loop_matching(tokens: &[BfToken]) -> Result<Vec<usize>, BfParserError> {
+        let mut jump_table: Vec<usize> = (0..tokens.len()).collect();
         let mut loop_record = vec![];
 
-        for index in 0..tokens.len() {
-            match tokens[index] {
-                BfToken::LoopStart(_) => {
+        for (index, token) in tokens.iter().enumerate() {
+            match token {
+                BfToken::LoopStart => {
                     loop_record.push(index);
                 }
-                BfToken::LoopEnd(_) => {
+                BfToken::LoopEnd => {
                     let match_start = loop_record
                         .pop()
                         .ok_or(BfParserError::LoopNotClosed(index))?;
-                    tokens[match_start] = BfToken::LoopStart(index);
-                    tokens[index] = BfToken::LoopEnd(match_start);
+                    jump_table[match_start] = index;
+                    jump_table[index] = match_start;
                 }
                 _ => {}
             }
         }
 
-        if !loop_record.is_empty() {
-            return Err(BfParserError::LoopNotClosed(loop_record.pop().unwrap()));
+        if let Some(start) = loop_record.pop() {
+            return Err(BfParserError::LoopNotClosed(start));
         }
 
-        Ok(())
+        Ok(jump_table)
+    }
+
+    /// A best-effort parse for tools that work on broken programs, e.g. to
+    /// highlight what is valid: every char becomes a token as in
+    /// [`BfParser::parse`], and every unmatched `]` and unclosed `[` is
+    /// reported, in order of index, instead of only the first.
+    /// [`BfParser::loop_matching_lossy`] pairs up what it can. The tokens are
+    /// unvalidated, so a program cannot be built from broken ones.
+    pub fn parse_lossy(code: &str) -> (Vec<BfToken>, Vec<BfParserError>) {
+        let tokens: Vec<_> = Self::lex(code, None).map(|(_, token)| token).collect();
+        let jump_table = Self::loop_matching_lossy(&tokens);
+        let errors = tokens
+            .iter()
+            .enumerate()
+            .filter(|&(index, token)| match token {
+                BfToken::LoopStart => jump_table[index] == tokens.len(),
+                BfToken::LoopEnd => jump_table[index] == index,
+                _ => false,
+            })
+            .map(|(index, _)| BfParserError::LoopNotClosed(index))
+            .collect();
+        (tokens, errors)
+    }
+
+    /// Like [`BfParser::loop_matching`], but never fails: an unmatched `]`
+    /// jumps to itself and an unclosed `[` is closed at the end of the tokens,
+    /// jumping to `tokens.len()`. [`BfParser::validate_jump_table`] rejects
+    /// both.
+    pub fn loop_matching_lossy(tokens: &[BfToken]) -> Vec<usize> {
+        let mut jump_table: Vec<usize> = (0..tokens.len()).collect();
+        let mut open = vec![];
+
+        for (index, token) in tokens.iter().enumerate() {
+            match token {
+                BfToken::LoopStart => open.push(index),
+                BfToken::LoopEnd => {
+                    if let Some(start) = open.pop() {
+                        jump_table[start] = index;
+                        jump_table[index] = start;
+                    }
+                }
+                _ => {}
+            }
+        }
+        for start in open {
+            jump_table[start] = tokens.len();
+        }
+
+        jump_table
+    }
+
+    /// Writes `tokens` back out as code, expanding counted tokens into runs
+    /// of their command and offset ones into a move there and back around
+    /// them. Extensions have no char of their own and are left out.
+    pub fn to_source(tokens: &[BfToken]) -> String {
+        let mut code = String::with_capacity(tokens.len());
+        let mut push = |ch: char, count: usize| code.extend(std::iter::repeat_n(ch, count));
+        // 256 `+` or `-` compress to 0.
+        let times = |count: u8| if count == 0 { 256 } else { count.into() };
+        for token in tokens {
+            match *token {
+                BfToken::NotCommand(ch) => push(ch, 1),
+                BfToken::Increment(count) => push('+', times(count)),
+                BfToken::Decrement(count) => push('-', times(count)),
+                BfToken::IncrementAt(offset, count) | BfToken::DecrementAt(offset, count) => {
+                    let command = match token {
+                        BfToken::IncrementAt(..) => '+',
+                        _ => '-',
+                    };
+                    let (there, back) = if offset < 0 { ('<', '>') } else { ('>', '<') };
+                    push(there, offset.unsigned_abs());
+                    push(command, times(count));
+                    push(back, offset.unsigned_abs());
+                }
+                BfToken::CursorLeft(count) => push('<', count),
+                BfToken::CursorRight(count) => push('>', count),
+                BfToken::LoopStart => push('[', 1),
+                BfToken::LoopEnd => push(']', 1),
+                BfToken::PrintChar => push('.', 1),
+                BfToken::PrintCharN(count) => push('.', count),
+                BfToken::InputChar => push(',', 1),
+                BfToken::SwitchTape => push(SWITCH_TAPE, 1),
+                BfToken::Fork => push(FORK, 1),
+                BfToken::Extension(_) => {}
+            }
+        }
+        code
+    }
+
+    /// Checks a token stream built or transformed outside the parser without
+    /// running it, returning every violation in order of index.
+    pub fn validate(tokens: &[BfToken]) -> Result<(), Vec<ValidationError>> {
+        let mut errors = vec![];
+        let mut open = vec![];
+
+        for (index, token) in tokens.iter().enumerate() {
+            match token {
+                BfToken::LoopStart => open.push(index),
+                BfToken::LoopEnd if open.pop().is_none() => {
+                    errors.push(ValidationError::UnmatchedLoopEnd(index));
+                }
+                BfToken::CursorLeft(0) | BfToken::CursorRight(0) | BfToken::PrintCharN(0) => {
+                    errors.push(ValidationError::ZeroOperand(index));
+                }
+                _ => {}
+            }
+        }
+        errors.extend(open.into_iter().map(ValidationError::UnclosedLoop));
+        errors.sort_by_key(ValidationError::index);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks a jump table given alongside `tokens`, e.g. one decoded from a
+    /// file: each bracket must point at its matching bracket. Entries of other
+    /// tokens are never read and not checked. Matches are only known for
+    /// tokens that pass [`BfParser::validate`]; otherwise a bracket pointing
+    /// at any opposite bracket passes.
+    pub fn validate_jump_table(
+        tokens: &[BfToken],
+        jump_table: &[usize],
+    ) -> Result<(), Vec<ValidationError>> {
+        if jump_table.len() != tokens.len() {
+            return Err(vec![ValidationError::TableLength {
+                tokens: tokens.len(),
+                len: jump_table.len(),
+            }]);
+        }
+
+        let matching = Self::loop_matching(tokens).ok();
+        let mut errors = vec![];
+        for (index, token) in tokens.iter().enumerate() {
+            let opposite = match token {
+                BfToken::LoopStart => BfToken::LoopEnd,
+                BfToken::LoopEnd => BfToken::LoopStart,
+                _ => continue,
+            };
+            let target = jump_table[index];
+            match tokens.get(target) {
+                None => errors.push(ValidationError::JumpOutOfRange { index, target }),
+                Some(token) if *token != opposite => {
+                    errors.push(ValidationError::JumpToNonBracket { index, target });
+                }
+                _ => {
+                    if let Some(expected) = matching.as_ref().map(|table| table[index]) {
+                        if target != expected {
+                            errors.push(ValidationError::MismatchedJump {
+                                index,
+                                target,
+                                expected,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// A compile-time subset of the parser for programs embedded in Rust code.
+/// It keeps the eight commands and drops everything else, and an unbalanced
+/// bracket panics, which in a `const` item is a compile error:
+///
+/// ```
+/// use bf_rust::bf::{bf_parser::BfParser, bf_token::BfToken};
+///
+/// const CODE: &str = "add the cells: [->+<]";
+/// const ADD: [BfToken; BfParser::count_commands(CODE)] = BfParser::parse_const(CODE);
+/// assert_eq!(ADD.len(), 6);
+/// ```
+///
+/// ```compile_fail
+/// use bf_rust::bf::{bf_parser::BfParser, bf_token::BfToken};
+///
+/// const BROKEN: [BfToken; 3] = BfParser::parse_const("+[[");
+/// ```
+impl BfParser {
+    /// The number of tokens [`BfParser::parse_const`] makes from `code`.
+    pub const fn count_commands(code: &str) -> usize {
+        let bytes = code.as_bytes();
+        let mut count = 0;
+        let mut index = 0;
+        while index < bytes.len() {
+            if Self::const_token(bytes[index]).is_some() {
+                count += 1;
+            }
+            index += 1;
+        }
+        count
+    }
+
+    /// Parses `code` into its commands, one token each. `N` must be
+    /// [`BfParser::count_commands`] of `code`.
+    pub const fn parse_const<const N: usize>(code: &str) -> [BfToken; N] {
+        let bytes = code.as_bytes();
+        let mut tokens = [BfToken::NotCommand(' '); N];
+        let mut count = 0;
+        let mut depth = 0usize;
+        let mut index = 0;
+        while index < bytes.len() {
+            if let Some(token) = Self::const_token(bytes[index]) {
+                match token {
+                    BfToken::LoopStart => depth += 1,
+                    BfToken::LoopEnd if depth == 0 => panic!("unmatched ']' in Brainfuck code"),
+                    BfToken::LoopEnd => depth -= 1,
+                    _ => {}
+                }
+                if count == N {
+                    panic!("the array is shorter than the Brainfuck code");
+                }
+                tokens[count] = token;
+                count += 1;
+            }
+            index += 1;
+        }
+
+        if depth > 0 {
+            panic!("unclosed '[' in Brainfuck code");
+        }
+        if count < N {
+            panic!("the array is longer than the Brainfuck code");
+        }
+        tokens
+    }
+
+    /// The token of a command byte. Multi-byte chars never contain ASCII
+    /// bytes, so scanning bytes finds the same commands as scanning chars.
+    const fn const_token(byte: u8) -> Option<BfToken> {
+        match byte {
+            b'+' => Some(BfToken::Increment(1)),
+            b'-' => Some(BfToken::Decrement(1)),
+            b'<' => Some(BfToken::CursorLeft(1)),
+            b'>' => Some(BfToken::CursorRight(1)),
+            b'[' => Some(BfToken::LoopStart),
+            b']' => Some(BfToken::LoopEnd),
+            b'.' => Some(BfToken::PrintChar),
+            b',' => Some(BfToken::InputChar),
+            _ => None,
+        }
+    }
+}
+
+/// Matches brackets as tokens stream past, remembering the first error.
+struct Brackets {
+    open: Vec<usize>,
+    max_depth: usize,
+    unmatched: Option<usize>,
+    too_deep: Option<BfParserError>,
+}
+
+impl Brackets {
+    fn new(max_depth: usize) -> Self {
+        Self {
+            open: vec![],
+            max_depth,
+            unmatched: None,
+            too_deep: None,
+        }
+    }
+
+    fn open(&mut self, index: usize) {
+        if self.open.len() == self.max_depth {
+            self.too_deep.get_or_insert(BfParserError::NestingTooDeep {
+                index,
+                depth: self.max_depth + 1,
+            });
+        }
+        self.open.push(index);
+    }
+
+    /// The index of the `[` this `]` closes, if any.
+    fn close(&mut self, index: usize) -> Option<usize> {
+        let start = self.open.pop();
+        if start.is_none() {
+            self.unmatched.get_or_insert(index);
+        }
+        start
+    }
+
+    fn finish(mut self) -> Result<(), BfParserError> {
+        if let Some(err) = self.too_deep {
+            return Err(err);
+        }
+        match self.unmatched.or(self.open.pop()) {
+            Some(index) => Err(BfParserError::LoopNotClosed(index)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ParseOptions {
+    /// Blanks out everything the options treat as a comment, keeping every
+    /// other character (and so every char index) where it was. A `#!` line
+    /// at the very start, and the `#! bf:` directive lines after it, are
+    /// always comments, so programs can be scripts.
+    pub fn strip_comments<'a>(&self, code: &'a str) -> Cow<'a, str> {
+        let header = header_len(code);
+        if header == 0 && (!self.line_comments || !code.contains(';')) {
+            return Cow::Borrowed(code);
+        }
+
+        let mut in_comment = false;
+        let stripped = code
+            .char_indices()
+            .map(|(offset, ch)| {
+                match ch {
+                    ';' if self.line_comments => in_comment = true,
+                    '\n' => in_comment = false,
+                    _ => {}
+                }
+                if in_comment || (offset < header && ch != '\n') {
+                    ' '
+                } else {
+                    ch
+                }
+            })
+            .collect();
+        Cow::Owned(stripped)
+    }
+}
+
+/// The length in bytes of the header opening `code`, without its last
+/// newline: a `#!` first line and the `#! bf:` directive lines right after
+/// it, or 0 when there are none.
+pub(crate) fn header_len(code: &str) -> usize {
+    let mut len = 0;
+    for (index, line) in code.split_inclusive('\n').enumerate() {
+        let shebang = index == 0 && line.starts_with("#!");
+        if !shebang && directives::directive(line).is_none() {
+            break;
+        }
+        len += line.len();
+    }
+    code[..len].strip_suffix('\n').map_or(len, str::len)
+}
+
+impl ParseOptions {
+    fn check_extensions(&self) -> Result<(), BfParserError> {
+        match self
+            .extensions
+            .keys()
+            .filter(|&&ch| {
+                CORE_COMMANDS.contains(&ch)
+                    || self.multi_tape && ch == SWITCH_TAPE
+                    || self.brainfork && ch == FORK
+            })
+            .min()
+        {
+            Some(&ch) => Err(BfParserError::ReservedExtension(ch)),
+            None => Ok(()),
+        }
+    }
+
+    /// Splits `code` at the first `!` outside a comment when `bang_input` is
+    /// set, returning the program text and the embedded input after it.
+    pub fn split_input<'a>(&self, code: &'a str) -> (&'a str, Option<&'a str>) {
+        if !self.bang_input {
+            return (code, None);
+        }
+
+        let bang = self.strip_comments(code).chars().position(|ch| ch == '!');
+        match bang.and_then(|index| code.char_indices().nth(index)) {
+            Some((offset, _)) => (&code[..offset], Some(&code[offset + 1..])),
+            None => (code, None),
+        }
+    }
+}
+
+impl BfParserError {
+    pub fn index(&self) -> usize {
+        match self {
+            Self::LoopNotClosed(index) | Self::NestingTooDeep { index, .. } => *index,
+            Self::ReservedExtension(_) => 0,
+        }
+    }
+
+    pub fn map_index(self, f: impl FnOnce(usize) -> usize) -> Self {
+        match self {
+            Self::LoopNotClosed(index) => Self::LoopNotClosed(f(index)),
+            Self::ReservedExtension(ch) => Self::ReservedExtension(ch),
+            Self::NestingTooDeep { index, depth } => Self::NestingTooDeep {
+                index: f(index),
+                depth,
+            },
+        }
     }
 }
 
@@ -121,6 +850,12 @@ impl Display for BfParserError {
             Self::LoopNotClosed(index) => {
                 format!("The error occurred at index {index} due to an unclosed loop.")
             }
+            Self::ReservedExtension(ch) => {
+                format!("'{ch}' is a command and cannot be registered as an extension.")
+            }
+            Self::NestingTooDeep { index, depth } => {
+                format!("The error occurred at index {index} due to loops nested {depth} deep.")
+            }
         };
         write!(f, "{message}")
     }
@@ -128,8 +863,61 @@ impl Display for BfParserError {
 
 impl Error for BfParserError {}
 
+impl ValidationError {
+    /// The token the violation is at; 0 for a table of the wrong length.
+    pub fn index(&self) -> usize {
+        match self {
+            Self::UnmatchedLoopEnd(index)
+            | Self::UnclosedLoop(index)
+            | Self::ZeroOperand(index)
+            | Self::JumpOutOfRange { index, .. }
+            | Self::JumpToNonBracket { index, .. }
+            | Self::MismatchedJump { index, .. }
+            | Self::SpanOutOfOrder(index) => *index,
+            Self::TableLength { .. } => 0,
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnmatchedLoopEnd(index) => write!(f, "token {index}: loop end without a start"),
+            Self::UnclosedLoop(index) => write!(f, "token {index}: loop start without an end"),
+            Self::ZeroOperand(index) => write!(f, "token {index}: operand is zero"),
+            Self::TableLength { tokens, len } => {
+                write!(f, "table has {len} entries for {tokens} tokens")
+            }
+            Self::JumpOutOfRange { index, target } => {
+                write!(f, "token {index}: jump target {target} is out of range")
+            }
+            Self::JumpToNonBracket { index, target } => {
+                write!(
+                    f,
+                    "token {index}: jump target {target} is not the opposite bracket"
+                )
+            }
+            Self::MismatchedJump {
+                index,
+                target,
+                expected,
+            } => write!(
+                f,
+                "token {index}: jumps to {target} instead of its match at {expected}"
+            ),
+            Self::SpanOutOfOrder(index) => {
+                write!(f, "token {index}: span comes before the previous one")
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
 #[cfg(test)]
 mod tests {
+    use crate::bf::program::Program;
+
     use super::*;
 
     #[test]
@@ -142,14 +930,92 @@ mod tests {
                 BfToken::NotCommand('a'),
                 BfToken::Increment(1),
                 BfToken::Decrement(1),
-                BfToken::LoopStart(4),
-                BfToken::LoopEnd(3),
+                BfToken::LoopStart,
+                BfToken::LoopEnd,
                 BfToken::InputChar,
                 BfToken::PrintChar,
                 BfToken::CursorLeft(1),
                 BfToken::CursorRight(1),
             ]
         );
+        assert_eq!(
+            BfParser::loop_matching(&tokens).unwrap(),
+            vec![0, 1, 2, 4, 3, 5, 6, 7, 8]
+        );
+    }
+
+    #[test]
+    fn nesting_depth_limit() {
+        let nested = |depth: usize| format!("+{}-{}", "[".repeat(depth), "]".repeat(depth));
+        let options = ParseOptions {
+            max_nesting_depth: 3,
+            ..Default::default()
+        };
+        assert!(BfParser::parse_with_options(&nested(3), &options).is_ok());
+        assert_eq!(
+            BfParser::parse_with_options(&nested(5), &options),
+            Err(BfParserError::NestingTooDeep { index: 4, depth: 4 })
+        );
+        assert_eq!(
+            BfParser::parse_compress_with_options(&nested(4), &options),
+            Err(BfParserError::NestingTooDeep { index: 4, depth: 4 })
+        );
+
+        assert!(BfParser::parse(&nested(DEFAULT_MAX_NESTING_DEPTH)).is_ok());
+        assert_eq!(
+            BfParser::parse(&nested(100_000)),
+            Err(BfParserError::NestingTooDeep {
+                index: DEFAULT_MAX_NESTING_DEPTH + 1,
+                depth: DEFAULT_MAX_NESTING_DEPTH + 1
+            })
+        );
+    }
+
+    #[test]
+    fn lazy_tokens() {
+        let code = "+é[ ☃-]💾.";
+        let tokens: Vec<_> = BfParser::tokens(code).collect();
+        assert_eq!(
+            tokens,
+            [
+                (0, BfToken::Increment(1)),
+                (1, BfToken::NotCommand('é')),
+                (3, BfToken::LoopStart),
+                (4, BfToken::NotCommand(' ')),
+                (5, BfToken::NotCommand('☃')),
+                (8, BfToken::Decrement(1)),
+                (9, BfToken::LoopEnd),
+                (10, BfToken::NotCommand('💾')),
+                (14, BfToken::PrintChar),
+            ]
+        );
+        for ((offset, _), (expected, _)) in tokens.iter().zip(code.char_indices()) {
+            assert_eq!(*offset, expected);
+        }
+
+        let unbalanced: Vec<_> = BfParser::tokens("]]").map(|(_, token)| token).collect();
+        assert_eq!(unbalanced, [BfToken::LoopEnd, BfToken::LoopEnd]);
+    }
+
+    #[test]
+    fn const_hello_world() {
+        const HELLO_WORLD: &str = include_str!("../../tests/programs/hello_world.b");
+        const PROGRAM: [BfToken; BfParser::count_commands(HELLO_WORLD)] =
+            BfParser::parse_const(HELLO_WORLD);
+
+        let parsed: Vec<_> = BfParser::parse(HELLO_WORLD)
+            .unwrap()
+            .into_iter()
+            .filter(|token| !matches!(token, BfToken::NotCommand(_)))
+            .collect();
+        assert_eq!(PROGRAM.as_slice(), parsed);
+
+        let program = crate::bf::program::Program::from_tokens(PROGRAM.to_vec()).unwrap();
+        let mut output = vec![];
+        crate::bf::bf_machine::BfMachine::new(100, std::io::empty(), &mut output)
+            .run(&program)
+            .unwrap();
+        assert_eq!(output, b"Hello World!\n");
     }
 
     #[test]
@@ -162,7 +1028,7 @@ mod tests {
             &tokens,
             &[
                 BfToken::Increment(10),
-                BfToken::LoopStart(12),
+                BfToken::LoopStart,
                 BfToken::CursorRight(1),
                 BfToken::Increment(7),
                 BfToken::CursorRight(1),
@@ -173,7 +1039,7 @@ mod tests {
                 BfToken::Increment(1),
                 BfToken::CursorLeft(4),
                 BfToken::Decrement(1),
-                BfToken::LoopEnd(1),
+                BfToken::LoopEnd,
                 BfToken::CursorRight(1),
                 BfToken::Increment(2),
                 BfToken::PrintChar,
@@ -181,8 +1047,7 @@ mod tests {
                 BfToken::Increment(1),
                 BfToken::PrintChar,
                 BfToken::Increment(7),
-                BfToken::PrintChar,
-                BfToken::PrintChar,
+                BfToken::PrintCharN(2),
                 BfToken::Increment(3),
                 BfToken::PrintChar,
                 BfToken::CursorRight(1),
@@ -208,6 +1073,113 @@ mod tests {
         );
     }
 
+    #[test]
+    fn compress_repeated_output() {
+        let (tokens, spans) = BfParser::parse_compress_with_spans("+..... .>.").unwrap();
+        assert_eq!(
+            &tokens,
+            &[
+                BfToken::Increment(1),
+                BfToken::PrintCharN(6),
+                BfToken::CursorRight(1),
+                BfToken::PrintChar,
+            ]
+        );
+        assert_eq!(spans, vec![0, 1, 8, 9]);
+
+        let tokens = BfParser::parse_compress(".+.").unwrap();
+        assert_eq!(
+            &tokens,
+            &[
+                BfToken::PrintChar,
+                BfToken::Increment(1),
+                BfToken::PrintChar
+            ]
+        );
+    }
+
+    #[test]
+    fn line_comments() {
+        let options = ParseOptions {
+            line_comments: true,
+            ..Default::default()
+        };
+
+        let tokens =
+            BfParser::parse_with_options("+; this , and . are ignored\n+", &options).unwrap();
+        let commands: Vec<_> = tokens
+            .iter()
+            .filter(|token| !matches!(token, BfToken::NotCommand(_)))
+            .collect();
+        assert_eq!(
+            commands,
+            vec![&BfToken::Increment(1), &BfToken::Increment(1)]
+        );
+        assert_eq!(tokens[3], BfToken::NotCommand('t'));
+        assert_eq!(tokens[28], BfToken::Increment(1));
+
+        assert!(BfParser::parse_with_options("+ ; [ opens nothing\n.", &options).is_ok());
+        assert_eq!(
+            BfParser::parse_with_options("; fine\n[", &options),
+            Err(BfParserError::LoopNotClosed(7))
+        );
+        assert!(BfParser::parse_with_options("+ ; [", &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn shebang_line() {
+        const SCRIPT: &str = "#!/usr/bin/env bf-rust --max-steps 100 <in.txt\n+.";
+
+        let commands = |tokens: Vec<BfToken>| {
+            tokens
+                .into_iter()
+                .filter(|token| !matches!(token, BfToken::NotCommand(_)))
+                .collect::<Vec<_>>()
+        };
+        let expected = [BfToken::Increment(1), BfToken::PrintChar];
+        assert_eq!(commands(BfParser::parse(SCRIPT).unwrap()), expected);
+        assert_eq!(
+            commands(BfParser::parse_with_options(SCRIPT, &ParseOptions::default()).unwrap()),
+            expected
+        );
+        assert_eq!(
+            BfParser::parse_compress(&ParseOptions::default().strip_comments(SCRIPT)).unwrap(),
+            expected
+        );
+        assert_eq!(
+            BfParser::parse("#!/bin/[\n]"),
+            Err(BfParserError::LoopNotClosed(9))
+        );
+        assert!(BfParser::parse("\n#!+")
+            .unwrap()
+            .contains(&BfToken::Increment(1)));
+    }
+
+    #[test]
+    fn bang_input() {
+        let options = ParseOptions {
+            bang_input: true,
+            ..Default::default()
+        };
+
+        assert_eq!(options.split_input(",[.,]!abc"), (",[.,]", Some("abc")));
+        assert_eq!(options.split_input(",[.,]!a!b"), (",[.,]", Some("a!b")));
+        assert_eq!(options.split_input(",[.,]"), (",[.,]", None));
+        assert_eq!(
+            ParseOptions::default().split_input(",[.,]!abc"),
+            (",[.,]!abc", None)
+        );
+
+        let options = ParseOptions {
+            line_comments: true,
+            ..options
+        };
+        assert_eq!(
+            options.split_input("; wow!\n,.!x"),
+            ("; wow!\n,.", Some("x"))
+        );
+    }
+
     #[test]
     fn unclosed_loop() {
         let tokens = BfParser::parse("[]]").unwrap_err();
@@ -217,4 +1189,372 @@ mod tests {
         let tokens = BfParser::parse("[[[]").unwrap_err();
         assert_eq!(tokens, BfParserError::LoopNotClosed(1));
     }
+
+    #[test]
+    fn compressed_spans() {
+        let (tokens, spans) = BfParser::parse_compress_with_spans("a++-\n[>>.<]").unwrap();
+
+        assert_eq!(
+            &tokens,
+            &[
+                BfToken::Increment(1),
+                BfToken::LoopStart,
+                BfToken::CursorRight(2),
+                BfToken::PrintChar,
+                BfToken::CursorLeft(1),
+                BfToken::LoopEnd,
+            ]
+        );
+        assert_eq!(spans, vec![1, 5, 6, 8, 9, 10]);
+        assert_eq!(BfParser::loop_matching(&tokens).unwrap()[1], 5);
+
+        let (_, spans) = BfParser::parse_compress_with_spans("é+ ☃ [-]").unwrap();
+        assert_eq!(spans, vec![1, 5, 6, 7]);
+        assert_eq!(
+            BfParser::parse_compress("☃+]"),
+            Err(BfParserError::LoopNotClosed(2))
+        );
+    }
+
+    #[test]
+    fn compress_drops_comments() {
+        let tokens = BfParser::parse_compress("++ comment ++").unwrap();
+        assert_eq!(&tokens, &[BfToken::Increment(4)]);
+
+        let tokens = BfParser::parse_compress("[ loop ] > shift < >").unwrap();
+        assert_eq!(
+            &tokens,
+            &[
+                BfToken::LoopStart,
+                BfToken::LoopEnd,
+                BfToken::CursorRight(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn redundant_operation_warnings() {
+        let (tokens, warnings) = BfParser::parse_with_warnings("a+-b");
+
+        assert_eq!(tokens.unwrap(), BfParser::parse("a+-b").unwrap());
+        assert_eq!(
+            warnings,
+            vec![ParseWarning {
+                span: 1..3,
+                code: REDUNDANT_OPERATIONS,
+                message: "adjacent operations cancel each other out".to_string(),
+            }]
+        );
+
+        let (_, warnings) = BfParser::parse_with_warnings("+-+- > <");
+        let spans: Vec<_> = warnings.into_iter().map(|warning| warning.span).collect();
+        assert_eq!(spans, vec![0..2, 2..4, 5..8]);
+    }
+
+    #[test]
+    fn clean_program_has_no_warnings() {
+        let code = "++++++++++[>+++++++>++++++++++>+++>+<<<<-]>++.>+.+++++++..+++.>++.";
+        let (tokens, warnings) = BfParser::parse_with_warnings(code);
+
+        assert_eq!(tokens.unwrap(), BfParser::parse(code).unwrap());
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn deep_nesting_warning() {
+        let code = format!("+{}{}", "[".repeat(70), "]".repeat(70));
+        let (tokens, warnings) = BfParser::parse_with_warnings(&code);
+
+        assert!(tokens.is_ok());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, DEEP_NESTING);
+        assert_eq!(warnings[0].span, 65..66);
+    }
+
+    #[test]
+    fn lossy_parse_keeps_broken_programs() {
+        let (tokens, errors) = BfParser::parse_lossy("[+.");
+        assert_eq!(
+            tokens,
+            [
+                BfToken::LoopStart,
+                BfToken::Increment(1),
+                BfToken::PrintChar
+            ]
+        );
+        assert_eq!(errors, [BfParserError::LoopNotClosed(0)]);
+        assert_eq!(BfParser::to_source(&tokens), "[+.");
+        assert_eq!(BfParser::loop_matching_lossy(&tokens), [3, 1, 2]);
+
+        let (tokens, errors) = BfParser::parse_lossy("][x[-]][");
+        assert_eq!(
+            errors,
+            [
+                BfParserError::LoopNotClosed(0),
+                BfParserError::LoopNotClosed(7)
+            ]
+        );
+        assert_eq!(BfParser::to_source(&tokens), "][x[-]][");
+        assert_eq!(
+            BfParser::loop_matching_lossy(&tokens),
+            [0, 6, 2, 5, 4, 3, 1, 8]
+        );
+
+        let (tokens, errors) = BfParser::parse_lossy("+[-]");
+        assert!(errors.is_empty());
+        assert_eq!(
+            BfParser::loop_matching_lossy(&tokens),
+            BfParser::loop_matching(&tokens).unwrap()
+        );
+    }
+
+    #[test]
+    fn lossy_tokens_do_not_run() {
+        for code in ["[+.", "+]"] {
+            let (tokens, _) = BfParser::parse_lossy(code);
+            let jump_table = BfParser::loop_matching_lossy(&tokens);
+            assert!(BfParser::validate_jump_table(&tokens, &jump_table).is_err());
+            let spans = (0..tokens.len()).collect();
+            assert!(Program::from_parts(tokens.clone(), jump_table, spans).is_err());
+            assert!(Program::from_tokens(tokens).is_err());
+        }
+    }
+
+    #[test]
+    fn compressed_tokens_to_source() {
+        let tokens = BfParser::parse_compress("+++>>[-]..").unwrap();
+        assert_eq!(BfParser::to_source(&tokens), "+++>>[-]..");
+        assert_eq!(
+            BfParser::to_source(&[BfToken::Increment(0), BfToken::Extension(1)]).len(),
+            256
+        );
+    }
+
+    #[test]
+    fn warnings_survive_parse_errors() {
+        let (tokens, warnings) = BfParser::parse_with_warnings("[<>");
+
+        assert_eq!(tokens, Err(BfParserError::LoopNotClosed(0)));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn extensions() {
+        let mut options = ParseOptions::default();
+        options.extensions.insert('?', 0);
+        options.extensions.insert('#', 1);
+
+        assert_eq!(
+            BfParser::parse_with_options("+?x#!", &options).unwrap(),
+            vec![
+                BfToken::Increment(1),
+                BfToken::Extension(0),
+                BfToken::NotCommand('x'),
+                BfToken::Extension(1),
+                BfToken::NotCommand('!'),
+            ]
+        );
+        assert_eq!(
+            BfParser::parse_compress_with_options("+?? ++#", &options).unwrap(),
+            (
+                vec![
+                    BfToken::Increment(1),
+                    BfToken::Extension(0),
+                    BfToken::Extension(0),
+                    BfToken::Increment(2),
+                    BfToken::Extension(1),
+                ],
+                vec![0, 1, 2, 4, 6]
+            )
+        );
+        assert_eq!(BfParser::parse("+?#").unwrap()[1], BfToken::NotCommand('?'));
+
+        options.extensions.insert('+', 2);
+        assert_eq!(
+            BfParser::parse_with_options("+?", &options),
+            Err(BfParserError::ReservedExtension('+'))
+        );
+    }
+
+    #[test]
+    fn multi_tape() {
+        let mut options = ParseOptions {
+            multi_tape: true,
+            ..Default::default()
+        };
+        let tokens = BfParser::parse_with_options("+^>^", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                BfToken::Increment(1),
+                BfToken::SwitchTape,
+                BfToken::CursorRight(1),
+                BfToken::SwitchTape,
+            ]
+        );
+        assert_eq!(BfParser::to_source(&tokens), "+^>^");
+        assert_eq!(BfParser::parse("+^").unwrap()[1], BfToken::NotCommand('^'));
+
+        options.extensions.insert('^', 0);
+        assert_eq!(
+            BfParser::parse_with_options("+^", &options),
+            Err(BfParserError::ReservedExtension('^'))
+        );
+    }
+
+    #[test]
+    fn brainfork() {
+        let mut options = ParseOptions {
+            brainfork: true,
+            ..Default::default()
+        };
+        let tokens = BfParser::parse_with_options("+Y.", &options).unwrap();
+        assert_eq!(
+            tokens,
+            vec![BfToken::Increment(1), BfToken::Fork, BfToken::PrintChar]
+        );
+        assert_eq!(BfParser::to_source(&tokens), "+Y.");
+        assert_eq!(BfParser::parse("+Y").unwrap()[1], BfToken::NotCommand('Y'));
+
+        options.extensions.insert('Y', 0);
+        assert_eq!(
+            BfParser::parse_with_options("+Y", &options),
+            Err(BfParserError::ReservedExtension('Y'))
+        );
+    }
+
+    /// The char-by-char tokenizer `scan` replaced, kept to check it against.
+    fn reference_parse(code: &str) -> Result<(Vec<BfToken>, Vec<usize>), BfParserError> {
+        let header = header_len(code);
+        let tokens: Vec<_> = code
+            .char_indices()
+            .map(|(offset, ch)| match ch {
+                _ if offset < header => BfToken::NotCommand(ch),
+                '+' => BfToken::Increment(1),
+                '-' => BfToken::Decrement(1),
+                '<' => BfToken::CursorLeft(1),
+                '>' => BfToken::CursorRight(1),
+                '[' => BfToken::LoopStart,
+                ']' => BfToken::LoopEnd,
+                ',' => BfToken::InputChar,
+                '.' => BfToken::PrintChar,
+                _ => BfToken::NotCommand(ch),
+            })
+            .collect();
+        let jump_table = BfParser::loop_matching(&tokens)?;
+        Ok((tokens, jump_table))
+    }
+
+    #[test]
+    fn byte_scan_matches_char_parse() {
+        let mut sources = vec![
+            "é+[ü->.<]ß".to_string(),
+            "🦀[[-]]".to_string(),
+            "+]]".to_string(),
+            "[[+]".to_string(),
+            "][".to_string(),
+            String::new(),
+        ];
+        for dir in ["tests/programs", "tests/fixtures"] {
+            for entry in std::fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                if path.extension().is_some_and(|ext| ext == "b") {
+                    sources.push(std::fs::read_to_string(path).unwrap());
+                }
+            }
+        }
+
+        for code in &sources {
+            let expected = reference_parse(code);
+            assert_eq!(BfParser::parse_with_jump_table(code), expected, "{code}");
+            assert_eq!(
+                BfParser::parse(code),
+                expected.map(|(tokens, _)| tokens),
+                "{code}"
+            );
+        }
+    }
+
+    #[test]
+    fn non_ascii_is_one_token_per_char() {
+        let tokens = BfParser::parse("é+🦀").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                BfToken::NotCommand('é'),
+                BfToken::Increment(1),
+                BfToken::NotCommand('🦀'),
+            ]
+        );
+
+        let mut options = ParseOptions::default();
+        options.extensions.insert('é', 3);
+        assert_eq!(
+            BfParser::parse_with_options("é+🦀", &options).unwrap()[0],
+            BfToken::Extension(3)
+        );
+    }
+
+    #[test]
+    fn validate_reports_every_violation() {
+        let tokens = [
+            BfToken::LoopEnd,
+            BfToken::LoopStart,
+            BfToken::CursorLeft(0),
+            BfToken::Increment(0),
+            BfToken::PrintCharN(0),
+        ];
+        assert_eq!(
+            BfParser::validate(&tokens),
+            Err(vec![
+                ValidationError::UnmatchedLoopEnd(0),
+                ValidationError::UnclosedLoop(1),
+                ValidationError::ZeroOperand(2),
+                ValidationError::ZeroOperand(4),
+            ])
+        );
+        assert_eq!(
+            BfParser::validate(&BfParser::parse_compress("+[>.]").unwrap()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn validate_corrupted_jump_tables() {
+        let tokens = BfParser::parse("[[.]]").unwrap();
+        let valid = BfParser::loop_matching(&tokens).unwrap();
+        assert_eq!(BfParser::validate_jump_table(&tokens, &valid), Ok(()));
+
+        let check = |index: usize, target: usize| {
+            let mut table = valid.clone();
+            table[index] = target;
+            BfParser::validate_jump_table(&tokens, &table).unwrap_err()
+        };
+        assert_eq!(
+            check(0, 5),
+            [ValidationError::JumpOutOfRange {
+                index: 0,
+                target: 5
+            }]
+        );
+        assert_eq!(
+            check(3, 2),
+            [ValidationError::JumpToNonBracket {
+                index: 3,
+                target: 2
+            }]
+        );
+        assert_eq!(
+            check(4, 1),
+            [ValidationError::MismatchedJump {
+                index: 4,
+                target: 1,
+                expected: 0
+            }]
+        );
+        assert_eq!(
+            BfParser::validate_jump_table(&tokens, &[0, 1]),
+            Err(vec![ValidationError::TableLength { tokens: 5, len: 2 }])
+        );
+    }
 }
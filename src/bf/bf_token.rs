@@ -1,12 +1,30 @@
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BfToken {
     NotCommand(char),
     Increment(u8),
     Decrement(u8),
     CursorLeft(usize),
     CursorRight(usize),
-    LoopStart(usize),
-    LoopEnd(usize),
+    /// `Increment(count)` of the cell `offset` cells right of the cursor, or
+    /// left of it if negative, which leaves the cursor where it is. Only the
+    /// optimizer makes these, see [`sink_moves`](super::sink::sink_moves).
+    IncrementAt(isize, u8),
+    /// `Decrement(count)` of the cell `offset` cells away, like
+    /// [`BfToken::IncrementAt`].
+    DecrementAt(isize, u8),
+    LoopStart,
+    LoopEnd,
     PrintChar,
+    PrintCharN(usize),
     InputChar,
+    /// `^` with `ParseOptions::multi_tape`: makes the other of the machine's
+    /// two tapes the one the other commands act on.
+    SwitchTape,
+    /// `Y` with `ParseOptions::brainfork`: splits the running thread in two,
+    /// as Brainfork does.
+    Fork,
+    /// A command outside the eight core ones, run by the machine's
+    /// `InstructionHandler`.
+    Extension(u8),
 }
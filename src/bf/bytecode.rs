@@ -0,0 +1,272 @@
+use std::{error::Error, fmt::Display};
+
+use super::{
+    bf_parser::{BfParser, ValidationError},
+    bf_token::BfToken,
+    program::Program,
+};
+
+/// The first bytes of every `.bfc` file.
+pub const MAGIC: &[u8; 4] = b"BFC\0";
+
+/// Bumped whenever the encoding changes; files of another version are
+/// refused rather than misread.
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Why bytes could not be decoded into a program.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BytecodeError {
+    NotBytecode,
+    Version(u8),
+    /// The data ends in the middle of something.
+    Truncated,
+    /// The checksum does not match the data.
+    Corrupted,
+    UnknownTag(u8),
+    /// The tokens or spans decode, but do not make a valid program.
+    Invalid(Vec<ValidationError>),
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotBytecode => write!(f, "not a bytecode file"),
+            Self::Version(version) => write!(
+                f,
+                "bytecode version {version} is not the supported version {FORMAT_VERSION}"
+            ),
+            Self::Truncated => write!(f, "bytecode ends unexpectedly"),
+            Self::Corrupted => write!(f, "bytecode checksum does not match"),
+            Self::UnknownTag(tag) => write!(f, "unknown token tag {tag}"),
+            Self::Invalid(errors) => {
+                write!(f, "bytecode does not make a valid program: ")?;
+                let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "{}", errors.join(", "))
+            }
+        }
+    }
+}
+
+impl Error for BytecodeError {}
+
+/// Encodes `program` as bytecode: the magic and version, the token count,
+/// each token as a tag byte and its LEB128 operands, offsets zigzag-encoded
+/// first, the spans as LEB128 gaps, and an FNV-1a checksum of all of it. The
+/// jump table is rebuilt on decoding.
+pub fn encode(program: &Program) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(FORMAT_VERSION);
+    write_varint(&mut bytes, program.tokens().len() as u64);
+
+    for token in program.tokens() {
+        let (tag, operand) = match *token {
+            BfToken::NotCommand(ch) => (0, Some(u64::from(ch))),
+            BfToken::Increment(val) => (1, Some(val.into())),
+            BfToken::Decrement(val) => (2, Some(val.into())),
+            BfToken::CursorLeft(val) => (3, Some(val as u64)),
+            BfToken::CursorRight(val) => (4, Some(val as u64)),
+            BfToken::LoopStart => (5, None),
+            BfToken::LoopEnd => (6, None),
+            BfToken::PrintChar => (7, None),
+            BfToken::PrintCharN(count) => (8, Some(count as u64)),
+            BfToken::InputChar => (9, None),
+            BfToken::Extension(id) => (10, Some(id.into())),
+            BfToken::IncrementAt(offset, _) => (11, Some(zigzag(offset))),
+            BfToken::DecrementAt(offset, _) => (12, Some(zigzag(offset))),
+            BfToken::SwitchTape => (13, None),
+            BfToken::Fork => (14, None),
+        };
+        bytes.push(tag);
+        if let Some(operand) = operand {
+            write_varint(&mut bytes, operand);
+        }
+        if let BfToken::IncrementAt(_, val) | BfToken::DecrementAt(_, val) = *token {
+            write_varint(&mut bytes, val.into());
+        }
+    }
+
+    let mut previous = 0;
+    for pc in 0..program.tokens().len() {
+        let span = program.span(pc).expect("every token has a span");
+        write_varint(&mut bytes, (span - previous) as u64);
+        previous = span;
+    }
+
+    let checksum = fnv1a(&bytes);
+    bytes.extend(checksum.to_le_bytes());
+    bytes
+}
+
+/// Decodes what [`encode`] wrote, checking everything the machine relies on.
+pub fn decode(bytes: &[u8]) -> Result<Program, BytecodeError> {
+    let body = bytes
+        .strip_prefix(MAGIC)
+        .ok_or(BytecodeError::NotBytecode)?;
+    let (&version, _) = body.split_first().ok_or(BytecodeError::Truncated)?;
+    if version != FORMAT_VERSION {
+        return Err(BytecodeError::Version(version));
+    }
+    let (data, checksum) = bytes
+        .split_at_checked(bytes.len().saturating_sub(8))
+        .filter(|(data, _)| data.len() > MAGIC.len())
+        .ok_or(BytecodeError::Truncated)?;
+    if fnv1a(data).to_le_bytes() != checksum {
+        return Err(BytecodeError::Corrupted);
+    }
+
+    let mut reader = Reader {
+        bytes: data,
+        offset: MAGIC.len() + 1,
+    };
+    let len = reader.varint()?;
+    let mut tokens = vec![];
+    for _ in 0..len {
+        let tag = reader.byte()?;
+        let token = match tag {
+            0 => BfToken::NotCommand(
+                u32::try_from(reader.varint()?)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .ok_or(BytecodeError::Corrupted)?,
+            ),
+            1 => BfToken::Increment(reader.operand()?),
+            2 => BfToken::Decrement(reader.operand()?),
+            3 => BfToken::CursorLeft(reader.operand()?),
+            4 => BfToken::CursorRight(reader.operand()?),
+            5 => BfToken::LoopStart,
+            6 => BfToken::LoopEnd,
+            7 => BfToken::PrintChar,
+            8 => BfToken::PrintCharN(reader.operand()?),
+            9 => BfToken::InputChar,
+            10 => BfToken::Extension(reader.operand()?),
+            11 => BfToken::IncrementAt(reader.signed()?, reader.operand()?),
+            12 => BfToken::DecrementAt(reader.signed()?, reader.operand()?),
+            13 => BfToken::SwitchTape,
+            14 => BfToken::Fork,
+            tag => return Err(BytecodeError::UnknownTag(tag)),
+        };
+        tokens.push(token);
+    }
+
+    let mut spans = Vec::with_capacity(tokens.len());
+    let mut span = 0usize;
+    for _ in 0..tokens.len() {
+        span = span
+            .checked_add(reader.operand()?)
+            .ok_or(BytecodeError::Corrupted)?;
+        spans.push(span);
+    }
+    if reader.offset != data.len() {
+        return Err(BytecodeError::Corrupted);
+    }
+
+    let jump_table = BfParser::loop_matching_lossy(&tokens);
+    Program::from_parts(tokens, jump_table, spans).map_err(BytecodeError::Invalid)
+}
+
+/// FNV-1a, as for checkpoints, over bytes.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Maps small offsets of either sign to small varints: 0, -1, 1, -2, ...
+fn zigzag(offset: isize) -> u64 {
+    let offset = offset as i64;
+    ((offset << 1) ^ (offset >> 63)) as u64
+}
+
+fn write_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, BytecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.offset)
+            .ok_or(BytecodeError::Truncated)?;
+        self.offset += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, BytecodeError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(BytecodeError::Corrupted)
+    }
+
+    fn operand<T: TryFrom<u64>>(&mut self) -> Result<T, BytecodeError> {
+        T::try_from(self.varint()?).map_err(|_| BytecodeError::Corrupted)
+    }
+
+    /// Reads what [`zigzag`] wrote.
+    fn signed(&mut self) -> Result<isize, BytecodeError> {
+        let value = self.varint()?;
+        let offset = (value >> 1) as i64 ^ -((value & 1) as i64);
+        isize::try_from(offset).map_err(|_| BytecodeError::Corrupted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PI: &str = include_str!("../../tests/programs/pi.b");
+
+    #[test]
+    fn round_trip() {
+        for program in [
+            Program::parse_optimized(PI).unwrap(),
+            Program::parse("a+[->+<]é.").unwrap(),
+            Program::parse("").unwrap(),
+        ] {
+            assert_eq!(decode(&encode(&program)).unwrap(), program);
+        }
+    }
+
+    #[test]
+    fn refuses_damaged_bytes() {
+        let bytes = encode(&Program::parse_optimized("++[->+<]>.").unwrap());
+
+        assert_eq!(decode(b"not bytecode"), Err(BytecodeError::NotBytecode));
+        assert_eq!(
+            decode(&bytes[..bytes.len() - 3]),
+            Err(BytecodeError::Corrupted)
+        );
+        assert_eq!(decode(&bytes[..4]), Err(BytecodeError::Truncated));
+
+        let mut version = bytes.clone();
+        version[4] = FORMAT_VERSION + 1;
+        assert_eq!(
+            decode(&version),
+            Err(BytecodeError::Version(FORMAT_VERSION + 1))
+        );
+
+        for index in MAGIC.len() + 1..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[index] ^= 0x55;
+            assert!(decode(&flipped).is_err(), "flipping byte {index}");
+        }
+    }
+}
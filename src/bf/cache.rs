@@ -0,0 +1,164 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::debug;
+
+use super::{
+    bf_parser::{BfParserError, ParseOptions},
+    bytecode::{self, BytecodeError},
+    program::{OptLevel, Program},
+};
+
+/// Where [`ProgramCache::parse`] got its program from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum CacheOutcome {
+    Hit,
+    Miss,
+    /// The entry could not be decoded and was parsed again and replaced.
+    Replaced(BytecodeError),
+}
+
+/// A directory of parsed programs as `.bfc` bytecode, one file per source,
+/// optimization level, set of parse options and interpreter version, so that
+/// a hit skips parsing and optimizing altogether.
+///
+/// The cache is best effort: an entry that fails to decode is parsed again
+/// and rewritten, and an entry that cannot be written is only logged.
+#[derive(Debug, Clone)]
+pub struct ProgramCache {
+    dir: PathBuf,
+}
+
+impl ProgramCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The file that holds `code` parsed at `level` with `options`.
+    pub fn entry(&self, code: &str, options: &ParseOptions, level: OptLevel) -> PathBuf {
+        let mut extensions: Vec<_> = options.extensions.iter().collect();
+        extensions.sort();
+        let key = format!(
+            "{} {} {level} {} {} {} {} {} {extensions:?}\n{code}",
+            env!("CARGO_PKG_VERSION"),
+            bytecode::FORMAT_VERSION,
+            options.line_comments,
+            options.bang_input,
+            options.max_nesting_depth,
+            options.multi_tape,
+            options.brainfork,
+        );
+        self.dir
+            .join(format!("{:016x}.bfc", bytecode::fnv1a(key.as_bytes())))
+    }
+
+    /// [`Program::parse_at`], through the cache.
+    pub fn parse(
+        &self,
+        code: &str,
+        options: &ParseOptions,
+        level: OptLevel,
+    ) -> Result<(Program, CacheOutcome), BfParserError> {
+        let path = self.entry(code, options, level);
+        let outcome = match fs::read(&path) {
+            Ok(bytes) => match bytecode::decode(&bytes) {
+                Ok(program) => {
+                    debug!("loaded {} from the program cache", path.display());
+                    return Ok((program, CacheOutcome::Hit));
+                }
+                Err(err) => {
+                    debug!("ignoring cache entry {}: {err}", path.display());
+                    CacheOutcome::Replaced(err)
+                }
+            },
+            Err(_) => CacheOutcome::Miss,
+        };
+
+        let program = Program::parse_at(code, options, level)?;
+        match self.store(&path, &program) {
+            Ok(()) => debug!("stored {} in the program cache", path.display()),
+            Err(err) => debug!("could not store {}: {err}", path.display()),
+        }
+        Ok((program, outcome))
+    }
+
+    /// Removes every entry, returning how many there were. A missing
+    /// directory holds none.
+    pub fn clear(&self) -> io::Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err),
+        };
+
+        let mut removed = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "bfc") {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Writes through a temporary file, so a concurrent run never reads half
+    /// an entry.
+    fn store(&self, path: &Path, program: &Program) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp, bytecode::encode(program))?;
+        fs::rename(&temp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(name: &str) -> ProgramCache {
+        let dir = std::env::temp_dir().join(format!("bf-rust-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ProgramCache::new(dir)
+    }
+
+    #[test]
+    fn hits_after_a_miss() {
+        let cache = cache("cache-hits");
+        let options = ParseOptions::default();
+
+        let (first, outcome) = cache.parse("++[->+<]", &options, OptLevel::O2).unwrap();
+        assert_eq!(outcome, CacheOutcome::Miss);
+        let (second, outcome) = cache.parse("++[->+<]", &options, OptLevel::O2).unwrap();
+        assert_eq!(outcome, CacheOutcome::Hit);
+        assert_eq!(second, first);
+
+        let (_, outcome) = cache.parse("++[->+<]", &options, OptLevel::O0).unwrap();
+        assert_eq!(outcome, CacheOutcome::Miss);
+        assert_eq!(cache.clear().unwrap(), 2);
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+
+    #[test]
+    fn replaces_corrupted_entries() {
+        let cache = cache("cache-corrupted");
+        let options = ParseOptions::default();
+        cache.parse("+.", &options, OptLevel::O2).unwrap();
+        fs::write(cache.entry("+.", &options, OptLevel::O2), b"BFC\0garbage").unwrap();
+
+        let (program, outcome) = cache.parse("+.", &options, OptLevel::O2).unwrap();
+        assert!(matches!(outcome, CacheOutcome::Replaced(_)));
+        assert_eq!(program, Program::parse_optimized("+.").unwrap());
+        let (_, outcome) = cache.parse("+.", &options, OptLevel::O2).unwrap();
+        assert_eq!(outcome, CacheOutcome::Hit);
+
+        assert!(cache.parse("+[", &options, OptLevel::O2).is_err());
+        fs::remove_dir_all(cache.dir()).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::{Debug, Display};
+
+/// The value in one cell of a [`BfMachine`] tape. Arithmetic wraps at the
+/// type's bounds and `[` tests for zero over its whole range.
+///
+/// Only `u8` and `i32` are cells: any bytes of their size are a valid value,
+/// which a tape mapped from a file relies on.
+///
+/// [`BfMachine`]: super::bf_machine::BfMachine
+pub trait Cell: Copy + Default + Eq + Debug + Display + sealed::Sealed + 'static {
+    /// What `--cell-size` calls it, and what checkpoints are keyed on.
+    const NAME: &'static str;
+    /// What [`EofMode::MinusOne`] stores.
+    ///
+    /// [`EofMode::MinusOne`]: super::bf_machine::EofMode::MinusOne
+    const MINUS_ONE: Self;
+
+    fn add(self, count: u8) -> Self;
+    fn sub(self, count: u8) -> Self;
+    /// The cell `,` stores for an input byte.
+    fn from_byte(byte: u8) -> Self;
+    /// The byte `.` writes: the low 8 bits, as C's `putchar` does.
+    fn to_byte(self) -> u8;
+}
+
+impl Cell for u8 {
+    const NAME: &'static str = "u8";
+    const MINUS_ONE: Self = u8::MAX;
+
+    fn add(self, count: u8) -> Self {
+        self.wrapping_add(count)
+    }
+
+    fn sub(self, count: u8) -> Self {
+        self.wrapping_sub(count)
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        byte
+    }
+
+    fn to_byte(self) -> u8 {
+        self
+    }
+}
+
+impl Cell for i32 {
+    const NAME: &'static str = "i32";
+    const MINUS_ONE: Self = -1;
+
+    fn add(self, count: u8) -> Self {
+        self.wrapping_add(count.into())
+    }
+
+    fn sub(self, count: u8) -> Self {
+        self.wrapping_sub(count.into())
+    }
+
+    fn from_byte(byte: u8) -> Self {
+        byte.into()
+    }
+
+    fn to_byte(self) -> u8 {
+        self as u8
+    }
+}
+
+mod sealed {
+    /// With serde, also what lets checkpoints, dumps and traces hold any cell.
+    #[cfg(feature = "serde")]
+    pub trait Sealed: serde::Serialize + serde::de::DeserializeOwned {}
+    #[cfg(not(feature = "serde"))]
+    pub trait Sealed {}
+
+    impl Sealed for u8 {}
+    impl Sealed for i32 {}
+}
+
+/// The cells of the tapes `run` uses, chosen with `--cell-size`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum CellSize {
+    /// Bytes, on a [`BfMachine`](super::bf_machine::BfMachine).
+    #[default]
+    U8,
+    /// C `int`s, on a `BfMachine<R, W, i32>`. Counts on `+` and `-` are taken
+    /// as given, but optimized programs fold 256 of them to none, so these run
+    /// programs parsed at [`OptLevel::O0`].
+    ///
+    /// [`OptLevel::O0`]: super::program::OptLevel::O0
+    I32,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{
+        bf_machine::{BfMachine, BfRuntimeError, EofMode, MachineConfig},
+        bf_parser::ParseOptions,
+        program::{OptLevel, Program},
+    };
+
+    use super::*;
+
+    /// Copies its input until `,` stores -1: the `+` makes that 0 and any
+    /// byte not, and the `-` takes it back off.
+    const CAT: &str = ",+[-.,+]";
+
+    fn program(code: &str) -> Program {
+        Program::parse_at(code, &ParseOptions::default(), OptLevel::O0).unwrap()
+    }
+
+    fn machine<C: Cell>(input: &[u8], eof_mode: EofMode) -> BfMachine<Cursor<Vec<u8>>, Vec<u8>, C> {
+        let config = MachineConfig {
+            memory_size: 8,
+            max_steps: Some(10_000),
+            eof_mode,
+            ..Default::default()
+        };
+        BfMachine::with_cells(config, Cursor::new(input.to_vec()), vec![])
+    }
+
+    fn run<C: Cell>(code: &str, input: &[u8], eof_mode: EofMode) -> (Vec<C>, Vec<u8>) {
+        let mut machine = machine::<C>(input, eof_mode);
+        machine.run(&program(code)).unwrap();
+        let (tape, _, _, output) = machine.into_parts();
+        (tape, output)
+    }
+
+    #[test]
+    fn int_cells_count_past_a_byte() {
+        // 8 * 8 * 4 is 256, which is 0 in a byte, so only wide cells print.
+        let code = "++++++++[>++++++++<-]>[<++++>-]<[.[-]]";
+        assert_eq!(run::<i32>(code, b"", EofMode::Error).1, [0]);
+        assert!(run::<u8>(code, b"", EofMode::Error).1.is_empty());
+
+        let (tape, output) = run::<i32>("-.>,", b"", EofMode::MinusOne);
+        assert_eq!(tape[..2], [-1, -1]);
+        assert_eq!(output, [0xff]);
+    }
+
+    #[test]
+    fn minus_one_ends_input_apart_from_byte_255() {
+        let input = b"a\xffb";
+        assert_eq!(run::<i32>(CAT, input, EofMode::MinusOne).1, input);
+        // In a byte, the 255 in the input already looks like the end.
+        assert_eq!(run::<u8>(CAT, input, EofMode::MinusOne).1, b"a");
+
+        let mut machine = machine::<u8>(b"a", EofMode::Zero).with_step_limit(100);
+        assert!(matches!(
+            machine.run(&program(CAT)),
+            Err(BfRuntimeError::StepLimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn int_cells_run_every_way_bytes_do() {
+        // Leaves 300 in cell 2.
+        let program = program("+++[>++++++++++[>++++++++++<-]<-]>>");
+        let mut slow = machine::<i32>(b"", EofMode::Error);
+        slow.run(&program).unwrap();
+        let mut fast = machine::<i32>(b"", EofMode::Error);
+        fast.run_fast(&program).unwrap();
+        assert!(slow.state_eq(&fast));
+
+        let snapshot = slow.snapshot();
+        assert_eq!(snapshot.memory[..3], [0, 0, 300]);
+        let mut restored = machine::<i32>(b"", EofMode::Error);
+        restored.restore(snapshot);
+        assert!(restored.state_eq(&slow));
+    }
+}
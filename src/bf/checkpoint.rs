@@ -0,0 +1,220 @@
+use std::{fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bf_machine::{BfSnapshot, EofMode, MachineConfig, TapePolicy},
+    bytecode,
+    cell::Cell,
+    program::Program,
+};
+
+/// Everything needed to resume a run: the machine state, the next instruction
+/// and how much input the program had already consumed.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct Checkpoint<C = u8> {
+    pub program_hash: u64,
+    pub pc: usize,
+    pub input_consumed: usize,
+    pub state: BfSnapshot<C>,
+}
+
+impl<C: Cell> Checkpoint<C> {
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(path, json)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// Whether the checkpoint resumes `program` on a machine with `config`:
+    /// written for both, and with a pc and tapes that machine can take, which
+    /// a checkpoint edited by hand may not have.
+    pub fn check(&self, program: &Program, config: &MachineConfig) -> Result<(), String> {
+        if self.program_hash != program_hash::<C>(program, config) {
+            return Err(
+                "the checkpoint was written for a different program or machine, refusing to resume"
+                    .into(),
+            );
+        }
+        if self.pc > program.tokens().len() {
+            return Err(format!(
+                "the checkpoint resumes at pc {}, past the end of the program",
+                self.pc
+            ));
+        }
+        let tapes = [(self.state.cursor, &self.state.memory)].into_iter().chain(
+            self.state
+                .other_tape
+                .iter()
+                .map(|tape| (tape.cursor, &tape.memory)),
+        );
+        for (cursor, memory) in tapes {
+            let fits = match config.tape_policy {
+                TapePolicy::Wrap => memory.len() == config.memory_size,
+                TapePolicy::Grow => (1..=config.max_memory_cells).contains(&memory.len()),
+            };
+            if !fits {
+                return Err(format!(
+                    "the checkpoint holds a tape of {} cells, which the machine cannot",
+                    memory.len()
+                ));
+            }
+            if cursor >= memory.len() {
+                return Err(format!(
+                    "the checkpoint puts the cursor at cell {cursor} of {}",
+                    memory.len()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// FNV-1a over `program` as bytecode and over the settings of `config` that
+/// change what its pcs do, so a checkpoint resumes only where they mean the
+/// same instructions on a machine set up alike. The settings go in a fixed
+/// layout, so checkpoints stay valid across builds: the cell name and a 0, the
+/// tape length, a byte for the tape policy, the memory limit and a byte for
+/// the EOF mode, the numbers as little-endian `u64`s. The step and output
+/// limits are left out: a run stopped by one may go on with another.
+pub fn program_hash<C: Cell>(program: &Program, config: &MachineConfig) -> u64 {
+    let mut key = bytecode::encode(program);
+    key.extend(C::NAME.bytes());
+    key.push(0);
+    key.extend((config.memory_size as u64).to_le_bytes());
+    key.push(match config.tape_policy {
+        TapePolicy::Wrap => 0,
+        TapePolicy::Grow => 1,
+    });
+    key.extend((config.max_memory_cells as u64).to_le_bytes());
+    key.push(match config.eof_mode {
+        EofMode::Error => 0,
+        EofMode::Zero => 1,
+        EofMode::Unchanged => 2,
+        EofMode::MinusOne => 3,
+    });
+    bytecode::fnv1a(&key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_machine::BfMachine, bf_parser::ParseOptions, program::OptLevel};
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let config = MachineConfig {
+            memory_size: 8,
+            ..Default::default()
+        };
+        let program = Program::parse(",>++<").unwrap();
+        let mut machine = BfMachine::with_config(config, Cursor::new(vec![7]), vec![]);
+        machine.run(&program).unwrap();
+        let checkpoint = Checkpoint {
+            program_hash: program_hash::<u8>(&program, &config),
+            pc: 3,
+            input_consumed: machine.bytes_read(),
+            state: machine.snapshot(),
+        };
+
+        let path = std::env::temp_dir().join(format!("bf-rust-{}.ckpt", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        let loaded = Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.state.memory, vec![7, 2, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(loaded.check(&program, &config), Ok(()));
+    }
+
+    #[test]
+    fn hash_depends_on_the_parse_and_the_machine() {
+        let parse = |code, level| Program::parse_at(code, &ParseOptions::default(), level).unwrap();
+        let config = MachineConfig::default();
+        let hash = program_hash::<u8>(&parse("+[-]", OptLevel::O0), &config);
+
+        assert_eq!(
+            hash,
+            program_hash::<u8>(&parse("+[-]", OptLevel::O0), &config)
+        );
+        assert_ne!(
+            hash,
+            program_hash::<u8>(&parse("+[+]", OptLevel::O0), &config)
+        );
+        assert_ne!(
+            hash,
+            program_hash::<u8>(&parse("++[-]", OptLevel::O1), &config)
+        );
+        let smaller = MachineConfig {
+            memory_size: 100,
+            ..config
+        };
+        assert_ne!(
+            hash,
+            program_hash::<u8>(&parse("+[-]", OptLevel::O0), &smaller)
+        );
+        let limited = MachineConfig {
+            max_steps: Some(20),
+            ..config
+        };
+        assert_eq!(
+            hash,
+            program_hash::<u8>(&parse("+[-]", OptLevel::O0), &limited)
+        );
+        assert_ne!(
+            hash,
+            program_hash::<i32>(&parse("+[-]", OptLevel::O0), &config)
+        );
+        let zero_at_eof = MachineConfig {
+            eof_mode: EofMode::Zero,
+            ..config
+        };
+        assert_ne!(
+            hash,
+            program_hash::<u8>(&parse("+[-]", OptLevel::O0), &zero_at_eof)
+        );
+
+        // Pinned, as checkpoints written by other builds have to keep loading.
+        assert_eq!(hash, 0xaf51_0a4f_2918_115b);
+    }
+
+    #[test]
+    fn rejects_what_the_machine_cannot_resume() {
+        let config = MachineConfig {
+            memory_size: 4,
+            ..Default::default()
+        };
+        let program = Program::parse("+>+").unwrap();
+        let valid: Checkpoint = Checkpoint {
+            program_hash: program_hash::<u8>(&program, &config),
+            pc: 3,
+            input_consumed: 0,
+            state: BfSnapshot {
+                cursor: 1,
+                memory: vec![1, 1, 0, 0],
+                other_tape: None,
+            },
+        };
+        assert_eq!(valid.check(&program, &config), Ok(()));
+
+        let mut past_the_end = valid.clone();
+        past_the_end.pc = 4;
+        let mut lost_cursor = valid.clone();
+        lost_cursor.state.cursor = 4;
+        let mut short_tape = valid.clone();
+        short_tape.state.memory.truncate(2);
+        for checkpoint in [past_the_end, lost_cursor, short_tape] {
+            assert!(
+                checkpoint.check(&program, &config).is_err(),
+                "{checkpoint:?}"
+            );
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use std::fmt::Write as _;
+
+use crate::bf::{
+    bf_machine::{EofMode, TapePolicy},
+    bf_token::BfToken,
+};
+
+/// How [`emit_js`] sets up the function it writes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct JsOptions {
+    /// The name of the function, which must be a JavaScript identifier.
+    pub function_name: String,
+    pub memory_size: usize,
+    pub tape_policy: TapePolicy,
+    pub eof_mode: EofMode,
+}
+
+impl Default for JsOptions {
+    fn default() -> Self {
+        Self {
+            function_name: "bf".to_string(),
+            memory_size: 30_000,
+            tape_policy: TapePolicy::default(),
+            eof_mode: EofMode::default(),
+        }
+    }
+}
+
+/// Translates `tokens`, whose loops must pair up, into a self-contained
+/// JavaScript function `(output, input, extension)` that runs them as the
+/// machine would with `options`.
+///
+/// `output(byte)` gets every byte printed, `input()` returns the next byte or
+/// `null` once there is none, and `extension(id, tape, p)` runs an extension
+/// command on the tape at cursor `p` and returns the cursor. Errors are thrown
+/// with the machine's messages. The function is also exported when loaded as a
+/// CommonJS module.
+pub fn emit_js(tokens: &[BfToken], options: &JsOptions) -> String {
+    let len = options.memory_size;
+    let grows = options.tape_policy == TapePolicy::Grow;
+    let name = &options.function_name;
+    let mut js = String::new();
+
+    writeln!(js, "function {name}(output, input, extension) {{").unwrap();
+    writeln!(js, "  let tape = new Uint8Array({len});").unwrap();
+    writeln!(js, "  let p = 0;").unwrap();
+    if tokens.contains(&BfToken::SwitchTape) {
+        // The tape switched away from and its cursor; tape 1 starts blank.
+        js.push_str("  let other = null;\n");
+        js.push_str("  let q = 0;\n");
+    }
+    if tokens.contains(&BfToken::InputChar) {
+        let eof = match options.eof_mode {
+            EofMode::Error => {
+                "throw new Error(`The error occurred at instruction ${pc} due to the end of input.`)"
+            }
+            EofMode::Zero => "return 0",
+            EofMode::Unchanged => "return tape[p]",
+            EofMode::MinusOne => "return 255",
+        };
+        js.push_str("  const read = (pc) => {\n");
+        js.push_str("    const byte = input();\n");
+        js.push_str("    if (byte === null || byte === undefined) {\n");
+        writeln!(js, "      {eof};").unwrap();
+        js.push_str("    }\n");
+        js.push_str("    return byte & 0xff;\n");
+        js.push_str("  };\n");
+    }
+    if grows
+        && tokens.iter().any(|token| {
+            matches!(
+                token,
+                BfToken::CursorRight(_) | BfToken::IncrementAt(..) | BfToken::DecrementAt(..)
+            )
+        })
+    {
+        js.push_str("  const grow = (cells) => {\n");
+        js.push_str("    const grown = new Uint8Array(Math.max(cells, tape.length * 2));\n");
+        js.push_str("    grown.set(tape);\n");
+        js.push_str("    tape = grown;\n");
+        js.push_str("  };\n");
+    }
+
+    let mut depth = 1;
+    for (pc, token) in tokens.iter().enumerate() {
+        if *token == BfToken::LoopEnd {
+            depth -= 1;
+        }
+        let indent = "  ".repeat(depth);
+        match *token {
+            BfToken::NotCommand(_) => {}
+            BfToken::Increment(n) => {
+                writeln!(js, "{indent}tape[p] = (tape[p] + {n}) & 0xff;").unwrap()
+            }
+            BfToken::Decrement(n) => {
+                writeln!(js, "{indent}tape[p] = (tape[p] - {n}) & 0xff;").unwrap()
+            }
+            BfToken::IncrementAt(offset, n) | BfToken::DecrementAt(offset, n) => {
+                let op = match token {
+                    BfToken::IncrementAt(..) => '+',
+                    _ => '-',
+                };
+                let cell = if grows {
+                    let cell = match offset {
+                        offset if offset < 0 => format!("p - {}", offset.unsigned_abs()),
+                        offset => format!("p + {offset}"),
+                    };
+                    if offset < 0 {
+                        writeln!(
+                            js,
+                            "{indent}if ({cell} < 0) throw new Error(\"The error occurred at instruction {pc} due to moving left of cell 0.\");"
+                        )
+                        .unwrap();
+                    } else {
+                        writeln!(js, "{indent}if ({cell} >= tape.length) grow({cell} + 1);")
+                            .unwrap();
+                    }
+                    cell
+                } else {
+                    match offset.rem_euclid(len as isize) {
+                        0 => "p".to_string(),
+                        offset => format!("(p + {offset}) % {len}"),
+                    }
+                };
+                writeln!(js, "{indent}tape[{cell}] = (tape[{cell}] {op} {n}) & 0xff;").unwrap();
+            }
+            BfToken::CursorRight(n) if grows => {
+                writeln!(js, "{indent}p += {n};").unwrap();
+                writeln!(js, "{indent}if (p >= tape.length) grow(p + 1);").unwrap();
+            }
+            BfToken::CursorLeft(n) if grows => {
+                writeln!(js, "{indent}p -= {n};").unwrap();
+                writeln!(
+                    js,
+                    "{indent}if (p < 0) throw new Error(\"The error occurred at instruction {pc} due to moving left of cell 0.\");"
+                )
+                .unwrap();
+            }
+            BfToken::CursorRight(n) => match n % len {
+                0 => {}
+                n => writeln!(js, "{indent}p = (p + {n}) % {len};").unwrap(),
+            },
+            BfToken::CursorLeft(n) => match n % len {
+                0 => {}
+                n => writeln!(js, "{indent}p = (p + {}) % {len};", len - n).unwrap(),
+            },
+            BfToken::LoopStart => {
+                writeln!(js, "{indent}while (tape[p] !== 0) {{").unwrap();
+                depth += 1;
+            }
+            BfToken::LoopEnd => writeln!(js, "{indent}}}").unwrap(),
+            BfToken::PrintChar => writeln!(js, "{indent}output(tape[p]);").unwrap(),
+            BfToken::PrintCharN(count) => {
+                writeln!(
+                    js,
+                    "{indent}for (let i = 0; i < {count}; i++) output(tape[p]);"
+                )
+                .unwrap();
+            }
+            BfToken::InputChar => writeln!(js, "{indent}tape[p] = read({pc});").unwrap(),
+            BfToken::Extension(id) => {
+                writeln!(js, "{indent}p = extension({id}, tape, p);").unwrap()
+            }
+            BfToken::SwitchTape => {
+                writeln!(
+                    js,
+                    "{indent}[tape, other] = [other ?? new Uint8Array(tape.length), tape];"
+                )
+                .unwrap();
+                writeln!(js, "{indent}[p, q] = [q, p];").unwrap();
+            }
+            BfToken::Fork => writeln!(
+                js,
+                "{indent}throw new Error(\"The error occurred at instruction {pc} due to a fork outside a threaded run.\");"
+            )
+            .unwrap(),
+        }
+    }
+
+    js.push_str("}\n\n");
+    js.push_str("if (typeof module !== \"undefined\") {\n");
+    writeln!(js, "  module.exports = {name};").unwrap();
+    js.push_str("}\n");
+    js
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::Cursor,
+        process::{Command, Stdio},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::bf::{
+        bf_machine::BfMachine,
+        bf_parser::ParseOptions,
+        handler::{ControlFlow, MachineContext},
+        program::{OptLevel, Program},
+    };
+
+    use super::*;
+
+    const HELLO_WORLD: &str = include_str!("../../../tests/programs/hello_world.b");
+
+    /// What the function `js` defines prints for `input` under node, or `None`
+    /// without node. Extension commands store their id.
+    fn run_node(js: &str, input: &[u8]) -> Option<Vec<u8>> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        let driver = format!(
+            "{js}\nconst input = {input:?};\nconst out = [];\n\
+             bf((byte) => out.push(byte), () => input.length ? input.shift() : null, \
+             (id, tape, p) => {{ tape[p] = id; return p; }});\n\
+             process.stdout.write(Buffer.from(out));\n"
+        );
+        let path = std::env::temp_dir().join(format!(
+            "bf-rust-{}-{}.js",
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&path, driver).unwrap();
+        let output = Command::new("node")
+            .arg(&path)
+            .stderr(Stdio::inherit())
+            .output();
+        fs::remove_file(&path).unwrap();
+        let output = output.ok()?;
+        assert!(output.status.success());
+        Some(output.stdout)
+    }
+
+    #[test]
+    fn hello_world_snapshot() {
+        let program =
+            Program::parse_at(HELLO_WORLD, &ParseOptions::default(), OptLevel::O1).unwrap();
+        let js = emit_js(program.tokens(), &JsOptions::default());
+        assert_eq!(js, include_str!("../../../tests/fixtures/hello_world.js"));
+        assert!(js.contains("  while (tape[p] !== 0) {\n"));
+        assert!(js.contains("tape[p] = (tape[p] + 7) & 0xff;"));
+        assert!(js.contains("p = (p + 29996) % 30000;"));
+    }
+
+    #[test]
+    fn node_prints_what_the_machine_prints() {
+        let options = ParseOptions {
+            extensions: [('!', 33)].into(),
+            multi_tape: true,
+            ..Default::default()
+        };
+        for (code, input, memory_size) in [
+            (HELLO_WORLD, &b""[..], 30_000),
+            (",[.,]", b"cat\0", 30_000),
+            // Wraps left past cell 0 and below 0 in the cell.
+            ("<-.>>-..", b"", 3),
+            ("!..", b"", 8),
+            ("+++++>+^+++++++^.^.>.<<^.", b"", 8),
+        ] {
+            for level in OptLevel::ALL {
+                let program = Program::parse_at(code, &options, level).unwrap();
+                let mut expected = vec![];
+                BfMachine::new(memory_size, Cursor::new(input), &mut expected)
+                    .with_handler(|_: &BfToken, ctx: &mut MachineContext| {
+                        *ctx.cell() = 33;
+                        Ok(ControlFlow::Continue)
+                    })
+                    .run(&program)
+                    .unwrap();
+
+                let js = emit_js(
+                    program.tokens(),
+                    &JsOptions {
+                        memory_size,
+                        ..Default::default()
+                    },
+                );
+                let Some(output) = run_node(&js, input) else {
+                    return;
+                };
+                assert_eq!(output, expected, "{code} at {level}");
+            }
+        }
+    }
+
+    #[test]
+    fn growing_tape_and_eof() {
+        let options = JsOptions {
+            tape_policy: TapePolicy::Grow,
+            eof_mode: EofMode::Zero,
+            memory_size: 1,
+            ..Default::default()
+        };
+        let program = Program::parse(">>>+.,.<<<").unwrap();
+        let js = emit_js(program.tokens(), &options);
+        assert!(js.contains("if (p >= tape.length) grow(p + 1);"));
+        assert!(js.contains("      return 0;\n"));
+        if let Some(output) = run_node(&js, b"") {
+            assert_eq!(output, [1, 0]);
+        }
+
+        let program = Program::parse("><<").unwrap();
+        let js = emit_js(program.tokens(), &options);
+        assert!(js.contains(
+            "if (p < 0) throw new Error(\"The error occurred at instruction 2 due to moving left of cell 0.\");"
+        ));
+    }
+}
@@ -0,0 +1,379 @@
+use std::fmt::Write as _;
+
+use crate::bf::bf_token::BfToken;
+
+/// The cells of the tape in the module [`emit_ir`] writes, the machine's
+/// default.
+pub const TAPE_CELLS: usize = 30_000;
+
+/// Translates `tokens`, whose loops must pair up, into a textual LLVM module
+/// whose `main` runs them on a wrapping tape of [`TAPE_CELLS`] cells, with
+/// output through `putchar` and input through `getchar`. A `,` at the end of
+/// the input leaves the cell as it is.
+///
+/// Extension commands call `i64 @bf_extension(i32 id, ptr tape, i64 cursor)`,
+/// which the module declares only if it needs it and which returns the new
+/// cursor. A program that switches tapes gets a second tape `@tape1`, and
+/// `%tape` points at the active one. A Brainfork fork traps, as the module
+/// runs a single thread. The IR uses opaque pointers.
+pub fn emit_ir(tokens: &[BfToken]) -> String {
+    let mut emitter = Emitter {
+        multi_tape: tokens.contains(&BfToken::SwitchTape),
+        ..Default::default()
+    };
+    for token in tokens {
+        emitter.token(*token);
+    }
+
+    let mut ir = String::new();
+    writeln!(
+        ir,
+        "@tape = internal global [{TAPE_CELLS} x i8] zeroinitializer"
+    )
+    .unwrap();
+    if emitter.multi_tape {
+        writeln!(
+            ir,
+            "@tape1 = internal global [{TAPE_CELLS} x i8] zeroinitializer"
+        )
+        .unwrap();
+    }
+    ir.push('\n');
+    ir.push_str("declare i32 @putchar(i32)\n");
+    ir.push_str("declare i32 @getchar()\n");
+    if tokens
+        .iter()
+        .any(|token| matches!(token, BfToken::Extension(_)))
+    {
+        ir.push_str("declare i64 @bf_extension(i32, ptr, i64)\n");
+    }
+    if tokens.contains(&BfToken::Fork) {
+        ir.push_str("declare void @llvm.trap()\n");
+    }
+    ir.push('\n');
+    ir.push_str("define i32 @main() {\n");
+    ir.push_str("entry:\n");
+    ir.push_str("  %p = alloca i64\n");
+    ir.push_str("  store i64 0, ptr %p\n");
+    if emitter.multi_tape {
+        // The other tape's cursor, and the tape the cursor in `%p` is on.
+        ir.push_str("  %q = alloca i64\n");
+        ir.push_str("  store i64 0, ptr %q\n");
+        ir.push_str("  %tape = alloca ptr\n");
+        ir.push_str("  store ptr @tape, ptr %tape\n");
+    }
+    ir.push_str(&emitter.body);
+    ir.push_str("  ret i32 0\n");
+    ir.push_str("}\n");
+    ir
+}
+
+/// The body of `main` as it is written, with the counters that keep its
+/// names unique.
+#[derive(Debug, Default)]
+struct Emitter {
+    body: String,
+    /// The next `%tN` value.
+    values: usize,
+    /// The next loop number, which names its blocks.
+    loops: usize,
+    /// The numbers of the loops the current token is in, innermost last.
+    open: Vec<usize>,
+    /// Whether the program switches tapes, so the tape is loaded from `%tape`.
+    multi_tape: bool,
+}
+
+impl Emitter {
+    fn value(&mut self) -> String {
+        self.values += 1;
+        format!("%t{}", self.values)
+    }
+
+    fn line(&mut self, line: &str) {
+        self.body.push_str("  ");
+        self.body.push_str(line);
+        self.body.push('\n');
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.body, "{label}:").unwrap();
+    }
+
+    /// A pointer to the active tape.
+    fn tape(&mut self) -> String {
+        if !self.multi_tape {
+            return "@tape".to_string();
+        }
+        let tape = self.value();
+        self.line(&format!("{tape} = load ptr, ptr %tape"));
+        tape
+    }
+
+    /// Loads the cursor and returns it with a pointer to its cell.
+    fn cell(&mut self) -> (String, String) {
+        let tape = self.tape();
+        let cursor = self.value();
+        self.line(&format!("{cursor} = load i64, ptr %p"));
+        let cell = self.value();
+        self.line(&format!(
+            "{cell} = getelementptr inbounds [{TAPE_CELLS} x i8], ptr {tape}, i64 0, i64 {cursor}"
+        ));
+        (cursor, cell)
+    }
+
+    fn add(&mut self, n: u8) {
+        let (_, cell) = self.cell();
+        self.add_to(&cell, n);
+    }
+
+    /// Adds `n` to the cell `offset` cells right of the cursor, around the end
+    /// of the tape.
+    fn add_at(&mut self, offset: isize, n: u8) {
+        let offset = offset.rem_euclid(TAPE_CELLS as isize);
+        let tape = self.tape();
+        let cursor = self.value();
+        self.line(&format!("{cursor} = load i64, ptr %p"));
+        let sum = self.value();
+        self.line(&format!("{sum} = add i64 {cursor}, {offset}"));
+        let wrapped = self.value();
+        self.line(&format!("{wrapped} = urem i64 {sum}, {TAPE_CELLS}"));
+        let cell = self.value();
+        self.line(&format!(
+            "{cell} = getelementptr inbounds [{TAPE_CELLS} x i8], ptr {tape}, i64 0, i64 {wrapped}"
+        ));
+        self.add_to(&cell, n);
+    }
+
+    fn add_to(&mut self, cell: &str, n: u8) {
+        let old = self.value();
+        self.line(&format!("{old} = load i8, ptr {cell}"));
+        let new = self.value();
+        self.line(&format!("{new} = add i8 {old}, {n}"));
+        self.line(&format!("store i8 {new}, ptr {cell}"));
+    }
+
+    /// Moves the cursor `n` cells right, around the end of the tape.
+    fn right(&mut self, n: usize) {
+        if n.is_multiple_of(TAPE_CELLS) {
+            return;
+        }
+        let cursor = self.value();
+        self.line(&format!("{cursor} = load i64, ptr %p"));
+        let sum = self.value();
+        self.line(&format!("{sum} = add i64 {cursor}, {}", n % TAPE_CELLS));
+        let wrapped = self.value();
+        self.line(&format!("{wrapped} = urem i64 {sum}, {TAPE_CELLS}"));
+        self.line(&format!("store i64 {wrapped}, ptr %p"));
+    }
+
+    fn token(&mut self, token: BfToken) {
+        match token {
+            BfToken::NotCommand(_) => {}
+            BfToken::Increment(n) => self.add(n),
+            BfToken::Decrement(n) => self.add(n.wrapping_neg()),
+            BfToken::IncrementAt(offset, n) => self.add_at(offset, n),
+            BfToken::DecrementAt(offset, n) => self.add_at(offset, n.wrapping_neg()),
+            BfToken::CursorRight(n) => self.right(n),
+            BfToken::CursorLeft(n) => self.right(TAPE_CELLS - n % TAPE_CELLS),
+            BfToken::LoopStart => {
+                let number = self.loops;
+                self.loops += 1;
+                self.open.push(number);
+                self.line(&format!("br label %loop{number}"));
+                self.label(&format!("loop{number}"));
+                let (_, cell) = self.cell();
+                let value = self.value();
+                self.line(&format!("{value} = load i8, ptr {cell}"));
+                let nonzero = self.value();
+                self.line(&format!("{nonzero} = icmp ne i8 {value}, 0"));
+                self.line(&format!(
+                    "br i1 {nonzero}, label %body{number}, label %end{number}"
+                ));
+                self.label(&format!("body{number}"));
+            }
+            BfToken::LoopEnd => {
+                let number = self.open.pop().expect("loops pair up");
+                self.line(&format!("br label %loop{number}"));
+                self.label(&format!("end{number}"));
+            }
+            BfToken::PrintChar => self.print(1),
+            BfToken::PrintCharN(count) => self.print(count),
+            BfToken::InputChar => {
+                let (_, cell) = self.cell();
+                let read = self.value();
+                self.line(&format!("{read} = call i32 @getchar()"));
+                let eof = self.value();
+                self.line(&format!("{eof} = icmp slt i32 {read}, 0"));
+                let byte = self.value();
+                self.line(&format!("{byte} = trunc i32 {read} to i8"));
+                let old = self.value();
+                self.line(&format!("{old} = load i8, ptr {cell}"));
+                let new = self.value();
+                self.line(&format!("{new} = select i1 {eof}, i8 {old}, i8 {byte}"));
+                self.line(&format!("store i8 {new}, ptr {cell}"));
+            }
+            BfToken::Extension(id) => {
+                let tape = self.tape();
+                let cursor = self.value();
+                self.line(&format!("{cursor} = load i64, ptr %p"));
+                let moved = self.value();
+                self.line(&format!(
+                    "{moved} = call i64 @bf_extension(i32 {id}, ptr {tape}, i64 {cursor})"
+                ));
+                self.line(&format!("store i64 {moved}, ptr %p"));
+            }
+            BfToken::SwitchTape => {
+                let tape = self.tape();
+                let first = self.value();
+                self.line(&format!("{first} = icmp eq ptr {tape}, @tape"));
+                let other = self.value();
+                self.line(&format!(
+                    "{other} = select i1 {first}, ptr @tape1, ptr @tape"
+                ));
+                self.line(&format!("store ptr {other}, ptr %tape"));
+                let cursor = self.value();
+                self.line(&format!("{cursor} = load i64, ptr %p"));
+                let other_cursor = self.value();
+                self.line(&format!("{other_cursor} = load i64, ptr %q"));
+                self.line(&format!("store i64 {other_cursor}, ptr %p"));
+                self.line(&format!("store i64 {cursor}, ptr %q"));
+            }
+            BfToken::Fork => self.line("call void @llvm.trap()"),
+        }
+    }
+
+    fn print(&mut self, count: usize) {
+        let (_, cell) = self.cell();
+        let value = self.value();
+        self.line(&format!("{value} = load i8, ptr {cell}"));
+        let char = self.value();
+        self.line(&format!("{char} = zext i8 {value} to i32"));
+        for _ in 0..count {
+            let result = self.value();
+            self.line(&format!("{result} = call i32 @putchar(i32 {char})"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fs,
+        io::{Cursor, Write},
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use crate::bf::{
+        bf_machine::{BfMachine, EofMode},
+        bf_parser::ParseOptions,
+        program::{OptLevel, Program},
+    };
+
+    use super::*;
+
+    /// Checks that every label is defined once and every branch goes to one of
+    /// them, returning the labels.
+    fn check_labels(ir: &str) -> Vec<&str> {
+        let mut defined: HashMap<&str, usize> = HashMap::new();
+        let mut labels = vec![];
+        for line in ir.lines() {
+            if let Some(label) = line.strip_suffix(':') {
+                *defined.entry(label).or_default() += 1;
+                labels.push(label);
+            }
+        }
+        assert!(
+            defined.values().all(|&count| count == 1),
+            "labels defined twice: {defined:?}"
+        );
+        for target in ir.split("label %").skip(1) {
+            let target = target.split(|c: char| !c.is_alphanumeric()).next().unwrap();
+            assert!(defined.contains_key(target), "undefined label {target}");
+        }
+        labels
+    }
+
+    #[test]
+    fn small_program_golden() {
+        let program =
+            Program::parse_at("++[>+++<-]>.,", &ParseOptions::default(), OptLevel::O1).unwrap();
+        let ir = emit_ir(program.tokens());
+        assert_eq!(ir, include_str!("../../../tests/fixtures/small.ll"));
+        assert_eq!(check_labels(&ir), ["entry", "loop0", "body0", "end0"]);
+    }
+
+    #[test]
+    fn labels_of_nested_loops() {
+        let program = Program::parse("+[>+[>+<-]<-[-]]>[.>]").unwrap();
+        let ir = emit_ir(program.tokens());
+        let labels = check_labels(&ir);
+        assert_eq!(labels.len(), 1 + 3 * 4);
+        assert!(!ir.contains("bf_extension"));
+    }
+
+    /// What `lli` prints running the module at `path` on `input`, or `None`
+    /// without LLVM.
+    fn lli(path: &Path, input: &[u8]) -> Option<Vec<u8>> {
+        let mut errors = vec![];
+        // LLVM 14 reads opaque pointers only when asked to, and later
+        // versions no longer know the flag.
+        for flags in [&["-opaque-pointers"][..], &[]] {
+            let mut child = Command::new("lli")
+                .args(flags)
+                .arg(path)
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .ok()?;
+            child.stdin.take().unwrap().write_all(input).unwrap();
+            let output = child.wait_with_output().unwrap();
+            if output.status.success() {
+                return Some(output.stdout);
+            }
+            errors.push(String::from_utf8_lossy(&output.stderr).into_owned());
+        }
+        panic!("lli failed: {errors:?}");
+    }
+
+    #[test]
+    fn lli_prints_what_the_machine_prints() {
+        for (code, input) in [
+            (
+                include_str!("../../../tests/programs/hello_world.b"),
+                &b""[..],
+            ),
+            (",[.,]", b"cat\0"),
+            ("<-.>>-..", b""),
+            ("+++++>+^+++++++^.^.>.<<^.", b""),
+        ] {
+            let options = ParseOptions {
+                multi_tape: true,
+                ..Default::default()
+            };
+            for level in OptLevel::ALL {
+                let program = Program::parse_at(code, &options, level).unwrap();
+                let mut expected = vec![];
+                BfMachine::new(TAPE_CELLS, Cursor::new(input), &mut expected)
+                    .with_eof_mode(EofMode::Unchanged)
+                    .run(&program)
+                    .unwrap();
+
+                let path = std::env::temp_dir().join(format!(
+                    "bf-rust-{}-{level}-{}.ll",
+                    std::process::id(),
+                    code.len()
+                ));
+                fs::write(&path, emit_ir(program.tokens())).unwrap();
+                let output = lli(&path, input);
+                fs::remove_file(&path).unwrap();
+                let Some(output) = output else {
+                    return;
+                };
+                assert_eq!(output, expected, "{code} at {level}");
+            }
+        }
+    }
+}
@@ -0,0 +1,7 @@
+//! Programs translated into other languages, to run without the machine.
+
+#[cfg(feature = "codegen-js")]
+pub mod js;
+
+#[cfg(feature = "codegen-llvm")]
+pub mod llvm;
@@ -0,0 +1,143 @@
+use super::{bf_token::BfToken, program::Program};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub(crate) enum OpCode {
+    Add,
+    /// `Add` to the cell `offset` cells away.
+    AddAt,
+    Left,
+    Right,
+    JumpIfZero,
+    JumpIfNonZero,
+    Output,
+    Input,
+    Extension,
+    SwitchTape,
+    Fork,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Op {
+    pub(crate) code: OpCode,
+    pub(crate) operand: usize,
+    /// The cell an `AddAt` adds to, relative to the cursor; 0 for the rest.
+    pub(crate) offset: isize,
+}
+
+/// A `Program` lowered into a dense op array: comments are dropped, `+`/`-`
+/// share one wrapping `Add` and loop operands are absolute op indices.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CompiledProgram {
+    pub(crate) ops: Vec<Op>,
+    pub(crate) pcs: Vec<usize>,
+}
+
+impl CompiledProgram {
+    pub fn compile(program: &Program) -> Self {
+        let mut ops: Vec<Op> = vec![];
+        let mut pcs = vec![];
+        let mut open = vec![];
+
+        for (pc, token) in program.tokens().iter().enumerate() {
+            let op = match *token {
+                BfToken::NotCommand(_) => continue,
+                BfToken::Increment(val) => Op::new(OpCode::Add, val as usize),
+                BfToken::Decrement(val) => Op::new(OpCode::Add, val.wrapping_neg() as usize),
+                BfToken::IncrementAt(offset, val) => Op::at(offset, val as usize),
+                BfToken::DecrementAt(offset, val) => Op::at(offset, val.wrapping_neg() as usize),
+                BfToken::CursorLeft(val) => Op::new(OpCode::Left, val),
+                BfToken::CursorRight(val) => Op::new(OpCode::Right, val),
+                BfToken::LoopStart => {
+                    open.push(ops.len());
+                    Op::new(OpCode::JumpIfZero, 0)
+                }
+                BfToken::LoopEnd => {
+                    let start = open.pop().expect("program loops are balanced");
+                    ops[start].operand = ops.len();
+                    Op::new(OpCode::JumpIfNonZero, start)
+                }
+                BfToken::PrintChar => Op::new(OpCode::Output, 1),
+                BfToken::PrintCharN(count) => Op::new(OpCode::Output, count),
+                BfToken::InputChar => Op::new(OpCode::Input, 0),
+                BfToken::Extension(id) => Op::new(OpCode::Extension, id as usize),
+                BfToken::SwitchTape => Op::new(OpCode::SwitchTape, 0),
+                BfToken::Fork => Op::new(OpCode::Fork, 0),
+            };
+            ops.push(op);
+            pcs.push(pc);
+        }
+
+        Self { ops, pcs }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl Op {
+    fn new(code: OpCode, operand: usize) -> Self {
+        Self {
+            code,
+            operand,
+            offset: 0,
+        }
+    }
+
+    fn at(offset: isize, operand: usize) -> Self {
+        Self {
+            code: OpCode::AddAt,
+            operand,
+            offset,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compile_loops() {
+        let program = Program::parse_optimized("a+[-->+<] b.").unwrap();
+        let compiled = CompiledProgram::compile(&program);
+
+        #[cfg(feature = "optimizer")]
+        let expected = vec![
+            Op::new(OpCode::Add, 1),
+            Op::new(OpCode::JumpIfZero, 4),
+            Op::new(OpCode::Add, 254),
+            Op::at(1, 1),
+            Op::new(OpCode::JumpIfNonZero, 1),
+            Op::new(OpCode::Output, 1),
+        ];
+        // Without the optimizer the moves are not sunk.
+        #[cfg(not(feature = "optimizer"))]
+        let expected = vec![
+            Op::new(OpCode::Add, 1),
+            Op::new(OpCode::JumpIfZero, 6),
+            Op::new(OpCode::Add, 254),
+            Op::new(OpCode::Right, 1),
+            Op::new(OpCode::Add, 1),
+            Op::new(OpCode::Left, 1),
+            Op::new(OpCode::JumpIfNonZero, 1),
+            Op::new(OpCode::Output, 1),
+        ];
+        assert_eq!(compiled.ops, expected);
+    }
+
+    #[test]
+    fn comments_are_dropped() {
+        let program = Program::parse("a+ b.").unwrap();
+        let compiled = CompiledProgram::compile(&program);
+
+        assert_eq!(compiled.len(), 2);
+        assert_eq!(compiled.pcs, vec![1, 4]);
+        assert!(CompiledProgram::compile(&Program::parse("only text").unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Write as _},
+    fs,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, BfSnapshot},
+    bytecode,
+    cell::Cell,
+    program::Program,
+    trace::TraceRecord,
+};
+
+/// Cells shown on either side of the cursor by [`CoreDump::report`].
+const TAPE_CONTEXT: usize = 8;
+
+/// The last steps of a run, keeping at most a fixed number and dropping the
+/// oldest.
+#[derive(Debug, Clone)]
+pub struct RecentSteps<C = u8> {
+    records: VecDeque<TraceRecord<C>>,
+    capacity: usize,
+}
+
+impl<C> RecentSteps<C> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, record: TraceRecord<C>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    /// Oldest first.
+    pub fn records(&self) -> impl Iterator<Item = &TraceRecord<C>> {
+        self.records.iter()
+    }
+}
+
+impl<R: Read, W: Write, C: Cell> BfMachine<R, W, C> {
+    /// Like [`BfMachine::run_from`], keeping the last steps in `recent`.
+    pub fn run_recording(
+        &mut self,
+        program: &Program,
+        pc: usize,
+        recent: &mut RecentSteps<C>,
+    ) -> Result<(), BfRuntimeError> {
+        let mut record = |record| {
+            recent.push(record);
+            Ok(())
+        };
+        self.execute(program, pc, Some(&mut record), None)
+            .map(|_| ())
+    }
+}
+
+/// The error that ended a run, as a stable kind and its message.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct DumpedError {
+    /// Such as `unexpected_eof` or `memory_limit`, for the error beneath the
+    /// loops it happened in.
+    pub kind: String,
+    pub message: String,
+}
+
+impl From<&BfRuntimeError> for DumpedError {
+    fn from(err: &BfRuntimeError) -> Self {
+        let kind = match err.root_cause() {
+            BfRuntimeError::UnexpectedEof { .. } => "unexpected_eof",
+            BfRuntimeError::Io { .. } => "io",
+            BfRuntimeError::StepLimitExceeded { .. } => "step_limit",
+            BfRuntimeError::OutputLimitExceeded { .. } => "output_limit",
+            BfRuntimeError::MemoryLimitExceeded { .. } => "memory_limit",
+            BfRuntimeError::CursorUnderflow { .. } => "cursor_underflow",
+            BfRuntimeError::UnhandledExtension { .. } => "unhandled_extension",
+            BfRuntimeError::HostError { .. } => "host_error",
+            BfRuntimeError::WriteProtected { .. } => "write_protected",
+            BfRuntimeError::UnhandledFork { .. } => "unhandled_fork",
+            BfRuntimeError::InLoops { .. } => unreachable!("the root cause is not in loops"),
+        };
+        Self {
+            kind: kind.to_string(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Everything left of a run that failed, for a look afterwards: the machine
+/// state, where it stopped and why, and the steps leading up to it. A dump of
+/// byte cells also loads as one of wider cells.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct CoreDump<C = u8> {
+    /// FNV-1a of the program text.
+    pub program_hash: u64,
+    pub pc: usize,
+    pub error: DumpedError,
+    pub state: BfSnapshot<C>,
+    /// Oldest first.
+    pub recent: Vec<TraceRecord<C>>,
+}
+
+impl<C: Cell> CoreDump<C> {
+    pub fn new<R: Read, W: Write>(
+        code: &str,
+        err: &BfRuntimeError,
+        machine: &BfMachine<R, W, C>,
+        recent: &RecentSteps<C>,
+    ) -> Self {
+        Self {
+            program_hash: bytecode::fnv1a(code.as_bytes()),
+            pc: err.pc(),
+            error: err.into(),
+            state: machine.snapshot(),
+            recent: recent.records().copied().collect(),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_string(self)?;
+        fs::write(path, json)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    /// The dump as text: the error, the cells around the cursor and the
+    /// recent steps.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "{} at pc {}", self.error.kind, self.pc).unwrap();
+        writeln!(report, "{}", self.error.message).unwrap();
+
+        report.push('\n');
+        match &self.state.other_tape {
+            None => write_cells(&mut report, &self.state.memory, self.state.cursor),
+            Some(other) => {
+                let active = self.state.active_tape();
+                let mut tapes = [
+                    (active, &self.state.memory, self.state.cursor),
+                    (other.tape, &other.memory, other.cursor),
+                ];
+                tapes.sort_by_key(|&(tape, ..)| tape);
+                for (tape, memory, cursor) in tapes {
+                    let label = if tape == active { " (active)" } else { "" };
+                    write!(report, "tape {tape}{label}: ").unwrap();
+                    write_cells(&mut report, memory, cursor);
+                }
+            }
+        }
+
+        writeln!(report, "\nlast {} steps:", self.recent.len()).unwrap();
+        for record in &self.recent {
+            writeln!(report, "{}", Step(record)).unwrap();
+        }
+        report
+    }
+}
+
+/// Writes the cells around `cursor` as a line of a report.
+fn write_cells(report: &mut String, tape: &[impl Display], cursor: usize) {
+    let start = cursor.saturating_sub(TAPE_CONTEXT);
+    let end = cursor.saturating_add(TAPE_CONTEXT + 1).min(tape.len());
+    write!(report, "cursor {cursor}, cells {start}..{end}:").unwrap();
+    for (cell, value) in tape[start..end].iter().enumerate() {
+        if start + cell == cursor {
+            write!(report, " [{value}]").unwrap();
+        } else {
+            write!(report, " {value}").unwrap();
+        }
+    }
+    writeln!(report).unwrap();
+}
+
+/// A trace record as one line of a report.
+struct Step<'a, C>(&'a TraceRecord<C>);
+
+impl<C: Display> Display for Step<'_, C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let record = self.0;
+        write!(
+            f,
+            "{:>8}  pc {:<6} {:<16} cell {} = {}",
+            record.step,
+            record.pc,
+            format!("{:?}", record.token),
+            record.cursor,
+            record.cell
+        )?;
+        if let Some(byte) = record.input {
+            write!(f, ", read {byte}")?;
+        }
+        if let Some(byte) = record.output {
+            write!(f, ", wrote {byte}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_parser::ParseOptions, bf_token::BfToken, program::OptLevel};
+
+    use super::*;
+
+    #[test]
+    fn keeps_the_last_steps() {
+        let mut recent = RecentSteps::new(3);
+        let program = Program::parse("+++++").unwrap();
+        let mut machine = BfMachine::new(4, Cursor::new(b""), vec![]);
+        machine.run_recording(&program, 0, &mut recent).unwrap();
+
+        let steps: Vec<_> = recent.records().map(|record| record.step).collect();
+        assert_eq!(steps, [3, 4, 5]);
+    }
+
+    #[test]
+    fn reports_both_tapes() {
+        let code = "+++++^+++++++^.^,";
+        let options = ParseOptions {
+            multi_tape: true,
+            ..Default::default()
+        };
+        let program = Program::parse_at(code, &options, OptLevel::O0).unwrap();
+        let mut machine = BfMachine::new(4, Cursor::new(b""), vec![]);
+        let mut recent = RecentSteps::new(1);
+        let err = machine.run_recording(&program, 0, &mut recent).unwrap_err();
+        assert_eq!(machine.bytes_written(), 1);
+
+        let report = CoreDump::new(code, &err, &machine, &recent).report();
+        assert!(
+            report.contains(
+                "tape 0: cursor 0, cells 0..4: [5] 0 0 0\n\
+                 tape 1 (active): cursor 0, cells 0..4: [7] 0 0 0\n"
+            ),
+            "{report}"
+        );
+    }
+
+    #[test]
+    fn dump_round_trip() {
+        let code = "+>++[>,<-]";
+        let program = Program::parse(code).unwrap();
+        let mut machine = BfMachine::new(8, Cursor::new(b"a"), vec![]);
+        let mut recent = RecentSteps::new(4);
+        let err = machine.run_recording(&program, 0, &mut recent).unwrap_err();
+
+        let dump = CoreDump::new(code, &err, &machine, &recent);
+        let path = std::env::temp_dir().join(format!("bf-rust-{}.core", std::process::id()));
+        dump.save(&path).unwrap();
+        let loaded = CoreDump::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, dump);
+        assert_eq!(loaded.error.kind, "unexpected_eof");
+        assert_eq!(loaded.pc, 6);
+        assert_eq!(loaded.state.cursor, 2);
+        assert_eq!(loaded.state.memory[..3], [1, 1, b'a']);
+        assert_eq!(loaded.program_hash, bytecode::fnv1a(code.as_bytes()));
+        // The failed read is not a step that ran, so the trace ends just
+        // before it.
+        let tokens: Vec<_> = loaded.recent.iter().map(|record| record.token).collect();
+        assert_eq!(
+            tokens,
+            [
+                BfToken::CursorLeft(1),
+                BfToken::Decrement(1),
+                BfToken::LoopEnd,
+                BfToken::CursorRight(1),
+            ]
+        );
+        assert_eq!(loaded.recent.last().unwrap().step, 11);
+
+        let report = loaded.report();
+        assert!(report.starts_with("unexpected_eof at pc 6\n"), "{report}");
+        assert!(
+            report.contains("cursor 2, cells 0..8: 1 1 [97] 0"),
+            "{report}"
+        );
+        assert!(report.contains("last 4 steps:"), "{report}");
+    }
+}
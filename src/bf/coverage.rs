@@ -0,0 +1,106 @@
+/// Which instructions of a program ran at least once, one bit per token.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Coverage {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Coverage {
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(64)],
+            len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_covered(&self, pc: usize) -> bool {
+        pc < self.len && self.words[pc / 64] & (1 << (pc % 64)) != 0
+    }
+
+    pub fn covered_count(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    /// The pcs that never ran, in order.
+    pub fn uncovered(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.len).filter(|&pc| !self.is_covered(pc))
+    }
+
+    /// Makes room for a program of `len` tokens, keeping what was recorded.
+    pub(crate) fn grow(&mut self, len: usize) {
+        if len > self.len {
+            self.words.resize(len.div_ceil(64), 0);
+            self.len = len;
+        }
+    }
+
+    pub(crate) fn mark(&mut self, pc: usize) {
+        self.words[pc / 64] |= 1 << (pc % 64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{
+        bf_machine::{BfMachine, BfSnapshot},
+        bf_token::BfToken,
+        program::Program,
+    };
+
+    use super::*;
+
+    #[test]
+    fn bits() {
+        let mut coverage = Coverage::new(70);
+        coverage.mark(0);
+        coverage.mark(65);
+        assert!(coverage.is_covered(65));
+        assert!(!coverage.is_covered(64));
+        assert!(!coverage.is_covered(70));
+        assert_eq!(coverage.covered_count(), 2);
+        assert_eq!(coverage.uncovered().count(), 68);
+
+        coverage.grow(130);
+        assert_eq!(coverage.len(), 130);
+        assert!(coverage.is_covered(65));
+    }
+
+    #[test]
+    fn skipped_loop_is_uncovered() {
+        let program = Program::parse("+[->+<]>[.]").unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b""), vec![]).with_coverage();
+        let mut memory = vec![0; 10];
+        memory[1] = 255;
+        machine.restore(BfSnapshot {
+            cursor: 0,
+            memory,
+            other_tape: None,
+        });
+        machine.run(&program).unwrap();
+
+        let coverage = machine.coverage().unwrap();
+        assert_eq!(program.tokens()[9], BfToken::PrintChar);
+        assert!((0..9).all(|pc| coverage.is_covered(pc)));
+        assert_eq!(coverage.uncovered().collect::<Vec<_>>(), [9, 10]);
+    }
+
+    #[test]
+    fn off_by_default() {
+        let mut machine = BfMachine::new(10, Cursor::new(b""), vec![]);
+        machine.run(&Program::parse("+").unwrap()).unwrap();
+        assert!(machine.coverage().is_none());
+    }
+}
@@ -0,0 +1,419 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, BfSnapshot},
+    bf_token::BfToken,
+    diff::SnapshotDiff,
+    program::Program,
+};
+
+/// Why [`Debugger::step_back`] could not undo an instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepBackError {
+    /// Recording was never turned on with [`Debugger::record`].
+    NotRecording,
+    /// Every instruction run since recording started has been undone.
+    AtStart,
+    /// The instructions before these were dropped from the history, which
+    /// holds this many.
+    HistoryExhausted(usize),
+}
+
+impl Display for StepBackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotRecording => {
+                write!(f, "recording is off, so there is nothing to step back to")
+            }
+            Self::AtStart => write!(f, "already at the first recorded step"),
+            Self::HistoryExhausted(capacity) => write!(
+                f,
+                "cannot step back further: only the last {capacity} steps are recorded"
+            ),
+        }
+    }
+}
+
+impl Error for StepBackError {}
+
+/// What undoes one instruction: where it was, where the cursor was and the
+/// cells it may have changed.
+#[derive(Debug, Clone)]
+struct Undo {
+    pc: usize,
+    cursor: usize,
+    cells: Changed,
+}
+
+#[derive(Debug, Clone)]
+enum Changed {
+    None,
+    /// The index and old value of the one cell `+`, `-` or `,` wrote.
+    Cell(usize, u8),
+    /// The whole tape, as extensions may write anywhere.
+    Tape(BfSnapshot),
+    /// `^` switched tapes, which switching again undoes.
+    SwitchedTape,
+}
+
+/// The undo records of the last `capacity` instructions, oldest first.
+#[derive(Debug)]
+struct History {
+    records: VecDeque<Undo>,
+    capacity: usize,
+    /// Whether older records were dropped to make room.
+    dropped: bool,
+}
+
+/// Runs a program under control, an instruction or a loop at a time.
+///
+/// Every command that runs more than one instruction takes a step budget and
+/// stops with `BfRuntimeError::StepLimitExceeded` once it is spent, leaving
+/// the debugger at the instruction it would have run next.
+pub struct Debugger<R: Read, W: Write> {
+    machine: BfMachine<R, W>,
+    program: Program,
+    pc: usize,
+    steps: usize,
+    snapshot: Option<BfSnapshot>,
+    history: Option<History>,
+}
+
+impl<R: Read, W: Write> Debugger<R, W> {
+    pub fn new(machine: BfMachine<R, W>, program: Program) -> Self {
+        Self::with_pc(machine, program, 0)
+    }
+
+    /// A debugger paused at `pc`, e.g. to resume a run that stopped there.
+    pub fn with_pc(mut machine: BfMachine<R, W>, program: Program, pc: usize) -> Self {
+        // Single steps, so each `run_from` executes exactly one instruction.
+        machine.set_step_limit(1);
+        Self {
+            machine,
+            program,
+            pc,
+            steps: 0,
+            snapshot: None,
+            history: None,
+        }
+    }
+
+    /// Records how to undo each instruction from now on, for
+    /// [`Debugger::step_back`], keeping the last `capacity`. Output already
+    /// written and input already read are not undone. Replaces any history
+    /// recorded before.
+    pub fn record(&mut self, capacity: usize) {
+        self.history = Some(History {
+            records: VecDeque::with_capacity(capacity.min(1 << 16)),
+            capacity,
+            dropped: false,
+        });
+    }
+
+    /// The instruction that runs next.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The instructions run so far.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.pc >= self.program.tokens().len()
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    pub fn machine(&self) -> &BfMachine<R, W> {
+        &self.machine
+    }
+
+    pub fn into_machine(self) -> BfMachine<R, W> {
+        self.machine
+    }
+
+    /// Remembers the tape and cursor as they are now, for [`Debugger::diff`].
+    pub fn snapshot(&mut self) {
+        self.snapshot = Some(self.machine.snapshot());
+    }
+
+    /// What changed since the last [`Debugger::snapshot`], if one was taken.
+    pub fn diff(&self) -> Option<SnapshotDiff> {
+        let snapshot = self.snapshot.as_ref()?;
+        Some(snapshot.diff(&self.machine.snapshot()))
+    }
+
+    /// Runs one instruction, stepping into a loop rather than over it. Does
+    /// nothing once the program has halted.
+    pub fn step(&mut self) -> Result<(), BfRuntimeError> {
+        if self.is_halted() {
+            return Ok(());
+        }
+        let undo = self.history.as_ref().map(|_| self.undo_record());
+        match self.machine.run_from(&self.program, self.pc) {
+            Ok(()) => self.pc = self.program.tokens().len(),
+            Err(BfRuntimeError::StepLimitExceeded { pc }) => self.pc = pc,
+            Err(err) => return Err(err),
+        }
+        self.steps += 1;
+        if let (Some(history), Some(undo)) = (&mut self.history, undo) {
+            if history.records.len() == history.capacity {
+                history.records.pop_front();
+                history.dropped = true;
+            }
+            if history.capacity > 0 {
+                history.records.push_back(undo);
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes the last instruction run while recording, putting back the
+    /// cursor, the cell it changed and the pc. The program's I/O stays as it
+    /// is, and a tape that grew stays grown.
+    pub fn step_back(&mut self) -> Result<(), StepBackError> {
+        let history = self.history.as_mut().ok_or(StepBackError::NotRecording)?;
+        let Some(undo) = history.records.pop_back() else {
+            return Err(match history.dropped {
+                true => StepBackError::HistoryExhausted(history.capacity),
+                false => StepBackError::AtStart,
+            });
+        };
+        match undo.cells {
+            Changed::None => self.machine.rewind(undo.cursor, None),
+            Changed::Cell(index, value) => self.machine.rewind(undo.cursor, Some((index, value))),
+            Changed::Tape(snapshot) => self.machine.restore(snapshot),
+            Changed::SwitchedTape => {
+                self.machine.switch_tape();
+                self.machine.rewind(undo.cursor, None);
+            }
+        }
+        self.pc = undo.pc;
+        self.steps -= 1;
+        Ok(())
+    }
+
+    /// How to undo the instruction at the pc.
+    fn undo_record(&self) -> Undo {
+        let cursor = self.machine.cursor();
+        let cells = match self.program.tokens()[self.pc] {
+            BfToken::Increment(_) | BfToken::Decrement(_) | BfToken::InputChar => {
+                Changed::Cell(cursor, self.machine.tape()[cursor])
+            }
+            BfToken::IncrementAt(offset, _) | BfToken::DecrementAt(offset, _) => {
+                let tape = self.machine.tape();
+                match cursor
+                    .checked_add_signed(offset)
+                    .filter(|&at| at < tape.len())
+                {
+                    Some(at) => Changed::Cell(at, tape[at]),
+                    // Around the end of the tape, or past it on one that grows.
+                    None => Changed::Tape(self.machine.snapshot()),
+                }
+            }
+            BfToken::Extension(_) => Changed::Tape(self.machine.snapshot()),
+            BfToken::SwitchTape => Changed::SwitchedTape,
+            _ => Changed::None,
+        };
+        Undo {
+            pc: self.pc,
+            cursor,
+            cells,
+        }
+    }
+
+    /// Steps over a loop: at a `[`, runs until the loop it starts has exited,
+    /// however often its body repeats. Anywhere else, the same as `step`.
+    pub fn next(&mut self, budget: usize) -> Result<(), BfRuntimeError> {
+        match self.program.tokens().get(self.pc) {
+            Some(BfToken::LoopStart) => {
+                let end = self.program.jump_table()[self.pc];
+                self.run_until(end + 1, budget)
+            }
+            _ => self.run_until_stepped(budget),
+        }
+    }
+
+    /// Runs until the innermost loop around the current instruction exits,
+    /// or to the end of the program outside of any loop.
+    pub fn finish(&mut self, budget: usize) -> Result<(), BfRuntimeError> {
+        let tokens = self.program.tokens();
+        let jump_table = self.program.jump_table();
+        let target = (0..self.pc.min(tokens.len()))
+            .rev()
+            .find(|&start| tokens[start] == BfToken::LoopStart && jump_table[start] >= self.pc)
+            .map_or(tokens.len(), |start| jump_table[start] + 1);
+        self.run_until(target, budget)
+    }
+
+    fn run_until_stepped(&mut self, budget: usize) -> Result<(), BfRuntimeError> {
+        if budget == 0 && !self.is_halted() {
+            return Err(BfRuntimeError::StepLimitExceeded { pc: self.pc });
+        }
+        self.step()
+    }
+
+    /// Steps until `pc` reaches `target` or the program halts. Leaving a loop
+    /// is the only way to reach the instruction after its `]`, so the first
+    /// arrival there is the exit of the activation being waited for.
+    fn run_until(&mut self, target: usize, budget: usize) -> Result<(), BfRuntimeError> {
+        for _ in 0..budget {
+            self.step()?;
+            if self.pc == target || self.is_halted() {
+                return Ok(());
+            }
+        }
+        if self.is_halted() {
+            return Ok(());
+        }
+        Err(BfRuntimeError::StepLimitExceeded { pc: self.pc })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    fn debugger(code: &str) -> Debugger<Cursor<&'static [u8]>, Vec<u8>> {
+        let machine = BfMachine::new(10, Cursor::new(&b""[..]), vec![]);
+        Debugger::new(machine, Program::parse(code).unwrap())
+    }
+
+    #[test]
+    fn step_over_and_into_loops() {
+        let mut over = debugger("++[->+<]>.");
+        over.step().unwrap();
+        over.step().unwrap();
+        assert_eq!(over.pc(), 2);
+
+        over.next(usize::MAX).unwrap();
+        assert_eq!(over.pc(), 8);
+        assert_eq!(over.steps(), 2 + 11);
+        assert_eq!(over.machine().tape()[..2], [0, 2]);
+
+        let mut into = debugger("++[->+<]>.");
+        into.run_until(2, 2).unwrap();
+        into.step().unwrap();
+        assert_eq!(into.pc(), 3);
+        assert_eq!(into.machine().tape()[..2], [2, 0]);
+
+        into.finish(usize::MAX).unwrap();
+        assert_eq!(into.pc(), 8);
+        into.finish(usize::MAX).unwrap();
+        assert!(into.is_halted());
+        assert_eq!(into.into_machine().into_parts().3, [2]);
+    }
+
+    #[test]
+    fn nested_loops_and_budgets() {
+        // The inner loop's exit is passed on every outer iteration.
+        let mut debugger = debugger("+++[>++[-]<-]>.");
+        debugger.run_until(3, 3).unwrap();
+        debugger.next(usize::MAX).unwrap();
+        assert_eq!(debugger.pc(), 13);
+        assert_eq!(debugger.machine().tape()[..2], [0, 0]);
+
+        let mut debugger = self::debugger("+++[>++[-]<-]>.");
+        debugger.run_until(8, 8).unwrap();
+        assert!(matches!(
+            debugger.finish(2),
+            Err(BfRuntimeError::StepLimitExceeded { pc: 8 })
+        ));
+        debugger.finish(usize::MAX).unwrap();
+        assert_eq!(debugger.pc(), 10);
+
+        assert!(debugger.next(0).is_err());
+        debugger.next(1).unwrap();
+        assert_eq!(debugger.pc(), 11);
+    }
+
+    #[test]
+    fn diff_against_the_last_snapshot() {
+        let mut debugger = debugger("+>++[-]");
+        assert_eq!(debugger.diff(), None);
+        debugger.snapshot();
+        debugger.finish(usize::MAX).unwrap();
+
+        let diff = debugger.diff().unwrap();
+        assert_eq!(diff.cells.len(), 1);
+        assert_eq!((diff.cells[0].index, diff.cells[0].after), (0, 1));
+        assert_eq!(diff.cursor_delta(), 1);
+
+        debugger.snapshot();
+        assert!(debugger.diff().unwrap().is_empty());
+    }
+
+    #[test]
+    fn skipped_loops_and_errors() {
+        let mut debugger = debugger("[.]>,");
+        debugger.next(1).unwrap();
+        assert_eq!(debugger.pc(), 3);
+        debugger.next(1).unwrap();
+        assert!(matches!(
+            debugger.next(1),
+            Err(BfRuntimeError::UnexpectedEof { pc: 4 })
+        ));
+        assert_eq!(debugger.pc(), 4);
+
+        let machine = BfMachine::new(10, io::empty(), io::sink());
+        let mut debugger = Debugger::with_pc(machine, Program::parse("+").unwrap(), 1);
+        assert!(debugger.is_halted());
+        debugger.step().unwrap();
+        debugger.finish(0).unwrap();
+        assert_eq!(debugger.steps(), 0);
+    }
+
+    #[test]
+    fn stepping_back_undoes_steps() {
+        let mut debugger = debugger("+++[>++<-]>[->+>+<<]<+");
+        let initial = debugger.machine().snapshot();
+        debugger.record(100);
+        for _ in 0..50 {
+            debugger.step().unwrap();
+        }
+        assert_eq!(debugger.steps(), 50);
+        assert_ne!(debugger.machine().snapshot(), initial);
+
+        for _ in 0..50 {
+            debugger.step_back().unwrap();
+        }
+        assert_eq!(debugger.machine().snapshot(), initial);
+        assert_eq!((debugger.pc(), debugger.steps()), (0, 0));
+        assert_eq!(debugger.step_back(), Err(StepBackError::AtStart));
+
+        // Stepping forward again takes the same path.
+        debugger.finish(usize::MAX).unwrap();
+        assert_eq!(debugger.machine().tape()[..4], [1, 0, 6, 6]);
+    }
+
+    #[test]
+    fn stepping_back_past_the_history() {
+        let mut debugger = debugger("+>+>+>+>+");
+        assert_eq!(debugger.step_back(), Err(StepBackError::NotRecording));
+
+        debugger.record(3);
+        debugger.finish(usize::MAX).unwrap();
+        for _ in 0..3 {
+            debugger.step_back().unwrap();
+        }
+        assert_eq!(debugger.pc(), 6);
+        assert_eq!(debugger.machine().tape()[..5], [1, 1, 1, 0, 0]);
+        let err = debugger.step_back().unwrap_err();
+        assert_eq!(err, StepBackError::HistoryExhausted(3));
+        assert_eq!(
+            err.to_string(),
+            "cannot step back further: only the last 3 steps are recorded"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+use std::fmt::Display;
+
+use super::bf_parser::ParseWarning;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub index: usize,
+    /// Where the flagged code ends, one past its last character, if known.
+    pub end: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn warning(code: &'static str, index: usize, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            code,
+            index,
+            end: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn error(code: &'static str, index: usize, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            index,
+            end: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<ParseWarning> for Diagnostic {
+    fn from(warning: ParseWarning) -> Self {
+        Self {
+            end: Some(warning.span.end),
+            ..Self::warning(warning.code, warning.span.start, warning.message)
+        }
+    }
+}
+
+pub fn line_col(source: &str, index: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut col = 1;
+
+    for ch in source.chars().take(index) {
+        if ch == '\n' {
+            line += 1;
+            col = 1;
+        } else {
+            col += 1;
+        }
+    }
+
+    (line, col)
+}
+
+const TAB_WIDTH: usize = 4;
+const MAX_LINE_WIDTH: usize = 80;
+
+pub fn render_snippet(source: &str, index: usize, message: &str) -> String {
+    let (line, col) = line_col(source, index);
+    let text = source.split('\n').nth(line - 1).unwrap_or("");
+
+    let mut expanded = vec![];
+    let mut caret = None;
+    for (position, ch) in text.trim_end_matches('\r').chars().enumerate() {
+        if position + 1 == col {
+            caret = Some(expanded.len());
+        }
+        match ch {
+            '\t' => expanded.extend([' '; TAB_WIDTH]),
+            _ => expanded.push(ch),
+        }
+    }
+    let mut caret = caret.unwrap_or(expanded.len());
+
+    let mut shown: String = expanded.iter().collect();
+    if expanded.len() > MAX_LINE_WIDTH {
+        let end = (caret.saturating_sub(MAX_LINE_WIDTH / 2) + MAX_LINE_WIDTH).min(expanded.len());
+        let start = end - MAX_LINE_WIDTH;
+
+        shown = expanded[start..end].iter().collect();
+        caret -= start;
+        if start > 0 {
+            shown.insert_str(0, "...");
+            caret += 3;
+        }
+        if end < expanded.len() {
+            shown.push_str("...");
+        }
+    }
+
+    let gutter = " ".repeat(line.to_string().len());
+    format!(
+        "{message}\n{gutter}--> {line}:{col}\n{gutter} |\n{line} | {shown}\n{gutter} | {}^",
+        " ".repeat(caret)
+    )
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_and_column() {
+        let source = "+-\n[>\n\n<]";
+
+        assert_eq!(line_col(source, 0), (1, 1));
+        assert_eq!(line_col(source, 1), (1, 2));
+        assert_eq!(line_col(source, 3), (2, 1));
+        assert_eq!(line_col(source, 4), (2, 2));
+        assert_eq!(line_col(source, 6), (3, 1));
+        assert_eq!(line_col(source, 8), (4, 2));
+        assert_eq!(line_col(source, 9), (4, 3));
+    }
+
+    #[test]
+    fn multi_byte_characters() {
+        assert_eq!(line_col("é+", 1), (1, 2));
+        assert_eq!(line_col("日本\n語+", 4), (2, 2));
+    }
+
+    #[test]
+    fn snippet_in_middle_of_line() {
+        let source = "+++\n++[>+\n<-";
+
+        assert_eq!(
+            render_snippet(source, 6, "error: unclosed loop"),
+            "error: unclosed loop
+ --> 2:3
+  |
+2 | ++[>+
+  |   ^"
+        );
+    }
+
+    #[test]
+    fn snippet_with_wide_gutter() {
+        let source = format!("{}]", "\n".repeat(11));
+
+        assert_eq!(
+            render_snippet(&source, 11, "error: unmatched bracket"),
+            "error: unmatched bracket
+  --> 12:1
+   |
+12 | ]
+   | ^"
+        );
+    }
+
+    #[test]
+    fn snippet_expands_tabs() {
+        assert_eq!(
+            render_snippet("\t+\t[", 3, "error"),
+            "error
+ --> 1:4
+  |
+1 |     +    [
+  |          ^"
+        );
+    }
+
+    #[test]
+    fn snippet_truncates_long_lines() {
+        let source = format!("{}[{}", "+".repeat(100), "-".repeat(100));
+        let snippet = render_snippet(&source, 100, "error");
+        let lines: Vec<_> = snippet.lines().collect();
+
+        assert_eq!(
+            lines[3],
+            format!("1 | ...{}[{}...", "+".repeat(40), "-".repeat(39))
+        );
+        assert_eq!(lines[4], format!("  | {}^", " ".repeat(43)));
+    }
+
+    #[test]
+    fn snippet_on_last_character() {
+        let source = "++\n+[";
+
+        assert_eq!(
+            render_snippet(source, 4, "error: unclosed loop"),
+            "error: unclosed loop
+ --> 2:2
+  |
+2 | +[
+  |  ^"
+        );
+    }
+
+    #[test]
+    fn snippet_at_end_of_file() {
+        assert_eq!(
+            render_snippet("+,\n", 3, "error: end of input"),
+            "error: end of input
+ --> 2:1
+  |
+2 | 
+  | ^"
+        );
+        assert_eq!(
+            render_snippet("+,", 2, "error"),
+            "error
+ --> 1:3
+  |
+1 | +,
+  |   ^"
+        );
+    }
+}
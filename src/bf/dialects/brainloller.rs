@@ -0,0 +1,244 @@
+use std::{error::Error, fmt::Display, fs, path::Path};
+
+use png::{Decoder, Transformations};
+
+use crate::bf::bf_token::BfToken;
+
+/// Why an image could not be read as a BrainLoller program.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BrainLollerError {
+    /// The file could not be read or is not a PNG image.
+    Image(String),
+    /// The walk left the canvas with the loop opened at this pixel still open.
+    Unclosed { x: usize, y: usize },
+    /// The loop end at this pixel has no loop to close.
+    Unopened { x: usize, y: usize },
+}
+
+impl Display for BrainLollerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Image(message) => write!(f, "{message}"),
+            Self::Unclosed { x, y } => write!(
+                f,
+                "walked off the canvas with the loop at pixel ({x}, {y}) still open"
+            ),
+            Self::Unopened { x, y } => {
+                write!(f, "the loop end at pixel ({x}, {y}) has no loop to close")
+            }
+        }
+    }
+}
+
+impl Error for BrainLollerError {}
+
+/// Reads a BrainLoller program from PNG `bytes`.
+///
+/// The walk starts at the top-left pixel heading right and ends when it steps
+/// off the canvas, which every path does eventually. Red and dark red are `>`
+/// and `<`, green and dark green `+` and `-`, blue and dark blue `.` and `,`,
+/// yellow and dark yellow `[` and `]`, where the dark shades have 128 in place
+/// of 255. Cyan turns the walk clockwise and dark cyan counter-clockwise. Every
+/// other color is skipped, and alpha is ignored.
+pub fn parse_image(bytes: &[u8]) -> Result<Vec<BfToken>, BrainLollerError> {
+    let canvas = Canvas::decode(bytes)?;
+    let (mut x, mut y) = (0, 0);
+    let mut heading = Heading::East;
+    let mut tokens = vec![];
+    let mut open = vec![];
+
+    loop {
+        let token = match canvas.pixel(x, y) {
+            [255, 0, 0] => Some(BfToken::CursorRight(1)),
+            [128, 0, 0] => Some(BfToken::CursorLeft(1)),
+            [0, 255, 0] => Some(BfToken::Increment(1)),
+            [0, 128, 0] => Some(BfToken::Decrement(1)),
+            [0, 0, 255] => Some(BfToken::PrintChar),
+            [0, 0, 128] => Some(BfToken::InputChar),
+            [255, 255, 0] => Some(BfToken::LoopStart),
+            [128, 128, 0] => Some(BfToken::LoopEnd),
+            [0, 255, 255] => {
+                heading = heading.clockwise();
+                None
+            }
+            [0, 128, 128] => {
+                heading = heading.clockwise().clockwise().clockwise();
+                None
+            }
+            _ => None,
+        };
+        match token {
+            Some(BfToken::LoopStart) => open.push((x, y)),
+            Some(BfToken::LoopEnd) if open.pop().is_none() => {
+                return Err(BrainLollerError::Unopened { x, y })
+            }
+            _ => {}
+        }
+        tokens.extend(token);
+
+        match canvas.step(x, y, heading) {
+            Some(next) => (x, y) = next,
+            None => break,
+        }
+    }
+
+    match open.pop() {
+        Some((x, y)) => Err(BrainLollerError::Unclosed { x, y }),
+        None => Ok(tokens),
+    }
+}
+
+/// [`parse_image`] on the file at `path`.
+pub fn parse_image_file(path: impl AsRef<Path>) -> Result<Vec<BfToken>, BrainLollerError> {
+    let bytes = fs::read(path).map_err(|err| BrainLollerError::Image(err.to_string()))?;
+    parse_image(&bytes)
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Heading {
+    East,
+    South,
+    West,
+    North,
+}
+
+impl Heading {
+    fn clockwise(self) -> Self {
+        match self {
+            Self::East => Self::South,
+            Self::South => Self::West,
+            Self::West => Self::North,
+            Self::North => Self::East,
+        }
+    }
+}
+
+/// An image as 8-bit RGB pixels, row by row.
+struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl Canvas {
+    fn decode(bytes: &[u8]) -> Result<Self, BrainLollerError> {
+        let image_error = |err: png::DecodingError| BrainLollerError::Image(err.to_string());
+        let mut decoder = Decoder::new(bytes);
+        // Palettes, low bit depths and 16-bit samples all become 8-bit gray or
+        // color.
+        decoder.set_transformations(Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info().map_err(image_error)?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buffer).map_err(image_error)?;
+
+        let pixels = buffer[..info.buffer_size()]
+            .chunks_exact(info.color_type.samples())
+            .map(|samples| match *samples {
+                [gray] | [gray, _] => [gray; 3],
+                [red, green, blue, ..] => [red, green, blue],
+                [] => unreachable!("every color type has samples"),
+            })
+            .collect();
+        Ok(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+        })
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    /// The pixel after `(x, y)` heading `heading`, unless that is off the
+    /// canvas.
+    fn step(&self, x: usize, y: usize, heading: Heading) -> Option<(usize, usize)> {
+        let (x, y) = match heading {
+            Heading::East => (x + 1, y),
+            Heading::South => (x, y + 1),
+            Heading::West => (x.checked_sub(1)?, y),
+            Heading::North => (x, y.checked_sub(1)?),
+        };
+        (x < self.width && y < self.height).then_some((x, y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use png::{ColorType, Encoder};
+
+    use crate::bf::bf_parser::BfParser;
+
+    use super::*;
+
+    const INC: [u8; 3] = [0, 255, 0];
+    const PRINT: [u8; 3] = [0, 0, 255];
+    const START: [u8; 3] = [255, 255, 0];
+    const END: [u8; 3] = [128, 128, 0];
+    const CW: [u8; 3] = [0, 255, 255];
+    const CCW: [u8; 3] = [0, 128, 128];
+    const NOP: [u8; 3] = [0, 0, 0];
+
+    fn png(rows: &[&[[u8; 3]]]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut encoder = Encoder::new(&mut bytes, rows[0].len() as u32, rows.len() as u32);
+        encoder.set_color(ColorType::Rgb);
+        let data: Vec<u8> = rows.iter().flat_map(|row| row.concat()).collect();
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&data)
+            .unwrap();
+        bytes
+    }
+
+    fn source(rows: &[&[[u8; 3]]]) -> String {
+        BfParser::to_source(&parse_image(&png(rows)).unwrap())
+    }
+
+    #[test]
+    fn reads_commands_left_to_right() {
+        assert_eq!(source(&[&[INC, [9, 9, 9], PRINT]]), "+.");
+        assert_eq!(
+            source(&[&[
+                [255, 0, 0],
+                [128, 0, 0],
+                INC,
+                [0, 128, 0],
+                PRINT,
+                [0, 0, 128],
+                START,
+                END
+            ]]),
+            "><+-.,[]"
+        );
+    }
+
+    #[test]
+    fn follows_rotations() {
+        // Right along the top, down the right edge, then left along the
+        // bottom.
+        assert_eq!(
+            source(&[&[INC, INC, CW], &[NOP, NOP, INC], &[PRINT, INC, CW]]),
+            "++++."
+        );
+        // Turning up at the start walks straight off the canvas.
+        assert_eq!(source(&[&[CCW, INC], &[INC, INC]]), "");
+    }
+
+    #[test]
+    fn rejects_unbalanced_loops() {
+        assert_eq!(
+            parse_image(&png(&[&[INC, START, INC]])),
+            Err(BrainLollerError::Unclosed { x: 1, y: 0 })
+        );
+        assert_eq!(
+            parse_image(&png(&[&[CW, NOP], &[END, START]])),
+            Err(BrainLollerError::Unopened { x: 0, y: 1 })
+        );
+        assert!(matches!(
+            parse_image(b"not a png"),
+            Err(BrainLollerError::Image(_))
+        ));
+    }
+}
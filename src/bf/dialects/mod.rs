@@ -0,0 +1,7 @@
+//! Languages that encode Brainfuck some other way, read into [`BfToken`]s.
+//!
+//! [`BfToken`]: super::bf_token::BfToken
+
+#[cfg(feature = "image")]
+pub mod brainloller;
+pub mod ook;
@@ -0,0 +1,147 @@
+use std::{error::Error, fmt::Display};
+
+use crate::bf::{bf_token::BfToken, lower};
+
+/// Commands per line in what [`emit`] writes.
+const LINE_COMMANDS: usize = 8;
+
+/// Why source could not be read as Ook!.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OokError {
+    /// The last word, at this byte offset, has no second word to pair with.
+    Unpaired(usize),
+    /// `Ook? Ook?`, which is no command, starts at this byte offset.
+    Unknown(usize),
+}
+
+impl Display for OokError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unpaired(offset) => write!(f, "the Ook at byte {offset} has no pair"),
+            Self::Unknown(offset) => write!(f, "`Ook? Ook?` at byte {offset} is no command"),
+        }
+    }
+}
+
+impl Error for OokError {}
+
+/// The two words of every command.
+const COMMANDS: [(char, char, BfToken); 8] = [
+    ('.', '?', BfToken::CursorRight(1)),
+    ('?', '.', BfToken::CursorLeft(1)),
+    ('.', '.', BfToken::Increment(1)),
+    ('!', '!', BfToken::Decrement(1)),
+    ('!', '.', BfToken::PrintChar),
+    ('.', '!', BfToken::InputChar),
+    ('!', '?', BfToken::LoopStart),
+    ('?', '!', BfToken::LoopEnd),
+];
+
+/// Reads Ook! source, where each command is a pair of the words `Ook.`,
+/// `Ook?` and `Ook!`. Everything between the words is a comment. Loops are
+/// not checked to pair up.
+pub fn parse(code: &str) -> Result<Vec<BfToken>, OokError> {
+    let words: Vec<(usize, char)> = code
+        .match_indices("Ook")
+        .filter_map(|(offset, _)| {
+            let mark = code[offset + 3..].chars().next()?;
+            matches!(mark, '.' | '?' | '!').then_some((offset, mark))
+        })
+        .collect();
+
+    words
+        .chunks(2)
+        .map(|pair| match *pair {
+            [(offset, first), (_, second)] => COMMANDS
+                .iter()
+                .find(|&&(a, b, _)| (a, b) == (first, second))
+                .map(|&(_, _, token)| token)
+                .ok_or(OokError::Unknown(offset)),
+            [(offset, _)] => Err(OokError::Unpaired(offset)),
+            _ => unreachable!("chunks of two"),
+        })
+        .collect()
+}
+
+/// The Ook! words of one standard command, or `None` for comments and
+/// extensions.
+fn command(token: BfToken) -> Option<(char, char)> {
+    COMMANDS
+        .iter()
+        .find(|&&(_, _, command)| command == token)
+        .map(|&(first, second, _)| (first, second))
+}
+
+/// Writes `tokens` as Ook! source, [`LINE_COMMANDS`] commands to a line.
+/// Counted tokens are expanded through [`lower::to_standard`] first; comments
+/// and extensions have no Ook! form and are left out.
+pub fn emit(tokens: &[BfToken]) -> String {
+    let commands: Vec<_> = lower::to_standard(tokens)
+        .into_iter()
+        .filter_map(command)
+        .map(|(first, second)| format!("Ook{first} Ook{second}"))
+        .collect();
+    let mut ook = String::new();
+    for line in commands.chunks(LINE_COMMANDS) {
+        ook.push_str(&line.join(" "));
+        ook.push('\n');
+    }
+    ook
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_machine::BfMachine, bf_parser::BfParser, program::Program};
+
+    use super::*;
+
+    const HELLO_WORLD: &str = include_str!("../../../tests/programs/hello_world.b");
+
+    fn run(tokens: Vec<BfToken>) -> Vec<u8> {
+        let program = Program::from_tokens(tokens).unwrap();
+        let mut output = vec![];
+        BfMachine::new(30_000, Cursor::new(b""), &mut output)
+            .run(&program)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn hello_world_through_ook() {
+        let program = Program::parse_optimized(HELLO_WORLD).unwrap();
+        let ook = emit(program.tokens());
+        assert!(ook.starts_with("Ook. Ook. Ook. Ook."), "{ook}");
+        assert!(ook
+            .lines()
+            .all(|line| line.split(' ').count() <= 2 * LINE_COMMANDS));
+
+        assert_eq!(run(parse(&ook).unwrap()), b"Hello World!\n");
+    }
+
+    #[test]
+    fn back_to_brainfuck() {
+        let tokens = BfParser::parse("+[->,.<]").unwrap();
+        let ook = "Ook. Ook. Ook! Ook? Ook! Ook!\nOok. Ook? comment Ook. Ook! Ook! Ook.\n\
+                   Ook? Ook. Ook? Ook!";
+        assert_eq!(parse(ook).unwrap(), tokens);
+        assert_eq!(parse(&emit(&tokens)).unwrap(), tokens);
+
+        let standard = BfParser::parse(HELLO_WORLD).unwrap();
+        let commands: Vec<_> = standard
+            .into_iter()
+            .filter(|token| !matches!(token, BfToken::NotCommand(_)))
+            .collect();
+        assert_eq!(parse(&emit(&commands)).unwrap(), commands);
+    }
+
+    #[test]
+    fn malformed_ook() {
+        assert_eq!(parse("Ook. Ook. Ook!"), Err(OokError::Unpaired(10)));
+        assert_eq!(parse("Ook. Ook. Ook? Ook?"), Err(OokError::Unknown(10)));
+        // A word needs its mark right after it.
+        assert_eq!(parse("Ook Ook. Ook."), Ok(vec![BfToken::Increment(1)]));
+        assert_eq!(emit(&[BfToken::Extension(1), BfToken::NotCommand('x')]), "");
+    }
+}
@@ -0,0 +1,140 @@
+use std::fmt::Display;
+
+use super::bf_machine::BfSnapshot;
+
+/// A cell whose value differs between two snapshots.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct CellDiff {
+    pub index: usize,
+    pub before: u8,
+    pub after: u8,
+}
+
+/// What changed from one snapshot to another. Displays as a compact listing
+/// that elides the unchanged cells between changes.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SnapshotDiff {
+    pub cells: Vec<CellDiff>,
+    pub cursor_before: usize,
+    pub cursor_after: usize,
+}
+
+impl BfSnapshot {
+    /// The changes from `self` to `other`, in order of cell. Cells past the
+    /// end of the shorter tape, as after growth, count as 0.
+    pub fn diff(&self, other: &BfSnapshot) -> SnapshotDiff {
+        let len = self.memory.len().max(other.memory.len());
+        let cell = |memory: &[u8], index| memory.get(index).copied().unwrap_or(0);
+        let cells = (0..len)
+            .map(|index| CellDiff {
+                index,
+                before: cell(&self.memory, index),
+                after: cell(&other.memory, index),
+            })
+            .filter(|diff| diff.before != diff.after)
+            .collect();
+
+        SnapshotDiff {
+            cells,
+            cursor_before: self.cursor,
+            cursor_after: other.cursor,
+        }
+    }
+}
+
+impl SnapshotDiff {
+    /// True when neither a cell nor the cursor changed.
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty() && self.cursor_before == self.cursor_after
+    }
+
+    pub fn cursor_delta(&self) -> isize {
+        self.cursor_after as isize - self.cursor_before as isize
+    }
+}
+
+impl Display for SnapshotDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "no changes");
+        }
+        if self.cursor_before != self.cursor_after {
+            writeln!(
+                f,
+                "cursor {} -> {} ({:+})",
+                self.cursor_before,
+                self.cursor_after,
+                self.cursor_delta()
+            )?;
+        }
+
+        let mut previous: Option<usize> = None;
+        for cell in &self.cells {
+            match previous {
+                Some(index) if cell.index > index + 1 => {
+                    writeln!(f, "  ... {} unchanged", cell.index - index - 1)?;
+                }
+                _ => {}
+            }
+            writeln!(f, "  [{}] {} -> {}", cell.index, cell.before, cell.after)?;
+            previous = Some(cell.index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::bf::{bf_machine::BfMachine, program::Program};
+
+    use super::*;
+
+    #[test]
+    fn diff_after_a_run() {
+        let mut machine = BfMachine::new(10, io::empty(), io::sink());
+        let before = machine.snapshot();
+        machine.run(&Program::parse(">+>++").unwrap()).unwrap();
+        let after = machine.snapshot();
+
+        let diff = before.diff(&after);
+        assert_eq!(
+            diff.cells,
+            [
+                CellDiff {
+                    index: 1,
+                    before: 0,
+                    after: 1
+                },
+                CellDiff {
+                    index: 2,
+                    before: 0,
+                    after: 2
+                },
+            ]
+        );
+        assert_eq!(diff.cursor_delta(), 2);
+        assert!(after.diff(&after).is_empty());
+        assert_eq!(after.diff(&after).to_string(), "no changes\n");
+    }
+
+    #[test]
+    fn display_elides_unchanged_cells() {
+        let before = BfSnapshot {
+            cursor: 5,
+            memory: vec![0; 8],
+            other_tape: None,
+        };
+        let after = BfSnapshot {
+            cursor: 1,
+            memory: vec![1, 2, 0, 0, 0, 0, 0, 9, 4],
+            other_tape: None,
+        };
+
+        assert_eq!(
+            before.diff(&after).to_string(),
+            "cursor 5 -> 1 (-4)\n  [0] 0 -> 1\n  [1] 0 -> 2\n  ... 5 unchanged\n  [7] 0 -> 9\n  [8] 0 -> 4\n"
+        );
+    }
+}
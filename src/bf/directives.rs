@@ -0,0 +1,204 @@
+use std::{error::Error, fmt::Display};
+
+use super::{
+    bf_machine::{EofMode, MachineConfig, TapePolicy},
+    cell::CellSize,
+};
+
+/// Settings a program declares for itself in `#! bf: key=value ...` lines
+/// right at its top, after an optional shebang. Each is `None` unless
+/// declared; callers let their own settings win over these.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Header {
+    pub memory_size: Option<usize>,
+    pub max_steps: Option<usize>,
+    pub max_output_bytes: Option<usize>,
+    pub eof_mode: Option<EofMode>,
+    pub tape_policy: Option<TapePolicy>,
+    pub max_memory_cells: Option<usize>,
+    /// Not part of a [`MachineConfig`], so [`Header::apply`] leaves it to the
+    /// caller.
+    pub cell_size: Option<CellSize>,
+    /// Keys that are not understood, with their lines. They are skipped.
+    pub unknown: Vec<(usize, String)>,
+}
+
+impl Header {
+    /// `config` with every declared setting in place of its own.
+    pub fn apply(&self, config: MachineConfig) -> MachineConfig {
+        MachineConfig {
+            memory_size: self.memory_size.unwrap_or(config.memory_size),
+            max_steps: self.max_steps.or(config.max_steps),
+            max_output_bytes: self.max_output_bytes.or(config.max_output_bytes),
+            eof_mode: self.eof_mode.unwrap_or(config.eof_mode),
+            tape_policy: self.tape_policy.unwrap_or(config.tape_policy),
+            max_memory_cells: self.max_memory_cells.unwrap_or(config.max_memory_cells),
+        }
+    }
+
+    /// The default config with the declared settings.
+    pub fn config(&self) -> MachineConfig {
+        self.apply(MachineConfig::default())
+    }
+}
+
+/// A directive whose value is not valid for its key.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DirectiveError {
+    /// Counted from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for DirectiveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "directive on line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for DirectiveError {}
+
+/// Reads the `#! bf:` lines at the top of `code`. The parser treats the same
+/// lines as comments, so their characters never run.
+///
+/// Known keys are `memory`, `max-steps`, `max-output` and `max-memory`, all
+/// positive numbers, `eof` (`error`, `zero`, `unchanged` or `minus-one`),
+/// `tape` (`wrap` or `grow`) and `cells` (`8` or `32`).
+pub fn parse_header(code: &str) -> Result<Header, DirectiveError> {
+    let mut header = Header::default();
+    let lines = code.lines().enumerate().take_while(|(index, line)| {
+        (*index == 0 && line.starts_with("#!")) || directive(line).is_some()
+    });
+
+    for (index, line) in lines {
+        let line_number = index + 1;
+        let Some(directives) = directive(line) else {
+            continue;
+        };
+        for pair in directives.split_whitespace() {
+            let error = |message: String| DirectiveError {
+                line: line_number,
+                message,
+            };
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| error(format!("expected key=value, found `{pair}`")))?;
+            let count = || match value.parse() {
+                Ok(count) if count > 0 => Ok(count),
+                _ => Err(error(format!(
+                    "{key} requires a positive number, not `{value}`"
+                ))),
+            };
+
+            match key {
+                "memory" => header.memory_size = Some(count()?),
+                "max-steps" => header.max_steps = Some(count()?),
+                "max-output" => header.max_output_bytes = Some(count()?),
+                "max-memory" => header.max_memory_cells = Some(count()?),
+                "eof" => {
+                    header.eof_mode = Some(match value {
+                        "error" => EofMode::Error,
+                        "zero" => EofMode::Zero,
+                        "unchanged" => EofMode::Unchanged,
+                        "minus-one" => EofMode::MinusOne,
+                        _ => {
+                            return Err(error(format!(
+                                "eof requires error, zero, unchanged or minus-one, not `{value}`"
+                            )))
+                        }
+                    })
+                }
+                "tape" => {
+                    header.tape_policy = Some(match value {
+                        "wrap" => TapePolicy::Wrap,
+                        "grow" => TapePolicy::Grow,
+                        _ => {
+                            return Err(error(format!("tape requires wrap or grow, not `{value}`")))
+                        }
+                    })
+                }
+                "cells" => {
+                    header.cell_size = Some(match value {
+                        "8" => CellSize::U8,
+                        "32" => CellSize::I32,
+                        _ => return Err(error(format!("cells requires 8 or 32, not `{value}`"))),
+                    })
+                }
+                _ => header.unknown.push((line_number, key.to_string())),
+            }
+        }
+    }
+
+    Ok(header)
+}
+
+/// What follows `#! bf:` on a directive line.
+pub(crate) fn directive(line: &str) -> Option<&str> {
+    line.strip_prefix("#!")?.trim_start().strip_prefix("bf:")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_directives() {
+        let header = parse_header(
+            "#!/usr/bin/env bf-rust\n#! bf: memory=100 eof=zero\n#!bf: tape=grow cells=8 speed=9\n+.",
+        )
+        .unwrap();
+
+        assert_eq!(header.memory_size, Some(100));
+        assert_eq!(header.eof_mode, Some(EofMode::Zero));
+        assert_eq!(header.tape_policy, Some(TapePolicy::Grow));
+        assert_eq!(header.cell_size, Some(CellSize::U8));
+        assert_eq!(header.unknown, [(3, "speed".to_string())]);
+        assert_eq!(
+            header.config(),
+            MachineConfig {
+                memory_size: 100,
+                eof_mode: EofMode::Zero,
+                tape_policy: TapePolicy::Grow,
+                ..MachineConfig::default()
+            }
+        );
+    }
+
+    #[test]
+    fn reads_int_cells() {
+        let header = parse_header("#! bf: cells=32 eof=minus-one").unwrap();
+        assert_eq!(header.cell_size, Some(CellSize::I32));
+        assert_eq!(header.eof_mode, Some(EofMode::MinusOne));
+        assert_eq!(parse_header("+").unwrap().cell_size, None);
+    }
+
+    #[test]
+    fn only_reads_the_top() {
+        assert_eq!(
+            parse_header("+\n#! bf: memory=100").unwrap(),
+            Header::default()
+        );
+        assert_eq!(
+            parse_header("#! bf: memory=1\n\n#! bf: memory=2")
+                .unwrap()
+                .memory_size,
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_values_with_their_line() {
+        let err = parse_header("#!/bin/bf\n#! bf: memory=0").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert_eq!(
+            err.to_string(),
+            "directive on line 2: memory requires a positive number, not `0`"
+        );
+
+        assert_eq!(
+            parse_header("#! bf: cells=16").unwrap_err().message,
+            "cells requires 8 or 32, not `16`"
+        );
+        assert_eq!(parse_header("#! bf: eof").unwrap_err().line, 1);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{error::Error, fmt::Display, io};
+
+use super::{bf_machine::BfRuntimeError, bf_parser::BfParserError};
+
+/// Any error from reading, parsing or running a program, for callers that do
+/// not need the specific types.
+#[derive(Debug)]
+pub enum BfError {
+    Parse(BfParserError),
+    Runtime(BfRuntimeError),
+    Io(io::Error),
+}
+
+impl From<BfParserError> for BfError {
+    fn from(err: BfParserError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<BfRuntimeError> for BfError {
+    fn from(err: BfRuntimeError) -> Self {
+        Self::Runtime(err)
+    }
+}
+
+impl From<io::Error> for BfError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl Display for BfError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::Runtime(err) => write!(f, "{err}"),
+            Self::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for BfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Parse(err) => Some(err),
+            Self::Runtime(err) => Some(err),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bf::{self, program::Program};
+
+    use super::*;
+
+    #[test]
+    fn variants() {
+        let err = "+[".parse::<Program>().unwrap_err();
+        assert!(matches!(
+            err,
+            BfError::Parse(BfParserError::LoopNotClosed(1))
+        ));
+        assert!(err.source().is_some());
+
+        let err = bf::run_str(",,", b"a").unwrap_err();
+        assert!(matches!(
+            err,
+            BfError::Runtime(BfRuntimeError::UnexpectedEof { pc: 1 })
+        ));
+        assert_eq!(
+            err.to_string(),
+            "The error occurred at instruction 1 due to the end of input."
+        );
+
+        let err = bf::run_file("tests/programs/missing.b", b"").unwrap_err();
+        assert!(matches!(&err, BfError::Io(err) if err.kind() == io::ErrorKind::NotFound));
+    }
+
+    #[test]
+    fn helpers_run_programs() {
+        assert_eq!(bf::run_str(",+.", b"a").unwrap(), b"b");
+        assert_eq!(
+            bf::run_file("tests/programs/hello_world.b", b"").unwrap(),
+            b"Hello World!\n"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::io::{Read, Write};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError},
+    bf_token::BfToken,
+    program::Program,
+};
+
+/// The moments of a run that tools care about, without every step between.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ExecEvent {
+    Input {
+        pc: usize,
+        byte: u8,
+    },
+    /// One per byte, so `PrintCharN` emits several.
+    Output {
+        pc: usize,
+        byte: u8,
+    },
+    /// A loop whose body is about to run; a `[` that skips its loop emits
+    /// nothing.
+    LoopEnter {
+        start_pc: usize,
+    },
+    /// The end of the activation that the matching `LoopEnter` began, after
+    /// its body ran `iterations` times.
+    LoopExit {
+        start_pc: usize,
+        iterations: u64,
+    },
+    /// The program ran to its end after `steps` instructions.
+    Halt {
+        steps: usize,
+    },
+}
+
+impl<R: Read, W: Write> BfMachine<R, W> {
+    /// Like [`BfMachine::run`], calling `on_event` as the events happen.
+    pub fn run_with_events(
+        &mut self,
+        program: &Program,
+        mut on_event: impl FnMut(ExecEvent),
+    ) -> Result<(), BfRuntimeError> {
+        let jump_table = program.jump_table();
+        let mut iterations: Vec<u64> = vec![];
+        let mut steps = 0;
+
+        self.execute(
+            program,
+            0,
+            Some(&mut |record| {
+                let pc = record.pc;
+                steps = record.step;
+                match record.token {
+                    BfToken::LoopStart if record.cell != 0 => {
+                        iterations.push(0);
+                        on_event(ExecEvent::LoopEnter { start_pc: pc });
+                    }
+                    BfToken::LoopEnd => {
+                        if let Some(count) = iterations.last_mut() {
+                            *count += 1;
+                        }
+                        if record.cell == 0 {
+                            on_event(ExecEvent::LoopExit {
+                                start_pc: jump_table[pc],
+                                iterations: iterations.pop().unwrap_or(0),
+                            });
+                        }
+                    }
+                    BfToken::PrintChar => on_event(ExecEvent::Output {
+                        pc,
+                        byte: record.cell,
+                    }),
+                    BfToken::PrintCharN(count) => {
+                        for _ in 0..count {
+                            on_event(ExecEvent::Output {
+                                pc,
+                                byte: record.cell,
+                            });
+                        }
+                    }
+                    _ => {}
+                }
+                if let Some(byte) = record.input {
+                    on_event(ExecEvent::Input { pc, byte });
+                }
+                Ok(())
+            }),
+            None,
+        )?;
+
+        on_event(ExecEvent::Halt { steps });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn events(code: &str, input: &[u8]) -> Vec<ExecEvent> {
+        let program = Program::parse_optimized(code).unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(input), vec![]);
+        let mut events = vec![];
+        machine
+            .run_with_events(&program, |event| events.push(event))
+            .unwrap();
+        events
+    }
+
+    #[test]
+    fn loop_and_output_events() {
+        // The `>+<` is a single token once the optimizer sinks its moves.
+        #[cfg(feature = "optimizer")]
+        let (output_pc, steps) = (5, 9);
+        #[cfg(not(feature = "optimizer"))]
+        let (output_pc, steps) = (7, 13);
+        assert_eq!(
+            events(",[->+<].", b"\x02"),
+            [
+                ExecEvent::Input { pc: 0, byte: 2 },
+                ExecEvent::LoopEnter { start_pc: 1 },
+                ExecEvent::LoopExit {
+                    start_pc: 1,
+                    iterations: 2
+                },
+                ExecEvent::Output {
+                    pc: output_pc,
+                    byte: 0
+                },
+                ExecEvent::Halt { steps },
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_activations_and_input() {
+        assert_eq!(
+            events(",[[-]],..", b"\x01A"),
+            [
+                ExecEvent::Input { pc: 0, byte: 1 },
+                ExecEvent::LoopEnter { start_pc: 1 },
+                ExecEvent::LoopEnter { start_pc: 2 },
+                ExecEvent::LoopExit {
+                    start_pc: 2,
+                    iterations: 1
+                },
+                ExecEvent::LoopExit {
+                    start_pc: 1,
+                    iterations: 1
+                },
+                ExecEvent::Input { pc: 6, byte: b'A' },
+                ExecEvent::Output { pc: 7, byte: b'A' },
+                ExecEvent::Output { pc: 7, byte: b'A' },
+                ExecEvent::Halt { steps: 8 },
+            ]
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::fmt::Write as _;
+
+use super::{
+    ast::BfAst,
+    visitor::{fold, TransferFinder},
+};
+
+/// How [`explain`] lays out its pseudocode.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ExplainOptions {
+    pub indent: usize,
+    /// End each statement with `// @offset`, the source offset it starts at.
+    pub offsets: bool,
+}
+
+impl Default for ExplainOptions {
+    fn default() -> Self {
+        Self {
+            indent: 4,
+            offsets: false,
+        }
+    }
+}
+
+/// Writes `ast` as C-like pseudocode over a tape `p`, where `p[k]` is the cell
+/// `k` to the right of the cursor. Runs of `+`/`-` and `<`/`>` are folded and
+/// cursor moves become cell indices until a loop or extension needs the
+/// cursor itself.
+///
+/// Loops [`TransferFinder`] finds are written as what they do: `[-]` as
+/// `p[0] = 0;` and `[->++<]` as `p[1] += p[0] * 2; p[0] = 0;`.
+///
+/// Offsets count one per node, as [`from_tokens`] builds them from an
+/// [`OptLevel::O0`] program, so they are source char offsets for one.
+///
+/// [`from_tokens`]: super::ast::from_tokens
+/// [`OptLevel::O0`]: super::program::OptLevel::O0
+pub fn explain(ast: &[BfAst], options: &ExplainOptions) -> String {
+    let mut explainer = Explainer {
+        options: *options,
+        output: String::new(),
+        offset: 0,
+    };
+    explainer.block(&fold(ast.to_vec(), &mut TransferFinder), 0);
+    explainer.output
+}
+
+/// An addition to a cell not written out yet, so the next one to the same
+/// cell can join it.
+#[derive(Debug, Clone, Copy)]
+struct PendingAdd {
+    cell: isize,
+    delta: u8,
+    offset: usize,
+}
+
+struct Explainer {
+    options: ExplainOptions,
+    output: String,
+    /// The source offset of the next node.
+    offset: usize,
+}
+
+/// Where a block is: the cursor moves not written out yet and the addition
+/// that may still grow.
+#[derive(Debug, Default)]
+struct BlockState {
+    shift: isize,
+    shift_offset: Option<usize>,
+    add: Option<PendingAdd>,
+}
+
+impl Explainer {
+    fn block(&mut self, ast: &[BfAst], depth: usize) {
+        let mut state = BlockState::default();
+        for node in ast {
+            let offset = self.offset;
+            match node {
+                BfAst::Inc(n) => self.add(&mut state, *n, offset, depth),
+                BfAst::Dec(n) => self.add(&mut state, n.wrapping_neg(), offset, depth),
+                BfAst::Right(n) => self.shift(&mut state, *n as isize, offset),
+                BfAst::Left(n) => self.shift(&mut state, -(*n as isize), offset),
+                BfAst::Output => {
+                    self.flush_add(&mut state, depth);
+                    let line = format!("putchar({});", cell(state.shift));
+                    self.line(depth, &line, offset);
+                }
+                BfAst::Input => {
+                    self.flush_add(&mut state, depth);
+                    let line = format!("{} = getchar();", cell(state.shift));
+                    self.line(depth, &line, offset);
+                }
+                BfAst::Extension(id) => {
+                    self.flush(&mut state, depth);
+                    self.line(depth, &format!("p = extension({id}, p);"), offset);
+                }
+                BfAst::SwitchTape => {
+                    self.flush(&mut state, depth);
+                    self.line(depth, "p = other_tape(p);", offset);
+                }
+                BfAst::Fork => {
+                    self.flush(&mut state, depth);
+                    self.line(depth, "p = fork(p);", offset);
+                }
+                BfAst::Comment(_) => {}
+                BfAst::Transfer { factors, .. } => {
+                    self.flush_add(&mut state, depth);
+                    self.transfers(state.shift, factors, depth, offset);
+                    self.offset += node_len(node);
+                    continue;
+                }
+                BfAst::Loop(body) => {
+                    self.flush(&mut state, depth);
+                    self.line(depth, "while (p[0]) {", offset);
+                    self.offset += 1;
+                    self.block(body, depth + 1);
+                    self.offset += 1;
+                    self.line(depth, "}", None);
+                    continue;
+                }
+            }
+            self.offset += 1;
+        }
+        self.flush(&mut state, depth);
+    }
+
+    fn add(&mut self, state: &mut BlockState, delta: u8, offset: usize, depth: usize) {
+        match &mut state.add {
+            Some(add) if add.cell == state.shift => add.delta = add.delta.wrapping_add(delta),
+            _ => {
+                self.flush_add(state, depth);
+                state.add = Some(PendingAdd {
+                    cell: state.shift,
+                    delta,
+                    offset,
+                });
+            }
+        }
+    }
+
+    fn shift(&mut self, state: &mut BlockState, by: isize, offset: usize) {
+        state.shift += by;
+        state.shift_offset.get_or_insert(offset);
+    }
+
+    fn flush_add(&mut self, state: &mut BlockState, depth: usize) {
+        if let Some(add) = state.add.take() {
+            if let Some(line) = update(&cell(add.cell), add.delta, None) {
+                self.line(depth, &line, add.offset);
+            }
+        }
+    }
+
+    /// Writes out everything pending and moves the cursor for real.
+    fn flush(&mut self, state: &mut BlockState, depth: usize) {
+        self.flush_add(state, depth);
+        let offset = state.shift_offset.take();
+        match state.shift {
+            0 => {}
+            shift if shift > 0 => self.line(depth, &format!("p += {shift};"), offset),
+            shift => self.line(depth, &format!("p -= {};", -shift), offset),
+        }
+        state.shift = 0;
+    }
+
+    /// Writes a [`BfAst::Transfer`] whose counter is `base`.
+    fn transfers(&mut self, base: isize, transfers: &[(isize, u8)], depth: usize, offset: usize) {
+        let counter = cell(base);
+        let mut offset = Some(offset);
+        for &(cell_offset, factor) in transfers {
+            let target = cell(base + cell_offset);
+            let line = update(&target, factor, Some(&counter)).expect("factors are nonzero");
+            self.line(depth, &line, offset.take());
+        }
+        self.line(depth, &format!("{counter} = 0;"), offset);
+    }
+
+    fn line(&mut self, depth: usize, line: &str, offset: impl Into<Option<usize>>) {
+        let indent = " ".repeat(depth * self.options.indent);
+        write!(self.output, "{indent}{line}").unwrap();
+        if let (true, Some(offset)) = (self.options.offsets, offset.into()) {
+            write!(self.output, "  // @{offset}").unwrap();
+        }
+        self.output.push('\n');
+    }
+}
+
+fn cell(offset: isize) -> String {
+    format!("p[{offset}]")
+}
+
+/// `target += delta;`, with `delta` read as signed and multiplied by the cell
+/// `times` if given, or `None` if it adds nothing.
+fn update(target: &str, delta: u8, times: Option<&str>) -> Option<String> {
+    let (op, amount) = match delta {
+        0 => return None,
+        1..=128 => ("+=", delta),
+        _ => ("-=", delta.wrapping_neg()),
+    };
+    Some(match (times, amount) {
+        (None, amount) => format!("{target} {op} {amount};"),
+        (Some(times), 1) => format!("{target} {op} {times};"),
+        (Some(times), amount) => format!("{target} {op} {times} * {amount};"),
+    })
+}
+
+/// The nodes `node` spans, counted like [`explain`] counts offsets.
+fn node_len(node: &BfAst) -> usize {
+    match node {
+        BfAst::Loop(body) | BfAst::Transfer { body, .. } => {
+            2 + body.iter().map(node_len).sum::<usize>()
+        }
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bf::{
+        ast,
+        bf_parser::ParseOptions,
+        program::{OptLevel, Program},
+    };
+
+    use super::*;
+
+    fn explain_code(code: &str, offsets: bool) -> String {
+        let program = Program::parse_at(code, &ParseOptions::default(), OptLevel::O0).unwrap();
+        let ast = ast::from_tokens(program.tokens()).unwrap();
+        explain(
+            &ast,
+            &ExplainOptions {
+                offsets,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn hello_world_prologue() {
+        let code = "++++++++++[>+++++++>++++++++++>+++>+<<<<-]>++.>+.";
+        assert_eq!(
+            explain_code(code, true),
+            "p[0] += 10;  // @0\n\
+             p[1] += p[0] * 7;  // @10\n\
+             p[2] += p[0] * 10;\n\
+             p[3] += p[0] * 3;\n\
+             p[4] += p[0];\n\
+             p[0] = 0;\n\
+             p[1] += 2;  // @43\n\
+             putchar(p[1]);  // @45\n\
+             p[2] += 1;  // @47\n\
+             putchar(p[2]);  // @48\n\
+             p += 2;  // @42\n"
+        );
+    }
+
+    #[test]
+    fn idioms_read_as_what_they_do() {
+        assert_eq!(explain_code("+++[-]", false), "p[0] += 3;\np[0] = 0;\n");
+        assert_eq!(explain_code(">[+]", false), "p[1] = 0;\np += 1;\n");
+        assert_eq!(
+            explain_code("[->>>++<<<]", false),
+            "p[3] += p[0] * 2;\np[0] = 0;\n"
+        );
+        // Counting up subtracts, and a `-` target takes the counter away.
+        assert_eq!(
+            explain_code("[+>-<<++>]", false),
+            "p[-1] -= p[0] * 2;\np[1] += p[0];\np[0] = 0;\n"
+        );
+        for code in ["[->+<]", "[>+<-]"] {
+            let explained = explain_code(code, false);
+            assert!(!explained.contains("while"), "{code}: {explained}");
+        }
+    }
+
+    #[test]
+    fn other_loops_stay_loops() {
+        assert_eq!(
+            explain_code("+[>,.<-]>[<]", false),
+            "p[0] += 1;\n\
+             while (p[0]) {\n    \
+                 p[1] = getchar();\n    \
+                 putchar(p[1]);\n    \
+                 p[0] -= 1;\n\
+             }\n\
+             p += 1;\n\
+             while (p[0]) {\n    \
+                 p -= 1;\n\
+             }\n"
+        );
+        // Two steps at a time, or a cursor that drifts, is no transfer.
+        for code in ["[--]", "[->+]", "[-[-]]", "[-.]"] {
+            assert!(
+                explain_code(code, false).contains("while (p[0]) {"),
+                "{code}"
+            );
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    io::{Read, Write},
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, RunOutcome, ThreadState},
+    bf_token::BfToken,
+    cell::Cell,
+    program::Program,
+};
+
+/// Runs a Brainfork `program` on `machine`. Each `BfToken::Fork` splits the
+/// running thread in two: the child gets a copy of the tapes with its cursor
+/// one cell to the right and that cell set to 1, and the parent's current
+/// cell becomes 0.
+///
+/// The threads share the machine's input and output and take turns on it, in
+/// a queue. A turn runs the thread at the front for up to `quantum`
+/// instructions and ends early where it reaches a fork, which it then makes.
+/// A thread that has not finished goes to the back, behind the child it
+/// forked, if any, so a child always runs before its parent runs again. Which
+/// thread writes first and which reads which byte follow from this order
+/// alone, so a run is as repeatable as a single-threaded one.
+///
+/// `max_steps`, if given, limits the instructions of all threads together;
+/// forks are not counted. Every thread's tapes count toward the machine's
+/// memory limit, and the machine's step limit is spent on turns. The first
+/// thread to fail ends the run with its error and its tapes on the machine;
+/// otherwise the machine is left with the tapes of the thread that finished
+/// last.
+pub fn run_threads<R: Read, W: Write, C: Cell>(
+    machine: &mut BfMachine<R, W, C>,
+    program: &Program,
+    quantum: usize,
+    max_steps: Option<usize>,
+) -> Result<(), BfRuntimeError> {
+    assert!(quantum > 0, "turns must make progress");
+    let tokens = program.tokens();
+    // Stopping before every fork is stopping at a breakpoint on each.
+    let forks: BTreeSet<usize> = (0..tokens.len())
+        .filter(|&pc| tokens[pc] == BfToken::Fork)
+        .collect();
+    let mut remaining = max_steps.unwrap_or(usize::MAX);
+
+    let mut waiting: VecDeque<(ThreadState<C>, usize)> = VecDeque::new();
+    let mut pc = 0;
+    loop {
+        let stopped = match tokens.get(pc) {
+            Some(BfToken::Fork) => Some(pc),
+            _ => {
+                machine.set_step_limit(quantum.min(remaining));
+                let mut steps = 0;
+                let mut count = |_| {
+                    steps += 1;
+                    Ok(())
+                };
+                let result = machine.execute(program, pc, Some(&mut count), Some(&forks));
+                remaining -= steps;
+                match result {
+                    Ok(RunOutcome::Completed) => None,
+                    Err(BfRuntimeError::StepLimitExceeded { pc }) if remaining == 0 => {
+                        return Err(BfRuntimeError::StepLimitExceeded { pc })
+                    }
+                    Ok(RunOutcome::Breakpoint { pc } | RunOutcome::NeedsInput { pc })
+                    | Err(BfRuntimeError::StepLimitExceeded { pc }) => Some(pc),
+                    Err(err) => return Err(err),
+                }
+            }
+        };
+        let stopped = match stopped {
+            Some(fork) if tokens[fork] == BfToken::Fork => {
+                let held = waiting.iter().map(|(thread, _)| thread.cells()).sum();
+                let child = machine.fork(fork, held)?;
+                waiting.push_back((child, fork + 1));
+                Some(fork + 1)
+            }
+            stopped => stopped,
+        };
+
+        let Some((mut next, next_pc)) = waiting.pop_front() else {
+            match stopped {
+                Some(stopped) => {
+                    pc = stopped;
+                    continue;
+                }
+                None => return Ok(()),
+            }
+        };
+        machine.swap_thread(&mut next);
+        if let Some(stopped) = stopped {
+            waiting.push_back((next, stopped));
+        }
+        pc = next_pc;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_parser::ParseOptions, program::OptLevel};
+
+    use super::*;
+
+    /// Sets cells 1 and 2 to `p` and `c` and forks at cell 2, after which the
+    /// parent, whose cell is now 0, prints cell 1 and the child cell 2.
+    const PARENT_AND_CHILD: &str = "++++++++++[>+++++++++++>++++++++++<<-]>++>- Y <.";
+
+    fn brainfork(code: &str) -> Program {
+        let options = ParseOptions {
+            brainfork: true,
+            ..Default::default()
+        };
+        Program::parse_at(code, &options, OptLevel::O0).unwrap()
+    }
+
+    fn run(code: &str, input: &[u8], quantum: usize) -> Result<Vec<u8>, BfRuntimeError> {
+        let mut machine = BfMachine::new(8, Cursor::new(input.to_vec()), vec![]);
+        run_threads(&mut machine, &brainfork(code), quantum, None)?;
+        Ok(machine.into_parts().3)
+    }
+
+    #[test]
+    fn parent_and_child_both_print() {
+        // The child runs first after a fork, whatever the quantum.
+        for quantum in [1, 2, 1_000] {
+            assert_eq!(run(PARENT_AND_CHILD, b"", quantum).unwrap(), b"cp");
+        }
+
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+        run_threads(&mut machine, &brainfork(PARENT_AND_CHILD), 10, None).unwrap();
+        // The parent finished last.
+        assert_eq!(
+            (machine.cursor(), &machine.tape()[..4]),
+            (1, &[0, 112, 0, 0][..])
+        );
+    }
+
+    #[test]
+    fn threads_take_turns() {
+        // Each thread prints its cell three times; the child's is 1 and the
+        // parent's 0, both with 48 added for a digit.
+        let code = "Y>++++++[<++++++++>-]<...";
+        assert_eq!(run(code, b"", 1_000).unwrap(), b"111000");
+        assert_eq!(run(code, b"", 1).unwrap(), b"101010");
+
+        // Each thread prints the byte it reads, plus one in the child. The
+        // child spends five more steps on the way to its read, so in lock
+        // step the parent reads first.
+        let code = "Y[->+<]>>,[<+>-]<.";
+        assert_eq!(run(code, b"ab", 1_000).unwrap(), b"bb");
+        assert_eq!(run(code, b"ab", 1).unwrap(), b"ac");
+    }
+
+    #[test]
+    fn forks_outside_threads_and_runaway_forks() {
+        let program = brainfork("+Y");
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+        assert!(matches!(
+            machine.run(&program),
+            Err(BfRuntimeError::UnhandledFork { pc: 1 })
+        ));
+
+        // Every thread forks again and again, until the tapes fill the memory
+        // limit.
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]).with_memory_limit(64);
+        assert!(matches!(
+            run_threads(&mut machine, &brainfork("+[Y+]"), 10, None),
+            Err(BfRuntimeError::MemoryLimitExceeded { limit: 64, .. })
+        ));
+
+        // The step limit counts the steps of every thread: the child runs its
+        // four, and the parent is stopped before its last.
+        let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+        assert!(matches!(
+            run_threads(&mut machine, &brainfork("Y++++"), 3, Some(7)),
+            Err(BfRuntimeError::StepLimitExceeded { pc: 4 })
+        ));
+        assert_eq!(machine.tape()[..2], [3, 0]);
+    }
+}
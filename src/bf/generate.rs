@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+
+/// Code that sets a zeroed cell to `target`, as short as can be found among
+/// sequences of at most `max_len` chars.
+///
+/// Besides a plain run of `+` or `-`, it tries every multiplication loop
+/// `>a[<b>-]<c`: `a` steps on the scratch cell to the right as the counter,
+/// `b` steps on the current cell per round and `c` steps to adjust the
+/// product. The code ends on the current cell with the scratch cell back at
+/// zero. When no loop fits in `max_len`, the plain run is returned anyway.
+pub fn shortest_const(target: u8, max_len: usize) -> String {
+    let mut best = steps(i32::from(target));
+    if best.len() <= LOOP_OVERHEAD + 2 {
+        return best;
+    }
+
+    for counter in 1..=255 {
+        for step in 1..=255 {
+            let len = LOOP_OVERHEAD + counter + step;
+            if len >= best.len() || len > max_len {
+                break;
+            }
+            for sign in [1, -1] {
+                let product = sign * (counter * step) as i32;
+                let adjust = wrapped(i32::from(target) - product);
+                if len + adjust.unsigned_abs() as usize >= best.len().min(max_len.saturating_add(1))
+                {
+                    continue;
+                }
+                best = format!(
+                    ">{}[<{}>-]<{}",
+                    "+".repeat(counter),
+                    steps(sign * step as i32),
+                    steps(adjust)
+                );
+            }
+        }
+    }
+    best
+}
+
+/// [`shortest_const`] for every byte, without a length limit, worked out on
+/// first use.
+pub fn const_table() -> &'static [String; 256] {
+    static TABLE: OnceLock<[String; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|target| shortest_const(target as u8, usize::MAX)))
+}
+
+/// The chars of `>[<>-]<` around a multiplication loop.
+const LOOP_OVERHEAD: usize = 7;
+
+/// `amount` in -128..128, the shorter way around a wrapping cell.
+fn wrapped(amount: i32) -> i32 {
+    (amount + 128).rem_euclid(256) - 128
+}
+
+/// A run of `+` or `-`, whichever reaches `amount` sooner.
+fn steps(amount: i32) -> String {
+    let amount = wrapped(amount);
+    if amount >= 0 {
+        "+".repeat(amount as usize)
+    } else {
+        "-".repeat(amount.unsigned_abs() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_machine::BfMachine, program::Program};
+
+    use super::*;
+
+    #[test]
+    fn every_entry_sets_its_byte() {
+        for (target, code) in const_table().iter().enumerate() {
+            let mut machine = BfMachine::new(2, Cursor::new(b""), vec![]);
+            machine.run(&Program::parse(code).unwrap()).unwrap();
+
+            assert_eq!(machine.tape(), [target as u8, 0], "{code}");
+            assert_eq!(machine.cursor(), 0, "{code}");
+        }
+    }
+
+    #[test]
+    fn entries_are_short() {
+        let total: usize = const_table().iter().map(String::len).sum();
+
+        assert!(total / 256 < 25, "average length {}", total / 256);
+        assert_eq!(const_table()[65].len(), ">++++++++[<++++++++>-]<+".len());
+        assert_eq!(const_table()[255], "-");
+    }
+
+    #[test]
+    fn respects_max_len() {
+        assert_eq!(shortest_const(100, 5), "+".repeat(100));
+        assert!(shortest_const(100, 30).len() <= 30);
+    }
+}
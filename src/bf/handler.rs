@@ -0,0 +1,342 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{bf_machine::BfRuntimeError, bf_token::BfToken, cell::Cell};
+
+/// What the machine does after a handler ran.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ControlFlow {
+    Continue,
+    /// Ends the run successfully, as if the program had no more instructions.
+    Halt,
+}
+
+/// Runs `BfToken::Extension` instructions, which the machine itself does not
+/// know how to execute, on a machine with `C` cells.
+pub trait InstructionHandler<C: Cell = u8> {
+    fn handle(
+        &mut self,
+        token: &BfToken,
+        ctx: &mut MachineContext<C>,
+    ) -> Result<ControlFlow, BfRuntimeError>;
+}
+
+/// A function of the embedding application, called with the tape and cursor.
+pub type HostFn<C = u8> = Box<dyn FnMut(&mut [C], usize) -> Result<(), String>>;
+
+/// The parts of a machine a handler may touch while it runs one instruction.
+pub struct MachineContext<'a, C: Cell = u8> {
+    pc: usize,
+    cursor: &'a mut usize,
+    memory: &'a mut [C],
+    input: &'a mut dyn Read,
+    output: &'a mut dyn Write,
+    rng: &'a mut Rng,
+    host_fns: &'a mut HashMap<u8, HostFn<C>>,
+}
+
+impl<'a, C: Cell> MachineContext<'a, C> {
+    pub(crate) fn new(
+        pc: usize,
+        cursor: &'a mut usize,
+        memory: &'a mut [C],
+        input: &'a mut dyn Read,
+        output: &'a mut dyn Write,
+        rng: &'a mut Rng,
+        host_fns: &'a mut HashMap<u8, HostFn<C>>,
+    ) -> Self {
+        Self {
+            pc,
+            cursor,
+            memory,
+            input,
+            output,
+            rng,
+            host_fns,
+        }
+    }
+
+    /// The instruction being handled, for building errors.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn cursor(&self) -> usize {
+        *self.cursor
+    }
+
+    /// Moves the cursor; it must stay on the tape.
+    pub fn set_cursor(&mut self, cursor: usize) {
+        assert!(cursor < self.memory.len());
+        *self.cursor = cursor;
+    }
+
+    pub fn cell(&mut self) -> &mut C {
+        &mut self.memory[*self.cursor]
+    }
+
+    pub fn memory(&mut self) -> &mut [C] {
+        self.memory
+    }
+
+    pub fn input(&mut self) -> &mut dyn Read {
+        self.input
+    }
+
+    pub fn output(&mut self) -> &mut dyn Write {
+        self.output
+    }
+
+    /// The next byte from the machine's generator, see `BfMachine::set_rng_seed`.
+    pub fn random_byte(&mut self) -> u8 {
+        self.rng.next_byte()
+    }
+
+    /// Runs the host function registered under `selector` with the tape.
+    pub fn call_host(&mut self, selector: u8) -> Result<(), BfRuntimeError> {
+        let pc = self.pc;
+        let host_fn =
+            self.host_fns
+                .get_mut(&selector)
+                .ok_or_else(|| BfRuntimeError::HostError {
+                    pc,
+                    message: format!("no host function is registered for selector {selector}"),
+                })?;
+        host_fn(self.memory, *self.cursor)
+            .map_err(|message| BfRuntimeError::HostError { pc, message })
+    }
+}
+
+/// The built-in `?` command: stores a random byte in the current cell.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RandomByte;
+
+impl<C: Cell> InstructionHandler<C> for RandomByte {
+    fn handle(
+        &mut self,
+        _token: &BfToken,
+        ctx: &mut MachineContext<C>,
+    ) -> Result<ControlFlow, BfRuntimeError> {
+        *ctx.cell() = C::from_byte(ctx.random_byte());
+        Ok(ControlFlow::Continue)
+    }
+}
+
+/// The built-in `%` command: calls the host function selected by the current
+/// cell, or its low byte in a wider cell, see `BfMachine::register_host_fn`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HostCall;
+
+impl<C: Cell> InstructionHandler<C> for HostCall {
+    fn handle(
+        &mut self,
+        _token: &BfToken,
+        ctx: &mut MachineContext<C>,
+    ) -> Result<ControlFlow, BfRuntimeError> {
+        let selector = ctx.cell().to_byte();
+        ctx.call_host(selector)?;
+        Ok(ControlFlow::Continue)
+    }
+}
+
+/// xorshift64*, which is plenty for games and keeps runs reproducible without
+/// pulling in a dependency.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    pub(crate) fn from_seed(seed: u64) -> Self {
+        // splitmix64, so that nearby seeds (and 0) give unrelated nonzero states.
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Self((z ^ (z >> 31)).max(1))
+    }
+
+    pub(crate) fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+        Self::from_seed(nanos)
+    }
+
+    pub(crate) fn next_byte(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl<C, F> InstructionHandler<C> for F
+where
+    C: Cell,
+    F: FnMut(&BfToken, &mut MachineContext<C>) -> Result<ControlFlow, BfRuntimeError>,
+{
+    fn handle(
+        &mut self,
+        token: &BfToken,
+        ctx: &mut MachineContext<C>,
+    ) -> Result<ControlFlow, BfRuntimeError> {
+        self(token, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{
+        bf_machine::BfMachine, bf_parser::ParseOptions, compiled::CompiledProgram, program::Program,
+    };
+
+    use super::*;
+
+    struct Doubler;
+
+    impl InstructionHandler for Doubler {
+        fn handle(
+            &mut self,
+            token: &BfToken,
+            ctx: &mut MachineContext,
+        ) -> Result<ControlFlow, BfRuntimeError> {
+            match token {
+                BfToken::Extension(0) => {
+                    let cell = ctx.cell();
+                    *cell = cell.wrapping_mul(2);
+                    Ok(ControlFlow::Continue)
+                }
+                _ => Ok(ControlFlow::Halt),
+            }
+        }
+    }
+
+    fn doubling_program() -> Program {
+        let tokens = vec![
+            BfToken::Increment(3),
+            BfToken::Extension(0),
+            BfToken::Extension(0),
+            BfToken::PrintChar,
+            BfToken::Extension(1),
+            BfToken::PrintChar,
+        ];
+        Program::from_tokens(tokens).unwrap()
+    }
+
+    #[test]
+    fn handler_runs_extensions() {
+        let program = doubling_program();
+        let compiled = CompiledProgram::compile(&program);
+
+        for fast in [false, true] {
+            let mut output = vec![];
+            let mut machine =
+                BfMachine::new(10, Cursor::new(b""), &mut output).with_handler(Doubler);
+            if fast {
+                machine.run_fast(&program).unwrap();
+            } else {
+                machine.run(&program).unwrap();
+            }
+            drop(machine);
+            assert_eq!(output, [12]);
+        }
+
+        let mut output = vec![];
+        BfMachine::new(10, Cursor::new(b""), &mut output)
+            .with_handler(Doubler)
+            .run_compiled(&compiled)
+            .unwrap();
+        assert_eq!(output, [12]);
+    }
+
+    #[test]
+    fn closures_are_handlers() {
+        let mut output = vec![];
+        BfMachine::new(10, Cursor::new(b""), &mut output)
+            .with_handler(|_: &BfToken, ctx: &mut MachineContext| {
+                let next = ctx.cursor() + 1;
+                ctx.set_cursor(next);
+                ctx.output().write_all(b"!").unwrap();
+                Ok(ControlFlow::Continue)
+            })
+            .run(&doubling_program())
+            .unwrap();
+        assert_eq!(output, b"!!\0!\0");
+    }
+
+    fn random_bytes(seed: u64) -> Vec<u8> {
+        let mut options = ParseOptions::default();
+        options.extensions.insert('?', 0);
+        let program = Program::parse_with_options("?.?.", &options).unwrap();
+
+        let mut output = vec![];
+        let mut machine =
+            BfMachine::new(10, Cursor::new(b""), &mut output).with_handler(RandomByte);
+        machine.set_rng_seed(seed);
+        machine.run(&program).unwrap();
+        drop(machine);
+        output
+    }
+
+    #[test]
+    fn seeded_random_bytes() {
+        assert_eq!(random_bytes(42).len(), 2);
+        assert_eq!(random_bytes(42), random_bytes(42));
+        assert_ne!(random_bytes(42), random_bytes(43));
+        assert_ne!(random_bytes(0), random_bytes(1));
+    }
+
+    fn host_machine() -> BfMachine<Cursor<&'static [u8]>, Vec<u8>> {
+        let mut machine = BfMachine::new(10, Cursor::new(&b""[..]), vec![]).with_handler(HostCall);
+        machine.register_host_fn(1, |tape, _| {
+            tape[5] = tape[..4]
+                .iter()
+                .fold(0, |sum, cell| sum.wrapping_add(*cell));
+            Ok(())
+        });
+        machine.register_host_fn(2, |_, cursor| Err(format!("refused at cell {cursor}")));
+        machine
+    }
+
+    #[test]
+    fn host_functions() {
+        let mut options = ParseOptions::default();
+        options.extensions.insert('%', 0);
+        let program = Program::parse_with_options("+>++>+++>++++>>>+%", &options).unwrap();
+
+        let mut machine = host_machine();
+        machine.run(&program).unwrap();
+        assert_eq!(machine.snapshot().memory[..7], [1, 2, 3, 4, 0, 10, 1]);
+
+        let program = Program::parse_with_options(">++%", &options).unwrap();
+        let err = host_machine().run(&program).unwrap_err();
+        assert!(matches!(
+            &err,
+            BfRuntimeError::HostError { pc: 3, message } if message == "refused at cell 1"
+        ));
+
+        let program = Program::parse_with_options("+++%", &options).unwrap();
+        let err = host_machine().run(&program).unwrap_err();
+        assert!(matches!(
+            &err,
+            BfRuntimeError::HostError { pc: 3, message } if message.contains("selector 3")
+        ));
+    }
+
+    #[test]
+    fn extension_without_handler() {
+        let mut machine = BfMachine::new(10, Cursor::new(b""), vec![]);
+        let err = machine.run(&doubling_program()).unwrap_err();
+        assert!(matches!(
+            err,
+            BfRuntimeError::UnhandledExtension { pc: 1, id: 0 }
+        ));
+    }
+}
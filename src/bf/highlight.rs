@@ -0,0 +1,203 @@
+use std::fmt::Write;
+
+use super::{
+    bf_parser::{BfParser, ParseOptions, FORK, SWITCH_TAPE},
+    bf_token::BfToken,
+};
+
+/// How many colors the brackets cycle through, one per nesting depth.
+pub const DEPTH_COLORS: usize = 6;
+
+/// The classes [`to_html`] uses, as included by [`to_page`].
+pub const STYLESHEET: &str = "\
+pre.bf { background: #1e1e1e; color: #d4d4d4; padding: 1em; }
+.bf-comment { color: #6a737d; }
+.bf-move { color: #9cdcfe; }
+.bf-io { color: #4ec9b0; font-weight: bold; }
+.bf-ext { color: #c586c0; }
+.bf-depth-0 { color: #ffd700; }
+.bf-depth-1 { color: #da70d6; }
+.bf-depth-2 { color: #179fff; }
+.bf-depth-3 { color: #3fb950; }
+.bf-depth-4 { color: #f78166; }
+.bf-depth-5 { color: #e3b341; }
+.bf-unmatched { color: #ffffff; background: #b62324; }
+.bf-heat-0 { opacity: 0.45; }
+.bf-heat-1 { background: rgba(255, 96, 0, 0.12); }
+.bf-heat-2 { background: rgba(255, 96, 0, 0.28); }
+.bf-heat-3 { background: rgba(255, 96, 0, 0.44); }
+.bf-heat-4 { background: rgba(255, 96, 0, 0.6); }
+";
+
+/// Renders `code` as a `<pre class="bf">` fragment with a span per run of
+/// alike characters. Brackets get a `bf-depth-N` class by nesting depth,
+/// cycling through [`DEPTH_COLORS`], or `bf-unmatched`.
+///
+/// With `counts`, as read by [`profile::parse_counts`], every command is
+/// also shaded from `bf-heat-0`, never ran, to `bf-heat-4`, ran the most,
+/// on a log scale, and titled with its count.
+///
+/// [`profile::parse_counts`]: super::profile::parse_counts
+pub fn to_html(code: &str, options: &ParseOptions, counts: Option<&[u64]>) -> String {
+    let stripped = options.strip_comments(code);
+    let tokens: Vec<_> = BfParser::tokens(&stripped)
+        .map(|(_, token)| token)
+        .collect();
+    let jump_table = BfParser::loop_matching_lossy(&tokens);
+    let max_count = counts.and_then(|counts| counts.iter().max().copied());
+
+    let mut html = String::from("<pre class=\"bf\">");
+    let mut run: Option<(String, Option<u64>)> = None;
+    let mut depth = 0;
+    for (index, (offset, ch)) in code.char_indices().enumerate() {
+        let class = match &tokens[index] {
+            BfToken::Increment(_) | BfToken::Decrement(_) => "bf-arith".to_string(),
+            BfToken::CursorLeft(_) | BfToken::CursorRight(_) => "bf-move".to_string(),
+            BfToken::InputChar | BfToken::PrintChar => "bf-io".to_string(),
+            BfToken::NotCommand(ch) if options.extensions.contains_key(ch) => "bf-ext".to_string(),
+            BfToken::NotCommand(SWITCH_TAPE) if options.multi_tape => "bf-ext".to_string(),
+            BfToken::NotCommand(FORK) if options.brainfork => "bf-ext".to_string(),
+            BfToken::LoopStart if jump_table[index] < tokens.len() => {
+                depth += 1;
+                format!("bf-loop bf-depth-{}", (depth - 1) % DEPTH_COLORS)
+            }
+            BfToken::LoopEnd if jump_table[index] != index => {
+                depth -= 1;
+                format!("bf-loop bf-depth-{}", depth % DEPTH_COLORS)
+            }
+            BfToken::LoopStart | BfToken::LoopEnd => "bf-loop bf-unmatched".to_string(),
+            _ => "bf-comment".to_string(),
+        };
+        let count = match counts {
+            Some(counts) if class != "bf-comment" => Some(counts.get(index).copied().unwrap_or(0)),
+            _ => None,
+        };
+
+        if run.as_ref() != Some(&(class.clone(), count)) {
+            if run.is_some() {
+                html.push_str("</span>");
+            }
+            html.push_str(&open_span(&class, count, max_count.unwrap_or(0)));
+            run = Some((class, count));
+        }
+        escape_into(&mut html, &code[offset..offset + ch.len_utf8()]);
+    }
+    if run.is_some() {
+        html.push_str("</span>");
+    }
+    html.push_str("</pre>\n");
+    html
+}
+
+/// Wraps a fragment from [`to_html`] in a page with the [`STYLESHEET`].
+pub fn to_page(fragment: &str, title: &str) -> String {
+    let mut page = String::new();
+    writeln!(page, "<!DOCTYPE html>").unwrap();
+    writeln!(page, "<html>").unwrap();
+    writeln!(page, "<head>").unwrap();
+    writeln!(page, "<meta charset=\"utf-8\">").unwrap();
+    write!(page, "<title>").unwrap();
+    escape_into(&mut page, title);
+    writeln!(page, "</title>").unwrap();
+    writeln!(page, "<style>\n{STYLESHEET}</style>").unwrap();
+    writeln!(page, "</head>").unwrap();
+    writeln!(page, "<body>").unwrap();
+    write!(page, "{fragment}").unwrap();
+    writeln!(page, "</body>").unwrap();
+    writeln!(page, "</html>").unwrap();
+    page
+}
+
+fn open_span(class: &str, count: Option<u64>, max_count: u64) -> String {
+    match count {
+        Some(count) => format!(
+            "<span class=\"{class} bf-heat-{}\" title=\"{count}\">",
+            heat(count, max_count)
+        ),
+        None => format!("<span class=\"{class}\">"),
+    }
+}
+
+/// 0 for a command that never ran, else 1 to 4 by the magnitude of `count`
+/// against the largest one.
+fn heat(count: u64, max_count: u64) -> u64 {
+    if count == 0 {
+        return 0;
+    }
+    let magnitude = |count: u64| u64::from(u64::BITS - count.leading_zeros() - 1);
+    1 + magnitude(count) * 3 / magnitude(max_count).max(1)
+}
+
+fn escape_into(html: &mut String, text: &str) {
+    for ch in text.chars() {
+        match ch {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            ch => html.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn html(code: &str) -> String {
+        to_html(code, &ParseOptions::default(), None)
+    }
+
+    #[test]
+    fn highlights_a_program() {
+        assert_eq!(
+            html("+[>.<-] hi"),
+            "<pre class=\"bf\"><span class=\"bf-arith\">+</span>\
+             <span class=\"bf-loop bf-depth-0\">[</span>\
+             <span class=\"bf-move\">&gt;</span>\
+             <span class=\"bf-io\">.</span>\
+             <span class=\"bf-move\">&lt;</span>\
+             <span class=\"bf-arith\">-</span>\
+             <span class=\"bf-loop bf-depth-0\">]</span>\
+             <span class=\"bf-comment\"> hi</span></pre>\n"
+        );
+    }
+
+    #[test]
+    fn bracket_depths_balance() {
+        let html = html("[[[[[[[-]]]]]]] ]+[");
+        for depth in 0..DEPTH_COLORS {
+            let class = format!("bf-depth-{depth}\">");
+            assert_eq!(
+                html.matches(&format!("{class}[")).count(),
+                html.matches(&format!("{class}]")).count(),
+                "{depth} in {html}"
+            );
+        }
+        assert_eq!(html.matches("bf-depth-0\">").count(), 4);
+        assert_eq!(html.matches("bf-unmatched\">").count(), 2);
+    }
+
+    #[test]
+    fn escapes_comments() {
+        let options = ParseOptions {
+            line_comments: true,
+            ..ParseOptions::default()
+        };
+        let html = to_html("+ ; a < b & \"c\"\n", &options, None);
+
+        assert!(
+            html.contains("<span class=\"bf-comment\"> ; a &lt; b &amp; &quot;c&quot;\n</span>")
+        );
+        assert!(to_page(&html, "<x>").contains("<title>&lt;x&gt;</title>"));
+    }
+
+    #[test]
+    fn shades_by_count() {
+        let html = to_html("+[-]", &ParseOptions::default(), Some(&[1, 1, 255]));
+
+        assert!(html.contains("<span class=\"bf-arith bf-heat-1\" title=\"1\">+</span>"));
+        assert!(html.contains("<span class=\"bf-arith bf-heat-4\" title=\"255\">-</span>"));
+        assert!(html.contains("<span class=\"bf-loop bf-depth-0 bf-heat-0\" title=\"0\">]</span>"));
+    }
+}
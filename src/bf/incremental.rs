@@ -0,0 +1,196 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{Read, Write},
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError},
+    bf_parser::BfParserError,
+    program::Program,
+};
+
+/// Runs source as it arrives, e.g. from a pipe or a REPL. Everything up to the
+/// first unclosed `[` runs as soon as it is fed; the rest is held back until
+/// its brackets balance.
+///
+/// Error positions (parse indices and runtime pcs) are char indices into the
+/// whole stream fed so far.
+pub struct IncrementalInterpreter<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    machine: BfMachine<R, W>,
+    pending: String,
+    pending_start: usize,
+    open: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub enum IncrementalError {
+    Parse(BfParserError),
+    Runtime(BfRuntimeError),
+}
+
+impl<R, W> IncrementalInterpreter<R, W>
+where
+    R: Read,
+    W: Write,
+{
+    pub fn new(machine: BfMachine<R, W>) -> Self {
+        Self {
+            machine,
+            pending: String::new(),
+            pending_start: 0,
+            open: vec![],
+        }
+    }
+
+    /// Feeds the next piece of source. A chunk with an unmatched `]` is
+    /// rejected as a whole and nothing in it runs.
+    pub fn feed(&mut self, chunk: &str) -> Result<(), IncrementalError> {
+        let first = self.pending_start + self.pending.chars().count();
+        let mut open = self.open.clone();
+        let mut ready = None;
+
+        for (index, (offset, ch)) in (first..).zip(chunk.char_indices()) {
+            match ch {
+                '[' => open.push(index),
+                ']' if open.pop().is_none() => {
+                    return Err(BfParserError::LoopNotClosed(index).into());
+                }
+                _ => {}
+            }
+            if open.is_empty() {
+                ready = Some(self.pending.len() + offset + ch.len_utf8());
+            }
+        }
+
+        self.pending.push_str(chunk);
+        self.open = open;
+        match ready {
+            Some(len) => self.run_pending(len),
+            None => Ok(()),
+        }
+    }
+
+    /// Ends the stream, failing if a loop was never closed.
+    pub fn finish(self) -> Result<BfMachine<R, W>, IncrementalError> {
+        match self.open.first() {
+            Some(&index) => Err(BfParserError::LoopNotClosed(index).into()),
+            None => Ok(self.machine),
+        }
+    }
+
+    pub fn machine(&self) -> &BfMachine<R, W> {
+        &self.machine
+    }
+
+    fn run_pending(&mut self, len: usize) -> Result<(), IncrementalError> {
+        let code: String = self.pending.drain(..len).collect();
+        let start = self.pending_start;
+        self.pending_start += code.chars().count();
+
+        let program = Program::parse(&code).expect("only balanced code is run");
+        self.machine
+            .run(&program)
+            .map_err(|err| err.map_pc(|pc| start + pc).into())
+    }
+}
+
+impl From<BfParserError> for IncrementalError {
+    fn from(err: BfParserError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<BfRuntimeError> for IncrementalError {
+    fn from(err: BfRuntimeError) -> Self {
+        Self::Runtime(err)
+    }
+}
+
+impl Display for IncrementalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse(err) => write!(f, "{err}"),
+            Self::Runtime(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for IncrementalError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Parse(err) => Some(err),
+            Self::Runtime(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn interpreter(input: &[u8]) -> IncrementalInterpreter<Cursor<Vec<u8>>, Vec<u8>> {
+        IncrementalInterpreter::new(BfMachine::new(10, Cursor::new(input.to_vec()), vec![]))
+    }
+
+    #[test]
+    fn chunks_match_whole_program() {
+        let mut chunked = interpreter(b"");
+        chunked.feed("+++[->").unwrap();
+        chunked.feed("+<]").unwrap();
+        let chunked = chunked.finish().unwrap();
+
+        let mut whole = BfMachine::new(10, Cursor::new(vec![]), vec![]);
+        whole.run(&Program::parse("+++[->+<]").unwrap()).unwrap();
+        assert!(chunked.state_eq(&whole));
+        assert_eq!(chunked.snapshot().memory[..2], [0, 3]);
+    }
+
+    #[test]
+    fn runs_before_the_loop_closes() {
+        let mut interpreter = interpreter(b"");
+        interpreter.feed("+.>++.[").unwrap();
+        assert_eq!(interpreter.machine().bytes_written(), 2);
+
+        interpreter.feed("-]").unwrap();
+        interpreter.feed("<.").unwrap();
+        assert_eq!(interpreter.machine().bytes_written(), 3);
+        assert_eq!(interpreter.finish().unwrap().snapshot().memory[..2], [1, 0]);
+    }
+
+    #[test]
+    fn bracket_errors() {
+        let mut interpreter = interpreter(b"");
+        interpreter.feed("+[").unwrap();
+        assert!(matches!(
+            interpreter.feed("-]]+"),
+            Err(IncrementalError::Parse(BfParserError::LoopNotClosed(4)))
+        ));
+        assert_eq!(interpreter.machine().snapshot().memory[0], 1);
+
+        interpreter.feed("-]+").unwrap();
+        interpreter.feed("[>").unwrap();
+        assert!(matches!(
+            interpreter.finish(),
+            Err(IncrementalError::Parse(BfParserError::LoopNotClosed(5)))
+        ));
+    }
+
+    #[test]
+    fn runtime_errors_point_into_the_stream() {
+        let mut interpreter = interpreter(b"a");
+        interpreter.feed(",.").unwrap();
+        assert!(matches!(
+            interpreter.feed(">,"),
+            Err(IncrementalError::Runtime(BfRuntimeError::UnexpectedEof {
+                pc: 3
+            }))
+        ));
+    }
+}
@@ -0,0 +1,843 @@
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{Receiver, SyncSender},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Copies every byte handed out by `inner` into `log`, so a run's input can be
+/// replayed later exactly as the program consumed it.
+#[derive(Debug)]
+pub struct RecordingReader<R, L> {
+    inner: R,
+    log: L,
+}
+
+impl<R: Read, L: Write> RecordingReader<R, L> {
+    pub fn new(inner: R, log: L) -> Self {
+        Self { inner, log }
+    }
+
+    pub fn log(&self) -> &L {
+        &self.log
+    }
+
+    pub fn into_inner(self) -> (R, L) {
+        (self.inner, self.log)
+    }
+}
+
+impl<R: Read, L: Write> Read for RecordingReader<R, L> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.log.write_all(&buf[..read])?;
+        Ok(read)
+    }
+}
+
+/// How line endings in the input are rewritten before `,` sees them, for
+/// programs that expect one convention when given files written with another.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum InputTranslation {
+    #[default]
+    None,
+    /// `\r\n` becomes a single `\n`; a lone `\r` is kept.
+    CrlfToLf,
+    /// Every `\n` becomes `\r`.
+    LfToCr,
+}
+
+/// Rewrites the line endings read from `inner` per its [`InputTranslation`].
+/// A `\r` at the end of one read is held back until the next shows whether a
+/// `\n` follows it.
+#[derive(Debug)]
+pub struct TranslatingReader<R> {
+    inner: R,
+    translation: InputTranslation,
+    pending_cr: bool,
+    translated: VecDeque<u8>,
+}
+
+impl<R: Read> TranslatingReader<R> {
+    pub fn new(inner: R, translation: InputTranslation) -> Self {
+        Self {
+            inner,
+            translation,
+            pending_cr: false,
+            translated: VecDeque::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads from `inner` until there is something to hand out or it is at
+    /// its end.
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        let mut chunk = [0; 512];
+        let chunk = &mut chunk[..len.min(512)];
+        while self.translated.is_empty() {
+            let read = self.inner.read(chunk)?;
+            if read == 0 {
+                if mem::take(&mut self.pending_cr) {
+                    self.translated.push_back(b'\r');
+                }
+                return Ok(());
+            }
+            for &byte in &chunk[..read] {
+                match self.translation {
+                    InputTranslation::None => self.translated.push_back(byte),
+                    InputTranslation::LfToCr => self.translated.push_back(match byte {
+                        b'\n' => b'\r',
+                        byte => byte,
+                    }),
+                    InputTranslation::CrlfToLf => {
+                        if mem::take(&mut self.pending_cr) && byte != b'\n' {
+                            self.translated.push_back(b'\r');
+                        }
+                        match byte {
+                            b'\r' => self.pending_cr = true,
+                            byte => self.translated.push_back(byte),
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for TranslatingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.translation == InputTranslation::None {
+            return self.inner.read(buf);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.fill(buf.len())?;
+        self.translated.read(buf)
+    }
+}
+
+/// How line endings in the output are rewritten, for programs written for
+/// another terminal convention. Only the mapped byte is touched, and `None`
+/// passes binary output through as is.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputTranslation {
+    #[default]
+    None,
+    /// Every `\n` becomes `\r\n`.
+    LfToCrlf,
+    /// Every `\r` becomes `\n`.
+    CrToLf,
+}
+
+/// Rewrites the line endings written to `inner` per its
+/// [`OutputTranslation`]. Wrapped around a [`FormatWriter`], translation
+/// happens first and formatting second, so a hex dump shows the translated
+/// bytes.
+#[derive(Debug)]
+pub struct TranslatingWriter<W> {
+    inner: W,
+    translation: OutputTranslation,
+}
+
+impl<W: Write> TranslatingWriter<W> {
+    pub fn new(inner: W, translation: OutputTranslation) -> Self {
+        Self { inner, translation }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for TranslatingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.translation == OutputTranslation::None {
+            return self.inner.write(buf);
+        }
+        let mut translated = Vec::with_capacity(buf.len());
+        for &byte in buf {
+            match (self.translation, byte) {
+                (OutputTranslation::LfToCrlf, b'\n') => translated.extend_from_slice(b"\r\n"),
+                (OutputTranslation::CrToLf, b'\r') => translated.push(b'\n'),
+                (_, byte) => translated.push(byte),
+            }
+        }
+        self.inner.write_all(&translated)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes everything to both `primary` and `secondary`. Each write is
+/// completed on both sides before returning, so the two copies never drift
+/// apart; the first failure from either writer is returned as is.
+#[derive(Debug)]
+pub struct TeeWriter<A, B> {
+    primary: A,
+    secondary: B,
+}
+
+impl<A: Write, B: Write> TeeWriter<A, B> {
+    pub fn new(primary: A, secondary: B) -> Self {
+        Self { primary, secondary }
+    }
+
+    pub fn into_inner(self) -> (A, B) {
+        (self.primary, self.secondary)
+    }
+}
+
+impl<A: Write, B: Write> Write for TeeWriter<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.primary.write_all(buf)?;
+        self.secondary.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.primary.flush()?;
+        self.secondary.flush()
+    }
+}
+
+/// The longest [`DelayWriter`] sleeps before looking at its interrupt flag
+/// again.
+const DELAY_SLICE: Duration = Duration::from_millis(10);
+
+/// Pauses after each byte written to `inner`, or with `per_line` after each
+/// `\n`, flushing first so the byte is seen before the pause. It slows
+/// animations down to watchable speed without the machine knowing.
+///
+/// Pauses are slept in slices of at most [`DELAY_SLICE`]; once the flag given
+/// to [`DelayWriter::with_interrupt`] is set, the rest of the output goes
+/// through without pausing. A zero delay never sleeps.
+#[derive(Debug)]
+pub struct DelayWriter<W> {
+    inner: W,
+    delay: Duration,
+    per_line: bool,
+    interrupted: Option<Arc<AtomicBool>>,
+}
+
+impl<W: Write> DelayWriter<W> {
+    pub fn new(inner: W, delay: Duration, per_line: bool) -> Self {
+        Self {
+            inner,
+            delay,
+            per_line,
+            interrupted: None,
+        }
+    }
+
+    /// Stops pausing once `interrupted` is set.
+    pub fn with_interrupt(mut self, interrupted: Arc<AtomicBool>) -> Self {
+        self.interrupted = Some(interrupted);
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn is_interrupted(&self) -> bool {
+        self.interrupted
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::SeqCst))
+    }
+
+    fn pause(&self) {
+        let mut left = self.delay;
+        while !left.is_zero() && !self.is_interrupted() {
+            let slice = left.min(DELAY_SLICE);
+            thread::sleep(slice);
+            left -= slice;
+        }
+    }
+}
+
+impl<W: Write> Write for DelayWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.delay.is_zero() || self.is_interrupted() {
+            return self.inner.write(buf);
+        }
+        // Up to and including the byte to pause after, if there is one.
+        let len = match self.per_line {
+            false => buf.len().min(1),
+            true => match buf.iter().position(|&byte| byte == b'\n') {
+                Some(newline) => newline + 1,
+                None => return self.inner.write(buf),
+            },
+        };
+        self.inner.write_all(&buf[..len])?;
+        self.inner.flush()?;
+        self.pause();
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Raw,
+    /// `xxd`-style rows of 16 bytes with their offset and an ASCII column.
+    Hex,
+    /// Printable ASCII as is, `\\` for a backslash and `\xNN` for anything else.
+    Escaped,
+}
+
+const HEX_ROW: usize = 16;
+
+/// Renders program output for display. Hex rows are only written once full;
+/// the last partial row is written by [`FormatWriter::finish`] or on drop, so
+/// flushing mid-run never splits a row.
+#[derive(Debug)]
+pub struct FormatWriter<W: Write> {
+    inner: W,
+    format: OutputFormat,
+    offset: usize,
+    row: Vec<u8>,
+}
+
+impl<W: Write> FormatWriter<W> {
+    pub fn new(inner: W, format: OutputFormat) -> Self {
+        Self {
+            inner,
+            format,
+            offset: 0,
+            row: Vec::with_capacity(HEX_ROW),
+        }
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        if !self.row.is_empty() {
+            self.write_row()?;
+        }
+        self.inner.flush()
+    }
+
+    fn write_row(&mut self) -> io::Result<()> {
+        let mut line = format!("{:08x}: ", self.offset);
+        for index in 0..HEX_ROW {
+            match self.row.get(index) {
+                Some(byte) => line.push_str(&format!("{byte:02x}")),
+                None => line.push_str("  "),
+            }
+            if index % 2 == 1 {
+                line.push(' ');
+            }
+        }
+        line.push(' ');
+        line.extend(self.row.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        line.push('\n');
+
+        self.offset += self.row.len();
+        self.row.clear();
+        self.inner.write_all(line.as_bytes())
+    }
+}
+
+impl<W: Write> Write for FormatWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.format {
+            OutputFormat::Raw => return self.inner.write(buf),
+            OutputFormat::Hex => {
+                for &byte in buf {
+                    self.row.push(byte);
+                    if self.row.len() == HEX_ROW {
+                        self.write_row()?;
+                    }
+                }
+            }
+            OutputFormat::Escaped => {
+                let mut escaped = String::with_capacity(buf.len());
+                for &byte in buf {
+                    match byte {
+                        b'\\' => escaped.push_str("\\\\"),
+                        b' '..=b'~' => escaped.push(byte as char),
+                        _ => escaped.push_str(&format!("\\x{byte:02x}")),
+                    }
+                }
+                self.inner.write_all(escaped.as_bytes())?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for FormatWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Turns command-line text into input bytes: `\n`, `\r`, `\t`, `\0`, `\\`
+/// and `\xNN` are escapes, anything else is taken as its UTF-8 bytes.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.char_indices();
+    while let Some((_, ch)) = chars.next() {
+        if ch != '\\' {
+            bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        let byte = match chars.next() {
+            Some((_, 'n')) => b'\n',
+            Some((_, 'r')) => b'\r',
+            Some((_, 't')) => b'\t',
+            Some((_, '0')) => 0,
+            Some((_, '\\')) => b'\\',
+            Some((start, 'x')) => {
+                let digits = text.get(start + 1..start + 3).unwrap_or_default();
+                match u8::from_str_radix(digits, 16) {
+                    Ok(byte) if digits.bytes().all(|d| d.is_ascii_hexdigit()) => {
+                        chars.nth(1);
+                        byte
+                    }
+                    _ => return Err(format!("\\x at byte {} needs two hex digits", start - 1)),
+                }
+            }
+            Some((start, other)) => {
+                return Err(format!("unknown escape \\{other} at byte {}", start - 1))
+            }
+            None => return Err("trailing \\ at the end of the input".to_string()),
+        };
+        bytes.push(byte);
+    }
+    Ok(bytes)
+}
+
+/// The reading end of a channel of byte chunks; it reaches EOF once every
+/// sender is gone.
+#[derive(Debug)]
+pub struct ChannelReader {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    position: usize,
+}
+
+impl ChannelReader {
+    pub fn new(receiver: Receiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            chunk: vec![],
+            position: 0,
+        }
+    }
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+/// The writing end of a bounded channel: writes block while the channel is
+/// full and fail with `BrokenPipe` once the reader is gone.
+#[derive(Debug, Clone)]
+pub struct ChannelWriter {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl ChannelWriter {
+    pub fn new(sender: SyncSender<Vec<u8>>) -> Self {
+        Self { sender }
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.sender
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "the reading end is closed"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Cursor, sync::mpsc, thread, time::Instant};
+
+    use crate::bf::{
+        bf_machine::{BfMachine, BfRuntimeError, EofMode},
+        program::Program,
+    };
+
+    use super::*;
+
+    #[test]
+    fn records_consumed_bytes() {
+        let program = Program::parse(",.,.").unwrap();
+        let mut log = vec![];
+        let input = RecordingReader::new(Cursor::new(b"abcd"), &mut log);
+        BfMachine::new(10, input, vec![]).run(&program).unwrap();
+
+        assert_eq!(log, b"ab");
+    }
+
+    #[test]
+    fn replay_matches_recorded_run() {
+        let program = Program::parse(",.,.,+.,.").unwrap();
+        let mut log = vec![];
+        let mut recorded = vec![];
+        let input = RecordingReader::new(Cursor::new(b"xy"), &mut log);
+        BfMachine::new(10, input, &mut recorded)
+            .with_eof_mode(EofMode::Zero)
+            .run(&program)
+            .unwrap();
+        assert_eq!(log, b"xy");
+        assert_eq!(recorded, b"xy\x01\x00");
+
+        let mut replayed = vec![];
+        BfMachine::new(10, Cursor::new(log), &mut replayed)
+            .with_eof_mode(EofMode::Zero)
+            .run(&program)
+            .unwrap();
+        assert_eq!(replayed, recorded);
+    }
+
+    #[test]
+    fn tee_copies_to_both_writers() {
+        let program = Program::parse("++++++++[>++++++++<-]>+.+.+.").unwrap();
+        let mut output = TeeWriter::new(vec![], vec![]);
+        BfMachine::new(10, Cursor::new(b""), &mut output)
+            .run(&program)
+            .unwrap();
+
+        let (primary, secondary) = output.into_inner();
+        assert_eq!(primary, b"ABC");
+        assert_eq!(primary, secondary);
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::new(ErrorKind::StorageFull, "disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Err(io::Error::new(ErrorKind::StorageFull, "disk full"))
+        }
+    }
+
+    #[test]
+    fn tee_surfaces_secondary_failure() {
+        let program = Program::parse("+.").unwrap();
+        let mut output = TeeWriter::new(vec![], FailingWriter);
+        let err = BfMachine::new(10, Cursor::new(b""), &mut output)
+            .run(&program)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            BfRuntimeError::Io { pc: 1, ref source } if source.kind() == ErrorKind::StorageFull
+        ));
+        assert!(output.flush().is_err());
+    }
+
+    fn format(bytes: &[u8], format: OutputFormat) -> String {
+        let mut output = vec![];
+        let mut writer = FormatWriter::new(&mut output, format);
+        for chunk in bytes.chunks(3) {
+            writer.write_all(chunk).unwrap();
+            writer.flush().unwrap();
+        }
+        drop(writer);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn hex_rows() {
+        let bytes: Vec<u8> = (b'A'..=b'R').chain([0, b'\n']).collect();
+        assert_eq!(
+            format(&bytes, OutputFormat::Hex),
+            "00000000: 4142 4344 4546 4748 494a 4b4c 4d4e 4f50  ABCDEFGHIJKLMNOP\n\
+             00000010: 5152 000a                                QR..\n"
+        );
+        assert_eq!(format(b"", OutputFormat::Hex), "");
+    }
+
+    #[test]
+    fn escaped_bytes() {
+        assert_eq!(
+            format("a\\b\0\n é".as_bytes(), OutputFormat::Escaped),
+            "a\\\\b\\x00\\x0a \\xc3\\xa9"
+        );
+        assert_eq!(format(b"raw\0\n", OutputFormat::Raw), "raw\0\n");
+    }
+
+    /// The bytes `,` reads from `input` under `translation`, up to four.
+    fn observed(input: impl Read, translation: InputTranslation) -> Vec<u8> {
+        let program = Program::parse(",.,.,.,.").unwrap();
+        let mut output = vec![];
+        BfMachine::new(10, input, &mut output)
+            .with_eof_mode(EofMode::Zero)
+            .with_input_translation(translation)
+            .run(&program)
+            .unwrap();
+        let end = output.iter().position(|&byte| byte == 0).unwrap_or(4);
+        output.truncate(end);
+        output
+    }
+
+    /// Hands out one chunk per read.
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            let len = chunk.len().min(buf.len());
+            buf[..len].copy_from_slice(&chunk[..len]);
+            if len < chunk.len() {
+                self.0.insert(0, &chunk[len..]);
+            }
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn translates_input_newlines() {
+        let input = b"a\r\nb";
+        assert_eq!(
+            observed(Cursor::new(input), InputTranslation::None),
+            b"a\r\nb"
+        );
+        assert_eq!(
+            observed(Cursor::new(input), InputTranslation::CrlfToLf),
+            b"a\nb"
+        );
+        assert_eq!(
+            observed(Cursor::new(input), InputTranslation::LfToCr),
+            b"a\r\rb"
+        );
+
+        let split = || Chunks(vec![b"a\r", b"\nb"]);
+        assert_eq!(observed(split(), InputTranslation::CrlfToLf), b"a\nb");
+        assert_eq!(observed(split(), InputTranslation::LfToCr), b"a\r\rb");
+    }
+
+    #[test]
+    fn keeps_lone_carriage_returns() {
+        let mut read = vec![];
+        TranslatingReader::new(
+            Chunks(vec![b"\r", b"\r", b"x\r"]),
+            InputTranslation::CrlfToLf,
+        )
+        .read_to_end(&mut read)
+        .unwrap();
+        assert_eq!(read, b"\r\rx\r");
+    }
+
+    /// What the final writer receives for a program printing 10 and 13.
+    fn translated(translation: OutputTranslation, format: OutputFormat) -> Vec<u8> {
+        let program = Program::parse("++++++++++.+++.").unwrap();
+        let mut output = vec![];
+        BfMachine::new(10, Cursor::new(b""), FormatWriter::new(&mut output, format))
+            .with_output_translation(translation)
+            .run(&program)
+            .unwrap();
+        output
+    }
+
+    #[test]
+    fn translates_output_newlines() {
+        let raw = |translation| translated(translation, OutputFormat::Raw);
+        assert_eq!(raw(OutputTranslation::None), b"\n\r");
+        assert_eq!(raw(OutputTranslation::LfToCrlf), b"\r\n\r");
+        assert_eq!(raw(OutputTranslation::CrToLf), b"\n\n");
+
+        let mut output = TranslatingWriter::new(vec![], OutputTranslation::None);
+        output.write_all(&[0, 10, 13, 255]).unwrap();
+        assert_eq!(output.into_inner(), [0, 10, 13, 255]);
+    }
+
+    #[test]
+    fn translates_before_formatting() {
+        let escaped = |translation| translated(translation, OutputFormat::Escaped);
+        assert_eq!(escaped(OutputTranslation::None), b"\\x0a\\x0d");
+        assert_eq!(escaped(OutputTranslation::LfToCrlf), b"\\x0d\\x0a\\x0d");
+        assert_eq!(escaped(OutputTranslation::CrToLf), b"\\x0a\\x0a");
+    }
+
+    #[test]
+    fn unescapes_input() {
+        assert_eq!(unescape("hi\\n").unwrap(), b"hi\n");
+        assert_eq!(unescape("a\\tb\\r\\0\\\\").unwrap(), b"a\tb\r\0\\");
+        assert_eq!(unescape("\\x41\\xff\\x0A!").unwrap(), b"A\xff\n!");
+        assert_eq!(unescape("é").unwrap(), "é".as_bytes());
+        assert_eq!(unescape("").unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_invalid_escapes() {
+        assert_eq!(
+            unescape("ab\\q").unwrap_err(),
+            "unknown escape \\q at byte 2"
+        );
+        assert_eq!(
+            unescape("\\x4").unwrap_err(),
+            "\\x at byte 0 needs two hex digits"
+        );
+        assert!(unescape("\\xg1").is_err());
+        assert!(unescape("\\x+1").is_err());
+        assert!(unescape("\\xé").is_err());
+        assert_eq!(
+            unescape("a\\").unwrap_err(),
+            "trailing \\ at the end of the input"
+        );
+    }
+
+    #[test]
+    fn channel_round_trip() {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let writer = thread::spawn(move || {
+            let mut writer = ChannelWriter::new(sender);
+            for chunk in [&b"ab"[..], b"", b"cde"] {
+                writer.write_all(chunk).unwrap();
+            }
+        });
+
+        let mut received = vec![];
+        let mut reader = ChannelReader::new(receiver);
+        let mut buf = [0; 2];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => break,
+                read => received.extend_from_slice(&buf[..read]),
+            }
+        }
+        writer.join().unwrap();
+        assert_eq!(received, b"abcde");
+
+        let (sender, receiver) = mpsc::sync_channel(1);
+        drop(receiver);
+        let err = ChannelWriter::new(sender).write(b"x").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::BrokenPipe);
+    }
+
+    /// Counts the writes and flushes that reach it.
+    #[derive(Debug, Default)]
+    struct CountingWriter {
+        bytes: Vec<u8>,
+        writes: usize,
+        flushes: usize,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.bytes.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn delays_each_byte() {
+        let program = Program::parse("+++[>++++++++++++++++<-]>.+.+.").unwrap();
+        let mut output =
+            DelayWriter::new(CountingWriter::default(), Duration::from_millis(10), false);
+        let start = Instant::now();
+        BfMachine::new(10, io::empty(), &mut output)
+            .run(&program)
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        let output = output.into_inner();
+        assert_eq!(output.bytes, b"012");
+        assert_eq!(output.flushes, 3);
+    }
+
+    #[test]
+    fn delays_each_line() {
+        let mut output =
+            DelayWriter::new(CountingWriter::default(), Duration::from_millis(20), true);
+        let start = Instant::now();
+        output.write_all(b"ab\ncd\nef").unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        let output = output.into_inner();
+        assert_eq!(output.bytes, b"ab\ncd\nef");
+        assert_eq!(output.flushes, 2);
+    }
+
+    #[test]
+    fn zero_delay_never_sleeps() {
+        let mut output = DelayWriter::new(CountingWriter::default(), Duration::ZERO, false);
+        output.write_all(b"abc").unwrap();
+
+        let output = output.into_inner();
+        assert_eq!(output.bytes, b"abc");
+        assert_eq!((output.writes, output.flushes), (1, 0));
+    }
+
+    #[test]
+    fn interrupt_cuts_the_delay_short() {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let mut output = DelayWriter::new(vec![], Duration::from_secs(60), false)
+            .with_interrupt(Arc::clone(&interrupted));
+        let setter = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            interrupted.store(true, Ordering::SeqCst);
+        });
+        let start = Instant::now();
+        output.write_all(b"abc").unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        setter.join().unwrap();
+        assert_eq!(output.into_inner(), b"abc");
+    }
+}
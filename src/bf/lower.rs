@@ -0,0 +1,105 @@
+use super::bf_token::BfToken;
+
+/// Rewrites `tokens` into the plain commands of standard Brainfuck, one token
+/// per command: counted `+`, `-`, `<` and `>` become runs of single steps,
+/// `PrintCharN` a run of `PrintChar` and offset `+` and `-` a move to their
+/// cell and back around them. Comments are kept. A count of 0 on `+` or `-`
+/// wraps all the way around and so lowers to nothing.
+///
+/// Extensions, tape switches and forks have no standard form and are kept as they
+/// are; a program without them lowers to [`is_standard`] tokens only.
+pub fn to_standard(tokens: &[BfToken]) -> Vec<BfToken> {
+    let mut lowered = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let (token, count) = match *token {
+            BfToken::Increment(count) => (BfToken::Increment(1), count.into()),
+            BfToken::Decrement(count) => (BfToken::Decrement(1), count.into()),
+            BfToken::CursorLeft(count) => (BfToken::CursorLeft(1), count),
+            BfToken::CursorRight(count) => (BfToken::CursorRight(1), count),
+            BfToken::PrintCharN(count) => (BfToken::PrintChar, count),
+            BfToken::IncrementAt(offset, count) | BfToken::DecrementAt(offset, count) => {
+                let (there, back) = if offset < 0 {
+                    (BfToken::CursorLeft(1), BfToken::CursorRight(1))
+                } else {
+                    (BfToken::CursorRight(1), BfToken::CursorLeft(1))
+                };
+                let command = match token {
+                    BfToken::IncrementAt(..) => BfToken::Increment(1),
+                    _ => BfToken::Decrement(1),
+                };
+                lowered.extend(std::iter::repeat_n(there, offset.unsigned_abs()));
+                lowered.extend(std::iter::repeat_n(command, count.into()));
+                lowered.extend(std::iter::repeat_n(back, offset.unsigned_abs()));
+                continue;
+            }
+            token => (token, 1),
+        };
+        lowered.extend(std::iter::repeat_n(token, count));
+    }
+    lowered
+}
+
+/// Whether `token` is one of the eight commands with a count of 1, or a
+/// comment.
+pub fn is_standard(token: &BfToken) -> bool {
+    matches!(
+        token,
+        BfToken::NotCommand(_)
+            | BfToken::Increment(1)
+            | BfToken::Decrement(1)
+            | BfToken::CursorLeft(1)
+            | BfToken::CursorRight(1)
+            | BfToken::LoopStart
+            | BfToken::LoopEnd
+            | BfToken::PrintChar
+            | BfToken::InputChar
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bf::{bf_parser::BfParser, program::Program, run_str};
+
+    use super::*;
+
+    const HELLO_WORLD: &str = include_str!("../../tests/programs/hello_world.b");
+
+    #[test]
+    fn lowered_hello_world_runs_the_same() {
+        let program = Program::parse_optimized(HELLO_WORLD).unwrap();
+        assert!(!program.tokens().iter().all(is_standard));
+
+        let lowered = to_standard(program.tokens());
+        assert!(lowered.iter().all(is_standard));
+
+        let source = BfParser::to_source(&lowered);
+        assert_eq!(BfParser::parse(&source).unwrap(), lowered);
+        assert_eq!(
+            run_str(&source, b"").unwrap(),
+            run_str(HELLO_WORLD, b"").unwrap()
+        );
+        assert_eq!(program.to_string(), source);
+    }
+
+    #[test]
+    fn lowers_counts() {
+        assert_eq!(
+            to_standard(&[
+                BfToken::Increment(2),
+                BfToken::Decrement(0),
+                BfToken::CursorLeft(1),
+                BfToken::PrintCharN(3),
+                BfToken::Extension(4),
+            ]),
+            [
+                BfToken::Increment(1),
+                BfToken::Increment(1),
+                BfToken::CursorLeft(1),
+                BfToken::PrintChar,
+                BfToken::PrintChar,
+                BfToken::PrintChar,
+                BfToken::Extension(4),
+            ]
+        );
+    }
+}
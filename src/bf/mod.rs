@@ -1,4 +1,74 @@
+pub mod analyzer;
+pub mod ast;
+pub mod bench;
 pub mod bf_machine;
+#[cfg(feature = "optimizer")]
 pub mod bf_optimizer;
 pub mod bf_parser;
 pub mod bf_token;
+pub mod bytecode;
+pub mod cache;
+pub mod cell;
+#[cfg(feature = "serde")]
+pub mod checkpoint;
+pub mod codegen;
+pub mod compiled;
+#[cfg(feature = "serde")]
+pub mod core_dump;
+pub mod coverage;
+pub mod debugger;
+pub mod diagnostics;
+#[cfg(feature = "dialects")]
+pub mod dialects;
+pub mod diff;
+pub mod directives;
+pub mod error;
+pub mod events;
+pub mod explain;
+pub mod fork;
+pub mod generate;
+pub mod handler;
+pub mod highlight;
+pub mod incremental;
+pub mod io;
+pub mod lower;
+pub mod pipe;
+pub mod profile;
+pub mod program;
+pub mod progress;
+pub mod pull;
+#[cfg(feature = "cli")]
+pub mod repl;
+pub mod sched;
+pub mod serve;
+#[cfg(feature = "optimizer")]
+pub mod sink;
+pub mod source_map;
+pub mod stats;
+pub mod suite;
+mod tape;
+#[cfg(feature = "cli")]
+pub mod terminal;
+pub mod testing;
+pub mod trace;
+#[cfg(feature = "optimizer")]
+pub mod unroll;
+pub mod visitor;
+pub mod visualize;
+
+use std::{fs, io::Cursor, path::Path};
+
+use self::{bf_machine::BfMachine, error::BfError, program::Program};
+
+/// Runs `code` on a default machine with `input` and returns what it wrote.
+pub fn run_str(code: &str, input: &[u8]) -> Result<Vec<u8>, BfError> {
+    let program: Program = code.parse()?;
+    let mut output = vec![];
+    BfMachine::new(30_000, Cursor::new(input), &mut output).run(&program)?;
+    Ok(output)
+}
+
+/// Like [`run_str`], with the program read from `path`.
+pub fn run_file(path: impl AsRef<Path>, input: &[u8]) -> Result<Vec<u8>, BfError> {
+    run_str(&fs::read_to_string(path)?, input)
+}
@@ -0,0 +1,215 @@
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    sync::mpsc,
+    thread,
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, MachineConfig},
+    cell::Cell,
+    io::{ChannelReader, ChannelWriter},
+    program::Program,
+};
+
+/// How many writes may wait between two stages before the writer blocks.
+const PIPE_CAPACITY: usize = 64;
+
+#[derive(Debug)]
+pub struct PipeError {
+    /// Index of the failing stage in the pipeline.
+    pub stage: usize,
+    pub source: BfRuntimeError,
+}
+
+/// Runs every stage on its own thread, like a shell pipeline: `input` feeds
+/// the first stage, each stage's output is the next one's input and the last
+/// one writes to `output`. A stage sees EOF once the stage before it halts.
+///
+/// As with shell pipelines, a stage that fails only because the next one
+/// stopped reading is not an error. Otherwise the first failing stage is
+/// reported.
+pub fn pipe<'a, R, W>(
+    stages: &[(&Program, MachineConfig)],
+    input: R,
+    output: W,
+) -> Result<(), PipeError>
+where
+    R: Read + Send + 'a,
+    W: Write + Send + 'a,
+{
+    pipe_cells::<u8, R, W>(stages, input, output)
+}
+
+/// Like [`pipe`], with stages on tapes of `C` cells.
+pub fn pipe_cells<'a, C, R, W>(
+    stages: &[(&Program, MachineConfig)],
+    input: R,
+    output: W,
+) -> Result<(), PipeError>
+where
+    C: Cell,
+    R: Read + Send + 'a,
+    W: Write + Send + 'a,
+{
+    let Some(last) = stages.len().checked_sub(1) else {
+        return Ok(());
+    };
+
+    thread::scope(|scope| {
+        let mut reader: Box<dyn Read + Send + 'a> = Box::new(input);
+        let mut output = Some(output);
+        let mut handles = vec![];
+
+        for (stage, &(program, config)) in stages.iter().enumerate() {
+            let (writer, next): (Box<dyn Write + Send + 'a>, Box<dyn Read + Send + 'a>) =
+                if stage == last {
+                    let output = output.take().expect("only the last stage writes to output");
+                    (Box::new(output), Box::new(io::empty()))
+                } else {
+                    let (sender, receiver) = mpsc::sync_channel(PIPE_CAPACITY);
+                    (
+                        Box::new(ChannelWriter::new(sender)),
+                        Box::new(ChannelReader::new(receiver)),
+                    )
+                };
+            let input = mem::replace(&mut reader, next);
+            handles.push(spawn_stage::<C>(scope, program, config, input, writer));
+        }
+
+        let results: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().expect("pipeline stages do not panic"))
+            .collect();
+        match results
+            .into_iter()
+            .enumerate()
+            .find_map(|(stage, result)| result.err().map(|source| PipeError { stage, source }))
+        {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    })
+}
+
+fn spawn_stage<'scope, 'env, C: Cell>(
+    scope: &'scope thread::Scope<'scope, 'env>,
+    program: &'env Program,
+    config: MachineConfig,
+    input: impl Read + Send + 'scope,
+    output: impl Write + Send + 'scope,
+) -> thread::ScopedJoinHandle<'scope, Result<(), BfRuntimeError>> {
+    scope.spawn(move || {
+        match BfMachine::<_, _, C>::with_cells(config, input, output).run(program) {
+            Err(err)
+                if matches!(
+                    err.root_cause(),
+                    BfRuntimeError::Io { source, .. } if source.kind() == ErrorKind::BrokenPipe
+                ) =>
+            {
+                Ok(())
+            }
+            result => result,
+        }
+    })
+}
+
+impl Display for PipeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Stage {} failed: {}", self.stage, self.source)
+    }
+}
+
+impl Error for PipeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::bf_machine::EofMode;
+
+    use super::*;
+
+    const GENERATE: &str = "++++++++[>++++++++++++<-]>+.+.+.";
+    const CAT: &str = ",[.,]";
+    const UPPERCASE: &str = ",[>++++[<-------->-]<.,]";
+
+    fn config(eof_mode: EofMode) -> MachineConfig {
+        MachineConfig {
+            eof_mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn three_stages() {
+        let programs = [GENERATE, CAT, UPPERCASE].map(|code| Program::parse(code).unwrap());
+        let stages: Vec<_> = programs
+            .iter()
+            .map(|program| (program, config(EofMode::Zero)))
+            .collect();
+
+        let mut output = vec![];
+        pipe(&stages, io::empty(), &mut output).unwrap();
+        assert_eq!(output, b"ABC");
+    }
+
+    #[test]
+    fn input_feeds_the_first_stage() {
+        let cat = Program::parse(CAT).unwrap();
+        let upper = Program::parse(UPPERCASE).unwrap();
+        let stages = [
+            (&cat, config(EofMode::Zero)),
+            (&upper, config(EofMode::Zero)),
+        ];
+
+        let mut output = vec![];
+        pipe(&stages, Cursor::new(b"pipe"), &mut output).unwrap();
+        assert_eq!(output, b"PIPE");
+    }
+
+    #[test]
+    fn upstream_halt_is_eof() {
+        let generate = Program::parse(GENERATE).unwrap();
+        let read_four = Program::parse(",.,.,.,.").unwrap();
+
+        let mut output = vec![];
+        let stages = [
+            (&generate, config(EofMode::Zero)),
+            (&read_four, config(EofMode::Unchanged)),
+        ];
+        pipe(&stages, io::empty(), &mut output).unwrap();
+        assert_eq!(output, b"abcc");
+
+        let stages = [
+            (&generate, config(EofMode::Zero)),
+            (&read_four, config(EofMode::Error)),
+        ];
+        let err = pipe(&stages, io::empty(), vec![]).unwrap_err();
+        assert_eq!(err.stage, 1);
+        assert!(matches!(
+            err.source,
+            BfRuntimeError::UnexpectedEof { pc: 6 }
+        ));
+    }
+
+    #[test]
+    fn downstream_may_stop_early() {
+        let endless = Program::parse("+[.]").unwrap();
+        let read_one = Program::parse(",.").unwrap();
+        let stages = [
+            (&endless, MachineConfig::default()),
+            (&read_one, MachineConfig::default()),
+        ];
+
+        let mut output = vec![];
+        pipe(&stages, io::empty(), &mut output).unwrap();
+        assert_eq!(output, [1]);
+    }
+}
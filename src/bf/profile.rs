@@ -0,0 +1,421 @@
+use std::{cmp::Reverse, fmt::Write, time::Duration};
+
+use super::{bf_token::BfToken, handler::Rng, program::Program, source_map::SourceMap};
+
+/// How many instructions each timed one stands for, on average, unless a
+/// machine is given another interval.
+pub const DEFAULT_SAMPLE_INTERVAL: u32 = 64;
+
+const TOKEN_KINDS: [&str; 15] = [
+    "NotCommand",
+    "Increment",
+    "Decrement",
+    "CursorLeft",
+    "CursorRight",
+    "LoopStart",
+    "LoopEnd",
+    "PrintChar",
+    "PrintCharN",
+    "InputChar",
+    "Extension",
+    "IncrementAt",
+    "DecrementAt",
+    "SwitchTape",
+    "Fork",
+];
+
+/// How often each instruction of a program ran, and how often the loop
+/// brackets among them jumped. Collected by a machine with profiling enabled.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Profile {
+    executions: Vec<u64>,
+    jumps: Vec<u64>,
+}
+
+impl Profile {
+    pub fn executions(&self, pc: usize) -> u64 {
+        self.executions.get(pc).copied().unwrap_or(0)
+    }
+
+    /// For `[` the times the loop was skipped, for `]` the times it repeated.
+    pub fn jumps(&self, pc: usize) -> u64 {
+        self.jumps.get(pc).copied().unwrap_or(0)
+    }
+
+    pub fn total_steps(&self) -> u64 {
+        self.executions.iter().sum()
+    }
+
+    /// Makes room for a program of `len` tokens, keeping what was recorded.
+    pub(crate) fn grow(&mut self, len: usize) {
+        if len > self.executions.len() {
+            self.executions.resize(len, 0);
+            self.jumps.resize(len, 0);
+        }
+    }
+
+    pub(crate) fn record(&mut self, pc: usize) {
+        self.executions[pc] += 1;
+    }
+
+    pub(crate) fn record_jump(&mut self, pc: usize) {
+        self.jumps[pc] += 1;
+    }
+}
+
+/// Wall time per token type, estimated by sampling. About one instruction in
+/// `interval`, at random gaps so loop bodies cannot alias with the sampling,
+/// is timed on its own and counted `interval` times over.
+///
+/// The estimates are unbiased, but each rests on the samples of its token
+/// type: with `n` samples the relative error is around `1 / sqrt(n)`, so
+/// rare token types are noisy. Each sample also includes the cost of reading
+/// the clock, tens of nanoseconds, which inflates the cheap token types.
+#[derive(Debug, Clone)]
+pub struct Timings {
+    totals: [Duration; TOKEN_KINDS.len()],
+    samples: [u64; TOKEN_KINDS.len()],
+    interval: u32,
+    countdown: u64,
+    rng: Rng,
+}
+
+impl Timings {
+    pub fn new(interval: u32) -> Self {
+        assert!(interval > 0);
+
+        let mut timings = Self {
+            totals: Default::default(),
+            samples: Default::default(),
+            interval,
+            countdown: 0,
+            rng: Rng::from_seed(u64::from(interval)),
+        };
+        timings.countdown = timings.next_gap();
+        timings
+    }
+
+    /// The estimated time spent running tokens of the same type as `token`.
+    pub fn estimate(&self, token: &BfToken) -> Duration {
+        self.totals[kind(token)]
+    }
+
+    /// The name, estimated time and sample count of every sampled token type,
+    /// most time first.
+    pub fn estimates(&self) -> Vec<(&'static str, Duration, u64)> {
+        let mut estimates: Vec<_> = (0..TOKEN_KINDS.len())
+            .filter(|&kind| self.samples[kind] > 0)
+            .map(|kind| (TOKEN_KINDS[kind], self.totals[kind], self.samples[kind]))
+            .collect();
+        estimates.sort_by_key(|&(_, time, _)| Reverse(time));
+        estimates
+    }
+
+    pub fn total(&self) -> Duration {
+        self.totals.iter().sum()
+    }
+
+    /// Whether the next instruction is to be timed.
+    pub(crate) fn due(&mut self) -> bool {
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return false;
+        }
+        self.countdown = self.next_gap();
+        true
+    }
+
+    pub(crate) fn record(&mut self, token: &BfToken, elapsed: Duration) {
+        let kind = kind(token);
+        self.totals[kind] += elapsed * self.interval;
+        self.samples[kind] += 1;
+    }
+
+    /// Uniform in `1..2 * interval`, so `interval` on average.
+    fn next_gap(&mut self) -> u64 {
+        1 + self.rng.next_u64() % (2 * u64::from(self.interval) - 1)
+    }
+}
+
+fn kind(token: &BfToken) -> usize {
+    match token {
+        BfToken::NotCommand(_) => 0,
+        BfToken::Increment(_) => 1,
+        BfToken::Decrement(_) => 2,
+        BfToken::CursorLeft(_) => 3,
+        BfToken::CursorRight(_) => 4,
+        BfToken::LoopStart => 5,
+        BfToken::LoopEnd => 6,
+        BfToken::PrintChar => 7,
+        BfToken::PrintCharN(_) => 8,
+        BfToken::InputChar => 9,
+        BfToken::Extension(_) => 10,
+        BfToken::IncrementAt(..) => 11,
+        BfToken::DecrementAt(..) => 12,
+        BfToken::SwitchTape => 13,
+        BfToken::Fork => 14,
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LoopProfile {
+    pub start: usize,
+    pub end: usize,
+    /// The index of the enclosing loop in [`LoopProfiles::loops`].
+    pub parent: Option<usize>,
+    pub entries: u64,
+    /// Times the body ran, over all entries.
+    pub iterations: u64,
+    /// Instructions run directly in this loop, not in the loops it contains.
+    /// The loop's own `]` counts here, its `[` in the enclosing loop.
+    pub self_steps: u64,
+}
+
+/// The loops of a program, in source order, with what ran inside each.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LoopProfiles {
+    loops: Vec<LoopProfile>,
+}
+
+impl LoopProfiles {
+    pub fn new(program: &Program, profile: &Profile) -> Self {
+        let mut loops: Vec<LoopProfile> = vec![];
+        let mut open: Vec<usize> = vec![];
+
+        for (pc, token) in program.tokens().iter().enumerate() {
+            if let Some(&current) = open.last() {
+                loops[current].self_steps += profile.executions(pc);
+            }
+            match token {
+                BfToken::LoopStart => {
+                    open.push(loops.len());
+                    loops.push(LoopProfile {
+                        start: pc,
+                        end: program.jump_table()[pc],
+                        parent: open.len().checked_sub(2).map(|index| open[index]),
+                        entries: profile.executions(pc) - profile.jumps(pc),
+                        iterations: 0,
+                        self_steps: 0,
+                    });
+                }
+                BfToken::LoopEnd => {
+                    if let Some(current) = open.pop() {
+                        loops[current].iterations = profile.executions(pc);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self { loops }
+    }
+
+    pub fn loops(&self) -> &[LoopProfile] {
+        &self.loops
+    }
+
+    /// How often each loop's body ran, keyed by the pc of its `[`. Nested
+    /// loops are counted on their own, over every entry.
+    pub fn iteration_counts(&self) -> Vec<(usize, u64)> {
+        self.loops.iter().map(|l| (l.start, l.iterations)).collect()
+    }
+}
+
+/// Renders `profiles` as folded stacks for inferno or flamegraph.pl, one
+/// `loop@12;loop@45 12345` line per loop that ran. Frames are the source
+/// positions of the nested loops' `[`, and the value is the loop's
+/// `self_steps`, so a parent's line never includes its children's work and
+/// the tool's sums are exact. Instructions outside every loop are not listed.
+pub fn to_folded(profiles: &LoopProfiles, source_map: &SourceMap) -> String {
+    let mut folded = String::new();
+    for profile in profiles.loops().iter().filter(|l| l.self_steps > 0) {
+        let mut stack = vec![profile];
+        while let Some(parent) = stack.last().unwrap().parent {
+            stack.push(&profiles.loops()[parent]);
+        }
+
+        let frames: Vec<_> = stack
+            .iter()
+            .rev()
+            .map(|l| format!("loop@{}", source_map.original(l.start)))
+            .collect();
+        writeln!(folded, "{} {}", frames.join(";"), profile.self_steps).unwrap();
+    }
+    folded
+}
+
+/// Renders how often each instruction ran as `position count` lines, one per
+/// instruction that ran, where the position is the char index of its source.
+/// A compressed token only counts at its first char, so parse the program
+/// without optimizing it to get a count for every command.
+pub fn to_counts(profile: &Profile, source_map: &SourceMap) -> String {
+    let mut counts = String::new();
+    for pc in 0..source_map.len() {
+        let executions = profile.executions(pc);
+        if executions > 0 {
+            writeln!(counts, "{} {executions}", source_map.original(pc)).unwrap();
+        }
+    }
+    counts
+}
+
+/// Reads what [`to_counts`] wrote back as a count per source position.
+pub fn parse_counts(text: &str) -> Result<Vec<u64>, String> {
+    let mut counts = vec![];
+    for (number, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parsed = line
+            .split_once(' ')
+            .and_then(|(position, count)| Some((position.parse().ok()?, count.parse().ok()?)));
+        let Some((position, count)) = parsed else {
+            return Err(format!("line {} is not `position count`", number + 1));
+        };
+        if counts.len() <= position {
+            counts.resize(position + 1, 0);
+        }
+        counts[position] = count;
+    }
+    Ok(counts)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Cursor},
+        thread,
+    };
+
+    use crate::bf::bf_machine::BfMachine;
+
+    use super::*;
+
+    fn profile(code: &str) -> (Program, Profile) {
+        let program = Program::parse(code).unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b""), vec![]).with_profiling();
+        machine.run(&program).unwrap();
+        let profile = machine.profile().unwrap().clone();
+        (program, profile)
+    }
+
+    #[test]
+    fn loop_profiles() {
+        let (program, profile) = profile("++[>++[->+<]<-]");
+        let profiles = LoopProfiles::new(&program, &profile);
+
+        assert_eq!(
+            profiles.loops(),
+            [
+                LoopProfile {
+                    start: 2,
+                    end: 14,
+                    parent: None,
+                    entries: 1,
+                    iterations: 2,
+                    self_steps: 14,
+                },
+                LoopProfile {
+                    start: 6,
+                    end: 11,
+                    parent: Some(0),
+                    entries: 2,
+                    iterations: 4,
+                    self_steps: 20,
+                },
+            ]
+        );
+        assert_eq!(profile.total_steps(), 3 + 14 + 20);
+    }
+
+    #[test]
+    fn iteration_counts() {
+        let (program, profile) = profile("+++[->++[->+<]<]");
+        let profiles = LoopProfiles::new(&program, &profile);
+
+        assert_eq!(profiles.iteration_counts(), [(3, 3), (8, 6)]);
+    }
+
+    #[test]
+    fn folded_stacks_are_exclusive() {
+        // The outer loop runs `>++[` and `<-]` twice (14 steps); the inner
+        // loop runs `->+<]` twice per entry, for two entries (20 steps).
+        let (program, profile) = profile("++[>++[->+<]<-]");
+        let profiles = LoopProfiles::new(&program, &profile);
+
+        assert_eq!(
+            to_folded(&profiles, &program.source_map()),
+            "loop@2 14\nloop@2;loop@6 20\n"
+        );
+    }
+
+    #[test]
+    fn folded_stacks_use_source_positions() {
+        let code = ", ,[ - ] [>].";
+        let program = Program::parse_optimized(code).unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b"\x01\x02"), vec![]).with_profiling();
+        machine.run(&program).unwrap();
+        let profiles = LoopProfiles::new(&program, machine.profile().unwrap());
+
+        assert_eq!(to_folded(&profiles, &program.source_map()), "loop@3 4\n");
+    }
+
+    #[test]
+    fn counts_round_trip() {
+        let (program, profile) = profile("++[->+<]");
+        let counts = to_counts(&profile, &program.source_map());
+
+        assert_eq!(counts, "0 1\n1 1\n2 1\n3 2\n4 2\n5 2\n6 2\n7 2\n");
+        assert_eq!(parse_counts(&counts).unwrap(), [1, 1, 1, 2, 2, 2, 2, 2]);
+        assert_eq!(
+            parse_counts("3 x").unwrap_err(),
+            "line 1 is not `position count`"
+        );
+    }
+
+    #[test]
+    fn slow_output_dominates_timings() {
+        struct SlowWriter;
+
+        impl io::Write for SlowWriter {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                thread::sleep(Duration::from_micros(200));
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let program = Program::parse("+[.+]").unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b""), SlowWriter).with_timings(4);
+        machine.run(&program).unwrap();
+
+        let timings = machine.timings().unwrap();
+        assert!(timings.estimate(&BfToken::PrintChar) > timings.total() * 9 / 10);
+        assert_eq!(timings.estimates()[0].0, "PrintChar");
+    }
+
+    #[test]
+    fn arithmetic_dominates_timings() {
+        // Nearly nine steps in ten are arithmetic or loops, so one slow sample
+        // of a cursor move cannot tip the balance.
+        let program = Program::parse(&">+++++++[-]<".repeat(2000)).unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b""), vec![]).with_timings(4);
+        machine.run(&program).unwrap();
+
+        let timings = machine.timings().unwrap();
+        let arithmetic_and_loops: Duration = [
+            BfToken::Increment(1),
+            BfToken::Decrement(1),
+            BfToken::LoopStart,
+            BfToken::LoopEnd,
+        ]
+        .iter()
+        .map(|token| timings.estimate(token))
+        .sum();
+        assert!(arithmetic_and_loops > timings.total() / 2);
+        assert_eq!(timings.estimate(&BfToken::PrintChar), Duration::ZERO);
+    }
+}
@@ -0,0 +1,532 @@
+use std::{fmt::Display, str::FromStr};
+
+use super::{
+    bf_machine::{MachineConfig, TapePolicy},
+    bf_parser::{BfParser, BfParserError, ParseOptions, ValidationError},
+    bf_token::BfToken,
+    error::BfError,
+    lower,
+    source_map::SourceMap,
+};
+#[cfg(feature = "optimizer")]
+use super::{bf_optimizer::BfCodeOptimizer, sink, unroll};
+
+/// How much work goes into a program before it runs, from none to the full
+/// optimizer.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
+pub enum OptLevel {
+    /// One token per source char, comments included, as [`Program::parse`].
+    O0,
+    /// Runs of `+`/`-`, `<`/`>` and `.` folded into single tokens and
+    /// comments dropped.
+    O1,
+    /// The optimizer's passes first, then O1 and [`sink::sink_moves`], as
+    /// [`Program::parse_optimized`]. Without the `optimizer` feature it is O1.
+    ///
+    /// [`sink::sink_moves`]: super::sink::sink_moves
+    O2,
+}
+
+impl OptLevel {
+    pub const ALL: [Self; 3] = [Self::O0, Self::O1, Self::O2];
+
+    /// The most a program for a machine with `config` can be optimized and
+    /// still run as it does at O0: O2, but O0 itself on a growing tape, where
+    /// moving left of cell 0 fails and both folding `<>` and O2's trimming
+    /// can take the move away.
+    pub fn highest_for(config: &MachineConfig) -> Self {
+        match config.tape_policy {
+            TapePolicy::Wrap => Self::O2,
+            TapePolicy::Grow => Self::O0,
+        }
+    }
+}
+
+impl Display for OptLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::O0 => write!(f, "O0"),
+            Self::O1 => write!(f, "O1"),
+            Self::O2 => write!(f, "O2"),
+        }
+    }
+}
+
+/// Parsed tokens with their jump table and source positions, checked to be
+/// safe to run.
+///
+/// With the serde feature it serializes as its three parts, and deserializes
+/// through [`Program::from_parts`], so hand-edited data is checked like any
+/// other.
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "ProgramParts")
+)]
+pub struct Program {
+    tokens: Vec<BfToken>,
+    jump_table: Vec<usize>,
+    spans: Vec<usize>,
+}
+
+/// A [`Program`] as read, before it is checked.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct ProgramParts {
+    tokens: Vec<BfToken>,
+    jump_table: Vec<usize>,
+    spans: Vec<usize>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<ProgramParts> for Program {
+    type Error = String;
+
+    fn try_from(parts: ProgramParts) -> Result<Self, Self::Error> {
+        Self::from_parts(parts.tokens, parts.jump_table, parts.spans).map_err(|errors| {
+            let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            format!("invalid program: {}", errors.join(", "))
+        })
+    }
+}
+
+impl Program {
+    pub fn parse(code: &str) -> Result<Self, BfParserError> {
+        let (tokens, jump_table) = BfParser::parse_with_jump_table(code)?;
+        let spans = (0..tokens.len()).collect();
+        Ok(Self {
+            tokens,
+            jump_table,
+            spans,
+        })
+    }
+
+    pub fn parse_with_options(code: &str, options: &ParseOptions) -> Result<Self, BfParserError> {
+        let tokens = BfParser::parse_with_options(code, options)?;
+        let jump_table = BfParser::loop_matching(&tokens)?;
+        let spans = (0..tokens.len()).collect();
+        Ok(Self {
+            tokens,
+            jump_table,
+            spans,
+        })
+    }
+
+    pub fn parse_optimized(code: &str) -> Result<Self, BfParserError> {
+        Self::parse_optimized_with_options(code, &ParseOptions::default())
+    }
+
+    #[cfg(feature = "optimizer")]
+    pub fn parse_optimized_with_options(
+        code: &str,
+        options: &ParseOptions,
+    ) -> Result<Self, BfParserError> {
+        let code = options.strip_comments(code);
+        // The optimizer keeps the chars it is given as extensions, by key.
+        let mut kept = options.extensions.clone();
+        if options.multi_tape {
+            kept.insert(super::bf_parser::SWITCH_TAPE, 0);
+        }
+        if options.brainfork {
+            kept.insert(super::bf_parser::FORK, 0);
+        }
+        let (optimized_code, map) = BfCodeOptimizer::optimize_with_extensions(&code, &kept);
+        let (tokens, spans) = BfParser::parse_compress_with_options(&optimized_code, options)
+            .map_err(|err| err.map_index(|index| map.original(index)))?;
+        let spans: Vec<_> = spans.into_iter().map(|index| map.original(index)).collect();
+        let (tokens, spans) = sink::sink_moves(&tokens, &spans);
+        let jump_table = BfParser::loop_matching(&tokens)?;
+        Ok(Self {
+            tokens,
+            jump_table,
+            spans,
+        })
+    }
+
+    /// `code` parsed at [`OptLevel::highest_for`] `config`, then
+    /// [`Program::unrolled_for_fresh_tape`].
+    pub fn parse_for_fresh_tape(
+        code: &str,
+        options: &ParseOptions,
+        config: &MachineConfig,
+    ) -> Result<Self, BfParserError> {
+        let program = Self::parse_at(code, options, OptLevel::highest_for(config))?;
+        Ok(program.unrolled_for_fresh_tape(config))
+    }
+
+    /// The program with the loops near its start whose counts are known
+    /// unrolled, by `unroll::unroll_loops`. That is only sound for a run from
+    /// pc 0 on a machine built with `config` whose tapes are still blank, as
+    /// they are when it is new or was just reset, and not on a wrapping tape
+    /// shorter than `unroll::REACH`, where that program comes back as it is.
+    #[cfg(feature = "optimizer")]
+    pub fn unrolled_for_fresh_tape(self, config: &MachineConfig) -> Self {
+        if config.tape_policy == TapePolicy::Wrap && config.memory_size < unroll::REACH {
+            return self;
+        }
+        let (tokens, spans) = unroll::unroll_loops(&self.tokens, &self.spans, &self.jump_table);
+        let jump_table = BfParser::loop_matching(&tokens).expect("unrolling keeps loops paired");
+        Self {
+            tokens,
+            jump_table,
+            spans,
+        }
+    }
+
+    /// Without the optimizer nothing is unrolled.
+    #[cfg(not(feature = "optimizer"))]
+    pub fn unrolled_for_fresh_tape(self, _config: &MachineConfig) -> Self {
+        self
+    }
+
+    /// Without the optimizer, folds runs like [`OptLevel::O1`].
+    #[cfg(not(feature = "optimizer"))]
+    pub fn parse_optimized_with_options(
+        code: &str,
+        options: &ParseOptions,
+    ) -> Result<Self, BfParserError> {
+        Self::parse_at(code, options, OptLevel::O1)
+    }
+
+    /// Parses `code` through the pipeline of `level`.
+    pub fn parse_at(
+        code: &str,
+        options: &ParseOptions,
+        level: OptLevel,
+    ) -> Result<Self, BfParserError> {
+        match level {
+            OptLevel::O0 => Self::parse_with_options(code, options),
+            OptLevel::O1 => {
+                let (tokens, spans) = BfParser::parse_compress_with_options(code, options)?;
+                let jump_table = BfParser::loop_matching(&tokens)?;
+                Ok(Self {
+                    tokens,
+                    jump_table,
+                    spans,
+                })
+            }
+            OptLevel::O2 => Self::parse_optimized_with_options(code, options),
+        }
+    }
+
+    /// Builds a program from tokens made outside the parser, rejecting any
+    /// that fail [`BfParser::validate`].
+    pub fn from_tokens(tokens: Vec<BfToken>) -> Result<Self, Vec<ValidationError>> {
+        BfParser::validate(&tokens)?;
+        let jump_table = BfParser::loop_matching(&tokens).expect("validated tokens pair up");
+        let spans = (0..tokens.len()).collect();
+        Ok(Self {
+            tokens,
+            jump_table,
+            spans,
+        })
+    }
+
+    /// Rebuilds a program from its parts, e.g. as decoded from a file. The
+    /// tokens, the jump table and the spans are all checked, since the
+    /// machine trusts them; the spans must not decrease.
+    pub fn from_parts(
+        tokens: Vec<BfToken>,
+        jump_table: Vec<usize>,
+        spans: Vec<usize>,
+    ) -> Result<Self, Vec<ValidationError>> {
+        let mut errors = BfParser::validate(&tokens).err().unwrap_or_default();
+        errors.extend(
+            BfParser::validate_jump_table(&tokens, &jump_table)
+                .err()
+                .into_iter()
+                .flatten(),
+        );
+        if spans.len() != tokens.len() {
+            errors.push(ValidationError::TableLength {
+                tokens: tokens.len(),
+                len: spans.len(),
+            });
+        } else {
+            errors.extend(
+                (1..spans.len())
+                    .filter(|&index| spans[index] < spans[index - 1])
+                    .map(ValidationError::SpanOutOfOrder),
+            );
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(Self {
+            tokens,
+            jump_table,
+            spans,
+        })
+    }
+
+    pub fn tokens(&self) -> &[BfToken] {
+        &self.tokens
+    }
+
+    pub fn jump_table(&self) -> &[usize] {
+        &self.jump_table
+    }
+
+    pub fn span(&self, pc: usize) -> Option<usize> {
+        self.spans.get(pc).copied()
+    }
+
+    /// The first instruction at or after byte `offset` of `code`, the source
+    /// the program was parsed from, or `None` past the last one or inside a
+    /// multi-byte char.
+    pub fn pc_at(&self, code: &str, offset: usize) -> Option<usize> {
+        let index = code.get(..offset)?.chars().count();
+        self.spans.iter().position(|&span| span >= index)
+    }
+
+    /// The pcs of the `[` of every loop whose body holds `pc`, innermost
+    /// first.
+    pub fn enclosing_loops(&self, pc: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..pc.min(self.tokens.len())).rev().filter(move |&start| {
+            self.tokens[start] == BfToken::LoopStart && self.jump_table[start] > pc
+        })
+    }
+
+    /// Maps pcs to positions in the source, like [`Program::span`].
+    pub fn source_map(&self) -> SourceMap {
+        SourceMap::new(self.spans.clone())
+    }
+}
+
+/// Writes the program out as portable Brainfuck, lowered by
+/// [`lower::to_standard`] so it parses back with the plain parser.
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&BfParser::to_source(&lower::to_standard(&self.tokens)))
+    }
+}
+
+/// Parses through the optimizing pipeline, like [`Program::parse_optimized`].
+impl FromStr for Program {
+    type Err = BfError;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Ok(Self::parse_optimized(code)?)
+    }
+}
+
+/// Builds a [`Program`] from Brainfuck source checked at compile time. Several
+/// literals are joined as if they were one, and an unbalanced bracket fails
+/// the build at the `bf!` call.
+///
+/// ```
+/// use bf_rust::bf;
+///
+/// let add = bf!("++>+++", r"[-<+>]  moves the 3 onto the 2");
+/// assert_eq!(add.tokens().len(), 12);
+/// ```
+///
+/// ```compile_fail
+/// use bf_rust::bf;
+///
+/// let broken = bf!("++[->+<");
+/// ```
+#[macro_export]
+macro_rules! bf {
+    ($($code:expr),+ $(,)?) => {{
+        const CODE: &str = concat!($($code),+);
+        const TOKENS: [$crate::bf::bf_token::BfToken;
+            $crate::bf::bf_parser::BfParser::count_commands(CODE)] =
+            $crate::bf::bf_parser::BfParser::parse_const(CODE);
+        $crate::bf::program::Program::from_tokens(TOKENS.to_vec())
+            .expect("brackets are checked at compile time")
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_machine::BfMachine, diagnostics::line_col};
+
+    use super::*;
+
+    const COMMENTED: &str = "This program adds two cells, or it would
+if the author had closed every loop properly.
+
+++[>+<-]]>.";
+
+    #[test]
+    fn validated_tokens() {
+        let tokens = BfParser::parse("+[->+<]").unwrap();
+        let program = Program::from_tokens(tokens.clone()).unwrap();
+        assert_eq!(program.tokens(), &tokens);
+        assert_eq!(program.jump_table(), &[0, 6, 2, 3, 4, 5, 1]);
+
+        for tokens in [
+            vec![BfToken::LoopStart, BfToken::Increment(1)],
+            vec![BfToken::LoopEnd, BfToken::LoopStart],
+        ] {
+            assert!(Program::from_tokens(tokens).is_err());
+        }
+        assert_eq!(
+            Program::from_tokens(vec![BfToken::CursorRight(0)]),
+            Err(vec![ValidationError::ZeroOperand(0)])
+        );
+    }
+
+    #[test]
+    fn parts_round_trip() {
+        let program = Program::parse_optimized(COMMENTED.replace("]]", "]").as_str()).unwrap();
+        let spans = (0..program.tokens().len())
+            .map(|pc| program.span(pc).unwrap())
+            .collect();
+        let rebuilt = Program::from_parts(
+            program.tokens().to_vec(),
+            program.jump_table().to_vec(),
+            spans,
+        );
+        assert_eq!(rebuilt, Ok(program));
+    }
+
+    #[test]
+    fn corrupted_parts() {
+        let tokens = BfParser::parse("+[-]").unwrap();
+        let corrupted = Program::from_parts(tokens.clone(), vec![0, 9, 2, 1], vec![0, 1, 3, 2]);
+        assert_eq!(
+            corrupted,
+            Err(vec![
+                ValidationError::JumpOutOfRange {
+                    index: 1,
+                    target: 9
+                },
+                ValidationError::SpanOutOfOrder(3),
+            ])
+        );
+        assert_eq!(
+            Program::from_parts(tokens, vec![0, 3], vec![0, 1, 2, 3]),
+            Err(vec![ValidationError::TableLength { tokens: 4, len: 2 }])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_round_trip() {
+        let code = include_str!("../../tests/programs/hello_world.b");
+        let program = Program::parse_optimized(code).unwrap();
+        let json = serde_json::to_string(&program).unwrap();
+        let decoded: Program = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, program);
+
+        let mut output = vec![];
+        BfMachine::new(30000, Cursor::new(vec![]), &mut output)
+            .run(&decoded)
+            .unwrap();
+        assert_eq!(output, b"Hello World!\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_with_bad_jump_is_rejected() {
+        let json = r#"{
+            "tokens": [{"Increment": 1}, "LoopStart", {"Decrement": 1}, "LoopEnd"],
+            "jump_table": [0, 99, 2, 1],
+            "spans": [0, 1, 2, 3]
+        }"#;
+        let err = serde_json::from_str::<Program>(json).unwrap_err();
+        assert!(err.to_string().starts_with("invalid program: "), "{err}");
+        assert!(err.to_string().contains("99"), "{err}");
+    }
+
+    #[test]
+    fn inserted_token_rebuilds_table() {
+        let mut tokens = BfParser::parse("++[>+<-]>.").unwrap();
+        tokens.insert(0, BfToken::Increment(1));
+        let program = Program::from_tokens(tokens).unwrap();
+        assert_eq!(program.jump_table()[3], 8);
+        assert_eq!(program.jump_table()[8], 3);
+
+        let mut output = vec![];
+        BfMachine::new(10, Cursor::new(vec![]), &mut output)
+            .run(&program)
+            .unwrap();
+        assert_eq!(output, vec![3]);
+    }
+
+    #[test]
+    fn parse_error_position() {
+        let err = Program::parse(COMMENTED).unwrap_err();
+        assert_eq!(line_col(COMMENTED, err.index()), (4, 9));
+
+        let err = Program::parse_optimized(COMMENTED).unwrap_err();
+        assert_eq!(line_col(COMMENTED, err.index()), (4, 9));
+    }
+
+    #[test]
+    fn shebang_keeps_positions() {
+        let code = "#!/usr/bin/env bf-rust\n+.+]";
+
+        for level in OptLevel::ALL {
+            let err = Program::parse_at(code, &ParseOptions::default(), level).unwrap_err();
+            assert_eq!(line_col(code, err.index()), (2, 4), "{level}");
+        }
+    }
+
+    #[test]
+    fn optimized_spans() {
+        let code = "comment\n+++ >>. more\n<<,";
+        let program = Program::parse_optimized(code).unwrap();
+
+        assert_eq!(
+            program.tokens(),
+            &[
+                BfToken::Increment(3),
+                BfToken::CursorRight(2),
+                BfToken::PrintChar,
+                BfToken::CursorLeft(2),
+                BfToken::InputChar,
+            ]
+        );
+        let positions: Vec<_> = (0..program.tokens().len())
+            .map(|pc| line_col(code, program.span(pc).unwrap()))
+            .collect();
+        assert_eq!(positions, vec![(2, 1), (2, 5), (2, 7), (3, 1), (3, 3)]);
+        assert_eq!(program.span(5), None);
+    }
+
+    #[test]
+    fn line_comment_positions() {
+        let code = "; reads , and prints .\n+; more [\n[";
+        let options = ParseOptions {
+            line_comments: true,
+            ..Default::default()
+        };
+
+        for result in [
+            Program::parse_with_options(code, &options),
+            Program::parse_optimized_with_options(code, &options),
+        ] {
+            assert_eq!(line_col(code, result.unwrap_err().index()), (3, 1));
+        }
+
+        let program = Program::parse_optimized_with_options("+; , .\n+.", &options).unwrap();
+        assert_eq!(
+            program.tokens(),
+            &[BfToken::Increment(2), BfToken::PrintChar]
+        );
+        assert_eq!(program.span(1), Some(8));
+    }
+
+    #[test]
+    fn runtime_error_position() {
+        let code = "read twice\n,\n+,";
+
+        for program in [
+            Program::parse(code).unwrap(),
+            Program::parse_optimized(code).unwrap(),
+        ] {
+            let mut machine = BfMachine::new(10, Cursor::new(vec![1]), vec![]);
+            let err = machine.run(&program).unwrap_err();
+            let span = program.span(err.pc()).unwrap();
+
+            assert_eq!(line_col(code, span), (3, 2));
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    cell::RefCell,
+    io::{self, Read, Write},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError},
+    cell::Cell,
+    program::Program,
+};
+
+/// How far a run has come, as [`run_with_progress`] reports it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Instructions executed so far.
+    pub steps: u64,
+    /// Bytes the program wrote so far.
+    pub output_bytes: usize,
+    pub elapsed: Duration,
+}
+
+/// Writes `n` with an SI suffix and three significant digits, e.g. `999`,
+/// `1.23k` or `45.6M`.
+pub fn si(n: f64) -> String {
+    const SUFFIXES: [&str; 6] = ["", "k", "M", "G", "T", "P"];
+
+    let mut n = n.max(0.0);
+    let mut suffix = 0;
+    // Rounding decides the suffix, so 999.9k is written as 1.00M.
+    while suffix + 1 < SUFFIXES.len() && n >= 999.5 {
+        n /= 1000.0;
+        suffix += 1;
+    }
+    match (suffix, n) {
+        (0, n) => format!("{n:.0}"),
+        (_, n) if n >= 99.95 => format!("{n:.0}{}", SUFFIXES[suffix]),
+        (_, n) if n >= 9.995 => format!("{n:.1}{}", SUFFIXES[suffix]),
+        (_, n) => format!("{n:.2}{}", SUFFIXES[suffix]),
+    }
+}
+
+/// The status line for `progress`, e.g.
+/// `1.23G steps, 98.1M steps/s, 4.10k bytes out, 12.5s`.
+pub fn format_progress(progress: &Progress) -> String {
+    let seconds = progress.elapsed.as_secs_f64();
+    let rate = match seconds > 0.0 {
+        true => si(progress.steps as f64 / seconds),
+        false => "-".to_string(),
+    };
+    let elapsed = match progress.elapsed.as_secs() {
+        secs if secs < 60 => format!("{seconds:.1}s"),
+        secs => format!("{}m{:02}s", secs / 60, secs % 60),
+    };
+    format!(
+        "{} steps, {rate} steps/s, {} bytes out, {elapsed}",
+        si(progress.steps as f64),
+        si(progress.output_bytes as f64),
+    )
+}
+
+/// Runs `program` from `pc` in slices of `slice` steps through the step
+/// limit, calling `update` between slices; a run shorter than a slice never
+/// calls it. `max_steps`, if given, still limits the whole run.
+pub fn run_with_progress<R: Read, W: Write, C: Cell>(
+    machine: &mut BfMachine<R, W, C>,
+    program: &Program,
+    mut pc: usize,
+    slice: usize,
+    max_steps: Option<usize>,
+    mut update: impl FnMut(&Progress),
+) -> Result<(), BfRuntimeError> {
+    assert!(slice > 0, "slices must make progress");
+    let start = Instant::now();
+    let mut remaining = max_steps.unwrap_or(usize::MAX);
+    let mut steps = 0u64;
+    loop {
+        let limit = slice.min(remaining);
+        machine.set_step_limit(limit);
+        match machine.run_from(program, pc) {
+            Err(BfRuntimeError::StepLimitExceeded { pc: next }) if remaining > limit => {
+                pc = next;
+                remaining -= limit;
+                steps += limit as u64;
+                update(&Progress {
+                    steps,
+                    output_bytes: machine.bytes_written(),
+                    elapsed: start.elapsed(),
+                });
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A status line on a terminal that program output shares. Output erases it
+/// first, and it is only drawn while the output is at the start of a line,
+/// so it never overwrites a partial line.
+#[derive(Debug)]
+pub struct StatusLine<E: Write> {
+    terminal: E,
+    shown: bool,
+    mid_line: bool,
+}
+
+impl<E: Write> StatusLine<E> {
+    pub fn new(terminal: E) -> Self {
+        Self {
+            terminal,
+            shown: false,
+            mid_line: false,
+        }
+    }
+
+    /// Draws `text` in place of the line shown before, if any.
+    pub fn show(&mut self, text: &str) -> io::Result<()> {
+        if self.mid_line {
+            return Ok(());
+        }
+        write!(self.terminal, "\r{text}\x1b[K")?;
+        self.shown = true;
+        self.terminal.flush()
+    }
+
+    /// Clears the line, e.g. before output or once the run is over.
+    pub fn erase(&mut self) -> io::Result<()> {
+        if !self.shown {
+            return Ok(());
+        }
+        self.terminal.write_all(b"\r\x1b[K")?;
+        self.shown = false;
+        self.terminal.flush()
+    }
+
+    pub fn into_inner(self) -> E {
+        self.terminal
+    }
+}
+
+/// Writes to `inner`, erasing `status` before each write.
+#[derive(Debug)]
+pub struct StatusAwareWriter<W, E: Write> {
+    inner: W,
+    status: Rc<RefCell<StatusLine<E>>>,
+}
+
+impl<W: Write, E: Write> StatusAwareWriter<W, E> {
+    pub fn new(inner: W, status: Rc<RefCell<StatusLine<E>>>) -> Self {
+        Self { inner, status }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write, E: Write> Write for StatusAwareWriter<W, E> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut status = self.status.borrow_mut();
+        status.erase()?;
+        let written = self.inner.write(buf)?;
+        if let Some(&last) = buf[..written].last() {
+            status.mid_line = last != b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_parser::ParseOptions, program::OptLevel};
+
+    use super::*;
+
+    #[test]
+    fn si_suffixes() {
+        for (n, expected) in [
+            (0.0, "0"),
+            (7.0, "7"),
+            (999.0, "999"),
+            (999.6, "1.00k"),
+            (1_234.0, "1.23k"),
+            (12_345.0, "12.3k"),
+            (123_456.0, "123k"),
+            (999_499.0, "999k"),
+            (999_960.0, "1.00M"),
+            (45_600_000.0, "45.6M"),
+            (7.5e9, "7.50G"),
+            (1e12, "1.00T"),
+            (3e18, "3000P"),
+        ] {
+            assert_eq!(si(n), expected, "{n}");
+        }
+    }
+
+    #[test]
+    fn formats_status() {
+        let progress = Progress {
+            steps: 1_230_000_000,
+            output_bytes: 4_100,
+            elapsed: Duration::from_millis(12_500),
+        };
+        assert_eq!(
+            format_progress(&progress),
+            "1.23G steps, 98.4M steps/s, 4.10k bytes out, 12.5s"
+        );
+
+        let progress = Progress {
+            steps: 0,
+            output_bytes: 0,
+            elapsed: Duration::ZERO,
+        };
+        assert_eq!(
+            format_progress(&progress),
+            "0 steps, - steps/s, 0 bytes out, 0.0s"
+        );
+
+        let progress = Progress {
+            steps: 3_600,
+            output_bytes: 12,
+            elapsed: Duration::from_secs(3_725),
+        };
+        assert_eq!(
+            format_progress(&progress),
+            "3.60k steps, 1 steps/s, 12 bytes out, 62m05s"
+        );
+    }
+
+    #[test]
+    fn updates_once_per_full_slice() {
+        let program =
+            Program::parse_at(&"+".repeat(10), &ParseOptions::default(), OptLevel::O0).unwrap();
+        for (slice, updates) in [(1, 9), (3, 3), (5, 1), (10, 0)] {
+            let mut machine = BfMachine::new(1, Cursor::new(b""), vec![]);
+            let mut seen = vec![];
+            run_with_progress(&mut machine, &program, 0, slice, None, |progress| {
+                seen.push(progress.steps)
+            })
+            .unwrap();
+            assert_eq!(seen.len(), updates, "slice {slice}");
+            assert!(seen
+                .iter()
+                .enumerate()
+                .all(|(i, &steps)| steps == (i + 1) as u64 * slice as u64));
+            assert_eq!(machine.tape(), [10]);
+        }
+
+        let mut machine = BfMachine::new(1, Cursor::new(b""), vec![]);
+        let mut updates = 0;
+        let result = run_with_progress(&mut machine, &program, 0, 3, Some(7), |_| updates += 1);
+        assert!(matches!(
+            result,
+            Err(BfRuntimeError::StepLimitExceeded { pc: 7 })
+        ));
+        assert_eq!(updates, 2);
+    }
+
+    #[test]
+    fn output_erases_the_status() {
+        let status = Rc::new(RefCell::new(StatusLine::new(vec![])));
+        let mut output = StatusAwareWriter::new(vec![], Rc::clone(&status));
+
+        status.borrow_mut().show("1 steps").unwrap();
+        output.write_all(b"line\n").unwrap();
+        status.borrow_mut().show("2 steps").unwrap();
+        output.write_all(b"partial").unwrap();
+        // Drawing now would overwrite "partial".
+        status.borrow_mut().show("3 steps").unwrap();
+        output.write_all(b"\n").unwrap();
+        status.borrow_mut().erase().unwrap();
+
+        let printed = output.into_inner();
+        let status = Rc::try_unwrap(status).unwrap().into_inner().into_inner();
+        assert_eq!(
+            String::from_utf8(status).unwrap(),
+            "\r1 steps\x1b[K\r\x1b[K\r2 steps\x1b[K\r\x1b[K"
+        );
+        assert_eq!(printed, b"line\npartial\n");
+    }
+}
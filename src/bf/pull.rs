@@ -0,0 +1,207 @@
+use std::{
+    collections::BTreeSet,
+    io::{Read, Write},
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, RunOutcome},
+    bf_token::BfToken,
+    program::Program,
+};
+
+/// Why [`BfMachine::run_until_output`] returned.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StepResult {
+    /// The program printed a byte; `PrintCharN` hands out one per call.
+    Output(u8),
+    /// The program is at a `,` and waits for [`PullState::provide_input`].
+    NeedsInput,
+    /// The program ran to its end or an extension halted it.
+    Halted,
+}
+
+/// Where a program driven by [`BfMachine::run_until_output`] is, kept between
+/// calls.
+#[derive(Debug, Clone)]
+pub struct PullState {
+    pc: usize,
+    /// The pcs of every `.` and `,`, where the machine has to stop.
+    io_pcs: BTreeSet<usize>,
+    /// Bytes the `PrintCharN` at `pc` has handed out so far.
+    printed: usize,
+    input: Option<u8>,
+}
+
+impl PullState {
+    /// A state at the start of `program`.
+    pub fn new(program: &Program) -> Self {
+        let io_pcs = program
+            .tokens()
+            .iter()
+            .enumerate()
+            .filter(|(_, token)| {
+                matches!(
+                    token,
+                    BfToken::PrintChar | BfToken::PrintCharN(_) | BfToken::InputChar
+                )
+            })
+            .map(|(pc, _)| pc)
+            .collect();
+        Self {
+            pc: 0,
+            io_pcs,
+            printed: 0,
+            input: None,
+        }
+    }
+
+    /// The next instruction to run.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// The byte the pending `,` reads, replacing one provided before.
+    pub fn provide_input(&mut self, byte: u8) {
+        self.input = Some(byte);
+    }
+}
+
+impl<R: Read, W: Write> BfMachine<R, W> {
+    /// Runs `program` from `state` until it prints a byte, reaches a `,` with
+    /// no input provided, or ends. The machine's own input and output are
+    /// left alone: printed bytes go to the caller, and `,` only ever reads
+    /// what [`PullState::provide_input`] gave it, so a call never blocks.
+    pub fn run_until_output(
+        &mut self,
+        program: &Program,
+        state: &mut PullState,
+    ) -> Result<StepResult, BfRuntimeError> {
+        let tokens = program.tokens();
+        loop {
+            match tokens.get(state.pc) {
+                None => return Ok(StepResult::Halted),
+                Some(BfToken::PrintChar) => {
+                    state.pc += 1;
+                    return Ok(StepResult::Output(self.tape()[self.cursor()]));
+                }
+                Some(&BfToken::PrintCharN(count)) => {
+                    state.printed += 1;
+                    if state.printed == count {
+                        state.printed = 0;
+                        state.pc += 1;
+                    }
+                    return Ok(StepResult::Output(self.tape()[self.cursor()]));
+                }
+                Some(BfToken::InputChar) => {
+                    let Some(byte) = state.input.take() else {
+                        return Ok(StepResult::NeedsInput);
+                    };
+                    self.store_input(program, state.pc, byte)?;
+                    state.pc += 1;
+                }
+                Some(_) => match self.execute(program, state.pc, None, Some(&state.io_pcs))? {
+                    RunOutcome::Completed => state.pc = tokens.len(),
+                    RunOutcome::Breakpoint { pc } | RunOutcome::NeedsInput { pc } => {
+                        state.pc = pc;
+                    }
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    fn pull_all(program: &Program, input: &[u8]) -> Vec<u8> {
+        let mut machine = BfMachine::new(30_000, io::empty(), vec![]);
+        let mut state = PullState::new(program);
+        let mut input = input.iter();
+        let mut output = vec![];
+        loop {
+            match machine.run_until_output(program, &mut state).unwrap() {
+                StepResult::Output(byte) => output.push(byte),
+                StepResult::NeedsInput => state.provide_input(*input.next().unwrap()),
+                StepResult::Halted => return output,
+            }
+        }
+    }
+
+    #[test]
+    fn input_then_output_then_halt() {
+        let program = Program::parse(",+.").unwrap();
+        let mut machine = BfMachine::new(4, io::empty(), vec![]);
+        let mut state = PullState::new(&program);
+
+        assert_eq!(
+            machine.run_until_output(&program, &mut state).unwrap(),
+            StepResult::NeedsInput
+        );
+        // Asking again without input waits at the same `,`.
+        assert_eq!(
+            machine.run_until_output(&program, &mut state).unwrap(),
+            StepResult::NeedsInput
+        );
+        assert_eq!(state.pc(), 0);
+
+        state.provide_input(b'a');
+        assert_eq!(
+            machine.run_until_output(&program, &mut state).unwrap(),
+            StepResult::Output(b'b')
+        );
+        assert_eq!(
+            machine.run_until_output(&program, &mut state).unwrap(),
+            StepResult::Halted
+        );
+        assert_eq!(
+            machine.run_until_output(&program, &mut state).unwrap(),
+            StepResult::Halted
+        );
+        assert_eq!(machine.bytes_read(), 1);
+        assert_eq!(machine.bytes_written(), 0);
+    }
+
+    #[test]
+    fn pulls_what_run_writes() {
+        for (code, input) in [
+            (include_str!("../../tests/programs/hello_world.b"), &b""[..]),
+            ("+++[>+...<-]", b""),
+            (",[.,]", b"cat\0"),
+            ("..,.,..", b"xy"),
+        ] {
+            for program in [
+                Program::parse(code).unwrap(),
+                Program::parse_optimized(code).unwrap(),
+            ] {
+                let mut expected = vec![];
+                BfMachine::new(30_000, Cursor::new(input), &mut expected)
+                    .run(&program)
+                    .unwrap();
+                assert_eq!(pull_all(&program, input), expected, "{code}");
+            }
+        }
+    }
+
+    #[test]
+    fn errors_keep_their_pc() {
+        let program = Program::parse("+[>,<-]").unwrap();
+        let mut machine = BfMachine::new(4, io::empty(), vec![]);
+        machine.protect_range(1..2);
+        let mut state = PullState::new(&program);
+
+        assert_eq!(
+            machine.run_until_output(&program, &mut state).unwrap(),
+            StepResult::NeedsInput
+        );
+        state.provide_input(1);
+        let err = machine.run_until_output(&program, &mut state).unwrap_err();
+        assert_eq!(err.pc(), 3);
+        assert!(matches!(
+            err.root_cause(),
+            BfRuntimeError::WriteProtected { cell: 1, .. }
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+use std::{fs, path::PathBuf};
+
+use rustyline::{error::ReadlineError, DefaultEditor};
+
+/// The prompt for the first line of an entry.
+pub const PROMPT: &str = "bf> ";
+/// The prompt for the lines of an entry after a `[` was left open.
+pub const CONTINUATION_PROMPT: &str = "...> ";
+
+/// Whether `partial` leaves a `[` open, so a REPL should read another line
+/// before running it. A stray `]` needs nothing more: running the entry
+/// reports it.
+pub fn needs_more_lines(partial: &str) -> bool {
+    let mut depth = 0usize;
+    for ch in partial.chars() {
+        match ch {
+            '[' => depth += 1,
+            ']' if depth == 0 => return false,
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
+
+/// Where the REPL keeps its history between sessions, in the user's data
+/// directory.
+pub fn history_path() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("bf-rust").join("repl_history"))
+}
+
+/// What [`LineEditor::read_entry`] read.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Entry {
+    /// Source whose brackets balance, or that has a stray `]`, as typed, lines
+    /// joined by `\n`.
+    Code(String),
+    /// Ctrl-C threw away what was typed of the entry.
+    Cleared,
+    /// Ctrl-D, or the end of the input.
+    End,
+}
+
+/// Reads REPL entries with line editing and a history that survives the
+/// session. An entry spans lines while a `[` is open and is remembered as one
+/// history item, which recalls as a whole.
+pub struct LineEditor {
+    editor: DefaultEditor,
+    history: Option<PathBuf>,
+}
+
+impl LineEditor {
+    /// Opens an editor whose history is loaded from and saved to `history`,
+    /// if given. A history file that does not exist yet starts empty.
+    pub fn open(history: Option<PathBuf>) -> Result<Self, ReadlineError> {
+        let mut editor = DefaultEditor::new()?;
+        if let Some(path) = &history {
+            if path.exists() {
+                editor.load_history(path)?;
+            }
+        }
+        Ok(Self { editor, history })
+    }
+
+    /// Reads lines until their brackets balance, prompting with
+    /// [`CONTINUATION_PROMPT`] after the first.
+    pub fn read_entry(&mut self) -> Result<Entry, ReadlineError> {
+        let mut entry = String::new();
+        loop {
+            let prompt = match entry.is_empty() {
+                true => PROMPT,
+                false => CONTINUATION_PROMPT,
+            };
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if !entry.is_empty() {
+                        entry.push('\n');
+                    }
+                    entry.push_str(&line);
+                    if !needs_more_lines(&entry) {
+                        self.remember(&entry)?;
+                        return Ok(Entry::Code(entry));
+                    }
+                }
+                Err(ReadlineError::Interrupted) => return Ok(Entry::Cleared),
+                Err(ReadlineError::Eof) if entry.is_empty() => return Ok(Entry::End),
+                Err(ReadlineError::Eof) => {
+                    self.remember(&entry)?;
+                    return Ok(Entry::Code(entry));
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Adds `entry` to the history, unless it is blank.
+    pub fn remember(&mut self, entry: &str) -> Result<(), ReadlineError> {
+        if !entry.trim().is_empty() {
+            self.editor.add_history_entry(entry)?;
+        }
+        Ok(())
+    }
+
+    /// The history, oldest first.
+    pub fn history(&self) -> Vec<String> {
+        self.editor.history().iter().cloned().collect()
+    }
+
+    /// Writes the history back to its file, creating its directory.
+    pub fn save(&mut self) -> Result<(), ReadlineError> {
+        let Some(path) = &self.history else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        self.editor.save_history(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced_input_is_complete() {
+        for partial in [
+            "",
+            "+",
+            "+++.",
+            "[-]",
+            "+[>[-]<-]>.",
+            "[[]][]",
+            "no commands",
+        ] {
+            assert!(!needs_more_lines(partial), "{partial:?}");
+        }
+    }
+
+    #[test]
+    fn open_loops_need_more() {
+        for partial in ["[", "+[->", "[[-]", "[]\n[", "[[]\n[]", "+[ comment ] [ ["] {
+            assert!(needs_more_lines(partial), "{partial:?}");
+        }
+    }
+
+    #[test]
+    fn stray_close_is_complete() {
+        // Running these reports the `]`, which more lines cannot fix.
+        for partial in ["]", "+]", "[]]", "][", "]]["] {
+            assert!(!needs_more_lines(partial), "{partial:?}");
+        }
+    }
+
+    #[test]
+    fn continued_entry_completes() {
+        let mut entry = String::from("+[");
+        assert!(needs_more_lines(&entry));
+        entry.push_str("\n>++[");
+        assert!(needs_more_lines(&entry));
+        entry.push_str("\n-]<-]");
+        assert!(!needs_more_lines(&entry));
+    }
+
+    #[test]
+    fn history_round_trips() {
+        let path = std::env::temp_dir()
+            .join(format!("bf-rust-{}", std::process::id()))
+            .join("repl_history");
+        let entries = ["+++.", "+[\n>++<-\n]", "back\\slash", "\t"];
+
+        let mut editor = LineEditor::open(Some(path.clone())).unwrap();
+        for entry in entries {
+            editor.remember(entry).unwrap();
+        }
+        assert_eq!(editor.history().len(), 3);
+        editor.save().unwrap();
+
+        let editor = LineEditor::open(Some(path.clone())).unwrap();
+        assert_eq!(editor.history(), &entries[..3]);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,202 @@
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+};
+
+use log::debug;
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, RunOutcome},
+    program::Program,
+};
+
+/// Numbers the tasks of a [`Scheduler`] in the order they were spawned.
+pub type TaskId = usize;
+
+/// A task that left the [`Scheduler`], with its machine for a look at the
+/// output and tape.
+#[derive(Debug)]
+pub struct TaskReport<R: Read, W: Write> {
+    pub id: TaskId,
+    pub machine: BfMachine<R, W>,
+    /// `Ok` if the program ran to its end.
+    pub result: Result<(), BfRuntimeError>,
+}
+
+struct Task<R: Read, W: Write> {
+    id: TaskId,
+    machine: BfMachine<R, W>,
+    program: Program,
+    /// The next instruction to run.
+    pc: usize,
+}
+
+/// Runs several machines on one thread, each for at most a quantum of
+/// instructions per turn before the next gets its go.
+///
+/// A task yields when its quantum runs out, at a breakpoint and, with
+/// [`BfMachine::with_nonblocking_input`], at input that is not there yet, and
+/// picks up at the same pc on its next turn. The scheduler sets the step
+/// limit of every machine it is given to the quantum.
+pub struct Scheduler<R: Read, W: Write> {
+    quantum: usize,
+    tasks: VecDeque<Task<R, W>>,
+    next_id: TaskId,
+}
+
+impl<R: Read, W: Write> Scheduler<R, W> {
+    pub fn new(quantum: usize) -> Self {
+        assert!(quantum > 0);
+
+        Self {
+            quantum,
+            tasks: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Adds a task running `program` on `machine` from pc 0. A task spawned
+    /// between turns gets its first go in the next one.
+    pub fn spawn(&mut self, mut machine: BfMachine<R, W>, program: Program) -> TaskId {
+        let id = self.next_id;
+        self.next_id += 1;
+        machine.set_step_limit(self.quantum);
+        self.tasks.push_back(Task {
+            id,
+            machine,
+            program,
+            pc: 0,
+        });
+        id
+    }
+
+    /// Tasks that have not finished yet.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Gives every task one go, in the order they were spawned, and returns
+    /// those that finished or failed in the order they did.
+    pub fn turn(&mut self) -> Vec<TaskReport<R, W>> {
+        let mut reports = vec![];
+        for _ in 0..self.tasks.len() {
+            let mut task = self.tasks.pop_front().expect("counted above");
+            let result = match task.machine.resume_until_break(&task.program, task.pc) {
+                Ok(RunOutcome::Completed) => Ok(()),
+                Ok(RunOutcome::Breakpoint { pc } | RunOutcome::NeedsInput { pc })
+                | Err(BfRuntimeError::StepLimitExceeded { pc }) => {
+                    task.pc = pc;
+                    self.tasks.push_back(task);
+                    continue;
+                }
+                Err(err) => Err(err),
+            };
+            debug!(
+                "task {} {}",
+                task.id,
+                if result.is_ok() { "finished" } else { "failed" }
+            );
+            reports.push(TaskReport {
+                id: task.id,
+                machine: task.machine,
+                result,
+            });
+        }
+        reports
+    }
+
+    /// Takes turns until every task has finished or failed.
+    pub fn run(&mut self) -> Vec<TaskReport<R, W>> {
+        let mut reports = vec![];
+        while !self.is_empty() {
+            reports.extend(self.turn());
+        }
+        reports
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Cursor};
+
+    use super::*;
+
+    type TestScheduler = Scheduler<Cursor<&'static [u8]>, Vec<u8>>;
+
+    fn spawn(scheduler: &mut TestScheduler, code: &str, input: &'static [u8]) -> TaskId {
+        let machine = BfMachine::new(8, Cursor::new(input), vec![]);
+        scheduler.spawn(machine, Program::parse(code).unwrap())
+    }
+
+    /// Prints 1 to `count`.
+    fn counter(count: usize) -> String {
+        format!("{}[>+.<-]", "+".repeat(count))
+    }
+
+    #[test]
+    fn shorter_tasks_finish_first() {
+        let mut scheduler = Scheduler::new(10);
+        let long = spawn(&mut scheduler, &counter(50), b"");
+        let short = spawn(&mut scheduler, &counter(5), b"");
+        assert_eq!(scheduler.len(), 2);
+
+        let reports = scheduler.run();
+        let ids: Vec<_> = reports.iter().map(|report| report.id).collect();
+        assert_eq!(ids, [short, long]);
+        let expected: [Vec<u8>; 2] = [(1..=5).collect(), (1..=50).collect()];
+        for (report, expected) in reports.into_iter().zip(expected) {
+            assert!(report.result.is_ok());
+            assert_eq!(report.machine.into_parts().3, expected);
+        }
+        assert!(scheduler.is_empty());
+    }
+
+    #[test]
+    fn tasks_join_between_turns() {
+        let mut scheduler = Scheduler::new(4);
+        let first = spawn(&mut scheduler, &counter(20), b"");
+        assert!(scheduler.turn().is_empty());
+
+        let second = spawn(&mut scheduler, "+.", b"");
+        let reports = scheduler.turn();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].id, second);
+        assert_eq!(scheduler.run()[0].id, first);
+    }
+
+    #[test]
+    fn a_failing_task_leaves_the_others_alone() {
+        let mut scheduler = Scheduler::new(3);
+        let before = spawn(&mut scheduler, &counter(12), b"");
+        let failing = spawn(&mut scheduler, "+++,,.", b"a");
+        let after = spawn(&mut scheduler, ",[.,]", b"cat\0");
+
+        let mut reports = scheduler.run();
+        reports.sort_by_key(|report| report.id);
+        let ids: Vec<_> = reports.iter().map(|report| report.id).collect();
+        assert_eq!(ids, [before, failing, after]);
+
+        assert!(matches!(
+            reports[1].result,
+            Err(BfRuntimeError::UnexpectedEof { pc: 4 })
+        ));
+        let outputs: Vec<_> = reports
+            .into_iter()
+            .map(|report| report.machine.into_parts().3)
+            .collect();
+        assert_eq!(outputs[0], (1..=12).collect::<Vec<u8>>());
+        assert_eq!(outputs[2], b"cat");
+
+        // Tasks of differing I/O can share a scheduler behind trait objects.
+        let mut scheduler: Scheduler<Box<dyn Read>, Box<dyn Write>> = Scheduler::new(2);
+        scheduler.spawn(
+            BfMachine::new(4, Box::new(io::empty()), Box::new(io::sink())),
+            Program::parse("+++").unwrap(),
+        );
+        assert!(scheduler.run()[0].result.is_ok());
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, MachineConfig},
+    program::Program,
+};
+
+/// Steps a session runs between looks at the clock.
+const SLICE: usize = 100_000;
+
+/// How long to wait before accepting again after a failed accept.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How a connection to a [`Server`] ended.
+#[derive(Debug)]
+pub enum SessionEnd {
+    /// The program ran to its end.
+    Completed,
+    /// The client went away, or stopped sending input the program waited for.
+    Disconnected,
+    StepLimit,
+    TimeLimit,
+    Failed(BfRuntimeError),
+}
+
+/// Serves a program over TCP: every connection runs it on a fresh machine
+/// that reads from and writes to the socket.
+///
+/// The machine config applies to every connection, its step limit counting
+/// per connection. Connections beyond the connection limit are closed as
+/// soon as they are accepted.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    program: Arc<Program>,
+    config: MachineConfig,
+    time_limit: Option<Duration>,
+    max_connections: usize,
+    active: Arc<AtomicUsize>,
+}
+
+impl Server {
+    pub fn bind(
+        addr: impl ToSocketAddrs,
+        program: Program,
+        config: MachineConfig,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(addr)?,
+            program: Arc::new(program),
+            config,
+            time_limit: None,
+            max_connections: usize::MAX,
+            active: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Ends each connection once it has been open for `limit`.
+    pub fn with_time_limit(mut self, limit: Duration) -> Self {
+        self.time_limit = Some(limit);
+        self
+    }
+
+    /// Serves at most `max` connections at once.
+    pub fn with_connection_limit(mut self, max: usize) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Where the server listens, with the port the system picked for port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts connections forever, running each session on its own thread.
+    /// A connection that fails before its session starts is dropped, and the
+    /// server goes on with the next.
+    pub fn serve(&self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                // The client gave up before it was accepted.
+                Err(err) if err.kind() == ErrorKind::ConnectionAborted => continue,
+                // Errors like running out of file descriptors tend to repeat,
+                // so give them a moment to clear instead of spinning.
+                Err(err) => {
+                    warn!("failed to accept a connection: {err}");
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            let peer = match stream.peer_addr() {
+                Ok(peer) => peer,
+                Err(err) => {
+                    debug!("dropped a connection that went away: {err}");
+                    continue;
+                }
+            };
+            if self.active.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+                self.active.fetch_sub(1, Ordering::SeqCst);
+                debug!("refused {peer}: {} connections open", self.max_connections);
+                continue;
+            }
+
+            let (program, config, time_limit) =
+                (Arc::clone(&self.program), self.config, self.time_limit);
+            let active = Arc::clone(&self.active);
+            thread::spawn(move || {
+                debug!("serving {peer}");
+                let end = session(&stream, &program, config, time_limit);
+                debug!("session with {peer} ended: {end:?}");
+                active.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Runs `program` with `stream` as its input and output until it ends, the
+/// client leaves or a limit is reached.
+pub fn session(
+    stream: &TcpStream,
+    program: &Program,
+    config: MachineConfig,
+    time_limit: Option<Duration>,
+) -> SessionEnd {
+    let deadline = time_limit.map(|limit| Instant::now() + limit);
+    let socket = Timed { stream, deadline };
+    let mut machine = BfMachine::with_config(config, socket, socket);
+    let mut remaining = config.max_steps.unwrap_or(usize::MAX);
+    let mut pc = 0;
+    loop {
+        let steps = SLICE.min(remaining);
+        machine.set_step_limit(steps);
+        match machine.run_from(program, pc) {
+            Ok(()) => return SessionEnd::Completed,
+            Err(BfRuntimeError::StepLimitExceeded { pc: next }) => {
+                pc = next;
+                remaining -= steps;
+                if remaining == 0 {
+                    return SessionEnd::StepLimit;
+                }
+            }
+            Err(err) => return ended_by(err, deadline),
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return SessionEnd::TimeLimit;
+        }
+    }
+}
+
+/// A session's socket, whose reads and writes wait no later than the
+/// deadline, so a client that stops sending input or reading output is let
+/// go at the time limit too.
+#[derive(Clone, Copy)]
+struct Timed<'a> {
+    stream: &'a TcpStream,
+    deadline: Option<Instant>,
+}
+
+impl Timed<'_> {
+    /// The time left until the deadline, or `TimedOut` once it has passed.
+    fn time_left(&self) -> io::Result<Option<Duration>> {
+        let Some(deadline) = self.deadline else {
+            return Ok(None);
+        };
+        match deadline.saturating_duration_since(Instant::now()) {
+            Duration::ZERO => Err(ErrorKind::TimedOut.into()),
+            left => Ok(Some(left)),
+        }
+    }
+}
+
+impl Read for Timed<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.set_read_timeout(self.time_left()?)?;
+        (&mut &*self.stream).read(buf)
+    }
+}
+
+impl Write for Timed<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.set_write_timeout(self.time_left()?)?;
+        (&mut &*self.stream).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&mut &*self.stream).flush()
+    }
+}
+
+/// How a session that failed with `err` ended.
+fn ended_by(err: BfRuntimeError, deadline: Option<Instant>) -> SessionEnd {
+    match err.root_cause() {
+        BfRuntimeError::UnexpectedEof { .. } => SessionEnd::Disconnected,
+        BfRuntimeError::Io { source, .. }
+            if matches!(source.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline) =>
+        {
+            SessionEnd::TimeLimit
+        }
+        BfRuntimeError::Io { .. } => SessionEnd::Disconnected,
+        _ => SessionEnd::Failed(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use super::*;
+
+    fn start(code: &str, config: MachineConfig, limit: Option<Duration>) -> SocketAddr {
+        let mut server =
+            Server::bind("127.0.0.1:0", Program::parse(code).unwrap(), config).unwrap();
+        if let Some(limit) = limit {
+            server = server.with_time_limit(limit);
+        }
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+        addr
+    }
+
+    #[test]
+    fn echoes_over_the_socket() {
+        let addr = start(",[.,]", MachineConfig::default(), None);
+
+        for message in ["hello", "again"] {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(message.as_bytes()).unwrap();
+            stream.shutdown(std::net::Shutdown::Write).unwrap();
+            let mut echoed = String::new();
+            stream.read_to_string(&mut echoed).unwrap();
+            assert_eq!(echoed, message);
+        }
+    }
+
+    #[test]
+    fn limits_end_runaway_sessions() {
+        let config = MachineConfig {
+            max_steps: Some(1_000),
+            ..MachineConfig::default()
+        };
+        let addr = start("+[]", config, None);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+        let addr = start(
+            "+[]",
+            MachineConfig::default(),
+            Some(Duration::from_millis(50)),
+        );
+        let mut stream = TcpStream::connect(addr).unwrap();
+        assert_eq!(stream.read(&mut [0; 1]).unwrap(), 0);
+
+        // A client that never sends what `,` waits for is let go as well.
+        let program = Program::parse(",.").unwrap();
+        let (client, server) = pair();
+        let end = session(
+            &server,
+            &program,
+            MachineConfig::default(),
+            Some(Duration::from_millis(50)),
+        );
+        assert!(matches!(end, SessionEnd::TimeLimit), "{end:?}");
+        drop(client);
+    }
+
+    #[test]
+    fn the_time_limit_counts_across_reads() {
+        // Each byte comes well within the limit, but all of them do not.
+        let program = Program::parse(",[.,]").unwrap();
+        let (mut client, server) = pair();
+        let sender = thread::spawn(move || {
+            for _ in 0..8 {
+                if client.write_all(b"x").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(40));
+            }
+        });
+
+        let start = Instant::now();
+        let limit = Duration::from_millis(100);
+        let end = session(&server, &program, MachineConfig::default(), Some(limit));
+        assert!(matches!(end, SessionEnd::TimeLimit), "{end:?}");
+        assert!(start.elapsed() >= limit);
+        drop(server);
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn a_client_that_does_not_read_is_let_go() {
+        let program = Program::parse("+[.]").unwrap();
+        let (client, server) = pair();
+        let limit = Duration::from_millis(50);
+        let end = session(&server, &program, MachineConfig::default(), Some(limit));
+        assert!(matches!(end, SessionEnd::TimeLimit), "{end:?}");
+        drop(client);
+    }
+
+    #[test]
+    fn a_disconnect_ends_the_session() {
+        let program = Program::parse(",[.,]").unwrap();
+        let (mut client, server) = pair();
+        client.write_all(b"x").unwrap();
+        drop(client);
+
+        let end = session(&server, &program, MachineConfig::default(), None);
+        assert!(matches!(end, SessionEnd::Disconnected), "{end:?}");
+    }
+
+    #[test]
+    fn refuses_connections_beyond_the_limit() {
+        let server = Server::bind(
+            "127.0.0.1:0",
+            Program::parse(",.").unwrap(),
+            MachineConfig::default(),
+        )
+        .unwrap()
+        .with_connection_limit(1);
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.serve());
+
+        let mut first = TcpStream::connect(addr).unwrap();
+        let mut second = TcpStream::connect(addr).unwrap();
+        // The second is closed without running, while the first still waits
+        // on its input.
+        assert_eq!(second.read(&mut [0; 1]).unwrap(), 0);
+        first.write_all(b"!").unwrap();
+        let mut echoed = [0; 1];
+        first.read_exact(&mut echoed).unwrap();
+        assert_eq!(&echoed, b"!");
+    }
+
+    /// Both ends of a local connection, client first.
+    fn pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, server)
+    }
+}
@@ -0,0 +1,219 @@
+use log::debug;
+
+use super::bf_token::BfToken;
+
+/// Sinks the cursor moves of each basic block, taking `spans` along and
+/// returning the new tokens and spans.
+///
+/// A block is a run of `+`, `-`, `<` and `>` between the tokens that look at
+/// the cursor: loops, I/O, extensions, tape switches and forks. Within one,
+/// `+` and `-` become [`BfToken::IncrementAt`] and [`BfToken::DecrementAt`]
+/// of the cell the moves before them had reached, and the moves themselves
+/// become one net move at the end of the block, left out if it is none. So
+/// `>+>+>+<<<` is three offset increments and the cursor never moves, while
+/// every token after a block finds the cursor where the moves would have left
+/// it.
+///
+/// The net move takes the span of the last token of its block.
+pub fn sink_moves(tokens: &[BfToken], spans: &[usize]) -> (Vec<BfToken>, Vec<usize>) {
+    let mut sunk = Vec::with_capacity(tokens.len());
+    let mut sunk_spans = Vec::with_capacity(spans.len());
+    let mut block = Block::default();
+
+    for (&token, &span) in tokens.iter().zip(spans) {
+        let token = match token {
+            BfToken::CursorLeft(n) | BfToken::CursorRight(n) => {
+                let left = matches!(token, BfToken::CursorLeft(_));
+                if block.step(left, n).is_none() {
+                    // Too far to add up; moves that far are rare enough to
+                    // keep as they are.
+                    block.flush(&mut sunk, &mut sunk_spans);
+                    sunk.push(token);
+                    sunk_spans.push(span);
+                    continue;
+                }
+                block.span = Some(span);
+                continue;
+            }
+            BfToken::Increment(n)
+            | BfToken::IncrementAt(_, n)
+            | BfToken::Decrement(n)
+            | BfToken::DecrementAt(_, n) => {
+                let at = match token {
+                    BfToken::IncrementAt(at, _) | BfToken::DecrementAt(at, _) => at,
+                    _ => 0,
+                };
+                let Some(at) = block.offset.checked_add(at) else {
+                    block.flush(&mut sunk, &mut sunk_spans);
+                    sunk.push(token);
+                    sunk_spans.push(span);
+                    continue;
+                };
+                let add = matches!(token, BfToken::Increment(_) | BfToken::IncrementAt(..));
+                block.span = block.span.map(|_| span);
+                match (add, at) {
+                    (true, 0) => BfToken::Increment(n),
+                    (false, 0) => BfToken::Decrement(n),
+                    (true, at) => BfToken::IncrementAt(at, n),
+                    (false, at) => BfToken::DecrementAt(at, n),
+                }
+            }
+            BfToken::NotCommand(_) => token,
+            BfToken::LoopStart
+            | BfToken::LoopEnd
+            | BfToken::PrintChar
+            | BfToken::PrintCharN(_)
+            | BfToken::InputChar
+            | BfToken::Extension(_)
+            | BfToken::SwitchTape
+            | BfToken::Fork => {
+                block.flush(&mut sunk, &mut sunk_spans);
+                token
+            }
+        };
+        sunk.push(token);
+        sunk_spans.push(span);
+    }
+    block.flush(&mut sunk, &mut sunk_spans);
+
+    debug!("pass sink_moves: {} -> {} tokens", tokens.len(), sunk.len());
+    (sunk, sunk_spans)
+}
+
+/// The moves of the block being sunk.
+#[derive(Debug, Default)]
+struct Block {
+    /// Where the moves so far took the cursor.
+    offset: isize,
+    /// The span of the last token since the first move, while there is one.
+    span: Option<usize>,
+}
+
+impl Block {
+    /// Adds a move, or returns `None` if the offset would overflow.
+    fn step(&mut self, left: bool, n: usize) -> Option<()> {
+        self.offset = if left {
+            self.offset.checked_sub_unsigned(n)?
+        } else {
+            self.offset.checked_add_unsigned(n)?
+        };
+        Some(())
+    }
+
+    /// Emits the net move of the block, if any, and starts the next one.
+    fn flush(&mut self, tokens: &mut Vec<BfToken>, spans: &mut Vec<usize>) {
+        let Block { offset, span } = std::mem::take(self);
+        let Some(span) = span else {
+            return;
+        };
+        let token = match offset {
+            0 => return,
+            offset if offset < 0 => BfToken::CursorLeft(offset.unsigned_abs()),
+            offset => BfToken::CursorRight(offset.unsigned_abs()),
+        };
+        tokens.push(token);
+        spans.push(span);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{bf_machine::BfMachine, program::Program};
+
+    use super::*;
+
+    const HELLO_WORLD: &str = include_str!("../../tests/programs/hello_world.b");
+    const PI: &str = include_str!("../../tests/programs/pi.b");
+
+    fn moves(program: &Program) -> usize {
+        program
+            .tokens()
+            .iter()
+            .filter(|token| matches!(token, BfToken::CursorLeft(_) | BfToken::CursorRight(_)))
+            .count()
+    }
+
+    #[test]
+    fn moves_become_offsets() {
+        let program = Program::parse_optimized(">+>+>+<<<").unwrap();
+        assert_eq!(moves(&program), 0);
+        assert_eq!(
+            program.tokens(),
+            [
+                BfToken::IncrementAt(1, 1),
+                BfToken::IncrementAt(2, 1),
+                BfToken::IncrementAt(3, 1),
+            ]
+        );
+        // Each keeps the span of its `+`.
+        let spans: Vec<_> = (0..3).map(|pc| program.span(pc).unwrap()).collect();
+        assert_eq!(spans, [1, 3, 5]);
+    }
+
+    #[test]
+    fn programs_run_the_same() {
+        for (code, input) in [(HELLO_WORLD, &b""[..]), (PI, b"")] {
+            let plain = Program::parse(code).unwrap();
+            let sunk = Program::parse_optimized(code).unwrap();
+            assert!(sunk.tokens().iter().any(|token| {
+                matches!(token, BfToken::IncrementAt(..) | BfToken::DecrementAt(..))
+            }));
+
+            let mut expected = BfMachine::new(30_000, Cursor::new(input), vec![]);
+            expected.run(&plain).unwrap();
+            let mut machine = BfMachine::new(30_000, Cursor::new(input), vec![]);
+            machine.run(&sunk).unwrap();
+            assert_eq!(machine.take_output(), expected.take_output());
+            assert!(machine.state_eq(&expected));
+
+            let mut fast = BfMachine::new(30_000, Cursor::new(input), vec![]);
+            fast.run_fast(&sunk).unwrap();
+            assert!(fast.state_eq(&expected));
+        }
+    }
+
+    #[test]
+    fn the_cursor_is_in_place_for_each_loop() {
+        let code = "+>>++<-[>+<-]";
+        let program = Program::parse_optimized(code).unwrap();
+        assert_eq!(
+            program.tokens(),
+            [
+                BfToken::Increment(1),
+                BfToken::IncrementAt(2, 2),
+                BfToken::DecrementAt(1, 1),
+                BfToken::CursorRight(1),
+                BfToken::LoopStart,
+                BfToken::IncrementAt(1, 1),
+                BfToken::Decrement(1),
+                BfToken::LoopEnd,
+            ]
+        );
+
+        // Stopping at the `[` finds the machine as the plain program leaves it.
+        let stopped_at = |program: &Program, pc| {
+            let mut machine = BfMachine::new(8, Cursor::new(b""), vec![]);
+            machine.add_breakpoint(pc);
+            machine.run_until_break(program).unwrap();
+            machine
+        };
+        let plain = Program::parse(code).unwrap();
+        let expected = stopped_at(&plain, 7);
+        let machine = stopped_at(&program, 4);
+        assert_eq!(machine.cursor(), 1);
+        assert!(machine.state_eq(&expected));
+    }
+
+    #[test]
+    fn spans_stay_in_order() {
+        let program = Program::parse_optimized("+>+>.<<[>>-<<-]>").unwrap();
+        let spans: Vec<_> = (0..program.tokens().len())
+            .map(|pc| program.span(pc).unwrap())
+            .collect();
+        assert!(spans.is_sorted(), "{spans:?}");
+        let tokens = program.tokens().to_vec();
+        assert!(Program::from_parts(tokens, program.jump_table().to_vec(), spans).is_ok());
+    }
+}
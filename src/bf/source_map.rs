@@ -0,0 +1,63 @@
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct SourceMap {
+    positions: Vec<usize>,
+}
+
+impl SourceMap {
+    pub fn new(positions: Vec<usize>) -> Self {
+        Self { positions }
+    }
+
+    pub fn identity(len: usize) -> Self {
+        Self::new((0..len).collect())
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn original(&self, index: usize) -> usize {
+        match self.positions.get(index) {
+            Some(position) => *position,
+            None => self.positions.last().copied().unwrap_or(0),
+        }
+    }
+
+    pub fn compose(&self, inner: &SourceMap) -> SourceMap {
+        SourceMap::new(
+            inner
+                .positions
+                .iter()
+                .map(|index| self.original(*index))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn original_positions() {
+        let map = SourceMap::new(vec![2, 5, 9]);
+
+        assert_eq!(map.original(0), 2);
+        assert_eq!(map.original(2), 9);
+        assert_eq!(map.original(3), 9);
+        assert_eq!(SourceMap::default().original(4), 0);
+    }
+
+    #[test]
+    fn composed_maps() {
+        let outer = SourceMap::new(vec![2, 5, 9]);
+        let inner = SourceMap::new(vec![0, 2]);
+
+        assert_eq!(outer.compose(&inner), SourceMap::new(vec![2, 9]));
+        assert_eq!(SourceMap::identity(3).compose(&inner), inner);
+    }
+}
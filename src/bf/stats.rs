@@ -0,0 +1,190 @@
+use super::{
+    ast::{self, BfAst},
+    bf_parser::{BfParserError, ParseOptions},
+    bf_token::BfToken,
+    program::{OptLevel, Program},
+};
+
+/// The command characters in the order [`ProgramStats::commands`] lists them.
+pub const COMMANDS: [char; 8] = ['+', '-', '<', '>', '[', ']', '.', ','];
+
+/// Metrics of a program's source, taken from what the parser makes of it
+/// rather than from the text, so comments and extensions count as they run.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ProgramStats {
+    /// How often each of [`COMMANDS`] appears, in that order.
+    pub commands: [usize; COMMANDS.len()],
+    /// Registered extension characters.
+    pub extensions: usize,
+    /// `^` with [`ParseOptions::multi_tape`].
+    pub tape_switches: usize,
+    /// `Y` with [`ParseOptions::brainfork`].
+    pub forks: usize,
+    pub comment_chars: usize,
+    pub total_chars: usize,
+    pub loops: usize,
+    pub max_depth: usize,
+    /// The longest run of one command, with comments in between ignored as
+    /// the parser ignores them, or `None` without commands.
+    pub longest_run: Option<(char, usize)>,
+    /// How many instructions are left after each [`OptLevel`].
+    pub tokens: Vec<(OptLevel, usize)>,
+}
+
+impl ProgramStats {
+    pub fn new(code: &str, options: &ParseOptions) -> Result<Self, BfParserError> {
+        let program = Program::parse_at(code, options, OptLevel::O0)?;
+
+        let mut commands = [0; COMMANDS.len()];
+        let mut extensions = 0;
+        let mut tape_switches = 0;
+        let mut forks = 0;
+        let mut comment_chars = 0;
+        let mut run: Option<(char, usize)> = None;
+        let mut longest_run: Option<(char, usize)> = None;
+        for token in program.tokens() {
+            let ch = match token {
+                BfToken::NotCommand(_) => {
+                    comment_chars += 1;
+                    continue;
+                }
+                BfToken::Extension(_) => {
+                    extensions += 1;
+                    run = None;
+                    continue;
+                }
+                BfToken::SwitchTape => {
+                    tape_switches += 1;
+                    run = None;
+                    continue;
+                }
+                BfToken::Fork => {
+                    forks += 1;
+                    run = None;
+                    continue;
+                }
+                BfToken::Increment(_) | BfToken::IncrementAt(..) => '+',
+                BfToken::Decrement(_) | BfToken::DecrementAt(..) => '-',
+                BfToken::CursorLeft(_) => '<',
+                BfToken::CursorRight(_) => '>',
+                BfToken::LoopStart => '[',
+                BfToken::LoopEnd => ']',
+                BfToken::PrintChar | BfToken::PrintCharN(_) => '.',
+                BfToken::InputChar => ',',
+            };
+            commands[COMMANDS.iter().position(|&command| command == ch).unwrap()] += 1;
+
+            let len = match run {
+                Some((last, len)) if last == ch => len + 1,
+                _ => 1,
+            };
+            run = Some((ch, len));
+            if longest_run.is_none_or(|(_, longest)| len > longest) {
+                longest_run = run;
+            }
+        }
+
+        let ast = ast::from_tokens(program.tokens())?;
+        let tokens = OptLevel::ALL
+            .into_iter()
+            .map(|level| {
+                let program = Program::parse_at(code, options, level)?;
+                let instructions = program
+                    .tokens()
+                    .iter()
+                    .filter(|token| !matches!(token, BfToken::NotCommand(_)))
+                    .count();
+                Ok((level, instructions))
+            })
+            .collect::<Result<_, BfParserError>>()?;
+
+        Ok(Self {
+            commands,
+            extensions,
+            tape_switches,
+            forks,
+            comment_chars,
+            total_chars: program.tokens().len(),
+            loops: count_loops(&ast),
+            max_depth: ast::max_depth(&ast),
+            longest_run,
+            tokens,
+        })
+    }
+
+    pub fn count(&self, command: char) -> usize {
+        COMMANDS
+            .iter()
+            .position(|&ch| ch == command)
+            .map_or(0, |index| self.commands[index])
+    }
+
+    pub fn total_commands(&self) -> usize {
+        self.commands.iter().sum::<usize>() + self.extensions + self.tape_switches + self.forks
+    }
+
+    /// The share of characters that are comments, from 0 to 1.
+    pub fn comment_ratio(&self) -> f64 {
+        if self.total_chars == 0 {
+            return 0.0;
+        }
+        self.comment_chars as f64 / self.total_chars as f64
+    }
+}
+
+fn count_loops(ast: &[BfAst]) -> usize {
+    ast.iter()
+        .map(|node| match node {
+            BfAst::Loop(body) | BfAst::Transfer { body, .. } => 1 + count_loops(body),
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_WORLD: &str = include_str!("../../tests/programs/hello_world.b");
+    const PI: &str = include_str!("../../tests/programs/pi.b");
+
+    #[test]
+    fn hello_world_stats() {
+        let stats = ProgramStats::new(HELLO_WORLD, &ParseOptions::default()).unwrap();
+
+        assert_eq!(stats.count('.'), 13);
+        assert_eq!(stats.count('['), 1);
+        assert_eq!(stats.count(','), 0);
+        assert_eq!(stats.loops, 1);
+        assert_eq!(stats.max_depth, 1);
+        assert_eq!(stats.longest_run, Some(('+', 15)));
+        assert_eq!(
+            stats.total_chars,
+            stats.total_commands() + stats.comment_chars
+        );
+        assert_eq!(stats.tokens[0], (OptLevel::O0, stats.total_commands()));
+        assert!(stats.tokens[1].1 < stats.tokens[0].1);
+    }
+
+    #[test]
+    fn pi_depth() {
+        let stats = ProgramStats::new(PI, &ParseOptions::default()).unwrap();
+
+        assert_eq!(stats.max_depth, 11);
+        assert_eq!(stats.count('['), stats.loops);
+        assert_eq!(stats.count(']'), stats.loops);
+    }
+
+    #[test]
+    fn runs_skip_comments() {
+        let stats = ProgramStats::new("++ + +\n-- x", &ParseOptions::default()).unwrap();
+
+        assert_eq!(stats.longest_run, Some(('+', 4)));
+        assert_eq!(stats.comment_chars, 5);
+        assert_eq!(stats.comment_ratio(), 5.0 / 11.0);
+        assert_eq!(
+            stats.tokens,
+            [(OptLevel::O0, 6), (OptLevel::O1, 1), (OptLevel::O2, 1)]
+        );
+    }
+}
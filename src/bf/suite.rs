@@ -0,0 +1,175 @@
+use std::{
+    fs,
+    io::{self, Cursor, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError},
+    program::Program,
+};
+
+const MEMORY_SIZE: usize = 30_000;
+
+/// A `name.b` or `name.bf` program, run with `name.in` as input if it exists
+/// and expected to write exactly `name.out`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TestCase {
+    pub name: String,
+    pub program: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Outcome {
+    Pass,
+    Fail(String),
+    Timeout,
+}
+
+/// The programs in `dir`, not its subdirectories, sorted by name. With a
+/// filter, only those whose name contains it.
+pub fn discover(dir: &Path, filter: Option<&str>) -> io::Result<Vec<TestCase>> {
+    let mut cases = vec![];
+    for entry in fs::read_dir(dir)? {
+        let program = entry?.path();
+        if !program
+            .extension()
+            .is_some_and(|ext| ext == "b" || ext == "bf")
+        {
+            continue;
+        }
+        let Some(name) = program.file_stem().map(|stem| stem.to_string_lossy()) else {
+            continue;
+        };
+        if filter.is_none_or(|filter| name.contains(filter)) {
+            cases.push(TestCase {
+                name: name.into_owned(),
+                program,
+            });
+        }
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+impl TestCase {
+    pub fn input_path(&self) -> PathBuf {
+        self.program.with_extension("in")
+    }
+
+    pub fn expected_path(&self) -> PathBuf {
+        self.program.with_extension("out")
+    }
+
+    /// Runs the program through `Program::parse`, or `Program::parse_optimized`
+    /// if `optimized`, and returns everything it wrote. A program that does
+    /// not run to its end yields the outcome to report instead.
+    pub fn execute(&self, optimized: bool, step_limit: usize) -> Result<Vec<u8>, Outcome> {
+        let fail = |err: &dyn std::fmt::Display| Outcome::Fail(err.to_string());
+        let code = fs::read_to_string(&self.program).map_err(|err| fail(&err))?;
+        let input = match fs::read(self.input_path()) {
+            Ok(input) => input,
+            Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+            Err(err) => return Err(fail(&err)),
+        };
+        let program = if optimized {
+            Program::parse_optimized(&code)
+        } else {
+            Program::parse(&code)
+        }
+        .map_err(|err| fail(&err))?;
+
+        let mut output = vec![];
+        BfMachine::new(MEMORY_SIZE, Cursor::new(input), &mut output)
+            .with_step_limit(step_limit)
+            .run(&program)
+            .map_err(|err| match err {
+                BfRuntimeError::StepLimitExceeded { .. } => Outcome::Timeout,
+                err => fail(&err),
+            })?;
+        Ok(output)
+    }
+
+    /// Compares `output` with the expected output file.
+    pub fn check(&self, output: &[u8]) -> Outcome {
+        let path = self.expected_path();
+        match fs::read(&path) {
+            Ok(expected) => match mismatch(&expected, output) {
+                Some(report) => Outcome::Fail(report),
+                None => Outcome::Pass,
+            },
+            Err(err) => Outcome::Fail(format!("{}: {err}", path.display())),
+        }
+    }
+}
+
+/// Describes where `actual` first differs from `expected`, with the bytes
+/// around it escaped so binary output stays readable, or `None` if they match.
+pub fn mismatch(expected: &[u8], actual: &[u8]) -> Option<String> {
+    const CONTEXT: usize = 16;
+
+    let offset = expected
+        .iter()
+        .zip(actual)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if offset == expected.len() && offset == actual.len() {
+        return None;
+    }
+
+    let byte = |bytes: &[u8]| match bytes.get(offset) {
+        Some(byte) => format!("0x{byte:02x} '{}'", byte.escape_ascii()),
+        None => "end of output".to_string(),
+    };
+    let window = |bytes: &[u8]| {
+        let start = offset.saturating_sub(CONTEXT).min(bytes.len());
+        let end = (offset + CONTEXT).min(bytes.len());
+        format!("\"{}\"", bytes[start..end].escape_ascii())
+    };
+    Some(format!(
+        "first difference at byte {offset}: expected {}, got {}\n  expected: {}\n  actual:   {}",
+        byte(expected),
+        byte(actual),
+        window(expected),
+        window(actual),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programs_dir() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/programs")
+    }
+
+    #[test]
+    fn discovers_programs() {
+        let cases = discover(&programs_dir(), None).unwrap();
+        let names: Vec<_> = cases.iter().map(|case| case.name.as_str()).collect();
+        assert_eq!(names, ["ascii_table", "cat", "hello_world", "pi", "quine"]);
+
+        let cases = discover(&programs_dir(), Some("at")).unwrap();
+        assert_eq!(cases.len(), 1);
+        assert_eq!(cases[0].input_path(), programs_dir().join("cat.in"));
+    }
+
+    #[test]
+    fn cases_pass_and_time_out() {
+        let case = &discover(&programs_dir(), Some("hello")).unwrap()[0];
+        let output = case.execute(true, 100_000).unwrap();
+        assert_eq!(case.check(&output), Outcome::Pass);
+        assert!(matches!(case.check(b"Hello"), Outcome::Fail(_)));
+        assert_eq!(case.execute(false, 10), Err(Outcome::Timeout));
+    }
+
+    #[test]
+    fn mismatch_windows() {
+        assert_eq!(mismatch(b"abc", b"abc"), None);
+        assert_eq!(
+            mismatch(b"ab", b"ab\xff").unwrap(),
+            "first difference at byte 2: expected end of output, got 0xff '\\xff'\n  \
+             expected: \"ab\"\n  actual:   \"ab\\xff\""
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::{
+    fmt::Debug,
+    io,
+    ops::{Deref, DerefMut},
+};
+
+#[cfg(feature = "mmap")]
+use std::{fs::OpenOptions, marker::PhantomData, mem, path::Path, slice};
+
+#[cfg(feature = "mmap")]
+use memmap2::MmapMut;
+
+use super::cell::Cell;
+
+/// The cells of a machine: on the heap, or with the `mmap` feature in a file
+/// mapped into memory. Either way the machine works on it as a slice.
+pub(crate) enum Tape<C: Cell = u8> {
+    Heap(Vec<C>),
+    #[cfg(feature = "mmap")]
+    Mapped(MappedTape<C>),
+}
+
+impl<C: Cell> Tape<C> {
+    /// Maps `path` as the tape, creating it or zero-extending it to at least
+    /// `len` cells first. The cells are the file's bytes in native byte order.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn map(path: &Path, len: usize) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let bytes = len.saturating_mul(mem::size_of::<C>()) as u64;
+        if file.metadata()?.len() < bytes {
+            file.set_len(bytes)?;
+        }
+        if file.metadata()?.len() < mem::size_of::<C>() as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a mapped tape needs at least one cell",
+            ));
+        }
+        // SAFETY: the mapping is only sound while no one else changes the
+        // file, which the caller of `with_mmap_tape` is told to ensure.
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(Self::Mapped(MappedTape(map, PhantomData)))
+    }
+
+    /// Whether the tape can grow past its current length.
+    pub(crate) fn is_growable(&self) -> bool {
+        matches!(self, Self::Heap(_))
+    }
+
+    /// Grows a heap tape to `len` cells with zeros; a mapped tape keeps its
+    /// length.
+    pub(crate) fn resize(&mut self, len: usize) {
+        match self {
+            Self::Heap(memory) => memory.resize(len, C::default()),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(_) => {}
+        }
+    }
+
+    /// Takes the cells of `memory`. A mapped tape copies them into the file,
+    /// so they must be as many as it has.
+    pub(crate) fn replace(&mut self, memory: Vec<C>) {
+        match self {
+            Self::Heap(heap) => *heap = memory,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(mapped) => {
+                assert_eq!(mapped.len(), memory.len(), "a mapped tape keeps its length");
+                mapped.copy_from_slice(&memory);
+            }
+        }
+    }
+
+    pub(crate) fn into_vec(self) -> Vec<C> {
+        match self {
+            Self::Heap(memory) => memory,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(mapped) => mapped.to_vec(),
+        }
+    }
+
+    /// Writes the cells of a mapped tape out to its file.
+    pub(crate) fn sync(&self) -> io::Result<()> {
+        match self {
+            Self::Heap(_) => Ok(()),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(mapped) => mapped.0.flush(),
+        }
+    }
+}
+
+impl<C: Cell> Deref for Tape<C> {
+    type Target = [C];
+
+    fn deref(&self) -> &[C] {
+        match self {
+            Self::Heap(memory) => memory,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(mapped) => mapped,
+        }
+    }
+}
+
+impl<C: Cell> DerefMut for Tape<C> {
+    fn deref_mut(&mut self) -> &mut [C] {
+        match self {
+            Self::Heap(memory) => memory,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(mapped) => mapped,
+        }
+    }
+}
+
+/// Clones to a heap tape, so a clone never writes to the file.
+impl<C: Cell> Clone for Tape<C> {
+    fn clone(&self) -> Self {
+        Self::Heap(self.to_vec())
+    }
+}
+
+impl<C: Cell> PartialEq for Tape<C> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<C: Cell> Debug for Tape<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+/// A file mapping that is flushed when dropped, read as cells. Bytes past
+/// the last whole cell are left alone.
+#[cfg(feature = "mmap")]
+pub(crate) struct MappedTape<C>(MmapMut, PhantomData<C>);
+
+#[cfg(feature = "mmap")]
+impl<C: Cell> Deref for MappedTape<C> {
+    type Target = [C];
+
+    fn deref(&self) -> &[C] {
+        // SAFETY: a mapping starts on a page boundary, aligned for any cell,
+        // the length leaves out a partial cell at the end, and any bytes are
+        // a valid cell, as `Cell` promises.
+        unsafe { slice::from_raw_parts(self.0.as_ptr().cast(), self.0.len() / mem::size_of::<C>()) }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<C: Cell> DerefMut for MappedTape<C> {
+    fn deref_mut(&mut self) -> &mut [C] {
+        let len = self.0.len() / mem::size_of::<C>();
+        // SAFETY: as for `deref`, and the mapping is borrowed mutably.
+        unsafe { slice::from_raw_parts_mut(self.0.as_mut_ptr().cast(), len) }
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl<C> Drop for MappedTape<C> {
+    fn drop(&mut self) {
+        let _ = self.0.flush();
+    }
+}
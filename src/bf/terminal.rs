@@ -0,0 +1,135 @@
+use std::io;
+
+/// A terminal whose line discipline can be turned off and put back.
+pub trait TerminalMode {
+    /// The settings [`TerminalMode::enter_raw`] replaced.
+    type Saved;
+
+    /// Switches to raw mode, in which every key reaches the reader at once
+    /// and is not echoed, returning the settings it replaced.
+    fn enter_raw(&mut self) -> io::Result<Self::Saved>;
+
+    fn restore(&mut self, saved: &Self::Saved) -> io::Result<()>;
+}
+
+/// Keeps a terminal in raw mode for as long as it lives. Dropping it puts the
+/// old settings back, which also happens while a panic unwinds; code that
+/// leaves by [`std::process::exit`] must drop it first.
+#[derive(Debug)]
+pub struct RawMode<T: TerminalMode> {
+    terminal: T,
+    saved: T::Saved,
+}
+
+impl<T: TerminalMode> RawMode<T> {
+    pub fn enter(mut terminal: T) -> io::Result<Self> {
+        let saved = terminal.enter_raw()?;
+        Ok(Self { terminal, saved })
+    }
+}
+
+impl<T: TerminalMode> Drop for RawMode<T> {
+    fn drop(&mut self) {
+        // Nothing is left to do if the terminal cannot be restored.
+        let _ = self.terminal.restore(&self.saved);
+    }
+}
+
+/// The terminal on stdin. Raw mode here keeps signals, so Ctrl-C still
+/// interrupts.
+#[cfg(unix)]
+#[derive(Debug, Clone, Copy)]
+pub struct StdinTerminal;
+
+#[cfg(unix)]
+impl TerminalMode for StdinTerminal {
+    type Saved = libc::termios;
+
+    fn enter_raw(&mut self) -> io::Result<Self::Saved> {
+        // SAFETY: `termios` is plain data that `tcgetattr` fills in.
+        let mut saved = unsafe { std::mem::zeroed() };
+        // SAFETY: `saved` is a valid `termios` to write to.
+        if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        self.restore(&raw)?;
+        Ok(saved)
+    }
+
+    fn restore(&mut self, saved: &Self::Saved) -> io::Result<()> {
+        // SAFETY: `saved` is a valid `termios` to read from.
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        panic::{self, AssertUnwindSafe},
+        rc::Rc,
+    };
+
+    use super::*;
+
+    /// Records the mode changes made to it.
+    #[derive(Debug, Clone, Default)]
+    struct FakeTerminal {
+        raw: Rc<RefCell<bool>>,
+        fail: bool,
+    }
+
+    impl TerminalMode for FakeTerminal {
+        type Saved = bool;
+
+        fn enter_raw(&mut self) -> io::Result<bool> {
+            if self.fail {
+                return Err(io::Error::other("not a terminal"));
+            }
+            Ok(self.raw.replace(true))
+        }
+
+        fn restore(&mut self, saved: &bool) -> io::Result<()> {
+            *self.raw.borrow_mut() = *saved;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn restores_on_drop() {
+        let terminal = FakeTerminal::default();
+        let guard = RawMode::enter(terminal.clone()).unwrap();
+        assert!(*terminal.raw.borrow());
+        drop(guard);
+        assert!(!*terminal.raw.borrow());
+    }
+
+    #[test]
+    fn restores_on_panic() {
+        let terminal = FakeTerminal::default();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _guard = RawMode::enter(terminal.clone()).unwrap();
+            assert!(*terminal.raw.borrow());
+            panic!("the run failed");
+        }));
+        assert!(result.is_err());
+        assert!(!*terminal.raw.borrow());
+    }
+
+    #[test]
+    fn failing_to_enter_changes_nothing() {
+        let terminal = FakeTerminal {
+            fail: true,
+            ..Default::default()
+        };
+        assert!(RawMode::enter(terminal.clone()).is_err());
+        assert!(!*terminal.raw.borrow());
+    }
+}
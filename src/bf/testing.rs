@@ -0,0 +1,460 @@
+use std::{
+    fs,
+    io::{Cursor, ErrorKind},
+    mem::{self, Discriminant},
+    path::PathBuf,
+};
+
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError, EofMode},
+    diagnostics::line_col,
+    handler::Rng,
+    program::Program,
+    suite::{self, TestCase},
+};
+
+const MEMORY_SIZE: usize = 30_000;
+const DEFAULT_STEP_LIMIT: usize = 100_000_000;
+/// The cells shown on each side of a failing one.
+const DUMP_CONTEXT: usize = 4;
+
+/// Sets up a run of a Brainfuck program for a test: the cells to preload, the
+/// input and a step limit. Reading past the input gives 0, as with
+/// [`assert_equivalent`]. [`BfAssert::run`] panics unless the program runs to
+/// its end, and the [`BfRun`] it returns checks the results, e.g.
+///
+/// ```
+/// use bf_rust::bf::testing::BfAssert;
+///
+/// BfAssert::program("[->+<]")
+///     .with_cells(&[(0, 5)])
+///     .run()
+///     .expect_cell(0, 0)
+///     .expect_cell(1, 5)
+///     .expect_output(b"");
+/// ```
+#[derive(Debug, Clone)]
+pub struct BfAssert {
+    code: String,
+    cells: Vec<(usize, u8)>,
+    input: Vec<u8>,
+    step_limit: usize,
+    golden: Option<PathBuf>,
+}
+
+/// The end state of a [`BfAssert`] run. Every `expect_` method panics with the
+/// program and what differs, and returns the run for chaining more checks.
+#[derive(Debug, Clone)]
+pub struct BfRun {
+    code: String,
+    tape: Vec<u8>,
+    cursor: usize,
+    output: Vec<u8>,
+    golden: Option<PathBuf>,
+}
+
+impl BfAssert {
+    pub fn program(code: &str) -> Self {
+        Self {
+            code: code.to_string(),
+            cells: vec![],
+            input: vec![],
+            step_limit: DEFAULT_STEP_LIMIT,
+            golden: None,
+        }
+    }
+
+    /// A case of the golden-test harness: its program with its `.in` file as
+    /// input, checked against its `.out` file by [`BfRun::expect_golden`].
+    #[track_caller]
+    pub fn case(case: &TestCase) -> Self {
+        let code = fs::read_to_string(&case.program)
+            .unwrap_or_else(|err| panic!("{}: {err}", case.program.display()));
+        let input = match fs::read(case.input_path()) {
+            Ok(input) => input,
+            Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+            Err(err) => panic!("{}: {err}", case.input_path().display()),
+        };
+        Self {
+            input,
+            golden: Some(case.expected_path()),
+            ..Self::program(&code)
+        }
+    }
+
+    /// Sets each `(index, value)` cell before the run.
+    pub fn with_cells(mut self, cells: &[(usize, u8)]) -> Self {
+        self.cells.extend_from_slice(cells);
+        self
+    }
+
+    pub fn with_input(mut self, input: &[u8]) -> Self {
+        self.input = input.to_vec();
+        self
+    }
+
+    pub fn with_step_limit(mut self, max_steps: usize) -> Self {
+        self.step_limit = max_steps;
+        self
+    }
+
+    #[track_caller]
+    pub fn run(&self) -> BfRun {
+        let code = &self.code;
+        let program =
+            Program::parse(code).unwrap_or_else(|err| panic!("{code:?} does not parse: {err}"));
+        let mut output = vec![];
+        let mut machine = machine(&self.input, &mut output).with_step_limit(self.step_limit);
+        for &(index, value) in &self.cells {
+            machine
+                .load_tape(index, &[value])
+                .unwrap_or_else(|_| panic!("cell {index} is past the end of the tape"));
+        }
+        let result = machine.run(&program);
+        let snapshot = machine.snapshot();
+        drop(machine);
+        if let Err(err) = result {
+            panic!(
+                "{code:?} failed: {err}\n  {}",
+                dump(&snapshot.memory, snapshot.cursor)
+            );
+        }
+
+        BfRun {
+            code: self.code.clone(),
+            tape: snapshot.memory,
+            cursor: snapshot.cursor,
+            output,
+            golden: self.golden.clone(),
+        }
+    }
+}
+
+impl BfRun {
+    #[track_caller]
+    pub fn expect_cell(&self, index: usize, value: u8) -> &Self {
+        let actual = self.tape.get(index).copied().unwrap_or_else(|| {
+            panic!(
+                "cell {index} of {:?} is past the end of the tape",
+                self.code
+            )
+        });
+        if actual != value {
+            panic!(
+                "cell {index} of {:?}: expected {value}, got {actual}\n  {}",
+                self.code,
+                dump(&self.tape, index)
+            );
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_cursor(&self, cursor: usize) -> &Self {
+        if self.cursor != cursor {
+            panic!(
+                "cursor of {:?}: expected {cursor}, got {}\n  {}",
+                self.code,
+                self.cursor,
+                dump(&self.tape, self.cursor)
+            );
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_output(&self, expected: &[u8]) -> &Self {
+        if let Some(report) = suite::mismatch(expected, &self.output) {
+            panic!("output of {:?}: {report}", self.code);
+        }
+        self
+    }
+
+    /// Compares the output with the `.out` file of the [`BfAssert::case`] the
+    /// run came from.
+    #[track_caller]
+    pub fn expect_golden(&self) -> &Self {
+        let path = self
+            .golden
+            .as_ref()
+            .expect("expect_golden needs a run made with BfAssert::case");
+        let expected = fs::read(path).unwrap_or_else(|err| panic!("{}: {err}", path.display()));
+        if let Some(report) = suite::mismatch(&expected, &self.output) {
+            panic!("output of {}: {report}", path.display());
+        }
+        self
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+}
+
+/// The cells around `focus`, with `focus` in brackets.
+fn dump(tape: &[u8], focus: usize) -> String {
+    let start = focus.saturating_sub(DUMP_CONTEXT);
+    let end = focus.saturating_add(DUMP_CONTEXT + 1).min(tape.len());
+    let cells: Vec<_> = (start..end)
+        .map(|cell| {
+            if cell == focus {
+                format!("[{}]", tape[cell])
+            } else {
+                tape[cell].to_string()
+            }
+        })
+        .collect();
+    format!("cells {start}..{end}: {}", cells.join(" "))
+}
+
+/// Runs `code` through `Program::parse` and through `Program::parse_optimized`
+/// once per input and panics unless both runs write the same bytes and end the
+/// same way. Programs without `.` or `,` must also leave the same tape; with
+/// I/O the optimizer may drop work after the last of it, so only the output
+/// is compared.
+///
+/// A run that hits `step_limit` proves nothing about the rest of the program:
+/// the plain output must then be a prefix of the optimized one, which does
+/// at least as much work per step.
+pub fn assert_equivalent(code: &str, inputs: &[&[u8]], step_limit: u64) {
+    let plain = Program::parse(code).unwrap_or_else(|err| panic!("plain parse failed: {err}"));
+    let optimized = Program::parse_optimized(code)
+        .unwrap_or_else(|err| panic!("optimized parse failed: {err}"));
+    let step_limit = usize::try_from(step_limit).unwrap_or(usize::MAX);
+    let has_io = code.contains(['.', ',']);
+
+    for input in inputs {
+        let expected = Run::new(&plain, input, step_limit);
+        let actual = Run::new(&optimized, input, step_limit);
+
+        if expected.hit_step_limit() {
+            if !actual.output.starts_with(&expected.output) {
+                diverged(
+                    code, input, &plain, &optimized, &expected, &actual, step_limit,
+                );
+            }
+            continue;
+        }
+        if expected.output != actual.output {
+            diverged(
+                code, input, &plain, &optimized, &expected, &actual, step_limit,
+            );
+        }
+        assert_eq!(
+            expected.outcome(),
+            actual.outcome(),
+            "runs of {code:?} on input {input:?} end differently"
+        );
+        if !has_io {
+            if let Some(cell) =
+                (0..MEMORY_SIZE).find(|&cell| expected.tape[cell] != actual.tape[cell])
+            {
+                panic!(
+                    "tapes of {code:?} differ at cell {cell}: plain {}, optimized {}",
+                    expected.tape[cell], actual.tape[cell]
+                );
+            }
+            assert_eq!(expected.cursor, actual.cursor, "cursors of {code:?} differ");
+        }
+    }
+}
+
+/// A random program of about `len` commands with balanced brackets, for
+/// feeding [`assert_equivalent`]. The same seed always gives the same program.
+pub fn random_program(seed: u64, len: usize) -> String {
+    const COMMANDS: &[u8] = b"++++----<<>>..,[]";
+
+    let mut rng = Rng::from_seed(seed);
+    let mut code = String::with_capacity(len + 8);
+    let mut depth = 0;
+    for _ in 0..len {
+        match COMMANDS[rng.next_byte() as usize % COMMANDS.len()] {
+            b'[' if depth < 4 => {
+                code.push('[');
+                depth += 1;
+            }
+            b']' if depth > 0 => {
+                code.push(']');
+                depth -= 1;
+            }
+            b'[' | b']' => code.push('-'),
+            command => code.push(command as char),
+        }
+    }
+    code.extend((0..depth).map(|_| ']'));
+    code
+}
+
+struct Run {
+    output: Vec<u8>,
+    tape: Vec<u8>,
+    cursor: usize,
+    result: Result<(), BfRuntimeError>,
+}
+
+impl Run {
+    fn new(program: &Program, input: &[u8], step_limit: usize) -> Self {
+        let mut output = vec![];
+        let mut machine = machine(input, &mut output).with_step_limit(step_limit);
+        let result = machine.run(program);
+        let snapshot = machine.snapshot();
+        drop(machine);
+        Self {
+            output,
+            tape: snapshot.memory,
+            cursor: snapshot.cursor,
+            result,
+        }
+    }
+
+    fn hit_step_limit(&self) -> bool {
+        matches!(self.result, Err(BfRuntimeError::StepLimitExceeded { .. }))
+    }
+
+    /// How the run ended, without pcs, which differ between the two programs.
+    fn outcome(&self) -> Option<Discriminant<BfRuntimeError>> {
+        self.result.as_ref().err().map(mem::discriminant)
+    }
+}
+
+fn machine<'a>(
+    input: &'a [u8],
+    output: &'a mut Vec<u8>,
+) -> BfMachine<Cursor<&'a [u8]>, &'a mut Vec<u8>> {
+    BfMachine::new(MEMORY_SIZE, Cursor::new(input), output).with_eof_mode(EofMode::Zero)
+}
+
+fn diverged(
+    code: &str,
+    input: &[u8],
+    plain: &Program,
+    optimized: &Program,
+    expected: &Run,
+    actual: &Run,
+    step_limit: usize,
+) -> ! {
+    let byte = expected
+        .output
+        .iter()
+        .zip(&actual.output)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let location = |program: &Program| match writer_of(program, input, byte, step_limit) {
+        Some(pc) => {
+            let (line, col) = line_col(code, program.span(pc).unwrap_or(pc));
+            format!("pc {pc} at {line}:{col}")
+        }
+        None => "no write".to_string(),
+    };
+    panic!(
+        "output of {code:?} on input {input:?} diverges at byte {byte}: \
+         plain wrote {:?} ({}), optimized wrote {:?} ({})",
+        expected.output.get(byte),
+        location(plain),
+        actual.output.get(byte),
+        location(optimized),
+    );
+}
+
+/// Single-steps `program` to find the instruction that writes output byte
+/// `byte`, if any does within `step_limit` steps.
+fn writer_of(program: &Program, input: &[u8], byte: usize, step_limit: usize) -> Option<usize> {
+    let mut output = vec![];
+    let mut machine = machine(input, &mut output).with_step_limit(1);
+    let mut pc = 0;
+    for _ in 0..step_limit {
+        let result = machine.run_from(program, pc);
+        if machine.bytes_written() > byte {
+            return Some(pc);
+        }
+        match result {
+            Err(BfRuntimeError::StepLimitExceeded { pc: next }) => pc = next,
+            _ => return None,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn random_programs_are_balanced_and_reproducible() {
+        for seed in 0..50 {
+            let code = random_program(seed, 40);
+            assert_eq!(code, random_program(seed, 40));
+            assert!(Program::parse(&code).is_ok(), "{code:?}");
+        }
+        assert_ne!(random_program(1, 40), random_program(2, 40));
+    }
+
+    #[test]
+    fn finds_the_writing_instruction() {
+        let program = Program::parse("+.>++[.-]").unwrap();
+        assert_eq!(writer_of(&program, b"", 0, 100), Some(1));
+        assert_eq!(writer_of(&program, b"", 2, 100), Some(6));
+        assert_eq!(writer_of(&program, b"", 3, 100), None);
+        assert_eq!(writer_of(&program, b"", 2, 5), None);
+    }
+
+    #[test]
+    fn bf_assert_chain() {
+        BfAssert::program("[->+<]")
+            .with_cells(&[(0, 5)])
+            .with_input(b"")
+            .run()
+            .expect_cell(0, 0)
+            .expect_cell(1, 5)
+            .expect_cursor(0)
+            .expect_output(b"");
+
+        let run = BfAssert::program(",[.,]").with_input(b"hi").run();
+        run.expect_output(b"hi");
+        assert_eq!(run.output(), b"hi");
+
+        let programs = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+        for case in suite::discover(&programs, Some("cat")).unwrap() {
+            BfAssert::case(&case).run().expect_golden();
+        }
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "cell 1 of \"[->+<]\": expected 4, got 5\n  cells 0..6: 0 [5] 0 0 0 0"
+    )]
+    fn bf_assert_reports_cells() {
+        BfAssert::program("[->+<]")
+            .with_cells(&[(0, 5)])
+            .run()
+            .expect_cell(1, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "first difference at byte 1: expected 0x62 'b', got 0x63 'c'")]
+    fn bf_assert_reports_output() {
+        BfAssert::program(",.+.")
+            .with_input(b"b")
+            .run()
+            .expect_output(b"bb");
+    }
+
+    #[test]
+    #[should_panic(expected = "due to the step limit")]
+    fn bf_assert_reports_failed_runs() {
+        BfAssert::program("+[]").with_step_limit(100).run();
+    }
+
+    #[test]
+    #[should_panic(expected = "diverges at byte 1")]
+    fn reports_divergence() {
+        let plain = Program::parse("+.+.").unwrap();
+        let optimized = Program::parse("+.++.").unwrap();
+        let expected = Run::new(&plain, b"", 100);
+        let actual = Run::new(&optimized, b"", 100);
+        diverged("+.+.", b"", &plain, &optimized, &expected, &actual, 100);
+    }
+}
@@ -0,0 +1,153 @@
+#[cfg(feature = "serde")]
+use std::io::{self, BufWriter, IntoInnerError, Read, Write};
+
+use super::bf_token::BfToken;
+#[cfg(feature = "serde")]
+use super::{
+    bf_machine::{BfMachine, BfRuntimeError},
+    cell::Cell,
+    program::Program,
+};
+
+/// One executed instruction and the state it left behind.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceRecord<C = u8> {
+    /// Counts from 1 in each run.
+    pub step: usize,
+    pub pc: usize,
+    pub token: BfToken,
+    pub cursor: usize,
+    pub cell: C,
+    /// The byte written, once per byte for `PrintCharN`.
+    pub output: Option<u8>,
+    /// The byte read, if the input had one.
+    pub input: Option<u8>,
+}
+
+/// Writes a [`TraceRecord`] per executed instruction as newline-delimited
+/// JSON, through a buffer so tracing costs little more than the formatting.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct JsonTrace<W: Write> {
+    out: BufWriter<W>,
+    limit: usize,
+    written: usize,
+}
+
+#[cfg(feature = "serde")]
+impl<W: Write> JsonTrace<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: BufWriter::new(out),
+            limit: usize::MAX,
+            written: 0,
+        }
+    }
+
+    /// Stops writing after `limit` records; the run itself goes on.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn written(&self) -> usize {
+        self.written
+    }
+
+    pub fn write<C: serde::Serialize>(&mut self, record: &TraceRecord<C>) -> io::Result<()> {
+        if self.written == self.limit {
+            return Ok(());
+        }
+        serde_json::to_writer(&mut self.out, record)?;
+        self.out.write_all(b"\n")?;
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Flushes the buffer and returns the writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.out.into_inner().map_err(IntoInnerError::into_error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<R: Read, W: Write, C: Cell> BfMachine<R, W, C> {
+    /// Like [`BfMachine::run`], writing every step to `trace`. A failed write
+    /// stops the run with `BfRuntimeError::Io`.
+    pub fn run_traced<T: Write>(
+        &mut self,
+        program: &Program,
+        trace: &mut JsonTrace<T>,
+    ) -> Result<(), BfRuntimeError> {
+        self.execute(program, 0, Some(&mut |record| trace.write(&record)), None)
+            .map(|_| ())
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn traces_every_step() {
+        let program = Program::parse("+>.").unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b""), vec![]);
+        let mut trace = JsonTrace::new(vec![]);
+        machine.run_traced(&program, &mut trace).unwrap();
+
+        let json = String::from_utf8(trace.into_inner().unwrap()).unwrap();
+        let records: Vec<TraceRecord> = json
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(
+            records,
+            [
+                TraceRecord {
+                    step: 1,
+                    pc: 0,
+                    token: BfToken::Increment(1),
+                    cursor: 0,
+                    cell: 1,
+                    output: None,
+                    input: None,
+                },
+                TraceRecord {
+                    step: 2,
+                    pc: 1,
+                    token: BfToken::CursorRight(1),
+                    cursor: 1,
+                    cell: 0,
+                    output: None,
+                    input: None,
+                },
+                TraceRecord {
+                    step: 3,
+                    pc: 2,
+                    token: BfToken::PrintChar,
+                    cursor: 1,
+                    cell: 0,
+                    output: Some(0),
+                    input: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn limit_and_input() {
+        let program = Program::parse(",[>,]").unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b"ab"), vec![])
+            .with_eof_mode(crate::bf::bf_machine::EofMode::Zero);
+        let mut trace = JsonTrace::new(vec![]).with_limit(1);
+        machine.run_traced(&program, &mut trace).unwrap();
+
+        assert_eq!(trace.written(), 1);
+        let json = String::from_utf8(trace.into_inner().unwrap()).unwrap();
+        let record: TraceRecord = serde_json::from_str(json.trim_end()).unwrap();
+        assert_eq!(record.input, Some(b'a'));
+    }
+}
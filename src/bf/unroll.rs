@@ -0,0 +1,383 @@
+use std::collections::HashMap;
+
+use log::debug;
+
+use super::bf_token::BfToken;
+
+/// The cells, from cell 0, over which [`unroll_loops`] follows the cursor. It
+/// gives up once the cursor leaves them, so what it proves holds on any blank
+/// tape at least this long.
+pub const REACH: usize = 256;
+
+/// The most tokens unrolling one loop that prints may produce; such a loop
+/// running longer is kept as it is.
+pub const UNROLL_BUDGET: usize = 256;
+
+/// Unrolls the loops near the start of a program whose iteration count is
+/// known, taking `spans` along and returning the new tokens and spans.
+///
+/// The walk follows the program from a blank tape. A loop is unrolled when
+/// the cell it starts on holds a known count k and its body is arithmetic,
+/// moves and output only, coming back to that cell having taken exactly 1
+/// from it, as such a loop runs exactly k times. A body without output folds
+/// into one pass with every `+` and `-` scaled by k; one with output is
+/// repeated k times if that fits [`UNROLL_BUDGET`]. The walk stops at the
+/// first loop it cannot unroll, at an extension, or when the cursor leaves
+/// the first [`REACH`] cells; input only makes the cell read unknown.
+///
+/// The first copy of a repeated body keeps the body's spans, and later copies
+/// take the span of the loop's `]`.
+pub fn unroll_loops(
+    tokens: &[BfToken],
+    spans: &[usize],
+    jump_table: &[usize],
+) -> (Vec<BfToken>, Vec<usize>) {
+    let mut unrolled = Vec::with_capacity(tokens.len());
+    let mut unrolled_spans = Vec::with_capacity(spans.len());
+    let mut tape = KnownTape::default();
+    let mut loops = 0;
+    let mut pc = 0;
+
+    while pc < tokens.len() {
+        if tokens[pc] == BfToken::LoopStart {
+            let end = jump_table[pc];
+            let Some(expansion) = unroll(&tokens[pc + 1..end], &tape) else {
+                break;
+            };
+            for (token, source) in expansion {
+                tape.step(token).expect("unrolled bodies stay within reach");
+                unrolled.push(token);
+                unrolled_spans.push(spans[source.map_or(end, |index| pc + 1 + index)]);
+            }
+            loops += 1;
+            pc = end + 1;
+        } else {
+            if tape.step(tokens[pc]).is_none() {
+                break;
+            }
+            unrolled.push(tokens[pc]);
+            unrolled_spans.push(spans[pc]);
+            pc += 1;
+        }
+    }
+    unrolled.extend_from_slice(&tokens[pc..]);
+    unrolled_spans.extend_from_slice(&spans[pc..]);
+
+    debug!(
+        "pass unroll_loops: {loops} loop(s), {} -> {} tokens",
+        tokens.len(),
+        unrolled.len()
+    );
+    (unrolled, unrolled_spans)
+}
+
+/// The loop `body` as straight-line tokens for the cell under the cursor of
+/// `tape`, each with the index in `body` it came from, or `None` for a later
+/// copy. `None` if the loop cannot be unrolled.
+fn unroll(body: &[BfToken], tape: &KnownTape) -> Option<Vec<(BfToken, Option<usize>)>> {
+    let count = usize::from(tape.cell()?);
+    let mut offset = 0isize;
+    let (mut lowest, mut highest) = (0, 0);
+    let mut counter = 0u8;
+    let mut prints = false;
+
+    for token in body {
+        match *token {
+            BfToken::Increment(n) if offset == 0 => counter = counter.wrapping_add(n),
+            BfToken::Decrement(n) if offset == 0 => counter = counter.wrapping_sub(n),
+            BfToken::Increment(_) | BfToken::Decrement(_) | BfToken::NotCommand(_) => {}
+            BfToken::CursorLeft(n) => offset = offset.checked_sub_unsigned(n)?,
+            BfToken::CursorRight(n) => offset = offset.checked_add_unsigned(n)?,
+            BfToken::IncrementAt(at, n) | BfToken::DecrementAt(at, n) => {
+                let at = offset.checked_add(at)?;
+                if at == 0 {
+                    counter = match token {
+                        BfToken::IncrementAt(..) => counter.wrapping_add(n),
+                        _ => counter.wrapping_sub(n),
+                    };
+                }
+                lowest = lowest.min(at);
+                highest = highest.max(at);
+            }
+            BfToken::PrintChar | BfToken::PrintCharN(_) => prints = true,
+            BfToken::LoopStart
+            | BfToken::LoopEnd
+            | BfToken::InputChar
+            | BfToken::Extension(_)
+            | BfToken::SwitchTape
+            | BfToken::Fork => return None,
+        }
+        lowest = lowest.min(offset);
+        highest = highest.max(offset);
+    }
+    // 255 is the -1 that makes the counter run out after exactly `count`
+    // passes.
+    if offset != 0 || counter != u8::MAX {
+        return None;
+    }
+    let cursor = tape.cursor as isize;
+    if cursor + lowest < 0 || cursor + highest >= REACH as isize {
+        return None;
+    }
+
+    if count == 0 {
+        return Some(vec![]);
+    }
+    if !prints {
+        let scale = |n: u8| ((usize::from(n) * count) % 256) as u8;
+        let folded = body.iter().enumerate().filter_map(|(index, token)| {
+            let token = match *token {
+                BfToken::Increment(n) => BfToken::Increment(scale(n)),
+                BfToken::Decrement(n) => BfToken::Decrement(scale(n)),
+                BfToken::IncrementAt(at, n) => BfToken::IncrementAt(at, scale(n)),
+                BfToken::DecrementAt(at, n) => BfToken::DecrementAt(at, scale(n)),
+                BfToken::NotCommand(_) => return None,
+                token => token,
+            };
+            // A count that wraps all the way around changes nothing.
+            let noop = matches!(
+                token,
+                BfToken::Increment(0)
+                    | BfToken::Decrement(0)
+                    | BfToken::IncrementAt(_, 0)
+                    | BfToken::DecrementAt(_, 0)
+            );
+            (!noop).then_some((token, Some(index)))
+        });
+        return Some(folded.collect());
+    }
+    if count * body.len() > UNROLL_BUDGET {
+        return None;
+    }
+    let first = body
+        .iter()
+        .enumerate()
+        .map(|(index, &token)| (token, Some(index)));
+    let rest = (1..count).flat_map(|_| body.iter().map(|&token| (token, None)));
+    Some(first.chain(rest).collect())
+}
+
+/// What the walk knows of the tape: the cursor and the cells changed so far,
+/// `None` for one that was read into. Other cells still hold 0.
+#[derive(Debug, Default)]
+struct KnownTape {
+    cursor: usize,
+    cells: HashMap<usize, Option<u8>>,
+}
+
+impl KnownTape {
+    fn cell(&self) -> Option<u8> {
+        self.cells.get(&self.cursor).copied().unwrap_or(Some(0))
+    }
+
+    /// Follows `token`, or returns `None` where the walk has to stop.
+    fn step(&mut self, token: BfToken) -> Option<()> {
+        match token {
+            BfToken::Increment(n) => {
+                let value = self.cell().map(|value| value.wrapping_add(n));
+                self.cells.insert(self.cursor, value);
+            }
+            BfToken::Decrement(n) => {
+                let value = self.cell().map(|value| value.wrapping_sub(n));
+                self.cells.insert(self.cursor, value);
+            }
+            BfToken::IncrementAt(offset, n) | BfToken::DecrementAt(offset, n) => {
+                let at = self
+                    .cursor
+                    .checked_add_signed(offset)
+                    .filter(|&at| at < REACH)?;
+                let value = self.cells.get(&at).copied().unwrap_or(Some(0));
+                let value = value.map(|value| match token {
+                    BfToken::IncrementAt(..) => value.wrapping_add(n),
+                    _ => value.wrapping_sub(n),
+                });
+                self.cells.insert(at, value);
+            }
+            BfToken::CursorLeft(n) => self.cursor = self.cursor.checked_sub(n)?,
+            BfToken::CursorRight(n) => {
+                self.cursor = self
+                    .cursor
+                    .checked_add(n)
+                    .filter(|&cursor| cursor < REACH)?
+            }
+            BfToken::InputChar => {
+                self.cells.insert(self.cursor, None);
+            }
+            BfToken::NotCommand(_) | BfToken::PrintChar | BfToken::PrintCharN(_) => {}
+            // The walk follows one tape.
+            BfToken::LoopStart
+            | BfToken::LoopEnd
+            | BfToken::Extension(_)
+            | BfToken::SwitchTape
+            | BfToken::Fork => return None,
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::{
+        bf_machine::{BfMachine, MachineConfig},
+        bf_parser::ParseOptions,
+        program::Program,
+    };
+
+    use crate::bf::bf_parser::BfParserError;
+
+    use super::*;
+
+    const HELLO_WORLD: &str = include_str!("../../tests/programs/hello_world.b");
+    const PI: &str = include_str!("../../tests/programs/pi.b");
+
+    fn run(program: &Program, input: &[u8]) -> Vec<u8> {
+        let mut output = vec![];
+        BfMachine::new(30_000, Cursor::new(input), &mut output)
+            .run(program)
+            .unwrap();
+        output
+    }
+
+    /// `code` unrolled for a new machine like the one [`run`] builds.
+    fn fresh(code: &str) -> Result<Program, BfParserError> {
+        let config = MachineConfig {
+            memory_size: 30_000,
+            ..Default::default()
+        };
+        Program::parse_for_fresh_tape(code, &ParseOptions::default(), &config)
+    }
+
+    fn count(program: &Program, token: BfToken) -> usize {
+        program.tokens().iter().filter(|&&t| t == token).count()
+    }
+
+    #[test]
+    fn counted_print_loop_unrolls() {
+        let program = fresh("+++[>.<-]").unwrap();
+        assert_eq!(count(&program, BfToken::LoopStart), 0);
+        assert_eq!(count(&program, BfToken::PrintChar), 3);
+        assert_eq!(run(&program, b""), [0, 0, 0]);
+
+        // Later copies are placed at the `]`.
+        let spans: Vec<_> = (0..program.tokens().len())
+            .map(|pc| program.span(pc).unwrap())
+            .collect();
+        assert_eq!(spans, [0, 4, 5, 7, 7, 8, 8, 8, 8, 8, 8, 8, 8]);
+    }
+
+    #[test]
+    fn arithmetic_loops_fold() {
+        let program = fresh(HELLO_WORLD).unwrap();
+        assert_eq!(count(&program, BfToken::LoopStart), 0);
+        assert!(program.tokens().contains(&BfToken::IncrementAt(1, 70)));
+        assert_eq!(run(&program, b""), b"Hello World!\n");
+
+        // 3 * 100 wraps around to 44.
+        let code = format!("+++[>{}<-]>.", "+".repeat(100));
+        let program = fresh(&code).unwrap();
+        assert!(program.tokens().contains(&BfToken::IncrementAt(1, 44)));
+        assert_eq!(run(&program, b""), [44]);
+    }
+
+    #[test]
+    fn unknown_counts_are_kept() {
+        for code in [
+            // Input in the body.
+            "+++[>,.<-]",
+            // Input as the count.
+            ",[>.<-]",
+            // A nested loop.
+            "++[>++[>.<-]<-]",
+            // A body that does not take exactly 1.
+            "++++[>.<--]",
+            // A body that ends elsewhere.
+            "+++[>.-]",
+        ] {
+            let program = fresh(code).unwrap();
+            assert!(program.tokens().contains(&BfToken::LoopStart), "{code}");
+        }
+
+        // Too long to repeat.
+        let code = format!("{}[>.<-]", "+".repeat(100));
+        let program = fresh(&code).unwrap();
+        assert!(program.tokens().contains(&BfToken::LoopStart));
+    }
+
+    #[test]
+    fn optimized_programs_print_the_same() {
+        for (code, input) in [
+            (HELLO_WORLD, &b""[..]),
+            (PI, b""),
+            ("+++[>.<-]", b""),
+            ("+++++[>+++[>+<-]<-]>>.", b""),
+            (",>++[<.>-]", b"x"),
+            ("++>+++[<[>.<-]>-]", b""),
+        ] {
+            let plain = Program::parse(code).unwrap();
+            let optimized = fresh(code).unwrap();
+            assert_eq!(run(&optimized, input), run(&plain, input), "{code}");
+        }
+    }
+
+    #[test]
+    fn cursor_before_a_kept_loop() {
+        // Each unrolled loop leaves the cursor back on cell 0, where the next
+        // one starts.
+        let code = "+++[>+.<-]+[>.<-]";
+        let plain = Program::parse(code).unwrap();
+        let optimized = fresh(code).unwrap();
+        assert_eq!(count(&optimized, BfToken::LoopStart), 0);
+        assert_eq!(run(&optimized, b""), run(&plain, b""));
+        assert_eq!(run(&optimized, b""), [1, 2, 3, 3]);
+
+        // The count of the kept loop is read into cell 0, not the 3 on cell 1.
+        let code = "+++[>+<-],[>.<-]";
+        let plain = Program::parse(code).unwrap();
+        let optimized = fresh(code).unwrap();
+        assert_eq!(count(&optimized, BfToken::LoopStart), 1);
+        assert_eq!(run(&optimized, b"\x02"), run(&plain, b"\x02"));
+        assert_eq!(run(&optimized, b"\x02"), [3, 3]);
+    }
+
+    #[test]
+    fn only_fresh_tapes_are_unrolled() {
+        fn output(program: &Program, machine: &mut BfMachine<Cursor<&[u8]>, Vec<u8>>) -> Vec<u8> {
+            machine.run(program).unwrap();
+            machine.take_output()
+        }
+
+        // A tape loaded before the run.
+        let code = "[>+<-]>.";
+        for program in [Program::parse(code), Program::parse_optimized(code)] {
+            let mut machine = BfMachine::new(30_000, Cursor::new(&b""[..]), vec![]);
+            machine.load_memory(0, &[3]).unwrap();
+            assert_eq!(output(&program.unwrap(), &mut machine), [3], "{code}");
+        }
+
+        // A machine run again without a reset.
+        let code = "+++[>+<-]>.";
+        for program in [Program::parse(code), Program::parse_optimized(code)] {
+            let program = program.unwrap();
+            let mut machine = BfMachine::new(30_000, Cursor::new(&b""[..]), vec![]);
+            assert_eq!(output(&program, &mut machine), [3]);
+            assert_eq!(output(&program, &mut machine), [6]);
+        }
+
+        // A ring shorter than the walk's reach, where cell 8 is cell 0.
+        let code = "+>>>>>>>>[-]<<<<<<<<.";
+        let config = MachineConfig {
+            memory_size: 8,
+            ..Default::default()
+        };
+        for program in [
+            Program::parse(code),
+            Program::parse_optimized(code),
+            Program::parse_for_fresh_tape(code, &ParseOptions::default(), &config),
+        ] {
+            let mut machine = BfMachine::with_config(config, Cursor::new(&b""[..]), vec![]);
+            assert_eq!(output(&program.unwrap(), &mut machine), [0], "{code}");
+        }
+    }
+}
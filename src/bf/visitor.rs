@@ -0,0 +1,345 @@
+use std::collections::BTreeMap;
+
+use super::ast::BfAst;
+
+pub trait BfVisitor {
+    fn visit_inc(&mut self, _val: u8) {}
+    fn visit_dec(&mut self, _val: u8) {}
+    fn visit_left(&mut self, _val: usize) {}
+    fn visit_right(&mut self, _val: usize) {}
+    fn visit_input(&mut self) {}
+    fn visit_output(&mut self) {}
+    fn visit_comment(&mut self, _ch: char) {}
+    fn visit_extension(&mut self, _id: u8) {}
+    fn visit_switch_tape(&mut self) {}
+    fn visit_fork(&mut self) {}
+
+    fn visit_loop(&mut self, body: &[BfAst]) {
+        walk(body, self);
+    }
+
+    fn visit_transfer(&mut self, _factors: &[(isize, u8)], body: &[BfAst]) {
+        self.visit_loop(body);
+    }
+}
+
+pub fn walk(ast: &[BfAst], visitor: &mut (impl BfVisitor + ?Sized)) {
+    for node in ast {
+        match node {
+            BfAst::Inc(val) => visitor.visit_inc(*val),
+            BfAst::Dec(val) => visitor.visit_dec(*val),
+            BfAst::Left(val) => visitor.visit_left(*val),
+            BfAst::Right(val) => visitor.visit_right(*val),
+            BfAst::Input => visitor.visit_input(),
+            BfAst::Output => visitor.visit_output(),
+            BfAst::Comment(ch) => visitor.visit_comment(*ch),
+            BfAst::Extension(id) => visitor.visit_extension(*id),
+            BfAst::SwitchTape => visitor.visit_switch_tape(),
+            BfAst::Fork => visitor.visit_fork(),
+            BfAst::Loop(body) => visitor.visit_loop(body),
+            BfAst::Transfer { factors, body } => visitor.visit_transfer(factors, body),
+        }
+    }
+}
+
+pub trait BfFolder {
+    fn fold_inc(&mut self, val: u8) -> Option<BfAst> {
+        Some(BfAst::Inc(val))
+    }
+
+    fn fold_dec(&mut self, val: u8) -> Option<BfAst> {
+        Some(BfAst::Dec(val))
+    }
+
+    fn fold_left(&mut self, val: usize) -> Option<BfAst> {
+        Some(BfAst::Left(val))
+    }
+
+    fn fold_right(&mut self, val: usize) -> Option<BfAst> {
+        Some(BfAst::Right(val))
+    }
+
+    fn fold_input(&mut self) -> Option<BfAst> {
+        Some(BfAst::Input)
+    }
+
+    fn fold_output(&mut self) -> Option<BfAst> {
+        Some(BfAst::Output)
+    }
+
+    fn fold_comment(&mut self, ch: char) -> Option<BfAst> {
+        Some(BfAst::Comment(ch))
+    }
+
+    fn fold_extension(&mut self, id: u8) -> Option<BfAst> {
+        Some(BfAst::Extension(id))
+    }
+
+    fn fold_switch_tape(&mut self) -> Option<BfAst> {
+        Some(BfAst::SwitchTape)
+    }
+
+    fn fold_fork(&mut self) -> Option<BfAst> {
+        Some(BfAst::Fork)
+    }
+
+    fn fold_loop(&mut self, body: Vec<BfAst>) -> Option<BfAst> {
+        Some(BfAst::Loop(fold(body, self)))
+    }
+
+    /// Folds the body and keeps the factors as they were.
+    fn fold_transfer(&mut self, factors: Vec<(isize, u8)>, body: Vec<BfAst>) -> Option<BfAst> {
+        Some(BfAst::Transfer {
+            factors,
+            body: fold(body, self),
+        })
+    }
+}
+
+pub fn fold(ast: Vec<BfAst>, folder: &mut (impl BfFolder + ?Sized)) -> Vec<BfAst> {
+    ast.into_iter()
+        .filter_map(|node| match node {
+            BfAst::Inc(val) => folder.fold_inc(val),
+            BfAst::Dec(val) => folder.fold_dec(val),
+            BfAst::Left(val) => folder.fold_left(val),
+            BfAst::Right(val) => folder.fold_right(val),
+            BfAst::Input => folder.fold_input(),
+            BfAst::Output => folder.fold_output(),
+            BfAst::Comment(ch) => folder.fold_comment(ch),
+            BfAst::Extension(id) => folder.fold_extension(id),
+            BfAst::SwitchTape => folder.fold_switch_tape(),
+            BfAst::Fork => folder.fold_fork(),
+            BfAst::Loop(body) => folder.fold_loop(body),
+            BfAst::Transfer { factors, body } => folder.fold_transfer(factors, body),
+        })
+        .collect()
+}
+
+/// Counts executable instructions the way they appear in the source:
+/// every node is one instruction and each loop counts its two brackets.
+#[derive(Debug, Default)]
+pub struct InstructionCounter {
+    pub count: usize,
+}
+
+impl BfVisitor for InstructionCounter {
+    fn visit_inc(&mut self, _val: u8) {
+        self.count += 1;
+    }
+
+    fn visit_dec(&mut self, _val: u8) {
+        self.count += 1;
+    }
+
+    fn visit_left(&mut self, _val: usize) {
+        self.count += 1;
+    }
+
+    fn visit_right(&mut self, _val: usize) {
+        self.count += 1;
+    }
+
+    fn visit_input(&mut self) {
+        self.count += 1;
+    }
+
+    fn visit_output(&mut self) {
+        self.count += 1;
+    }
+
+    fn visit_switch_tape(&mut self) {
+        self.count += 1;
+    }
+
+    fn visit_fork(&mut self) {
+        self.count += 1;
+    }
+
+    fn visit_loop(&mut self, body: &[BfAst]) {
+        self.count += 2;
+        walk(body, self);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MaxDepth {
+    depth: usize,
+    pub max: usize,
+}
+
+impl BfVisitor for MaxDepth {
+    fn visit_loop(&mut self, body: &[BfAst]) {
+        self.depth += 1;
+        self.max = self.max.max(self.depth);
+        walk(body, self);
+        self.depth -= 1;
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CommentStripper;
+
+impl BfFolder for CommentStripper {
+    fn fold_comment(&mut self, _ch: char) -> Option<BfAst> {
+        None
+    }
+}
+
+/// Turns loops that only move their counter into other cells into
+/// [`BfAst::Transfer`]s: a body of adds and moves that ends where it started
+/// and steps the counter by one, such as `[->++<]` or the clear `[-]`.
+#[derive(Debug, Default)]
+pub struct TransferFinder;
+
+impl BfFolder for TransferFinder {
+    fn fold_loop(&mut self, body: Vec<BfAst>) -> Option<BfAst> {
+        let body = fold(body, self);
+        Some(match transfer_factors(&body) {
+            Some(factors) => BfAst::Transfer { factors, body },
+            None => BfAst::Loop(body),
+        })
+    }
+}
+
+/// The cells a loop adds its counter to, with the factor for each, if it is a
+/// transfer.
+fn transfer_factors(body: &[BfAst]) -> Option<Vec<(isize, u8)>> {
+    let mut shift = 0isize;
+    let mut deltas: BTreeMap<isize, u8> = BTreeMap::new();
+    for node in body {
+        match node {
+            BfAst::Inc(n) => {
+                let delta = deltas.entry(shift).or_default();
+                *delta = delta.wrapping_add(*n);
+            }
+            BfAst::Dec(n) => {
+                let delta = deltas.entry(shift).or_default();
+                *delta = delta.wrapping_sub(*n);
+            }
+            BfAst::Right(n) => shift += *n as isize,
+            BfAst::Left(n) => shift -= *n as isize,
+            BfAst::Comment(_) => {}
+            _ => return None,
+        }
+    }
+    if shift != 0 {
+        return None;
+    }
+    // Counting up runs 256 - p[0] times, which adds the opposite.
+    let negate = match deltas.remove(&0) {
+        Some(255) => false,
+        Some(1) => true,
+        _ => return None,
+    };
+    Some(
+        deltas
+            .into_iter()
+            .filter(|&(_, delta)| delta != 0)
+            .map(|(offset, delta)| (offset, if negate { delta.wrapping_neg() } else { delta }))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bf::{ast, bf_parser::BfParser};
+
+    use super::*;
+
+    const PI: &str = include_str!("../../tests/programs/pi.b");
+
+    fn pi_ast() -> Vec<BfAst> {
+        ast::from_tokens(&BfParser::parse(PI).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn count_hand_built() {
+        let ast = vec![
+            BfAst::Inc(3),
+            BfAst::Comment('a'),
+            BfAst::Loop(vec![BfAst::Right(1), BfAst::Output, BfAst::Left(1)]),
+            BfAst::Input,
+        ];
+
+        let mut counter = InstructionCounter::default();
+        walk(&ast, &mut counter);
+        assert_eq!(counter.count, 7);
+    }
+
+    #[test]
+    fn count_pi() {
+        let mut counter = InstructionCounter::default();
+        walk(&pi_ast(), &mut counter);
+
+        let commands = PI.chars().filter(|c| "+-<>[].,".contains(*c)).count();
+        assert_eq!(counter.count, commands);
+    }
+
+    #[test]
+    fn depth_hand_built() {
+        let mut depth = MaxDepth::default();
+        walk(&[BfAst::Inc(1), BfAst::Output], &mut depth);
+        assert_eq!(depth.max, 0);
+
+        let ast = vec![
+            BfAst::Loop(vec![BfAst::Loop(vec![])]),
+            BfAst::Loop(vec![BfAst::Loop(vec![BfAst::Loop(vec![BfAst::Dec(1)])])]),
+            BfAst::Loop(vec![]),
+        ];
+        let mut depth = MaxDepth::default();
+        walk(&ast, &mut depth);
+        assert_eq!(depth.max, 3);
+    }
+
+    #[test]
+    fn depth_pi() {
+        let ast = pi_ast();
+
+        let mut depth = MaxDepth::default();
+        walk(&ast, &mut depth);
+        assert_eq!(depth.max, 11);
+        assert_eq!(depth.max, ast::max_depth(&ast));
+    }
+
+    #[test]
+    fn strip_comments() {
+        let ast = ast::from_tokens(&BfParser::parse("a+[ b-]c").unwrap()).unwrap();
+
+        assert_eq!(
+            fold(ast, &mut CommentStripper),
+            vec![BfAst::Inc(1), BfAst::Loop(vec![BfAst::Dec(1)])]
+        );
+    }
+
+    #[test]
+    fn find_transfers() {
+        let found = |code| {
+            let ast = ast::from_tokens(&BfParser::parse(code).unwrap()).unwrap();
+            fold(ast, &mut TransferFinder)
+        };
+
+        assert!(matches!(
+            &found("[-]")[..],
+            [BfAst::Transfer { factors, .. }] if factors.is_empty()
+        ));
+        assert!(matches!(
+            &found("[+>-<<++>]")[..],
+            [BfAst::Transfer { factors, .. }] if *factors == [(-1, 254), (1, 1)]
+        ));
+        // Only innermost loops can be transfers, and they stay where they are.
+        let [BfAst::Loop(body)] = &found("[>[->+<]<-]")[..] else {
+            panic!("the outer loop is no transfer");
+        };
+        assert!(matches!(body[1], BfAst::Transfer { .. }));
+        for code in ["[--]", "[->+]", "[-.]"] {
+            assert!(matches!(found(code)[..], [BfAst::Loop(_)]), "{code}");
+        }
+
+        // Transfers are still the loops they were.
+        let ast = found("+[->+<]a");
+        assert_eq!(ast::to_tokens(&ast), BfParser::parse("+[->+<]a").unwrap());
+        let mut counter = InstructionCounter::default();
+        walk(&ast, &mut counter);
+        assert_eq!(counter.count, 7);
+    }
+}
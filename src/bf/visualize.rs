@@ -0,0 +1,127 @@
+use std::{
+    fmt::{Display, Write as _},
+    io::{self, Write},
+};
+
+use super::program::Program;
+
+const CELL_WIDTH: usize = 4;
+const HIGHLIGHT: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+const CLEAR_LINE: &str = "\x1b[K";
+
+/// Renders the instruction at `pc` and `window` cells of `tape` around
+/// `cursor` as a few lines of text, the cursor's cell in reverse video and
+/// marked with `^` below it.
+pub fn render_frame(
+    tape: &[impl Display],
+    cursor: usize,
+    program: &Program,
+    pc: usize,
+    steps: usize,
+    window: usize,
+) -> String {
+    let window = window.clamp(1, tape.len());
+    let start = cursor.saturating_sub(window / 2).min(tape.len() - window);
+    let cells = start..start + window;
+
+    let mut frame = String::new();
+    match program.tokens().get(pc) {
+        Some(token) => write!(frame, "pc {pc}: {token:?}"),
+        None => write!(frame, "pc {pc}: end"),
+    }
+    .unwrap();
+    writeln!(frame, ", {steps} steps{CLEAR_LINE}").unwrap();
+
+    for cell in cells.clone() {
+        write!(frame, "{cell:>CELL_WIDTH$}").unwrap();
+    }
+    writeln!(frame, "{CLEAR_LINE}").unwrap();
+    for cell in cells.clone() {
+        let value = format!("{:>CELL_WIDTH$}", tape[cell]);
+        if cell == cursor {
+            write!(frame, "{HIGHLIGHT}{value}{RESET}").unwrap();
+        } else {
+            frame.push_str(&value);
+        }
+    }
+    writeln!(frame, "{CLEAR_LINE}").unwrap();
+    let marker = (cursor - start + 1) * CELL_WIDTH;
+    writeln!(frame, "{:>marker$}{CLEAR_LINE}", "^").unwrap();
+    frame
+}
+
+/// A status region at the bottom of a terminal that each frame overwrites.
+#[derive(Debug)]
+pub struct Screen<W: Write> {
+    inner: W,
+    lines: usize,
+}
+
+impl<W: Write> Screen<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, lines: 0 }
+    }
+
+    /// Moves up over the previous frame and writes `frame` in its place.
+    pub fn draw(&mut self, frame: &str) -> io::Result<()> {
+        if self.lines > 0 {
+            write!(self.inner, "\x1b[{}F", self.lines)?;
+        }
+        self.inner.write_all(frame.as_bytes())?;
+        self.lines = frame.lines().count();
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::bf::bf_machine::{BfMachine, BfRuntimeError};
+
+    use super::*;
+
+    fn strip_ansi(text: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = text.chars();
+        while let Some(ch) = chars.next() {
+            if ch == '\x1b' {
+                chars.by_ref().find(|ch| ch.is_ascii_alphabetic());
+            } else {
+                stripped.push(ch);
+            }
+        }
+        stripped
+    }
+
+    #[test]
+    fn frame_snapshot() {
+        let program = Program::parse("++>+++>+>,+").unwrap();
+        let mut machine = BfMachine::new(10, Cursor::new(b"A"), vec![]).with_step_limit(8);
+        let Err(BfRuntimeError::StepLimitExceeded { pc }) = machine.run(&program) else {
+            panic!("the run should stop at the step limit");
+        };
+
+        let frame = render_frame(machine.tape(), machine.cursor(), &program, pc, 8, 5);
+        assert!(frame.contains("\x1b[7m   1\x1b[0m"));
+        assert_eq!(
+            strip_ansi(&frame),
+            "pc 8: CursorRight(1), 8 steps\n   0   1   2   3   4\n   2   3   1   0   0\n           ^\n"
+        );
+
+        let frame = render_frame(machine.tape(), 9, &program, 11, 20, 3);
+        assert_eq!(
+            strip_ansi(&frame),
+            "pc 11: end, 20 steps\n   7   8   9\n   0   0   0\n           ^\n"
+        );
+    }
+
+    #[test]
+    fn screen_redraws_in_place() {
+        let mut screen = Screen::new(vec![]);
+        screen.draw("a\nb\n").unwrap();
+        screen.draw("c\nd\n").unwrap();
+        assert_eq!(screen.inner, b"a\nb\n\x1b[2Fc\nd\n");
+    }
+}
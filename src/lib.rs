@@ -0,0 +1,23 @@
+//! A Brainfuck interpreter: tokens, parser and machine, with tooling around
+//! them.
+//!
+//! The core, [`bf::bf_token`], [`bf::bf_parser`], [`bf::program`] and
+//! [`bf::bf_machine`], is always built. The rest can be left out by turning
+//! off default features:
+//!
+//! | Feature           | Default | Adds                                                      |
+//! |-------------------|---------|-----------------------------------------------------------|
+//! | `optimizer`       | yes     | `bf::bf_optimizer` and `bf::unroll`; without it O2 is O1  |
+//! | `dialects`        | yes     | `bf::dialects`                                            |
+//! | `codegen-js`      | yes     | `bf::codegen::js`                                         |
+//! | `codegen-llvm`    | yes     | `bf::codegen::llvm`                                       |
+//! | `serde`           | yes     | (de)serializing programs, checkpoints and core dumps      |
+//! | `image`           | yes     | BrainLoller images in `bf::dialects`, implies `dialects`  |
+//! | `cli`             | yes     | the `bf-rust` binary, `bf::repl` and `bf::terminal`       |
+//! | `mmap`            | no      | tapes in memory-mapped files                              |
+//! | `trace-execution` | no      | a trace-level log line per instruction                    |
+//!
+//! `cargo test --test features -- --ignored` checks that the crate builds with
+//! each feature alone.
+
+pub mod bf;
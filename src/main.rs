@@ -1,39 +1,1947 @@
-mod bf;
+use std::{
+    cell::RefCell,
+    env,
+    error::Error,
+    ffi::OsStr,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, stderr, stdin, stdout, Cursor, IsTerminal, Read, Stderr, Write},
+    path::Path,
+    process::exit,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock, PoisonError,
+    },
+    thread,
+    time::{Duration, Instant},
+};
 
-use std::{env, error::Error, ffi::OsStr, fs, path::Path, process::exit};
+use log::LevelFilter;
 
-use bf::{bf_machine::BfMachine, bf_optimizer::BfCodeOptimizer, bf_parser::BfParser};
+use bf_rust::bf::{
+    analyzer,
+    ast::{self, CommentStyle, PrettyOptions},
+    bench,
+    bf_machine::{
+        BfMachine, BfRuntimeError, BfSnapshot, EofMode, LoopFrame, MachineConfig, RunOutcome,
+        TapePolicy,
+    },
+    bf_parser::{BfParser, ParseOptions},
+    bf_token::BfToken,
+    bytecode,
+    cache::ProgramCache,
+    cell::{Cell, CellSize},
+    coverage::Coverage,
+    debugger::Debugger,
+    diagnostics::{self, Diagnostic, Severity},
+    directives,
+    error::BfError,
+    explain::{self, ExplainOptions},
+    fork,
+    handler::RandomByte,
+    highlight,
+    incremental::IncrementalInterpreter,
+    io::{
+        self as bf_io, DelayWriter, FormatWriter, InputTranslation, OutputFormat,
+        OutputTranslation, RecordingReader, TeeWriter,
+    },
+    pipe::pipe_cells,
+    profile::{self, LoopProfiles, Profile, Timings},
+    program::{OptLevel, Program},
+    progress::{self, StatusAwareWriter, StatusLine},
+    repl::{self, Entry, LineEditor},
+    serve::Server,
+    stats::{self, ProgramStats},
+    suite::{self, Outcome},
+    visualize::{self, Screen},
+};
+
+#[cfg(unix)]
+use bf_rust::bf::terminal::{RawMode, StdinTerminal};
+
+/// The subcommands, named by the first argument. Any other first argument is
+/// the program of an implicit `run`, as before there were subcommands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CliCommand {
+    Run,
+    Check,
+    Fmt,
+    Explain,
+    Highlight,
+    Stats,
+    Compile,
+    Transpile,
+    Test,
+    Repl,
+    Debug,
+    InspectCore,
+    BenchOpt,
+    Serve,
+}
+
+impl CliCommand {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "run" => Self::Run,
+            "check" | "lint" | "--check" => Self::Check,
+            "fmt" => Self::Fmt,
+            "explain" => Self::Explain,
+            "highlight" => Self::Highlight,
+            "stats" => Self::Stats,
+            "compile" => Self::Compile,
+            "transpile" => Self::Transpile,
+            "test" => Self::Test,
+            "repl" => Self::Repl,
+            "debug" => Self::Debug,
+            "inspect-core" => Self::InspectCore,
+            "bench-opt" => Self::BenchOpt,
+            "serve" => Self::Serve,
+            _ => return None,
+        })
+    }
+
+    /// Runs the command with the arguments after its name. `false` means it
+    /// ran but failed, like a lint with errors; `run` exits on its own
+    /// failures.
+    fn execute(self, args: &[String]) -> Result<bool, Box<dyn Error>> {
+        let done = |result: Result<(), Box<dyn Error>>| result.map(|()| true);
+        match self {
+            Self::Run => {
+                run_program(args);
+                Ok(true)
+            }
+            Self::Check => lint(args),
+            Self::Fmt => done(fmt(args)),
+            Self::Explain => done(explain(args)),
+            Self::Highlight => done(highlight(args)),
+            Self::Stats => done(stats(args)),
+            Self::Compile => done(compile(args)),
+            Self::Transpile => done(transpile(args)),
+            Self::Test => test(args),
+            Self::Repl => repl(args),
+            Self::Debug => debug(args),
+            Self::InspectCore => done(inspect_core(args)),
+            Self::BenchOpt => done(bench_opt(args)),
+            Self::Serve => done(serve(args)),
+        }
+    }
+
+    /// What the command was doing, for its error message.
+    fn activity(self) -> &'static str {
+        match self {
+            Self::Run => "running",
+            Self::Check => "linting",
+            Self::Fmt => "formatting",
+            Self::Explain => "explaining",
+            Self::Highlight => "highlighting",
+            Self::Stats => "counting",
+            Self::Compile => "compiling",
+            Self::Transpile => "transpiling",
+            Self::Test => "testing",
+            Self::Repl => "reading",
+            Self::Debug => "debugging",
+            Self::InspectCore => "inspecting",
+            Self::BenchOpt => "benchmarking",
+            Self::Serve => "serving",
+        }
+    }
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let bf_code = parse_args(&args).unwrap_or_else(|err| {
-        eprintln!("Error occurred during parsing arguments: {err}");
+    let (command, args) = match args.get(1).and_then(|name| CliCommand::parse(name)) {
+        Some(command) => (command, &args[2..]),
+        None => (CliCommand::Run, &args[1..]),
+    };
+
+    let passed = command.execute(args).unwrap_or_else(|err| {
+        eprintln!("Error occurred during {}: {err}", command.activity());
+        // A test run exits 1 when tests fail, so it cannot fail with 1 as well.
+        exit(if command == CliCommand::Test { 2 } else { 1 });
+    });
+    if !passed {
         exit(1);
+    }
+}
+
+/// Runs the program named by `args[0]`, or given inline with `-e`, with the
+/// flags after it.
+fn run_program(args: &[String]) {
+    let _ = ERROR_FORMAT.set(error_format(args));
+    let run_args = parse_args(args).unwrap_or_else(|err| match err.downcast::<BfError>() {
+        Ok(err) => Failure::new(
+            FailureKind::Io,
+            "Error occurred during reading the program",
+            err,
+        )
+        .exit(),
+        Err(err) => Failure::new(
+            FailureKind::Usage,
+            "Error occurred during parsing arguments",
+            err,
+        )
+        .exit(),
     });
-    let optimized_code = BfCodeOptimizer::optimize(&bf_code);
+    init_logger(run_args.verbosity);
+    let bf_code = &run_args.bf_code;
+    let (code, embedded_input) = run_args.parse_options.split_input(bf_code);
+    let level = run_args.opt_level();
+    let program = match &run_args.cache_dir {
+        Some(dir) => {
+            let cache = ProgramCache::new(dir);
+            if run_args.cache_clear {
+                cache.clear().unwrap_or_else(|err| {
+                    let context = format!("Error occurred during clearing cache {dir}");
+                    Failure::new(FailureKind::Io, context, err).exit()
+                });
+            }
+            cache
+                .parse(code, &run_args.parse_options, level)
+                .map(|(program, _)| program)
+        }
+        None => Program::parse_at(code, &run_args.parse_options, level),
+    }
+    .unwrap_or_else(|err| Failure::from_bf(&err.into(), &run_args.file, bf_code, None).exit());
 
-    let commands = BfParser::parse_compress(&optimized_code).unwrap_or_else(|err| {
-        eprintln!("Error occurred during parsing Brainfuck code: {err}");
-        exit(1);
+    if run_args.emit_json {
+        let json = program_json(&program).unwrap_or_else(|err| {
+            Failure::new(
+                FailureKind::Usage,
+                "Error occurred during emitting JSON",
+                err,
+            )
+            .exit()
+        });
+        println!("{json}");
+        return;
+    }
+    if let Some(path) = &run_args.emit_js {
+        let js = program_js(&program, &run_args.machine_config()).unwrap_or_else(|err| {
+            Failure::new(
+                FailureKind::Usage,
+                "Error occurred during emitting JavaScript",
+                err,
+            )
+            .exit()
+        });
+        fs::write(path, js).unwrap_or_else(|err| {
+            let context = format!("Error occurred during writing JavaScript {path}");
+            Failure::new(FailureKind::Io, context, err).exit()
+        });
+        return;
+    }
+    if let Some(path) = &run_args.emit_llvm {
+        let ir = program_ir(&program).unwrap_or_else(|err| {
+            Failure::new(
+                FailureKind::Usage,
+                "Error occurred during emitting LLVM IR",
+                err,
+            )
+            .exit()
+        });
+        fs::write(path, ir).unwrap_or_else(|err| {
+            let context = format!("Error occurred during writing LLVM IR {path}");
+            Failure::new(FailureKind::Io, context, err).exit()
+        });
+        return;
+    }
+
+    let program = run_args.for_run(program);
+    let empty_loops = report_empty_loops(bf_code, code, &run_args.parse_options);
+    if empty_loops > 0 && run_args.deny_empty_loops {
+        Failure::new(
+            FailureKind::Refused,
+            "Refusing to run",
+            format!("found {empty_loops} empty loop(s) and --deny-empty-loops is set"),
+        )
+        .file(&run_args.file)
+        .exit();
+    }
+
+    if !run_args.pipe.is_empty() {
+        run_pipeline(&run_args, program);
+        return;
+    }
+
+    match run_args.cell_size {
+        CellSize::U8 => run_cells::<u8>(&run_args, &program, code, embedded_input),
+        CellSize::I32 => run_cells::<i32>(&run_args, &program, code, embedded_input),
+    }
+}
+
+/// Runs `program` as the flags say, on a tape of `C` cells.
+fn run_cells<C: Cell>(
+    run_args: &RunArgs,
+    program: &Program,
+    code: &str,
+    embedded_input: Option<&str>,
+) {
+    let bf_code = &run_args.bf_code;
+    let resumed = run_args
+        .checkpoint
+        .as_deref()
+        .filter(|path| Path::new(path).exists())
+        .map(|path| {
+            load_checkpoint::<C>(path, program, run_args).unwrap_or_else(|err| {
+                let context = format!("Error occurred during loading checkpoint {path}");
+                Failure::new(FailureKind::Io, context, err).exit()
+            })
+        });
+
+    let input_file = run_args.replay_input.as_ref().or(run_args.input.as_ref());
+    let mut input: Box<dyn Read> = match (input_file, &run_args.input_str, embedded_input) {
+        (Some(path), _, _) => Box::new(File::open(path).unwrap_or_else(|err| {
+            let context = format!("Error occurred during opening input {path}");
+            Failure::new(FailureKind::Io, context, err).exit()
+        })),
+        (None, Some(bytes), Some(embedded)) => Box::new(embedded.as_bytes().chain(&bytes[..])),
+        (None, Some(bytes), None) => Box::new(&bytes[..]),
+        (None, None, Some(embedded)) => Box::new(embedded.as_bytes().chain(stdin())),
+        (None, None, None) => Box::new(stdin()),
+    };
+    let mut start = Resume::default();
+    if let Some(resume) = resumed {
+        io::copy(
+            &mut input.by_ref().take(resume.input_consumed as u64),
+            &mut io::sink(),
+        )
+        .unwrap_or_else(|err| {
+            let context = "Error occurred during skipping consumed input";
+            Failure::new(FailureKind::Io, context, err).exit()
+        });
+        start = resume;
+    }
+    if let Some(path) = &run_args.record_input {
+        let log = File::create(path).unwrap_or_else(|err| {
+            let context = format!("Error occurred during creating input record {path}");
+            Failure::new(FailureKind::Io, context, err).exit()
+        });
+        input = Box::new(RecordingReader::new(input, log));
+    }
+
+    let expected = run_args.expect.as_ref().map(|path| {
+        fs::read(path).unwrap_or_else(|err| {
+            let context = format!("Error occurred during reading expected output {path}");
+            Failure::new(FailureKind::Io, context, err).exit()
+        })
     });
+    // Under `--expect` the output is captured for comparing instead of printed,
+    // and under `--visualize` it waits until the tape display is done.
+    let mut captured = vec![];
+    let display: Box<dyn Write + '_> = match (&expected, &run_args.output) {
+        (Some(_), _) => Box::new(&mut captured),
+        (None, Some(path)) => {
+            let file = File::create(path).unwrap_or_else(|err| {
+                let context = format!("Error occurred during creating output file {path}");
+                Failure::new(FailureKind::Io, context, err).exit()
+            });
+            Box::new(FormatWriter::new(file, run_args.output_format))
+        }
+        (None, None) if run_args.visualize => {
+            Box::new(FormatWriter::new(&mut captured, run_args.output_format))
+        }
+        (None, None) => Box::new(FormatWriter::new(stdout(), run_args.output_format)),
+    };
+    let output: Box<dyn Write + '_> = match &run_args.tee {
+        Some(path) => {
+            let file = File::create(path).unwrap_or_else(|err| {
+                let context = format!("Error occurred during creating tee file {path}");
+                Failure::new(FailureKind::Io, context, err).exit()
+            });
+            Box::new(TeeWriter::new(
+                display,
+                TeeFile {
+                    path: path.clone(),
+                    file,
+                },
+            ))
+        }
+        None => Box::new(display),
+    };
+    let output: Box<dyn Write + '_> = match run_args.delay {
+        Some(delay) => {
+            let delayed = DelayWriter::new(output, delay, run_args.delay_per_line);
+            // With a checkpoint Ctrl-C stops the run at the next slice, which
+            // pausing must not hold up.
+            match run_args.checkpoint {
+                Some(_) => Box::new(delayed.with_interrupt(stop_at_interrupt())),
+                None => Box::new(delayed),
+            }
+        }
+        None => output,
+    };
+    // Without a terminal to draw on `--progress` does nothing.
+    let status = (run_args.progress && stderr().is_terminal())
+        .then(|| Rc::new(RefCell::new(StatusLine::new(stderr()))));
+    let output: Box<dyn Write + '_> = match &status {
+        Some(status) => Box::new(StatusAwareWriter::new(output, Rc::clone(status))),
+        None => output,
+    };
 
-    let mut machine = BfMachine::default();
-    machine.run(&commands).unwrap_or_else(|err| {
-        eprintln!("Error occurred during runtime: {err}",);
-        exit(1);
+    let mut machine = BfMachine::<_, _, C>::with_cells(run_args.machine_config(), input, output)
+        .with_input_translation(run_args.input_newlines)
+        .with_output_translation(run_args.output_newlines);
+    if run_args.random {
+        machine.set_handler(RandomByte);
+    }
+    if let Some(seed) = run_args.seed {
+        machine.set_rng_seed(seed);
+    }
+    for (path, offset) in &run_args.init_tape {
+        let context = format!("Error occurred during loading tape data {path}");
+        let data = fs::read(path)
+            .unwrap_or_else(|err| Failure::new(FailureKind::Io, context.clone(), err).exit());
+        machine
+            .load_tape(*offset, &data)
+            .unwrap_or_else(|err| Failure::new(FailureKind::Usage, context, err).exit());
+    }
+    if run_args.coverage {
+        machine.enable_coverage();
+    }
+    if run_args.profile_folded.is_some() || run_args.profile.is_some() || run_args.stats {
+        machine.enable_profiling();
+    }
+    if run_args.timings {
+        machine.enable_timings(profile::DEFAULT_SAMPLE_INTERVAL);
+    }
+    for at in &run_args.breaks {
+        let pc = match *at {
+            BreakAt::Pc(pc) => pc,
+            BreakAt::Offset(offset) => program.pc_at(code, offset).unwrap_or_else(|| {
+                let context = "Error occurred during setting breakpoints";
+                let err = format!("no instruction at or after offset {offset}");
+                Failure::new(FailureKind::Usage, context, err).exit()
+            }),
+        };
+        machine.add_breakpoint(pc);
+    }
+    let raw_input = run_args.raw_input.then(enter_raw_input).flatten();
+    let result = run(
+        &mut machine,
+        program,
+        code,
+        start,
+        run_args,
+        status.as_deref(),
+    );
+    drop(raw_input);
+    let coverage = machine.coverage().cloned();
+    let profile = machine.profile().cloned();
+    let timings = machine.timings().cloned();
+    // Dropping the machine writes out whatever the output format still holds.
+    drop(machine);
+    if run_args.visualize && expected.is_none() {
+        let _ = stdout().write_all(&captured);
+    }
+    if let Some(coverage) = coverage {
+        report_coverage(bf_code, program, &coverage);
+    }
+    if let Some(profile) = profile {
+        let profiles = LoopProfiles::new(program, &profile);
+        if run_args.stats {
+            report_stats(bf_code, program, &profile, &profiles);
+        }
+        if let Some(timings) = &timings {
+            report_timings(timings);
+        }
+        if let Some(path) = &run_args.profile {
+            let counts = profile::to_counts(&profile, &program.source_map());
+            fs::write(path, counts).unwrap_or_else(|err| {
+                let context = format!("Error occurred during writing profile {path}");
+                Failure::new(FailureKind::Io, context, err).exit()
+            });
+        }
+        if let Some(path) = &run_args.profile_folded {
+            let folded = profile::to_folded(&profiles, &program.source_map());
+            fs::write(path, folded).unwrap_or_else(|err| {
+                let context = format!("Error occurred during writing profile {path}");
+                Failure::new(FailureKind::Io, context, err).exit()
+            });
+        }
+    }
+    finish_run(result, run_args, program, expected, &captured);
+}
+
+/// Exits on a failed run, or on output that `--expect` did not expect.
+fn finish_run(
+    result: Result<(), BfRuntimeError>,
+    run_args: &RunArgs,
+    program: &Program,
+    expected: Option<Vec<u8>>,
+    captured: &[u8],
+) {
+    result.unwrap_or_else(|err| {
+        Failure::from_bf(
+            &err.into(),
+            &run_args.file,
+            &run_args.bf_code,
+            Some(program),
+        )
+        .exit()
+    });
+    if let (Some(expected), Some(path)) = (expected, &run_args.expect) {
+        if let Some(report) = suite::mismatch(&expected, captured) {
+            let context = format!("Output does not match {path}");
+            Failure::new(FailureKind::Mismatch, context, report)
+                .file(&run_args.file)
+                .exit();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+/// Set once at startup, before the arguments are fully parsed, so even
+/// argument errors come out in the requested format.
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+fn error_format(args: &[String]) -> ErrorFormat {
+    match args.windows(2).find(|pair| pair[0] == "--error-format") {
+        Some(pair) if pair[1] == "json" => ErrorFormat::Json,
+        _ => ErrorFormat::Text,
+    }
+}
+
+/// What went wrong, which decides the exit code so scripts can tell failures
+/// apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FailureKind {
+    /// `--deny-empty-loops` found something.
+    Refused,
+    /// The output differs from the `--expect` file.
+    Mismatch,
+    Usage,
+    Io,
+    Parse,
+    Runtime,
+    StepLimit,
+}
+
+impl FailureKind {
+    fn exit_code(self) -> i32 {
+        match self {
+            Self::Refused | Self::Mismatch => 1,
+            Self::Usage => 2,
+            Self::Io => 3,
+            Self::Parse => 4,
+            Self::Runtime => 5,
+            Self::StepLimit => 124,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Refused => "refused",
+            Self::Mismatch => "mismatch",
+            Self::Usage => "usage",
+            Self::Io => "io",
+            Self::Parse => "parse",
+            Self::Runtime => "runtime",
+            Self::StepLimit => "step_limit",
+        }
+    }
+}
+
+/// A fatal error of the run command, reported on stderr as text with a
+/// snippet of the program, or as one JSON object under `--error-format json`.
+struct Failure<'a> {
+    kind: FailureKind,
+    context: String,
+    message: String,
+    file: Option<&'a str>,
+    /// The program text and the char index the failure points at.
+    location: Option<(&'a str, usize)>,
+    pc: Option<usize>,
+    /// The loops a runtime error happened in, innermost first, with the
+    /// line and column of each `[` when the program is known.
+    loops: Vec<(LoopFrame, Option<(usize, usize)>)>,
+}
+
+impl<'a> Failure<'a> {
+    fn new(kind: FailureKind, context: impl Into<String>, message: impl Display) -> Self {
+        Self {
+            kind,
+            context: context.into(),
+            message: message.to_string(),
+            file: None,
+            location: None,
+            pc: None,
+            loops: vec![],
+        }
+    }
+
+    fn file(mut self, file: &'a str) -> Self {
+        self.file = Some(file);
+        self
+    }
+
+    /// Locates a parse or runtime error of `bf_code`, read from `file`;
+    /// runtime errors are located through `program`.
+    fn from_bf(err: &BfError, file: &'a str, bf_code: &'a str, program: Option<&Program>) -> Self {
+        let mut failure = match err {
+            BfError::Parse(parse) => {
+                let mut failure = Self::new(
+                    FailureKind::Parse,
+                    "Error occurred during parsing Brainfuck code",
+                    err,
+                );
+                failure.location = Some((bf_code, parse.index()));
+                failure
+            }
+            BfError::Runtime(runtime) => {
+                let root_cause = runtime.root_cause();
+                let kind = match root_cause {
+                    BfRuntimeError::StepLimitExceeded { .. } => FailureKind::StepLimit,
+                    BfRuntimeError::Io { .. } => FailureKind::Io,
+                    _ => FailureKind::Runtime,
+                };
+                let mut failure = Self::new(kind, "Error occurred during runtime", root_cause);
+                failure.pc = Some(runtime.pc());
+                failure.location = program
+                    .and_then(|program| program.span(runtime.pc()))
+                    .map(|span| (bf_code, span));
+                if let BfRuntimeError::InLoops { loops, .. } = runtime {
+                    failure.loops = loops
+                        .iter()
+                        .map(|frame| {
+                            let position = program
+                                .and(frame.span)
+                                .map(|span| diagnostics::line_col(bf_code, span));
+                            (*frame, position)
+                        })
+                        .collect();
+                }
+                failure
+            }
+            BfError::Io(_) => Self::new(FailureKind::Io, "Error occurred during I/O", err),
+        };
+        failure.file = Some(file);
+        failure
+    }
+
+    fn exit(self) -> ! {
+        match ERROR_FORMAT.get() {
+            Some(ErrorFormat::Json) => eprintln!("{}", self.to_json()),
+            _ => {
+                let message = format!("{}: {}", self.context, self.message);
+                match self.location {
+                    Some((source, index)) => {
+                        eprintln!("{}", diagnostics::render_snippet(source, index, &message))
+                    }
+                    None => eprintln!("{message}"),
+                }
+                for (frame, position) in &self.loops {
+                    let at = match position {
+                        Some((line, col)) => format!("{line}:{col} (instruction {})", frame.pc),
+                        None => format!("instruction {}", frame.pc),
+                    };
+                    eprintln!("  in the loop at {at}, iteration {}", frame.iteration);
+                }
+            }
+        }
+        leave_raw_input();
+        exit(self.kind.exit_code());
+    }
+
+    fn to_json(&self) -> String {
+        let mut fields = vec![
+            format!("\"kind\":{}", json_string(self.kind.name())),
+            format!("\"message\":{}", json_string(&self.message)),
+        ];
+        if let Some(file) = self.file {
+            fields.push(format!("\"file\":{}", json_string(file)));
+        }
+        if let Some((source, index)) = self.location {
+            let (line, col) = diagnostics::line_col(source, index);
+            fields.push(format!("\"line\":{line},\"column\":{col}"));
+        }
+        if let Some(pc) = self.pc {
+            fields.push(format!("\"pc\":{pc}"));
+        }
+        if !self.loops.is_empty() {
+            let loops: Vec<_> = self
+                .loops
+                .iter()
+                .map(|(frame, position)| {
+                    let position = match position {
+                        Some((line, col)) => format!(",\"line\":{line},\"column\":{col}"),
+                        None => String::new(),
+                    };
+                    format!(
+                        "{{\"pc\":{}{position},\"iteration\":{}}}",
+                        frame.pc, frame.iteration
+                    )
+                })
+                .collect();
+            fields.push(format!("\"loops\":[{}]", loops.join(",")));
+        }
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for ch in value.chars() {
+        match ch {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            ch if ch.is_control() => json.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => json.push(ch),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// `-v` logs the pipeline at debug level and `-vv` at trace level; without
+/// either, `RUST_LOG` decides as usual.
+fn init_logger(verbosity: u8) {
+    let mut logger = env_logger::Builder::from_default_env();
+    match verbosity {
+        0 => {}
+        1 => {
+            logger.filter_level(LevelFilter::Debug);
+        }
+        _ => {
+            logger.filter_level(LevelFilter::Trace);
+        }
+    }
+    logger.init();
+}
+
+/// Runs the program and every `--pipe` stage after it as one pipeline from
+/// stdin to stdout, each stage with its own tape.
+fn run_pipeline(run_args: &RunArgs, first: Program) {
+    let mut sources = vec![(run_args.file.as_str(), run_args.bf_code.as_str())];
+    let mut programs = vec![first];
+    for (file, bf_code) in &run_args.pipe {
+        let program = Program::parse_at(bf_code, &run_args.parse_options, run_args.opt_level())
+            .unwrap_or_else(|err| Failure::from_bf(&err.into(), file, bf_code, None).exit());
+        sources.push((file, bf_code));
+        programs.push(run_args.for_run(program));
+    }
+
+    let config = MachineConfig {
+        max_steps: run_args.max_steps,
+        ..run_args.machine_config()
+    };
+    let stages: Vec<_> = programs.iter().map(|program| (program, config)).collect();
+    let result = match run_args.cell_size {
+        CellSize::U8 => pipe_cells::<u8, _, _>(&stages, stdin(), stdout()),
+        CellSize::I32 => pipe_cells::<i32, _, _>(&stages, stdin(), stdout()),
+    };
+    result.unwrap_or_else(|err| {
+        let (file, bf_code) = sources[err.stage];
+        let mut failure = Failure::from_bf(
+            &err.source.into(),
+            file,
+            bf_code,
+            Some(&programs[err.stage]),
+        );
+        failure.context = format!(
+            "Error occurred during runtime in stage {} ({file})",
+            err.stage + 1
+        );
+        failure.exit()
+    });
+}
+
+/// Warns about `[]`-like loops on stderr, which are usually typos but are
+/// still run as written since they double as a halt idiom.
+fn report_empty_loops(bf_code: &str, code: &str, parse_options: &ParseOptions) -> usize {
+    let Ok(ast) = BfParser::parse_with_options(code, parse_options)
+        .and_then(|tokens| ast::from_tokens(&tokens))
+    else {
+        return 0;
+    };
+
+    let diagnostics = analyzer::check_empty_loops(&ast);
+    for diagnostic in &diagnostics {
+        let (line, col) = diagnostics::line_col(bf_code, diagnostic.index);
+        eprintln!(
+            "{}[{}]: {} at line {line}, col {col}",
+            diagnostic.severity, diagnostic.code, diagnostic.message
+        );
+    }
+    diagnostics.len()
+}
+
+/// Lists the commands that never ran on stderr.
+fn report_coverage(bf_code: &str, program: &Program, coverage: &Coverage) {
+    let commands: Vec<_> = program
+        .tokens()
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| !matches!(token, BfToken::NotCommand(_)))
+        .map(|(pc, _)| pc)
+        .collect();
+    let uncovered: Vec<_> = commands
+        .iter()
+        .copied()
+        .filter(|&pc| !coverage.is_covered(pc))
+        .collect();
+
+    eprintln!(
+        "coverage: {} of {} commands ran",
+        commands.len() - uncovered.len(),
+        commands.len()
+    );
+    for pc in uncovered {
+        let index = program.span(pc).unwrap_or(pc);
+        let command = bf_code.chars().nth(index).unwrap_or('?');
+        let (line, col) = diagnostics::line_col(bf_code, index);
+        eprintln!("never ran: '{command}' at line {line}, col {col}");
+    }
+}
+
+/// Prints the step count and a table of the loops that ran on stderr.
+fn report_stats(bf_code: &str, program: &Program, profile: &Profile, profiles: &LoopProfiles) {
+    eprintln!("steps: {}", profile.total_steps());
+    let loops: Vec<_> = profiles.loops().iter().filter(|l| l.entries > 0).collect();
+    if loops.is_empty() {
+        return;
+    }
+
+    eprintln!(
+        "{:<12} {:>10} {:>12} {:>12}",
+        "loop", "entries", "iterations", "self steps"
+    );
+    for l in loops {
+        let (line, col) = diagnostics::line_col(bf_code, program.span(l.start).unwrap_or(l.start));
+        eprintln!(
+            "{:<12} {:>10} {:>12} {:>12}",
+            format!("{line}:{col}"),
+            l.entries,
+            l.iterations,
+            l.self_steps
+        );
+    }
+}
+
+fn report_timings(timings: &Timings) {
+    eprintln!("{:<12} {:>12} {:>8}", "token", "est. time", "samples");
+    for (name, time, samples) in timings.estimates() {
+        eprintln!("{name:<12} {:>12} {samples:>8}", format!("{time:.2?}"));
+    }
+}
+
+struct RunArgs {
+    file: String,
+    bf_code: String,
+    parse_options: ParseOptions,
+    eof_mode: EofMode,
+    cell_size: CellSize,
+    max_steps: Option<usize>,
+    max_output: Option<usize>,
+    tape_policy: TapePolicy,
+    /// Cells on the tape, if not the default.
+    memory_size: Option<usize>,
+    max_memory: Option<usize>,
+    checkpoint: Option<String>,
+    checkpoint_every: Option<usize>,
+    record_input: Option<String>,
+    replay_input: Option<String>,
+    input: Option<String>,
+    /// The unescaped `--input-str` bytes.
+    input_str: Option<Vec<u8>>,
+    input_newlines: InputTranslation,
+    output_newlines: OutputTranslation,
+    expect: Option<String>,
+    output: Option<String>,
+    visualize: bool,
+    visualize_fps: u32,
+    visualize_steps: usize,
+    trace_json: Option<String>,
+    trace_json_limit: Option<usize>,
+    /// Where to write a core dump if the run fails, with `--core-dump`.
+    core_dump: Option<String>,
+    tee: Option<String>,
+    /// How long to pause after each output byte, with `--delay`.
+    delay: Option<Duration>,
+    /// Pause after each newline instead, with `--delay-per-line`.
+    delay_per_line: bool,
+    /// Put a terminal on stdin in raw mode for the run, with `--raw-input`.
+    raw_input: bool,
+    /// Show a status line on stderr while running, with `--progress`.
+    progress: bool,
+    /// Print the compiled program as JSON instead of running it, with
+    /// `--emit-json`.
+    emit_json: bool,
+    /// Where to write the program as JavaScript instead of running it, with
+    /// `--emit-js`.
+    emit_js: Option<String>,
+    /// Where to write the program as LLVM IR instead of running it, with
+    /// `--emit-llvm`.
+    emit_llvm: Option<String>,
+    /// Where parsed programs are cached as bytecode, with `--cache-dir`.
+    cache_dir: Option<String>,
+    cache_clear: bool,
+    output_format: OutputFormat,
+    random: bool,
+    seed: Option<u64>,
+    deny_empty_loops: bool,
+    coverage: bool,
+    profile_folded: Option<String>,
+    /// Where `--profile` writes how often each command ran.
+    profile: Option<String>,
+    stats: bool,
+    timings: bool,
+    verbosity: u8,
+    /// The `--init-tape` files and the cells they start at.
+    init_tape: Vec<(String, usize)>,
+    /// The `--pipe` stages as (file, code).
+    pipe: Vec<(String, String)>,
+    breaks: Vec<BreakAt>,
+}
+
+/// A `--break` location: a pc, or with `@` a byte offset in the source.
+#[derive(Debug, Clone, Copy)]
+enum BreakAt {
+    Pc(usize),
+    Offset(usize),
+}
+
+impl RunArgs {
+    /// Coverage and profile counts are reported per source command, so nothing
+    /// may be optimized away for them, and folding runs of `+` counts modulo
+    /// 256, which wider cells do not.
+    fn opt_level(&self) -> OptLevel {
+        if self.coverage || self.profile.is_some() || self.cell_size != CellSize::U8 {
+            OptLevel::O0
+        } else {
+            OptLevel::highest_for(&self.machine_config())
+        }
+    }
+
+    /// `program`, parsed at [`RunArgs::opt_level`], unrolled for the run if it
+    /// starts from pc 0 on a blank tape: not one loaded with `--init-tape`, and
+    /// not under `--checkpoint`, whose pcs must mean the same when resumed.
+    fn for_run(&self, program: Program) -> Program {
+        if self.opt_level() == OptLevel::O2
+            && self.init_tape.is_empty()
+            && self.checkpoint.is_none()
+        {
+            program.unrolled_for_fresh_tape(&self.machine_config())
+        } else {
+            program
+        }
+    }
+
+    /// The machine settings from the flags, but for the step limit, which
+    /// `run` applies itself. A growing tape starts no larger than its limit.
+    fn machine_config(&self) -> MachineConfig {
+        let defaults = MachineConfig::default();
+        let max_memory_cells = self.max_memory.unwrap_or(defaults.max_memory_cells);
+        let memory_size = match self.tape_policy {
+            TapePolicy::Wrap => self.memory_size.unwrap_or(defaults.memory_size),
+            TapePolicy::Grow => self
+                .memory_size
+                .unwrap_or(defaults.memory_size)
+                .min(max_memory_cells),
+        };
+        MachineConfig {
+            memory_size,
+            max_output_bytes: self.max_output,
+            eof_mode: self.eof_mode,
+            tape_policy: self.tape_policy,
+            max_memory_cells,
+            ..defaults
+        }
+    }
+}
+
+/// The `--tee` copy of the output; write errors name the file so a full disk
+/// is not mistaken for a broken terminal.
+struct TeeFile {
+    path: String,
+    file: File,
+}
+
+impl TeeFile {
+    fn name_error(&self, err: io::Error) -> io::Error {
+        io::Error::new(err.kind(), format!("{}: {err}", self.path))
+    }
+}
+
+impl Write for TeeFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf).map_err(|err| self.name_error(err))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush().map_err(|err| self.name_error(err))
+    }
+}
+
+struct Resume<C> {
+    pc: usize,
+    input_consumed: usize,
+    state: Option<BfSnapshot<C>>,
+}
+
+impl<C> Default for Resume<C> {
+    fn default() -> Self {
+        Self {
+            pc: 0,
+            input_consumed: 0,
+            state: None,
+        }
+    }
+}
+
+const CHECKPOINT_SLICE: usize = 1_000_000;
+/// The steps `--progress` runs between looking at the clock, and how often it
+/// redraws at most.
+const PROGRESS_SLICE: usize = 1_000_000;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+/// The steps each thread of a `--brainfork` run takes per turn.
+const THREAD_QUANTUM: usize = 1_000;
+
+fn run<R: Read, W: Write, C: Cell>(
+    machine: &mut BfMachine<R, W, C>,
+    program: &Program,
+    code: &str,
+    start: Resume<C>,
+    run_args: &RunArgs,
+    status: Option<&RefCell<StatusLine<Stderr>>>,
+) -> Result<(), BfRuntimeError> {
+    if let Some(state) = start.state {
+        machine.restore(state);
+    }
+    if run_args.visualize {
+        return visualize(machine, program, start.pc, run_args);
+    }
+    if let Some(path) = &run_args.trace_json {
+        if let Some(max_steps) = run_args.max_steps {
+            machine.set_step_limit(max_steps);
+        }
+        return run_traced(machine, program, path, run_args.trace_json_limit);
+    }
+    if let Some(path) = &run_args.core_dump {
+        if let Some(max_steps) = run_args.max_steps {
+            machine.set_step_limit(max_steps);
+        }
+        return run_dumping_core(machine, program, code, start.pc, path);
+    }
+    if !run_args.breaks.is_empty() {
+        if let Some(max_steps) = run_args.max_steps {
+            machine.set_step_limit(max_steps);
+        }
+        return run_with_breaks(machine, program);
+    }
+    if let Some(status) = status {
+        let mut drawn: Option<Instant> = None;
+        let result = progress::run_with_progress(
+            machine,
+            program,
+            start.pc,
+            PROGRESS_SLICE,
+            run_args.max_steps,
+            |progress| {
+                if drawn.is_none_or(|drawn| drawn.elapsed() >= PROGRESS_INTERVAL) {
+                    let _ = status
+                        .borrow_mut()
+                        .show(&progress::format_progress(progress));
+                    drawn = Some(Instant::now());
+                }
+            },
+        );
+        let _ = status.borrow_mut().erase();
+        return result;
+    }
+    if run_args.parse_options.brainfork {
+        return fork::run_threads(machine, program, THREAD_QUANTUM, run_args.max_steps);
+    }
+    let Some(path) = &run_args.checkpoint else {
+        if let Some(max_steps) = run_args.max_steps {
+            machine.set_step_limit(max_steps);
+        }
+        return machine.run_from(program, start.pc);
+    };
+
+    let interrupted = stop_at_interrupt();
+    let slice = run_args.checkpoint_every.unwrap_or(CHECKPOINT_SLICE);
+    let mut remaining = run_args.max_steps.unwrap_or(usize::MAX);
+    let mut pc = start.pc;
+    loop {
+        let steps = slice.min(remaining);
+        machine.set_step_limit(steps);
+        match machine.run_from(program, pc) {
+            Ok(()) => {
+                let _ = fs::remove_file(path);
+                return Ok(());
+            }
+            Err(BfRuntimeError::StepLimitExceeded { pc: next }) => {
+                pc = next;
+                remaining -= steps;
+                let consumed = start.input_consumed + machine.bytes_read();
+                let state = machine.snapshot();
+                save_checkpoint(path, program, run_args, pc, consumed, state).unwrap_or_else(
+                    |err| {
+                        let context = format!("Error occurred during saving checkpoint {path}");
+                        Failure::new(FailureKind::Io, context, err).exit()
+                    },
+                );
+
+                if remaining == 0 || interrupted.load(Ordering::SeqCst) {
+                    let _ = stdout().flush();
+                    eprintln!("Checkpoint saved to {path}");
+                }
+                if remaining == 0 {
+                    return Err(BfRuntimeError::StepLimitExceeded { pc });
+                }
+                if interrupted.load(Ordering::SeqCst) {
+                    leave_raw_input();
+                    exit(130);
+                }
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Whether Ctrl-C ends the process at once once its handler is installed,
+/// rather than leaving the run to stop at [`interrupt_flag`].
+static EXIT_ON_INTERRUPT: AtomicBool = AtomicBool::new(true);
+
+/// The terminal `--raw-input` put in raw mode, until the run ends.
+#[cfg(unix)]
+static RAW_INPUT: Mutex<Option<RawMode<StdinTerminal>>> = Mutex::new(None);
+
+/// The flag Ctrl-C sets, installing the handler that sets it on first use.
+/// The handler puts a raw terminal back before anything else.
+fn interrupt_flag() -> Arc<AtomicBool> {
+    static INTERRUPTED: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+    Arc::clone(INTERRUPTED.get_or_init(|| {
+        let interrupted = Arc::new(AtomicBool::new(false));
+        let handler_flag = Arc::clone(&interrupted);
+        ctrlc::set_handler(move || {
+            handler_flag.store(true, Ordering::SeqCst);
+            leave_raw_input();
+            if EXIT_ON_INTERRUPT.load(Ordering::SeqCst) {
+                exit(130);
+            }
+        })
+        .unwrap_or_else(|err| {
+            let context = "Error occurred during installing the interrupt handler";
+            Failure::new(FailureKind::Io, context, err).exit()
+        });
+        interrupted
+    }))
+}
+
+/// Like [`interrupt_flag`], for runs that check the flag and stop by
+/// themselves.
+fn stop_at_interrupt() -> Arc<AtomicBool> {
+    EXIT_ON_INTERRUPT.store(false, Ordering::SeqCst);
+    interrupt_flag()
+}
+
+/// Leaves raw mode when dropped, also on errors and panics; exits that skip
+/// it call [`leave_raw_input`] themselves.
+struct RawInput;
+
+impl Drop for RawInput {
+    fn drop(&mut self) {
+        leave_raw_input();
+    }
+}
+
+/// Puts the terminal on stdin in raw mode so `,` gets each key as it is
+/// pressed. Without a terminal there this warns and does nothing.
+#[cfg(unix)]
+fn enter_raw_input() -> Option<RawInput> {
+    if !stdin().is_terminal() {
+        eprintln!("warning: --raw-input is ignored as stdin is not a terminal");
+        return None;
+    }
+    let raw_mode = RawMode::enter(StdinTerminal).unwrap_or_else(|err| {
+        let context = "Error occurred during switching the terminal to raw mode";
+        Failure::new(FailureKind::Io, context, err).exit()
     });
+    *RAW_INPUT.lock().unwrap_or_else(PoisonError::into_inner) = Some(raw_mode);
+    interrupt_flag();
+    Some(RawInput)
 }
 
-fn parse_args(args: &[String]) -> Result<String, Box<dyn Error>> {
-    if args.len() < 2 {
-        return Err("Usage: bf-rust.exe [filename.(b/bf)] <--force-run>".into());
+#[cfg(not(unix))]
+fn enter_raw_input() -> Option<RawInput> {
+    eprintln!("warning: --raw-input is only supported on Unix terminals");
+    None
+}
+
+/// Restores the terminal `--raw-input` changed, if it still is in raw mode.
+fn leave_raw_input() {
+    #[cfg(unix)]
+    drop(
+        RAW_INPUT
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take(),
+    );
+}
+
+/// Runs `program` from breakpoint to breakpoint, printing the machine at each.
+/// The step limit applies to each stretch between two breakpoints.
+fn run_with_breaks<R: Read, W: Write, C: Cell>(
+    machine: &mut BfMachine<R, W, C>,
+    program: &Program,
+) -> Result<(), BfRuntimeError> {
+    let mut outcome = machine.run_until_break(program)?;
+    while let RunOutcome::Breakpoint { pc } = outcome {
+        let _ = stdout().flush();
+        eprintln!("Breakpoint at pc {pc}: {machine}");
+        outcome = machine.resume_until_break(program, pc)?;
+    }
+    Ok(())
+}
+
+/// Runs `program` in slices of `--visualize-steps` steps, drawing the tape
+/// before each slice at no more than `--visualize-fps` frames a second.
+fn visualize<R: Read, W: Write, C: Cell>(
+    machine: &mut BfMachine<R, W, C>,
+    program: &Program,
+    mut pc: usize,
+    run_args: &RunArgs,
+) -> Result<(), BfRuntimeError> {
+    const WINDOW: usize = 16;
+
+    let frame_time = Duration::from_secs(1) / run_args.visualize_fps;
+    let mut screen = Screen::new(stdout());
+    let mut remaining = run_args.max_steps.unwrap_or(usize::MAX);
+    let mut steps = 0;
+    loop {
+        let frame =
+            visualize::render_frame(machine.tape(), machine.cursor(), program, pc, steps, WINDOW);
+        let _ = screen.draw(&frame);
+        if pc >= program.tokens().len() {
+            return Ok(());
+        }
+        if remaining == 0 {
+            return Err(BfRuntimeError::StepLimitExceeded { pc });
+        }
+
+        thread::sleep(frame_time);
+        // Single steps, so the frame counts exactly what ran.
+        machine.set_step_limit(1);
+        for _ in 0..run_args.visualize_steps.min(remaining) {
+            match machine.run_from(program, pc) {
+                Ok(()) => pc = program.tokens().len(),
+                Err(BfRuntimeError::StepLimitExceeded { pc: next }) => pc = next,
+                Err(err) => return Err(err),
+            }
+            remaining -= 1;
+            steps += 1;
+            if pc >= program.tokens().len() {
+                break;
+            }
+        }
     }
+}
 
-    let file_path_str = &args[1];
-    let force_run = args.get(2).is_some();
+#[cfg(feature = "serde")]
+fn load_checkpoint<C: Cell>(
+    path: &str,
+    program: &Program,
+    run_args: &RunArgs,
+) -> Result<Resume<C>, Box<dyn Error>> {
+    use bf_rust::bf::checkpoint::Checkpoint;
 
+    let saved = Checkpoint::<C>::load(path)?;
+    saved.check(program, &run_args.machine_config())?;
+    Ok(Resume {
+        pc: saved.pc,
+        input_consumed: saved.input_consumed,
+        state: Some(saved.state),
+    })
+}
+
+#[cfg(feature = "serde")]
+fn save_checkpoint<C: Cell>(
+    path: &str,
+    program: &Program,
+    run_args: &RunArgs,
+    pc: usize,
+    input_consumed: usize,
+    state: BfSnapshot<C>,
+) -> Result<(), Box<dyn Error>> {
+    use bf_rust::bf::checkpoint::{self, Checkpoint};
+
+    let saved = Checkpoint {
+        program_hash: checkpoint::program_hash::<C>(program, &run_args.machine_config()),
+        pc,
+        input_consumed,
+        state,
+    };
+    Ok(saved.save(path)?)
+}
+
+/// Runs `program` writing a JSON trace to `path`, `limit` records at most.
+#[cfg(feature = "serde")]
+fn run_traced<R: Read, W: Write, C: Cell>(
+    machine: &mut BfMachine<R, W, C>,
+    program: &Program,
+    path: &str,
+    limit: Option<usize>,
+) -> Result<(), BfRuntimeError> {
+    use bf_rust::bf::trace::JsonTrace;
+
+    let context = format!("Error occurred during writing trace {path}");
+    let file = File::create(path)
+        .unwrap_or_else(|err| Failure::new(FailureKind::Io, context.clone(), err).exit());
+    let mut trace = JsonTrace::new(file).with_limit(limit.unwrap_or(usize::MAX));
+    let result = machine.run_traced(program, &mut trace);
+    trace
+        .into_inner()
+        .unwrap_or_else(|err| Failure::new(FailureKind::Io, context, err).exit());
+    result
+}
+
+/// Steps kept for the trace in a core dump.
+#[cfg(feature = "serde")]
+const CORE_DUMP_STEPS: usize = 32;
+
+/// Runs `program`, writing a core dump to `path` if it fails.
+#[cfg(feature = "serde")]
+fn run_dumping_core<R: Read, W: Write, C: Cell>(
+    machine: &mut BfMachine<R, W, C>,
+    program: &Program,
+    code: &str,
+    pc: usize,
+    path: &str,
+) -> Result<(), BfRuntimeError> {
+    use bf_rust::bf::core_dump::{CoreDump, RecentSteps};
+
+    let mut recent = RecentSteps::new(CORE_DUMP_STEPS);
+    let Err(err) = machine.run_recording(program, pc, &mut recent) else {
+        return Ok(());
+    };
+    let _ = stdout().flush();
+    match CoreDump::new(code, &err, machine, &recent).save(path) {
+        Ok(()) => eprintln!("Core dumped to {path}"),
+        Err(save_err) => eprintln!("Could not write core dump {path}: {save_err}"),
+    }
+    Err(err)
+}
+
+#[cfg(not(feature = "serde"))]
+fn run_dumping_core<R: Read, W: Write, C: Cell>(
+    _machine: &mut BfMachine<R, W, C>,
+    _program: &Program,
+    _code: &str,
+    _pc: usize,
+    _path: &str,
+) -> Result<(), BfRuntimeError> {
+    let message = "core dumps require the serde feature";
+    Failure::new(
+        FailureKind::Usage,
+        "Error occurred during parsing arguments",
+        message,
+    )
+    .exit()
+}
+
+#[cfg(not(feature = "serde"))]
+fn run_traced<R: Read, W: Write, C: Cell>(
+    _machine: &mut BfMachine<R, W, C>,
+    _program: &Program,
+    _path: &str,
+    _limit: Option<usize>,
+) -> Result<(), BfRuntimeError> {
+    let message = "JSON traces require the serde feature";
+    Failure::new(
+        FailureKind::Usage,
+        "Error occurred during parsing arguments",
+        message,
+    )
+    .exit()
+}
+
+#[cfg(not(feature = "serde"))]
+fn load_checkpoint<C: Cell>(
+    _path: &str,
+    _program: &Program,
+    _run_args: &RunArgs,
+) -> Result<Resume<C>, Box<dyn Error>> {
+    Err("checkpoints require the serde feature".into())
+}
+
+#[cfg(not(feature = "serde"))]
+fn save_checkpoint<C: Cell>(
+    _path: &str,
+    _program: &Program,
+    _run_args: &RunArgs,
+    _pc: usize,
+    _input_consumed: usize,
+    _state: BfSnapshot<C>,
+) -> Result<(), Box<dyn Error>> {
+    Err("checkpoints require the serde feature".into())
+}
+
+fn parse_args(args: &[String]) -> Result<RunArgs, Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe [run] [filename.(b/bf)|-e code] <--force-run> <--brainloller> <--emit-json> <--emit-js out.js> <--emit-llvm out.ll> <--line-comments> <--bang-input> <--multi-tape> <--brainfork> <--eof-mode error|zero|unchanged|minus-one> <--cell-size u8|i32> <--max-steps N> <--max-output N> <--tape wrap|grow> <--memory-size N> <--max-memory N> <--checkpoint file.ckpt> <--checkpoint-every N> <--record-input file> <--replay-input file> <--input file> <--input-str text> <--input-newlines none|crlf-to-lf|lf-to-cr> <--output-newlines none|lf-to-crlf|cr-to-lf> <--expect file> <--output file> <--visualize> <--visualize-fps N> <--visualize-steps N> <--trace-json file> <--trace-json-limit N> <--core-dump file> <--tee file> <--delay ms> <--delay-per-line> <--raw-input> <--progress> <--cache-dir dir> <--cache-clear> <--output-format raw|hex|escaped> <--random> <--seed N> <--deny-empty-loops> <--coverage> <--profile file> <--profile-folded file> <--stats> <--timings> <-v|-vv> <--error-format text|json> <--init-tape file[:offset]>... <--pipe filename.(b/bf)>... <--break pc|@offset,...>";
+
+    let file_path_str = args.first().ok_or(USAGE)?;
+    // `-e` takes the program from the next argument instead of a file.
+    let (inline_code, flags) = match file_path_str.as_str() {
+        "-e" => (
+            Some(args.get(1).ok_or("-e requires Brainfuck code")?),
+            &args[2..],
+        ),
+        _ => (None, &args[1..]),
+    };
+    let mut force_run = false;
+    let mut brainloller = false;
+    let mut emit_json = false;
+    let mut emit_js = None;
+    let mut emit_llvm = None;
+    let mut parse_options = ParseOptions::default();
+    let mut eof_mode = None;
+    let mut cell_size = None;
+    let mut max_steps = None;
+    let mut max_output = None;
+    let mut tape_policy = None;
+    let mut memory_size = None;
+    let mut max_memory = None;
+    let mut checkpoint = None;
+    let mut checkpoint_every = None;
+    let mut record_input = None;
+    let mut replay_input = None;
+    let mut input = None;
+    let mut input_str = None;
+    let mut input_newlines = InputTranslation::default();
+    let mut output_newlines = OutputTranslation::default();
+    let mut expect = None;
+    let mut output = None;
+    let mut visualize = false;
+    let mut visualize_fps = 10;
+    let mut visualize_steps = 1;
+    let mut trace_json = None;
+    let mut trace_json_limit = None;
+    let mut core_dump = None;
+    let mut tee = None;
+    let mut delay = None;
+    let mut delay_per_line = false;
+    let mut raw_input = false;
+    let mut progress = false;
+    let mut cache_dir = None;
+    let mut cache_clear = false;
+    let mut output_format = OutputFormat::default();
+    let mut random = false;
+    let mut seed = None;
+    let mut deny_empty_loops = false;
+    let mut coverage = false;
+    let mut profile_folded = None;
+    let mut profile = None;
+    let mut stats = false;
+    let mut timings = false;
+    let mut verbosity = 0;
+    let mut init_tape = vec![];
+    let mut pipe_files = vec![];
+    let mut breaks = vec![];
+
+    let mut rest = flags.iter();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--bang-input" => parse_options.bang_input = true,
+            "--eof-mode" => {
+                eof_mode = match rest.next().map(String::as_str) {
+                    Some("error") => Some(EofMode::Error),
+                    Some("zero") => Some(EofMode::Zero),
+                    Some("unchanged") => Some(EofMode::Unchanged),
+                    Some("minus-one") => Some(EofMode::MinusOne),
+                    _ => {
+                        return Err(format!(
+                            "--eof-mode requires error, zero, unchanged or minus-one. {USAGE}"
+                        )
+                        .into())
+                    }
+                }
+            }
+            "--cell-size" => {
+                cell_size = match rest.next().map(String::as_str) {
+                    Some("u8") => Some(CellSize::U8),
+                    Some("i32") => Some(CellSize::I32),
+                    _ => return Err(format!("--cell-size requires u8 or i32. {USAGE}").into()),
+                }
+            }
+            "--max-steps" => max_steps = Some(parse_count(rest.next(), "--max-steps")?),
+            "--max-output" => max_output = Some(parse_count(rest.next(), "--max-output")?),
+            "--tape" => {
+                tape_policy = match rest.next().map(String::as_str) {
+                    Some("wrap") => Some(TapePolicy::Wrap),
+                    Some("grow") => Some(TapePolicy::Grow),
+                    _ => return Err(format!("--tape requires wrap or grow. {USAGE}").into()),
+                }
+            }
+            "--max-memory" => max_memory = Some(parse_count(rest.next(), "--max-memory")?),
+            "--memory-size" => memory_size = Some(parse_count(rest.next(), "--memory-size")?),
+            "--checkpoint" => {
+                checkpoint = Some(rest.next().ok_or("--checkpoint requires a file")?.clone())
+            }
+            "--checkpoint-every" => {
+                checkpoint_every = Some(parse_count(rest.next(), "--checkpoint-every")?)
+            }
+            "--record-input" => {
+                record_input = Some(rest.next().ok_or("--record-input requires a file")?.clone())
+            }
+            "--replay-input" => {
+                replay_input = Some(rest.next().ok_or("--replay-input requires a file")?.clone())
+            }
+            "--input" => input = Some(rest.next().ok_or("--input requires a file")?.clone()),
+            "--input-str" => {
+                let text = rest.next().ok_or("--input-str requires text")?;
+                input_str =
+                    Some(bf_io::unescape(text).map_err(|err| format!("--input-str: {err}"))?)
+            }
+            "--input-newlines" => {
+                input_newlines = match rest.next().map(String::as_str) {
+                    Some("none") => InputTranslation::None,
+                    Some("crlf-to-lf") => InputTranslation::CrlfToLf,
+                    Some("lf-to-cr") => InputTranslation::LfToCr,
+                    _ => {
+                        return Err(format!(
+                            "--input-newlines requires none, crlf-to-lf or lf-to-cr. {USAGE}"
+                        )
+                        .into())
+                    }
+                }
+            }
+            "--output-newlines" => {
+                output_newlines = match rest.next().map(String::as_str) {
+                    Some("none") => OutputTranslation::None,
+                    Some("lf-to-crlf") => OutputTranslation::LfToCrlf,
+                    Some("cr-to-lf") => OutputTranslation::CrToLf,
+                    _ => {
+                        return Err(format!(
+                            "--output-newlines requires none, lf-to-crlf or cr-to-lf. {USAGE}"
+                        )
+                        .into())
+                    }
+                }
+            }
+            "--expect" => expect = Some(rest.next().ok_or("--expect requires a file")?.clone()),
+            "--output" => output = Some(rest.next().ok_or("--output requires a file")?.clone()),
+            "--visualize" => visualize = true,
+            "--trace-json" => {
+                trace_json = Some(rest.next().ok_or("--trace-json requires a file")?.clone())
+            }
+            "--trace-json-limit" => {
+                trace_json_limit = Some(parse_count(rest.next(), "--trace-json-limit")?)
+            }
+            "--core-dump" => {
+                core_dump = Some(rest.next().ok_or("--core-dump requires a file")?.clone())
+            }
+            "--visualize-fps" => {
+                visualize_fps = parse_count(rest.next(), "--visualize-fps")?
+                    .try_into()
+                    .map_err(|_| "--visualize-fps is too large")?
+            }
+            "--visualize-steps" => visualize_steps = parse_count(rest.next(), "--visualize-steps")?,
+            "--output-format" => {
+                output_format = match rest.next().map(String::as_str) {
+                    Some("raw") => OutputFormat::Raw,
+                    Some("hex") => OutputFormat::Hex,
+                    Some("escaped") => OutputFormat::Escaped,
+                    _ => {
+                        return Err(format!(
+                            "--output-format requires raw, hex or escaped. {USAGE}"
+                        )
+                        .into())
+                    }
+                }
+            }
+            "--random" => random = true,
+            "--deny-empty-loops" => deny_empty_loops = true,
+            "--coverage" => coverage = true,
+            "--stats" => stats = true,
+            "--timings" => timings = true,
+            "--error-format" => match rest.next().map(String::as_str) {
+                Some("text" | "json") => {}
+                _ => return Err(format!("--error-format requires text or json. {USAGE}").into()),
+            },
+            "-v" => verbosity = 1,
+            "-vv" => verbosity = 2,
+            "--init-tape" => {
+                init_tape.push(parse_init_tape(rest.next())?);
+            }
+            "--profile-folded" => {
+                profile_folded = Some(
+                    rest.next()
+                        .ok_or("--profile-folded requires a file")?
+                        .clone(),
+                )
+            }
+            "--profile" => profile = Some(rest.next().ok_or("--profile requires a file")?.clone()),
+            "--pipe" => pipe_files.push(rest.next().ok_or("--pipe requires a file")?),
+            "--seed" => {
+                random = true;
+                seed = Some(
+                    rest.next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or("--seed requires a number")?,
+                );
+            }
+            "--tee" => tee = Some(rest.next().ok_or("--tee requires a file")?.clone()),
+            "--delay" => {
+                delay = Some(Duration::from_millis(
+                    rest.next()
+                        .and_then(|value| value.parse().ok())
+                        .ok_or("--delay requires a number of milliseconds")?,
+                ))
+            }
+            "--delay-per-line" => delay_per_line = true,
+            "--raw-input" => raw_input = true,
+            "--progress" => progress = true,
+            "--cache-dir" => {
+                cache_dir = Some(
+                    rest.next()
+                        .ok_or("--cache-dir requires a directory")?
+                        .clone(),
+                )
+            }
+            "--cache-clear" => cache_clear = true,
+            "--brainloller" => brainloller = true,
+            "--emit-json" => emit_json = true,
+            "--emit-js" => emit_js = Some(rest.next().ok_or("--emit-js requires a file")?.clone()),
+            "--emit-llvm" => {
+                emit_llvm = Some(rest.next().ok_or("--emit-llvm requires a file")?.clone())
+            }
+            "--break" => breaks.extend(parse_breaks(rest.next())?),
+            _ => force_run = true,
+        }
+    }
+
+    if coverage && (checkpoint.is_some() || !pipe_files.is_empty()) {
+        return Err("--coverage cannot be combined with --checkpoint or --pipe".into());
+    }
+    if timings && !stats {
+        return Err("--timings requires --stats".into());
+    }
+    if (profile_folded.is_some() || profile.is_some() || stats) && !pipe_files.is_empty() {
+        return Err(
+            "--profile, --profile-folded and --stats cannot be combined with --pipe".into(),
+        );
+    }
+    if !init_tape.is_empty() && !pipe_files.is_empty() {
+        return Err("--init-tape cannot be combined with --pipe".into());
+    }
+    if cache_clear && cache_dir.is_none() {
+        return Err("--cache-clear requires --cache-dir".into());
+    }
+    if input.is_some() && replay_input.is_some() {
+        return Err("--input cannot be combined with --replay-input".into());
+    }
+    if input_str.is_some() && (input.is_some() || replay_input.is_some()) {
+        return Err("--input-str cannot be combined with --input or --replay-input".into());
+    }
+    if (input.is_some() || input_str.is_some() || expect.is_some() || output.is_some())
+        && !pipe_files.is_empty()
+    {
+        return Err(
+            "--input, --input-str, --expect and --output cannot be combined with --pipe".into(),
+        );
+    }
+    if (input_newlines != InputTranslation::None || output_newlines != OutputTranslation::None)
+        && !pipe_files.is_empty()
+    {
+        return Err("--input-newlines and --output-newlines cannot be combined with --pipe".into());
+    }
+    if delay_per_line && delay.is_none() {
+        return Err("--delay-per-line requires --delay".into());
+    }
+    if (delay.is_some() || raw_input) && !pipe_files.is_empty() {
+        return Err("--delay and --raw-input cannot be combined with --pipe".into());
+    }
+    if visualize && (checkpoint.is_some() || !pipe_files.is_empty()) {
+        return Err("--visualize cannot be combined with --checkpoint or --pipe".into());
+    }
+    if trace_json.is_some() && (checkpoint.is_some() || !pipe_files.is_empty() || visualize) {
+        return Err(
+            "--trace-json cannot be combined with --checkpoint, --pipe or --visualize".into(),
+        );
+    }
+    if !breaks.is_empty()
+        && (checkpoint.is_some() || !pipe_files.is_empty() || visualize || trace_json.is_some())
+    {
+        return Err(
+            "--break cannot be combined with --checkpoint, --pipe, --visualize or --trace-json"
+                .into(),
+        );
+    }
+    if core_dump.is_some()
+        && (checkpoint.is_some()
+            || !pipe_files.is_empty()
+            || visualize
+            || trace_json.is_some()
+            || !breaks.is_empty())
+    {
+        return Err(
+            "--core-dump cannot be combined with --checkpoint, --pipe, --visualize, --trace-json or --break"
+                .into(),
+        );
+    }
+    if progress
+        && (checkpoint.is_some()
+            || !pipe_files.is_empty()
+            || visualize
+            || trace_json.is_some()
+            || !breaks.is_empty()
+            || core_dump.is_some())
+    {
+        return Err(
+            "--progress cannot be combined with --checkpoint, --pipe, --visualize, --trace-json, --break or --core-dump"
+                .into(),
+        );
+    }
+    if parse_options.brainfork
+        && (checkpoint.is_some()
+            || !pipe_files.is_empty()
+            || visualize
+            || trace_json.is_some()
+            || !breaks.is_empty()
+            || core_dump.is_some()
+            || progress)
+    {
+        return Err(
+            "--brainfork cannot be combined with --checkpoint, --pipe, --visualize, --trace-json, --break, --core-dump or --progress"
+                .into(),
+        );
+    }
+    if visualize && !stdout().is_terminal() {
+        return Err("--visualize draws on the terminal, but stdout is not one".into());
+    }
+
+    let bf_code = match inline_code {
+        Some(code) => code.clone(),
+        None if brainloller || has_extension(file_path_str, "png") => {
+            read_brainloller(file_path_str)?
+        }
+        None if has_extension(file_path_str, "json") => read_json_program(file_path_str)?,
+        None => read_bf_file(file_path_str, force_run)?,
+    };
+    // What the program declares in `#! bf:` lines applies unless a flag says
+    // otherwise.
+    let header =
+        directives::parse_header(&bf_code).map_err(|err| format!("{file_path_str}: {err}"))?;
+    for (line, key) in &header.unknown {
+        eprintln!("warning: {file_path_str}: unknown directive `{key}` on line {line}");
+    }
+
+    // Inline input always runs out, so reading past it is not an error unless
+    // asked for.
+    let eof_mode = match (eof_mode.or(header.eof_mode), &input_str) {
+        (Some(eof_mode), _) => eof_mode,
+        (None, Some(_)) => EofMode::Zero,
+        (None, None) => EofMode::default(),
+    };
+
+    if random {
+        parse_options.extensions.insert('?', 0);
+    }
+
+    let mut pipe = vec![];
+    for file in pipe_files {
+        pipe.push((file.clone(), read_bf_file(file, force_run)?));
+    }
+
+    let tape_policy = tape_policy.or(header.tape_policy).unwrap_or_default();
+    let cell_size = cell_size.or(header.cell_size).unwrap_or_default();
+    // The generated code keeps its tape in bytes.
+    if cell_size == CellSize::I32 && (emit_js.is_some() || emit_llvm.is_some()) {
+        return Err("32-bit cells cannot be combined with --emit-js or --emit-llvm".into());
+    }
+
+    Ok(RunArgs {
+        file: file_path_str.clone(),
+        bf_code,
+        parse_options,
+        eof_mode,
+        cell_size,
+        max_steps: max_steps.or(header.max_steps),
+        max_output: max_output.or(header.max_output_bytes),
+        tape_policy,
+        memory_size: memory_size.or(header.memory_size),
+        max_memory: max_memory.or(header.max_memory_cells),
+        checkpoint,
+        checkpoint_every,
+        record_input,
+        replay_input,
+        input,
+        input_str,
+        input_newlines,
+        output_newlines,
+        expect,
+        output,
+        visualize,
+        visualize_fps,
+        visualize_steps,
+        trace_json,
+        trace_json_limit,
+        core_dump,
+        tee,
+        delay,
+        delay_per_line,
+        raw_input,
+        progress,
+        emit_json,
+        emit_js,
+        emit_llvm,
+        cache_dir,
+        cache_clear,
+        output_format,
+        random,
+        seed,
+        deny_empty_loops,
+        coverage,
+        profile_folded,
+        profile,
+        stats,
+        timings,
+        verbosity,
+        init_tape,
+        pipe,
+        breaks,
+    })
+}
+
+/// Splits a comma-separated `--break` list of pcs and `@offset`s.
+fn parse_breaks(value: Option<&String>) -> Result<Vec<BreakAt>, Box<dyn Error>> {
+    const MESSAGE: &str = "--break requires a comma-separated list of pcs or @offsets";
+
+    let value = value.ok_or(MESSAGE)?;
+    value
+        .split(',')
+        .map(|item| {
+            let at = match item.strip_prefix('@') {
+                Some(offset) => offset.parse().map(BreakAt::Offset),
+                None => item.parse().map(BreakAt::Pc),
+            };
+            at.map_err(|_| MESSAGE.into())
+        })
+        .collect()
+}
+
+fn parse_count(value: Option<&String>, flag: &str) -> Result<usize, Box<dyn Error>> {
+    match value.map(|value| value.parse()) {
+        Some(Ok(count)) if count > 0 => Ok(count),
+        _ => Err(format!("{flag} requires a positive number").into()),
+    }
+}
+
+/// Splits `file[:offset]`; a suffix that is not a number is part of the path.
+fn parse_init_tape(value: Option<&String>) -> Result<(String, usize), Box<dyn Error>> {
+    let value = value.ok_or("--init-tape requires a file")?;
+    if let Some((path, offset)) = value.rsplit_once(':') {
+        if let (false, Ok(offset)) = (path.is_empty(), offset.parse()) {
+            return Ok((path.to_string(), offset));
+        }
+    }
+    Ok((value.clone(), 0))
+}
+
+fn read_bf_file(file_path_str: &str, force_run: bool) -> Result<String, Box<dyn Error>> {
+    check_extension(file_path_str, force_run)?;
+    Ok(read_source(file_path_str)?)
+}
+
+fn read_source(file_path_str: &str) -> Result<String, BfError> {
+    fs::read_to_string(file_path_str)
+        .map_err(|err| io::Error::new(err.kind(), format!("{file_path_str}: {err}")).into())
+}
+
+fn has_extension(file_path_str: &str, extension: &str) -> bool {
+    Path::new(file_path_str)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+}
+
+/// Reads a BrainLoller image as the Brainfuck code it spells out.
+#[cfg(feature = "image")]
+fn read_brainloller(file_path_str: &str) -> Result<String, Box<dyn Error>> {
+    use bf_rust::bf::dialects::brainloller;
+
+    let tokens = brainloller::parse_image_file(file_path_str)
+        .map_err(|err| format!("{file_path_str}: {err}"))?;
+    Ok(BfParser::to_source(&tokens))
+}
+
+#[cfg(not(feature = "image"))]
+fn read_brainloller(file_path_str: &str) -> Result<String, Box<dyn Error>> {
+    Err(format!("{file_path_str}: BrainLoller images need the `image` feature").into())
+}
+
+/// Reads a program written by `--emit-json` as Brainfuck code. Deserializing
+/// checks the tokens and jump table, so a hand-edited file is refused rather
+/// than run.
+#[cfg(feature = "serde")]
+fn read_json_program(file_path_str: &str) -> Result<String, Box<dyn Error>> {
+    let json = read_source(file_path_str)?;
+    let program: Program =
+        serde_json::from_str(&json).map_err(|err| format!("{file_path_str}: {err}"))?;
+    Ok(program.to_string())
+}
+
+#[cfg(not(feature = "serde"))]
+fn read_json_program(file_path_str: &str) -> Result<String, Box<dyn Error>> {
+    Err(format!("{file_path_str}: JSON programs need the `serde` feature").into())
+}
+
+/// `program` as JSON, for `--emit-json` and `compile --target json`.
+#[cfg(feature = "serde")]
+fn program_json(program: &Program) -> Result<String, Box<dyn Error>> {
+    Ok(serde_json::to_string(program)?)
+}
+
+#[cfg(not(feature = "serde"))]
+fn program_json(_program: &Program) -> Result<String, Box<dyn Error>> {
+    Err("JSON programs need the `serde` feature".into())
+}
+
+/// `program` as a JavaScript function for a machine set up like `config`, for
+/// `--emit-js` and `compile --target js`.
+#[cfg(feature = "codegen-js")]
+fn program_js(program: &Program, config: &MachineConfig) -> Result<String, Box<dyn Error>> {
+    use bf_rust::bf::codegen::js::{self, JsOptions};
+
+    let options = JsOptions {
+        memory_size: config.memory_size,
+        tape_policy: config.tape_policy,
+        eof_mode: config.eof_mode,
+        ..JsOptions::default()
+    };
+    Ok(js::emit_js(program.tokens(), &options))
+}
+
+#[cfg(not(feature = "codegen-js"))]
+fn program_js(_program: &Program, _config: &MachineConfig) -> Result<String, Box<dyn Error>> {
+    Err("JavaScript output needs the `codegen-js` feature".into())
+}
+
+/// `program` as an LLVM module, for `--emit-llvm` and `compile --target llvm`.
+#[cfg(feature = "codegen-llvm")]
+fn program_ir(program: &Program) -> Result<String, Box<dyn Error>> {
+    Ok(bf_rust::bf::codegen::llvm::emit_ir(program.tokens()))
+}
+
+#[cfg(not(feature = "codegen-llvm"))]
+fn program_ir(_program: &Program) -> Result<String, Box<dyn Error>> {
+    Err("LLVM output needs the `codegen-llvm` feature".into())
+}
+
+fn check_extension(file_path_str: &str, force_run: bool) -> Result<(), Box<dyn Error>> {
     let file_path = Path::new(file_path_str);
-    let bf_code = fs::read_to_string(file_path)?;
     if !force_run {
         let ext = file_path
             .extension()
@@ -49,5 +1957,898 @@ fn parse_args(args: &[String]) -> Result<String, Box<dyn Error>> {
         }
     }
 
-    Ok(bf_code)
+    Ok(())
+}
+
+fn explain(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe explain [--pretty|--emit-tokens|--pseudocode] [--indent N] [--ranges] [--comments] [--offsets] [--line-comments] [--multi-tape] [--brainfork] <--force-run> [filename.(b/bf)]";
+
+    let mut pretty = false;
+    let mut emit_tokens = false;
+    let mut pseudocode = false;
+    let mut explain_options = ExplainOptions::default();
+    let mut force_run = false;
+    let mut options = PrettyOptions::default();
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pretty" => pretty = true,
+            "--emit-tokens" => emit_tokens = true,
+            "--pseudocode" => pseudocode = true,
+            "--offsets" => explain_options.offsets = true,
+            "--ranges" => options.annotate_ranges = true,
+            "--comments" => options.comments = CommentStyle::Dim,
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--force-run" => force_run = true,
+            "--indent" => {
+                options.indent = args
+                    .next()
+                    .ok_or("--indent requires a value")?
+                    .parse()
+                    .map_err(|_| "--indent requires a number")?;
+                explain_options.indent = options.indent;
+            }
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    // Pseudocode is what to explain with unless asked for something else.
+    if !pretty && !emit_tokens {
+        pseudocode = true;
+    }
+
+    let bf_code = read_bf_file(file, force_run)?;
+    let tokens = BfParser::parse_with_options(&bf_code, &parse_options)?;
+
+    if emit_tokens {
+        for (index, token) in tokens.iter().enumerate() {
+            println!("{index}: {token:?}");
+        }
+    }
+    if pretty {
+        print!("{}", ast::pretty(&ast::from_tokens(&tokens)?, options));
+    }
+    if pseudocode {
+        // One token per source char, so `--offsets` point into the file.
+        let ast = ast::from_tokens(&tokens)?;
+        print!("{}", explain::explain(&ast, &explain_options));
+    }
+
+    Ok(())
+}
+
+fn fmt(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe fmt [--indent N] [--ranges] [--comments] [--line-comments] [--multi-tape] [--brainfork] <--force-run> [filename.(b/bf)]";
+
+    let mut force_run = false;
+    let mut options = PrettyOptions::default();
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ranges" => options.annotate_ranges = true,
+            "--comments" => options.comments = CommentStyle::Dim,
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--force-run" => force_run = true,
+            "--indent" => {
+                options.indent = args
+                    .next()
+                    .ok_or("--indent requires a value")?
+                    .parse()
+                    .map_err(|_| "--indent requires a number")?;
+            }
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    let bf_code = read_bf_file(file, force_run)?;
+    let tokens = BfParser::parse_with_options(&bf_code, &parse_options)?;
+    print!("{}", ast::pretty(&ast::from_tokens(&tokens)?, options));
+    Ok(())
+}
+
+/// What `compile` turns a program into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CompileTarget {
+    Json,
+    Bytecode,
+    Js,
+    Llvm,
+}
+
+fn compile(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe compile [--target json|bytecode|js|llvm] [--output file] [--line-comments] [--multi-tape] [--brainfork] <--force-run> [filename.(b/bf)]";
+
+    let mut target = CompileTarget::Json;
+    let mut output = None;
+    let mut force_run = false;
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => {
+                target = match args.next().map(String::as_str) {
+                    Some("json") => CompileTarget::Json,
+                    Some("bytecode") => CompileTarget::Bytecode,
+                    Some("js") => CompileTarget::Js,
+                    Some("llvm") => CompileTarget::Llvm,
+                    _ => {
+                        return Err(format!(
+                            "--target requires json, bytecode, js or llvm. {USAGE}"
+                        )
+                        .into())
+                    }
+                }
+            }
+            "--output" => output = Some(args.next().ok_or("--output requires a file")?),
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--force-run" => force_run = true,
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    let bf_code = read_bf_file(file, force_run)?;
+    let header = directives::parse_header(&bf_code).map_err(|err| format!("{file}: {err}"))?;
+    let config = header.apply(MachineConfig::default());
+    // As in `run`, wider cells take the program unoptimized, and the generated
+    // code keeps its tape in bytes.
+    let opt_level = match header.cell_size.unwrap_or_default() {
+        CellSize::U8 => OptLevel::highest_for(&config),
+        CellSize::I32 if matches!(target, CompileTarget::Js | CompileTarget::Llvm) => {
+            return Err(format!("{file}: 32-bit cells cannot be compiled to js or llvm").into())
+        }
+        CellSize::I32 => OptLevel::O0,
+    };
+    let program = Program::parse_at(&bf_code, &parse_options, opt_level)?;
+    let compiled = match target {
+        CompileTarget::Json => format!("{}\n", program_json(&program)?).into_bytes(),
+        CompileTarget::Bytecode => bytecode::encode(&program),
+        CompileTarget::Js => program_js(&program, &config)?.into_bytes(),
+        CompileTarget::Llvm => program_ir(&program)?.into_bytes(),
+    };
+
+    match output {
+        Some(path) => fs::write(path, compiled).map_err(|err| format!("{path}: {err}"))?,
+        None => stdout().write_all(&compiled)?,
+    }
+    Ok(())
+}
+
+/// Translates a Brainfuck program into Ook!, or an Ook! one into Brainfuck.
+#[cfg(feature = "dialects")]
+fn transpile(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use bf_rust::bf::dialects::ook;
+
+    const USAGE: &str = "Usage: bf-rust.exe transpile --to ook|bf [--output file] [--line-comments] [--multi-tape] [--brainfork] <--force-run> [filename.(b/bf/ook)]";
+
+    let mut to_ook = None;
+    let mut output = None;
+    let mut force_run = false;
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--to" => {
+                to_ook = match args.next().map(String::as_str) {
+                    Some("ook") => Some(true),
+                    Some("bf") => Some(false),
+                    _ => return Err(format!("--to requires ook or bf. {USAGE}").into()),
+                }
+            }
+            "--output" => output = Some(args.next().ok_or("--output requires a file")?),
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--force-run" => force_run = true,
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let to_ook = to_ook.ok_or(USAGE)?;
+    let file = file.ok_or(USAGE)?;
+    let transpiled = if to_ook {
+        let bf_code = read_bf_file(file, force_run)?;
+        let program = Program::parse_with_options(&bf_code, &parse_options)?;
+        ook::emit(program.tokens())
+    } else {
+        if !force_run && !has_extension(file, "ook") {
+            return Err(format!("{file}: Ook! programs need the '.ook' extension").into());
+        }
+        let tokens = ook::parse(&read_source(file)?).map_err(|err| format!("{file}: {err}"))?;
+        let program = Program::from_tokens(tokens).map_err(|errors| {
+            let errors: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            format!("{file}: {}", errors.join(", "))
+        })?;
+        format!("{program}\n")
+    };
+
+    match output {
+        Some(path) => fs::write(path, transpiled).map_err(|err| format!("{path}: {err}"))?,
+        None => print!("{transpiled}"),
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "dialects"))]
+fn transpile(_args: &[String]) -> Result<(), Box<dyn Error>> {
+    Err("transpiling needs the `dialects` feature".into())
+}
+
+/// Reads code from stdin a line at a time and runs it as soon as its loops
+/// are closed, on one machine for the whole session.
+fn repl(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe repl [--input file] [--memory-size N] [--history file]";
+
+    let mut input = vec![];
+    let mut history = repl::history_path();
+    // Stdin carries the code, so `,` reads the `--input` file and then zeros.
+    let mut config = MachineConfig {
+        eof_mode: EofMode::Zero,
+        ..MachineConfig::default()
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => {
+                let path = args.next().ok_or("--input requires a file")?;
+                input = fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+            }
+            "--memory-size" => config.memory_size = parse_count(args.next(), "--memory-size")?,
+            "--history" => history = Some(args.next().ok_or("--history requires a file")?.into()),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let machine = BfMachine::with_config(config, Cursor::new(input), stdout());
+    let mut interpreter = IncrementalInterpreter::new(machine);
+    let mut passed = true;
+    if stdin().is_terminal() {
+        // Ctrl-C clears the entry being typed; only Ctrl-D leaves.
+        let mut editor = LineEditor::open(history)?;
+        loop {
+            let code = match editor.read_entry()? {
+                Entry::Code(code) => code,
+                Entry::Cleared => continue,
+                Entry::End => break,
+            };
+            if let Err(err) = interpreter.feed(&format!("{code}\n")) {
+                eprintln!("error: {err}");
+                passed = false;
+            }
+            stdout().flush()?;
+        }
+        editor.save()?;
+    } else {
+        for line in stdin().lines() {
+            if let Err(err) = interpreter.feed(&format!("{}\n", line?)) {
+                eprintln!("error: {err}");
+                passed = false;
+            }
+            stdout().flush()?;
+        }
+    }
+
+    if let Err(err) = interpreter.finish() {
+        eprintln!("error: {err}");
+        passed = false;
+    }
+    Ok(passed)
+}
+
+/// Steps through a program under commands read from stdin, one a line. The
+/// program's `,` reads the `--input` file and then zeros, and its output goes
+/// to stdout while the debugger reports on stderr.
+fn debug(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe debug [--input file] [--memory-size N] [--max-steps N] [--history N] [--line-comments] [--multi-tape] <--force-run> [filename.(b/bf)]";
+    const COMMANDS: &str = "commands: s/step, n/next, f/finish, rs/back, p/print, q/quit";
+
+    let mut input = vec![];
+    let mut memory_size = MachineConfig::default().memory_size;
+    let mut budget = 100_000_000;
+    let mut history = 10_000;
+    let mut force_run = false;
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--input" => {
+                let path = args.next().ok_or("--input requires a file")?;
+                input = fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+            }
+            "--memory-size" => memory_size = parse_count(args.next(), "--memory-size")?,
+            "--max-steps" => budget = parse_count(args.next(), "--max-steps")?,
+            "--history" => history = parse_count(args.next(), "--history")?,
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--force-run" => force_run = true,
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    let bf_code = read_bf_file(file, force_run)?;
+    // One instruction per command, so a step is a character of the source.
+    let program = Program::parse_at(&bf_code, &parse_options, OptLevel::O0)
+        .map_err(|err| format!("{file}: {err}"))?;
+    let config = MachineConfig {
+        memory_size,
+        eof_mode: EofMode::Zero,
+        ..MachineConfig::default()
+    };
+    let machine = BfMachine::with_config(config, Cursor::new(input), stdout());
+    let mut debugger = Debugger::new(machine, program);
+    debugger.record(history);
+
+    let interactive = stdin().is_terminal();
+    let mut passed = true;
+    let mut lines = stdin().lines();
+    loop {
+        if interactive {
+            eprint!("(bf) ");
+        }
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let result = match line?.trim() {
+            "" => continue,
+            "s" | "step" => debugger.step(),
+            "n" | "next" => debugger.next(budget),
+            "f" | "finish" => debugger.finish(budget),
+            "rs" | "back" => match debugger.step_back() {
+                Ok(()) => Ok(()),
+                Err(err) => {
+                    eprintln!("error: {err}");
+                    continue;
+                }
+            },
+            "p" | "print" => {
+                eprintln!("{}", debugger.machine());
+                continue;
+            }
+            "q" | "quit" => break,
+            command => {
+                eprintln!("unknown command `{command}`; {COMMANDS}");
+                continue;
+            }
+        };
+        stdout().flush()?;
+        match result {
+            // The budget ran out, which the next command can pick up from.
+            Err(BfRuntimeError::StepLimitExceeded { .. }) => {
+                eprintln!("stopped after {budget} steps");
+            }
+            Err(err) => {
+                eprintln!("error: {err}");
+                passed = false;
+            }
+            Ok(()) => {}
+        }
+        match debugger.program().tokens().get(debugger.pc()) {
+            Some(token) => eprintln!(
+                "step {}, pc {}: {}",
+                debugger.steps(),
+                debugger.pc(),
+                BfParser::to_source(std::slice::from_ref(token))
+            ),
+            None => eprintln!("step {}, halted", debugger.steps()),
+        }
+    }
+    Ok(passed)
+}
+
+fn highlight(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe highlight [filename.(b/bf)] [--html out.html] [--standalone] [--profile file] [--line-comments] [--multi-tape] [--brainfork] <--force-run>";
+
+    let mut html_path = None;
+    let mut standalone = false;
+    let mut profile_path = None;
+    let mut force_run = false;
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--html" => html_path = Some(args.next().ok_or("--html requires a file")?),
+            "--standalone" => standalone = true,
+            "--profile" => profile_path = Some(args.next().ok_or("--profile requires a file")?),
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--force-run" => force_run = true,
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    let bf_code = read_bf_file(file, force_run)?;
+    let counts = match profile_path {
+        Some(path) => Some(
+            profile::parse_counts(&fs::read_to_string(path)?)
+                .map_err(|err| format!("{path}: {err}"))?,
+        ),
+        None => None,
+    };
+
+    let mut html = highlight::to_html(&bf_code, &parse_options, counts.as_deref());
+    if standalone {
+        html = highlight::to_page(&html, file);
+    }
+    match html_path {
+        Some(path) => fs::write(path, html)?,
+        None => print!("{html}"),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "serde")]
+fn inspect_core(args: &[String]) -> Result<(), Box<dyn Error>> {
+    use bf_rust::bf::core_dump::CoreDump;
+
+    let [file] = args else {
+        return Err("Usage: bf-rust.exe inspect-core [file]".into());
+    };
+    // Dumps of byte cells read as `i32` ones too, and print the same.
+    let dump = CoreDump::<i32>::load(file).map_err(|err| format!("{file}: {err}"))?;
+    print!("{}", dump.report());
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn inspect_core(_args: &[String]) -> Result<(), Box<dyn Error>> {
+    Err("core dumps require the serde feature".into())
+}
+
+fn bench_opt(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe bench-opt [--runs N] [--input file] [--line-comments] [--multi-tape] <--force-run> [filename.(b/bf)]";
+
+    let mut runs = 5;
+    let mut input = vec![];
+    let mut force_run = false;
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--runs" => runs = parse_count(args.next(), "--runs")?,
+            "--input" => {
+                let path = args.next().ok_or("--input requires a file")?;
+                input = fs::read(path).map_err(|err| format!("{path}: {err}"))?;
+            }
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--force-run" => force_run = true,
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    let bf_code = read_bf_file(file, force_run)?;
+    let results = bench::bench_levels(&bf_code, &parse_options, &input, runs)
+        .map_err(|err| format!("{file}: {err}"))?;
+
+    let millis = |time: Duration| format!("{:.3}ms", time.as_secs_f64() * 1000.0);
+    println!(
+        "{:<6}{:>10}{:>14}{:>14}{:>14}{:>10}",
+        "level", "tokens", "steps", "mean", "stddev", "speedup"
+    );
+    for result in &results {
+        println!(
+            "{:<6}{:>10}{:>14}{:>14}{:>14}{:>9.2}x",
+            result.level.to_string(),
+            result.tokens,
+            result.steps,
+            millis(result.mean()),
+            millis(result.std_dev()),
+            result.speedup(&results[0]),
+        );
+    }
+
+    if let Some(diverged) = bench::first_divergence(&results) {
+        return Err(format!(
+            "output at {} differs from {}: {} bytes instead of {}",
+            diverged.level,
+            results[0].level,
+            diverged.output.len(),
+            results[0].output.len()
+        )
+        .into());
+    }
+    println!(
+        "outputs equal at every level ({} bytes)",
+        results[0].output.len()
+    );
+    Ok(())
+}
+
+fn serve(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe serve [--listen addr] [--max-steps N] [--max-time-ms N] [--max-connections N] [--eof-mode error|zero|unchanged|minus-one] [--line-comments] [--multi-tape] <--force-run> <-v|-vv> [filename.(b/bf)]";
+
+    let mut listen = "127.0.0.1:4000".to_string();
+    let mut config = MachineConfig::default();
+    let mut time_limit = None;
+    let mut max_connections = 16;
+    let mut force_run = false;
+    let mut parse_options = ParseOptions::default();
+    let mut verbosity = 0;
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => listen = args.next().ok_or("--listen requires an address")?.clone(),
+            "--max-steps" => config.max_steps = Some(parse_count(args.next(), "--max-steps")?),
+            "--max-time-ms" => {
+                time_limit = Some(Duration::from_millis(
+                    parse_count(args.next(), "--max-time-ms")? as u64,
+                ))
+            }
+            "--max-connections" => max_connections = parse_count(args.next(), "--max-connections")?,
+            "--eof-mode" => {
+                config.eof_mode = match args.next().map(String::as_str) {
+                    Some("error") => EofMode::Error,
+                    Some("zero") => EofMode::Zero,
+                    Some("unchanged") => EofMode::Unchanged,
+                    Some("minus-one") => EofMode::MinusOne,
+                    _ => {
+                        return Err(format!(
+                            "--eof-mode requires error, zero, unchanged or minus-one. {USAGE}"
+                        )
+                        .into())
+                    }
+                }
+            }
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--force-run" => force_run = true,
+            "-v" => verbosity = 1,
+            "-vv" => verbosity = 2,
+            _ if file.is_none() && !arg.starts_with('-') => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    let bf_code = read_bf_file(file, force_run)?;
+    let config = directives::parse_header(&bf_code)
+        .map_err(|err| format!("{file}: {err}"))?
+        .apply(config);
+    // Every connection gets a new machine.
+    let program = Program::parse_for_fresh_tape(&bf_code, &parse_options, &config)?;
+    init_logger(verbosity);
+
+    let mut server = Server::bind(&listen, program, config)?.with_connection_limit(max_connections);
+    if let Some(limit) = time_limit {
+        server = server.with_time_limit(limit);
+    }
+    eprintln!("Serving {file} on {}", server.local_addr()?);
+    Ok(server.serve()?)
+}
+
+fn stats(args: &[String]) -> Result<(), Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe stats [--format text|json] [--line-comments] [--multi-tape] [--brainfork] <--force-run> [filename.(b/bf)]";
+
+    let mut json = false;
+    let mut force_run = false;
+    let mut parse_options = ParseOptions::default();
+    let mut file = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                json = match args.next().map(String::as_str) {
+                    Some("text") => false,
+                    Some("json") => true,
+                    _ => return Err(format!("--format requires text or json. {USAGE}").into()),
+                }
+            }
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--force-run" => force_run = true,
+            _ if file.is_none() && !arg.starts_with("--") => file = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let file = file.ok_or(USAGE)?;
+    let bf_code = read_bf_file(file, force_run)?;
+    let stats = ProgramStats::new(&bf_code, &parse_options)?;
+
+    if json {
+        let commands: Vec<_> = stats::COMMANDS
+            .iter()
+            .map(|&ch| format!("{}:{}", json_string(&ch.to_string()), stats.count(ch)))
+            .collect();
+        let tokens: Vec<_> = stats
+            .tokens
+            .iter()
+            .map(|(level, count)| format!("\"{level}\":{count}"))
+            .collect();
+        let longest_run = match stats.longest_run {
+            Some((ch, len)) => format!(
+                "{{\"command\":{},\"length\":{len}}}",
+                json_string(&ch.to_string())
+            ),
+            None => "null".to_string(),
+        };
+        println!(
+            "{{\"commands\":{{{}}},\"extensions\":{},\"tape_switches\":{},\"forks\":{},\"total_commands\":{},\"comment_chars\":{},\"total_chars\":{},\"comment_ratio\":{},\"loops\":{},\"max_depth\":{},\"longest_run\":{longest_run},\"tokens\":{{{}}}}}",
+            commands.join(","),
+            stats.extensions,
+            stats.tape_switches,
+            stats.forks,
+            stats.total_commands(),
+            stats.comment_chars,
+            stats.total_chars,
+            stats.comment_ratio(),
+            stats.loops,
+            stats.max_depth,
+            tokens.join(","),
+        );
+        return Ok(());
+    }
+
+    for ch in stats::COMMANDS {
+        println!("{ch:<16}{:>8}", stats.count(ch));
+    }
+    if stats.extensions > 0 {
+        println!("{:<16}{:>8}", "extensions", stats.extensions);
+    }
+    if stats.tape_switches > 0 {
+        println!("{:<16}{:>8}", "tape switches", stats.tape_switches);
+    }
+    if stats.forks > 0 {
+        println!("{:<16}{:>8}", "forks", stats.forks);
+    }
+    println!("{:<16}{:>8}", "commands", stats.total_commands());
+    println!(
+        "{:<16}{:>8} ({:.1}%)",
+        "comment chars",
+        stats.comment_chars,
+        stats.comment_ratio() * 100.0
+    );
+    println!("{:<16}{:>8}", "loops", stats.loops);
+    println!("{:<16}{:>8}", "max depth", stats.max_depth);
+    if let Some((ch, len)) = stats.longest_run {
+        println!("{:<16}{len:>8} ({ch})", "longest run");
+    }
+    for (level, count) in &stats.tokens {
+        println!("{:<16}{count:>8}", format!("tokens at {level}"));
+    }
+
+    Ok(())
+}
+
+fn lint(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    const USAGE: &str = "Usage: bf-rust.exe (lint|--check) [--deny-warnings] [--allow CODE]... [--format text|json] [--line-comments] [--multi-tape] [--brainfork] <--force-run> [filename.(b/bf)]...";
+
+    let mut deny_warnings = false;
+    let mut force_run = false;
+    let mut json = false;
+    let mut allowed = vec![];
+    let mut parse_options = ParseOptions::default();
+    let mut files = vec![];
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--deny-warnings" => deny_warnings = true,
+            "--force-run" => force_run = true,
+            "--line-comments" => parse_options.line_comments = true,
+            "--multi-tape" => parse_options.multi_tape = true,
+            "--brainfork" => parse_options.brainfork = true,
+            "--allow" => allowed.push(args.next().ok_or("--allow requires a code")?),
+            "--format" => {
+                json = match args.next().map(String::as_str) {
+                    Some("text") => false,
+                    Some("json") => true,
+                    _ => return Err(format!("--format requires text or json. {USAGE}").into()),
+                }
+            }
+            _ if !arg.starts_with("--") => files.push(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    if files.is_empty() {
+        return Err(USAGE.into());
+    }
+
+    let mut errors = 0;
+    let mut warnings = 0;
+    let mut entries = vec![];
+    for file in files {
+        let bf_code = read_bf_file(file, force_run)?;
+
+        let mut file_errors = 0;
+        let mut file_warnings = 0;
+        for diagnostic in analyzer::lint(&parse_options.strip_comments(&bf_code)) {
+            if allowed.iter().any(|code| *code == diagnostic.code) {
+                continue;
+            }
+
+            match diagnostic.severity {
+                Severity::Error => file_errors += 1,
+                Severity::Warning => file_warnings += 1,
+            }
+            if json {
+                entries.push(diagnostic_json(file, &bf_code, &diagnostic));
+                continue;
+            }
+            let (line, col) = diagnostics::line_col(&bf_code, diagnostic.index);
+            println!(
+                "{}[{}]: {} at line {line}, col {col}",
+                diagnostic.severity, diagnostic.code, diagnostic.message
+            );
+        }
+
+        if !json && file_errors + file_warnings > 0 {
+            println!("{file}: {file_errors} error(s), {file_warnings} warning(s)");
+        }
+        errors += file_errors;
+        warnings += file_warnings;
+    }
+
+    if json {
+        println!(
+            "{{\"version\":{DIAGNOSTICS_SCHEMA_VERSION},\"diagnostics\":[{}]}}",
+            entries.join(",")
+        );
+    }
+
+    Ok(errors == 0 && (warnings == 0 || !deny_warnings))
+}
+
+/// Bumped whenever a field of `--format json` diagnostics changes meaning or
+/// goes away; new fields may appear without a bump.
+const DIAGNOSTICS_SCHEMA_VERSION: u32 = 1;
+
+/// One diagnostic as JSON. Offsets count bytes into the file, lines and
+/// columns count characters from 1, and `end` is one past the flagged code.
+fn diagnostic_json(file: &str, bf_code: &str, diagnostic: &Diagnostic) -> String {
+    let position = |index: usize| {
+        let offset = bf_code
+            .char_indices()
+            .nth(index)
+            .map_or(bf_code.len(), |(offset, _)| offset);
+        let (line, column) = diagnostics::line_col(bf_code, index);
+        format!("\"offset\":{offset},\"line\":{line},\"column\":{column}")
+    };
+
+    let mut fields = vec![
+        format!(
+            "\"severity\":{}",
+            json_string(&diagnostic.severity.to_string())
+        ),
+        format!("\"code\":{}", json_string(diagnostic.code)),
+        format!("\"message\":{}", json_string(&diagnostic.message)),
+        format!("\"file\":{}", json_string(file)),
+        position(diagnostic.index),
+    ];
+    if let Some(end) = diagnostic.end {
+        fields.push(format!("\"end\":{{{}}}", position(end)));
+    }
+    format!("{{{}}}", fields.join(","))
+}
+
+/// Runs every program in a directory against its expected output, through
+/// both parsers, and prints a table of the results.
+fn test(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    const USAGE: &str =
+        "Usage: bf-rust.exe test [--filter substring] [--max-steps N] [--update] [directory]";
+    const DEFAULT_MAX_STEPS: usize = 100_000_000;
+
+    let mut filter = None;
+    let mut max_steps = DEFAULT_MAX_STEPS;
+    let mut update = false;
+    let mut dir = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--filter" => filter = Some(args.next().ok_or("--filter requires a substring")?),
+            "--max-steps" => max_steps = parse_count(args.next(), "--max-steps")?,
+            "--update" => update = true,
+            _ if dir.is_none() && !arg.starts_with("--") => dir = Some(arg),
+            _ => return Err(format!("Unknown argument: {arg}. {USAGE}").into()),
+        }
+    }
+
+    let dir = dir.ok_or(USAGE)?;
+    let cases = suite::discover(Path::new(dir), filter.map(String::as_str))
+        .map_err(|err| format!("{dir}: {err}"))?;
+    let width = cases.iter().map(|case| case.name.len()).max().unwrap_or(0);
+    let label = |outcome: &Outcome| match outcome {
+        Outcome::Pass => "pass",
+        Outcome::Fail(_) => "fail",
+        Outcome::Timeout => "timeout",
+    };
+
+    let (mut passed, mut failed, mut timed_out, mut updated) = (0, 0, 0, 0);
+    let mut reports = vec![];
+    println!("{:width$}  {:9}optimized", "case", "plain");
+    for case in &cases {
+        let mut outcomes = vec![];
+        for optimized in [false, true] {
+            let outcome = match case.execute(optimized, max_steps) {
+                // The plain run decides what the optimized one must match.
+                Ok(output) if update && !optimized && case.check(&output) != Outcome::Pass => {
+                    fs::write(case.expected_path(), &output)
+                        .map_err(|err| format!("{}: {err}", case.expected_path().display()))?;
+                    updated += 1;
+                    Outcome::Pass
+                }
+                Ok(output) => case.check(&output),
+                Err(outcome) => outcome,
+            };
+            if let Outcome::Fail(report) = &outcome {
+                let pipeline = if optimized { "optimized" } else { "plain" };
+                reports.push(format!("{} ({pipeline}): {report}", case.name));
+            }
+            outcomes.push(outcome);
+        }
+
+        println!(
+            "{:width$}  {:9}{}",
+            case.name,
+            label(&outcomes[0]),
+            label(&outcomes[1])
+        );
+        if outcomes
+            .iter()
+            .any(|outcome| matches!(outcome, Outcome::Fail(_)))
+        {
+            failed += 1;
+        } else if outcomes.contains(&Outcome::Timeout) {
+            timed_out += 1;
+        } else {
+            passed += 1;
+        }
+    }
+
+    for report in &reports {
+        println!("\n{report}");
+    }
+    print!(
+        "\n{} case(s): {passed} passed, {failed} failed, {timed_out} timed out",
+        cases.len()
+    );
+    if update {
+        print!(", {updated} updated");
+    }
+    println!();
+
+    Ok(failed + timed_out == 0)
 }
@@ -0,0 +1,45 @@
+use std::{env, fs, path::Path, process::Command};
+
+/// The features declared in `Cargo.toml`, except `default`.
+fn features() -> Vec<String> {
+    let manifest =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml")).unwrap();
+    manifest
+        .lines()
+        .skip_while(|line| line.trim() != "[features]")
+        .skip(1)
+        .take_while(|line| !line.starts_with('['))
+        .filter_map(|line| line.split_once('=').map(|(name, _)| name.trim()))
+        .filter(|&name| !name.is_empty() && !name.starts_with('#') && name != "default")
+        .map(str::to_string)
+        .collect()
+}
+
+/// Checks the crate with no features and with each one alone, and runs its
+/// library tests that way, in a target dir of its own. Slow, so it only runs
+/// when asked for: `cargo test --test features -- --ignored`.
+#[test]
+#[ignore]
+fn every_feature_builds_alone() {
+    let features = features();
+    assert!(features.iter().any(|feature| feature == "cli"));
+
+    let target = Path::new(env!("CARGO_TARGET_TMPDIR")).join("features");
+    let cargo = env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    for feature in [None].into_iter().chain(features.iter().map(Some)) {
+        for args in [&["check", "--all-targets"][..], &["test", "--lib"]] {
+            let mut command = Command::new(&cargo);
+            command
+                .args(args)
+                .arg("--no-default-features")
+                .arg("--manifest-path")
+                .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+                .env("CARGO_TARGET_DIR", &target);
+            if let Some(feature) = feature {
+                command.args(["--features", feature]);
+            }
+            let status = command.status().unwrap();
+            assert!(status.success(), "{args:?} with features {feature:?}");
+        }
+    }
+}
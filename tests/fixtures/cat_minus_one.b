@@ -0,0 +1,2 @@
+Copies its input until a read gives minus one
+,+[-.,+]
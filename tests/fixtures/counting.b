@@ -0,0 +1,2 @@
+Print every byte from A up to 255
+++++++++[>++++++++<-]>+[.+]
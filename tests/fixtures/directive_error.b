@@ -0,0 +1,3 @@
+#!/usr/bin/env bf-rust
+#! bf: eof=never
++.
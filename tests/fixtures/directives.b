@@ -0,0 +1,6 @@
+#! bf: memory=100 speed=9
+Sets cell 1 to A then walks a hundred cells right and prints
+++++++++[>++++++++<-]>+
+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>>
+.
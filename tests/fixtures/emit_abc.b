@@ -0,0 +1,2 @@
+Emit the three letters abc
+++++++++[>++++++++++++<-]>+.+.+.
@@ -0,0 +1,3 @@
+An empty loop at the start is never entered
+[+ this cancels out -]
+++++++++[>++++++++<-]>+.
@@ -0,0 +1,50 @@
+function bf(output, input, extension) {
+  let tape = new Uint8Array(30000);
+  let p = 0;
+  tape[p] = (tape[p] + 10) & 0xff;
+  while (tape[p] !== 0) {
+    p = (p + 1) % 30000;
+    tape[p] = (tape[p] + 7) & 0xff;
+    p = (p + 1) % 30000;
+    tape[p] = (tape[p] + 10) & 0xff;
+    p = (p + 1) % 30000;
+    tape[p] = (tape[p] + 3) & 0xff;
+    p = (p + 1) % 30000;
+    tape[p] = (tape[p] + 1) & 0xff;
+    p = (p + 29996) % 30000;
+    tape[p] = (tape[p] - 1) & 0xff;
+  }
+  p = (p + 1) % 30000;
+  tape[p] = (tape[p] + 2) & 0xff;
+  output(tape[p]);
+  p = (p + 1) % 30000;
+  tape[p] = (tape[p] + 1) & 0xff;
+  output(tape[p]);
+  tape[p] = (tape[p] + 7) & 0xff;
+  for (let i = 0; i < 2; i++) output(tape[p]);
+  tape[p] = (tape[p] + 3) & 0xff;
+  output(tape[p]);
+  p = (p + 1) % 30000;
+  tape[p] = (tape[p] + 2) & 0xff;
+  output(tape[p]);
+  p = (p + 29998) % 30000;
+  tape[p] = (tape[p] + 15) & 0xff;
+  output(tape[p]);
+  p = (p + 1) % 30000;
+  output(tape[p]);
+  tape[p] = (tape[p] + 3) & 0xff;
+  output(tape[p]);
+  tape[p] = (tape[p] - 6) & 0xff;
+  output(tape[p]);
+  tape[p] = (tape[p] - 8) & 0xff;
+  output(tape[p]);
+  p = (p + 1) % 30000;
+  tape[p] = (tape[p] + 1) & 0xff;
+  output(tape[p]);
+  p = (p + 1) % 30000;
+  output(tape[p]);
+}
+
+if (typeof module !== "undefined") {
+  module.exports = bf;
+}
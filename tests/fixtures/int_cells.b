@@ -0,0 +1,3 @@
+#! bf: cells=32 eof=minus-one
+Copies its input in int cells until a read gives minus one
+,+[-.,+]
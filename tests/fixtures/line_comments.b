@@ -0,0 +1 @@
++++++ +++++ [>+++++ ++<-]>-. ; prints E, and nothing else.
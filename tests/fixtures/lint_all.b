@@ -0,0 +1,7 @@
+Every lint in one file
+<
++[]
+[-]
++[>+<]
+]
++-
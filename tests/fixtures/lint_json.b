@@ -0,0 +1,3 @@
+Naïve check
++[]
+]
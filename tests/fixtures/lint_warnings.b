@@ -0,0 +1,2 @@
+Only warnings
++[]
@@ -0,0 +1,4 @@
+Reads past the end of input two loops deep
+++[>+++
+  [>,<-]
+<-]
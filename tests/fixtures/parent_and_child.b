@@ -0,0 +1,4 @@
+++++++++++[>+++++++++++>++++++++++<<-]   cells 1 and 2 get 110 and 100
+>++>-                                    make them p and c
+Y                                        fork: the parent's cell becomes 0
+<.                                       so each thread prints its own letter
@@ -0,0 +1,2 @@
+Print two random bytes when the random command is enabled
+?.?.
@@ -0,0 +1,2 @@
+Read four bytes and print each one
+,.,.,+.,.
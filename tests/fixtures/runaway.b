@@ -0,0 +1,2 @@
+Marks every cell to the right until memory runs out
++[>+]
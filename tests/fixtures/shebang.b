@@ -0,0 +1,2 @@
+#!/usr/bin/env -S bf-rust --max-steps 1000 +.+.
+++++++++[>++++++++<-]>+.
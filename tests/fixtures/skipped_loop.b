@@ -0,0 +1,2 @@
+A loop that never runs
++[-]>[.]
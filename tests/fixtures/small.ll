@@ -0,0 +1,61 @@
+@tape = internal global [30000 x i8] zeroinitializer
+
+declare i32 @putchar(i32)
+declare i32 @getchar()
+
+define i32 @main() {
+entry:
+  %p = alloca i64
+  store i64 0, ptr %p
+  %t1 = load i64, ptr %p
+  %t2 = getelementptr inbounds [30000 x i8], ptr @tape, i64 0, i64 %t1
+  %t3 = load i8, ptr %t2
+  %t4 = add i8 %t3, 2
+  store i8 %t4, ptr %t2
+  br label %loop0
+loop0:
+  %t5 = load i64, ptr %p
+  %t6 = getelementptr inbounds [30000 x i8], ptr @tape, i64 0, i64 %t5
+  %t7 = load i8, ptr %t6
+  %t8 = icmp ne i8 %t7, 0
+  br i1 %t8, label %body0, label %end0
+body0:
+  %t9 = load i64, ptr %p
+  %t10 = add i64 %t9, 1
+  %t11 = urem i64 %t10, 30000
+  store i64 %t11, ptr %p
+  %t12 = load i64, ptr %p
+  %t13 = getelementptr inbounds [30000 x i8], ptr @tape, i64 0, i64 %t12
+  %t14 = load i8, ptr %t13
+  %t15 = add i8 %t14, 3
+  store i8 %t15, ptr %t13
+  %t16 = load i64, ptr %p
+  %t17 = add i64 %t16, 29999
+  %t18 = urem i64 %t17, 30000
+  store i64 %t18, ptr %p
+  %t19 = load i64, ptr %p
+  %t20 = getelementptr inbounds [30000 x i8], ptr @tape, i64 0, i64 %t19
+  %t21 = load i8, ptr %t20
+  %t22 = add i8 %t21, 255
+  store i8 %t22, ptr %t20
+  br label %loop0
+end0:
+  %t23 = load i64, ptr %p
+  %t24 = add i64 %t23, 1
+  %t25 = urem i64 %t24, 30000
+  store i64 %t25, ptr %p
+  %t26 = load i64, ptr %p
+  %t27 = getelementptr inbounds [30000 x i8], ptr @tape, i64 0, i64 %t26
+  %t28 = load i8, ptr %t27
+  %t29 = zext i8 %t28 to i32
+  %t30 = call i32 @putchar(i32 %t29)
+  %t31 = load i64, ptr %p
+  %t32 = getelementptr inbounds [30000 x i8], ptr @tape, i64 0, i64 %t31
+  %t33 = call i32 @getchar()
+  %t34 = icmp slt i32 %t33, 0
+  %t35 = trunc i32 %t33 to i8
+  %t36 = load i8, ptr %t32
+  %t37 = select i1 %t34, i8 %t36, i8 %t35
+  store i8 %t37, ptr %t32
+  ret i32 0
+}
@@ -0,0 +1,2 @@
+Print the two cells staged at ten
+>>>>>>>>>>.>.
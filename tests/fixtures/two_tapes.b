@@ -0,0 +1,4 @@
++++++       five on tape 0
+^ +++++++   seven on tape 1
+^ .         print the five
+,           and fail to read
@@ -0,0 +1,2 @@
+An unclosed loop
++[-
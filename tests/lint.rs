@@ -0,0 +1,125 @@
+use std::process::{Command, Output};
+
+fn lint(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bf-rust"))
+        .arg("lint")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn reports_every_code() {
+    let output = lint(&["tests/fixtures/lint_all.b"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    for code in ["BF001", "BF002", "BF003", "BF004", "BF005", "BF006"] {
+        assert!(
+            stdout.contains(&format!("[{code}]")),
+            "{code} missing in:\n{stdout}"
+        );
+    }
+    assert!(stdout.contains("error[BF001]: unmatched ']' has no opening bracket at line 6, col 1"));
+    assert!(stdout.contains("warning[BF005]: loop never terminates once entered at line 5, col 2"));
+}
+
+#[test]
+fn errors_always_fail() {
+    assert_eq!(lint(&["tests/fixtures/lint_all.b"]).status.code(), Some(1));
+    assert_eq!(
+        lint(&["--deny-warnings", "tests/fixtures/lint_all.b"])
+            .status
+            .code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn warnings_fail_only_when_denied() {
+    assert_eq!(
+        lint(&["tests/fixtures/lint_warnings.b"]).status.code(),
+        Some(0)
+    );
+    assert_eq!(
+        lint(&["--deny-warnings", "tests/fixtures/lint_warnings.b"])
+            .status
+            .code(),
+        Some(1)
+    );
+}
+
+#[test]
+fn allowed_codes_are_skipped() {
+    let output = lint(&[
+        "--deny-warnings",
+        "--allow",
+        "BF003",
+        "tests/fixtures/lint_warnings.b",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn check_reports_json() {
+    let output = Command::new(env!("CARGO_BIN_EXE_bf-rust"))
+        .args(["--check", "--format", "json", "tests/fixtures/lint_json.b"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(report["version"], 1);
+    assert_eq!(
+        report["diagnostics"],
+        serde_json::json!([
+            {
+                "severity": "warning",
+                "code": "BF003",
+                "message": "empty loop either does nothing or never terminates",
+                "file": "tests/fixtures/lint_json.b",
+                "offset": 14,
+                "line": 2,
+                "column": 2,
+                "end": { "offset": 16, "line": 2, "column": 4 }
+            },
+            {
+                "severity": "error",
+                "code": "BF001",
+                "message": "unmatched ']' has no opening bracket",
+                "file": "tests/fixtures/lint_json.b",
+                "offset": 17,
+                "line": 3,
+                "column": 1,
+                "end": { "offset": 18, "line": 3, "column": 2 }
+            }
+        ])
+    );
+}
+
+#[test]
+fn check_covers_every_file() {
+    let output = lint(&[
+        "--format",
+        "json",
+        "tests/fixtures/lint_json.b",
+        "tests/fixtures/lint_warnings.b",
+    ]);
+    let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = report["diagnostics"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|diagnostic| diagnostic["file"].as_str().unwrap())
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        files,
+        [
+            "tests/fixtures/lint_json.b",
+            "tests/fixtures/lint_json.b",
+            "tests/fixtures/lint_warnings.b"
+        ]
+    );
+}
@@ -0,0 +1,43 @@
+use std::sync::Mutex;
+
+use bf_rust::bf::program::Program;
+use log::{LevelFilter, Log, Metadata, Record};
+
+/// The global logger can only be set once, so this file holds a single test.
+struct Capture(Mutex<Vec<String>>);
+
+impl Log for Capture {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        self.0.lock().unwrap().push(record.args().to_string());
+    }
+
+    fn flush(&self) {}
+}
+
+static CAPTURE: Capture = Capture(Mutex::new(vec![]));
+
+#[test]
+fn optimizer_logs_each_pass() {
+    log::set_logger(&CAPTURE).unwrap();
+    log::set_max_level(LevelFilter::Debug);
+
+    Program::parse_optimized("+-+ [->+<] .>>").unwrap();
+
+    let records = CAPTURE.0.lock().unwrap();
+    for expected in [
+        "pass remove_not_command: 14 -> 12 tokens",
+        "pass remove_unnecessary_relative_operate: 12 -> 10 tokens",
+        "pass remove_trailing_dead_code: 10 -> 8 tokens",
+        "pass remove_unnecessary_relative_operate: 8 -> 8 tokens",
+        "pass remove_trailing_dead_code: 8 -> 8 tokens",
+    ] {
+        assert!(
+            records.iter().any(|record| record == expected),
+            "no {expected:?} in {records:#?}"
+        );
+    }
+}
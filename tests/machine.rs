@@ -0,0 +1,33 @@
+use std::io::Cursor;
+
+use bf_rust::bf::{bf_machine::BfMachine, program::Program};
+
+const HELLO_WORLD: &str = include_str!("programs/hello_world.b");
+
+#[test]
+fn hello_world() {
+    let mut machine = BfMachine::new(30000, Cursor::new(vec![]), vec![]);
+    let program = Program::parse(HELLO_WORLD).unwrap();
+
+    machine.run(&program).unwrap();
+    assert_eq!(machine.bytes_written(), 13);
+    assert_eq!(machine.take_output(), b"Hello World!\n");
+    assert!(machine.take_output().is_empty());
+}
+
+#[test]
+fn output_is_collected_between_runs() {
+    let mut machine = BfMachine::new(10, Cursor::new(b"ab".to_vec()), vec![]);
+    let program = Program::parse(",.+.").unwrap();
+
+    machine.run(&program).unwrap();
+    assert_eq!(machine.take_output(), b"ab");
+    machine.run(&program).unwrap();
+    assert_eq!(machine.take_output(), b"bc");
+
+    let (memory, cursor, input, output) = machine.into_parts();
+    assert_eq!(memory[0], b'c');
+    assert_eq!(cursor, 0);
+    assert_eq!(input.position(), 2);
+    assert!(output.is_empty());
+}
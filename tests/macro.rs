@@ -0,0 +1,30 @@
+use std::io::Cursor;
+
+use bf_rust::bf::bf_machine::BfMachine;
+
+fn output(program: &bf_rust::bf::program::Program, input: &[u8]) -> Vec<u8> {
+    let mut output = vec![];
+    BfMachine::new(100, Cursor::new(input), &mut output)
+        .run(program)
+        .unwrap();
+    output
+}
+
+#[test]
+fn expands_to_a_runnable_program() {
+    let add = bf_rust::bf!("++[->+<]");
+    assert_eq!(add.tokens().len(), 8);
+
+    let hello = bf_rust::bf!(include_str!("programs/hello_world.b"));
+    assert_eq!(output(&hello, b""), b"Hello World!\n");
+}
+
+#[test]
+fn joins_fragments_and_raw_strings() {
+    let next = bf_rust::bf!(
+        ",+.[-]",
+        r#"  comments "quoted" [even with brackets] are fine  "#,
+    );
+    assert_eq!(next.tokens().len(), 8);
+    assert_eq!(output(&next, b"a"), b"b");
+}
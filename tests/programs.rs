@@ -0,0 +1,89 @@
+use std::{fs, io::Cursor, path::PathBuf};
+
+use bf_rust::bf::{bf_machine::BfMachine, program::Program, testing};
+
+const STEP_LIMIT: usize = 500_000_000;
+
+fn programs() -> Vec<PathBuf> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "b"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+fn run(program: &Program, input: &[u8]) -> Result<Vec<u8>, String> {
+    let mut output = vec![];
+    BfMachine::new(30000, Cursor::new(input), &mut output)
+        .with_step_limit(STEP_LIMIT)
+        .run(program)
+        .map_err(|err| err.to_string())?;
+    Ok(output)
+}
+
+fn mismatch(expected: &[u8], actual: &[u8]) -> Option<String> {
+    let offset = expected
+        .iter()
+        .zip(actual)
+        .position(|(a, b)| a != b)
+        .or((expected.len() != actual.len()).then(|| expected.len().min(actual.len())))?;
+
+    Some(format!(
+        "first mismatch at byte {offset}: expected {:?}, got {:?}\n  expected: {:?}\n  actual:   {:?}",
+        expected.get(offset),
+        actual.get(offset),
+        String::from_utf8_lossy(expected),
+        String::from_utf8_lossy(actual),
+    ))
+}
+
+#[test]
+fn golden_programs() {
+    let programs = programs();
+    assert!(!programs.is_empty());
+
+    let mut failures = vec![];
+    for path in programs {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let code = fs::read_to_string(&path).unwrap();
+        let input = fs::read(path.with_extension("in")).unwrap_or_default();
+        let expected = fs::read(path.with_extension("out"))
+            .unwrap_or_else(|_| panic!("{name}.b has no {name}.out"));
+
+        for (pipeline, program) in [
+            ("plain", Program::parse(&code)),
+            ("optimized", Program::parse_optimized(&code)),
+        ] {
+            let result = program
+                .map_err(|err| err.to_string())
+                .and_then(|program| run(&program, &input));
+            match result {
+                Ok(actual) => {
+                    if let Some(message) = mismatch(&expected, &actual) {
+                        failures.push(format!("{name} ({pipeline}): {message}"));
+                    }
+                }
+                Err(err) => failures.push(format!("{name} ({pipeline}): {err}")),
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn optimizer_preserves_behaviour() {
+    for path in programs() {
+        let code = fs::read_to_string(&path).unwrap();
+        let input = fs::read(path.with_extension("in")).unwrap_or_default();
+        testing::assert_equivalent(&code, &[&input], STEP_LIMIT as u64);
+    }
+
+    for seed in 0..500 {
+        let code = testing::random_program(seed, 60);
+        testing::assert_equivalent(&code, &[b"", b"\x03\xff", b"hello"], 10_000);
+    }
+}
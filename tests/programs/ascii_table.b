@@ -0,0 +1,2 @@
+Print every ASCII byte from 0 to 127
+++++++++[>++++++++++++++++<-]>[<.+>-]
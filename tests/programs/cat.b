@@ -0,0 +1,2 @@
+Echo the first input line back without its newline
+,----------[++++++++++.,----------]
@@ -0,0 +1,1417 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::{Shutdown, TcpStream},
+    path::Path,
+    process::{Command, Output, Stdio},
+    time::{Duration, Instant},
+};
+
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_bf-rust"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+fn run_with_stdin(args: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bf-rust"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The program may exit without reading all of its input, closing the pipe.
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn line_comments_hide_commands() {
+    let output = run(&["tests/fixtures/line_comments.b", "--line-comments"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"E");
+}
+
+#[test]
+fn line_comments_are_opt_in() {
+    let output = run(&["tests/fixtures/line_comments.b"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr.contains("due to the end of input"), "{stderr}");
+}
+
+#[test]
+fn bang_input_feeds_the_program() {
+    let output = run(&[
+        "tests/fixtures/bang_input.b",
+        "--bang-input",
+        "--eof-mode",
+        "zero",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"abc");
+}
+
+#[test]
+fn bang_input_is_opt_in() {
+    let output = run(&["tests/fixtures/bang_input.b", "--eof-mode", "zero"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn bang_input_falls_through_to_stdin() {
+    let output = run_with_stdin(
+        &[
+            "tests/fixtures/bang_input.b",
+            "--bang-input",
+            "--eof-mode",
+            "zero",
+        ],
+        b"def",
+    );
+
+    assert_eq!(output.stdout, b"abcdef");
+}
+
+#[test]
+fn checkpoint_resumes_where_it_stopped() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("counting.ckpt");
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+
+    let whole = run(&["tests/fixtures/counting.b"]);
+    let first = run(&[
+        "tests/fixtures/counting.b",
+        "--checkpoint",
+        path,
+        "--checkpoint-every",
+        "50",
+        "--max-steps",
+        "300",
+    ]);
+    assert_eq!(first.status.code(), Some(124));
+    assert!(Path::new(path).exists());
+
+    let second = run(&["tests/fixtures/counting.b", "--checkpoint", path]);
+    assert_eq!(second.status.code(), Some(0));
+    assert!(!first.stdout.is_empty() && !second.stdout.is_empty());
+    assert_eq!([first.stdout, second.stdout].concat(), whole.stdout);
+    assert!(!Path::new(path).exists());
+}
+
+#[test]
+fn checkpoint_refuses_other_programs() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("other.ckpt");
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+
+    run(&[
+        "tests/fixtures/counting.b",
+        "--checkpoint",
+        path,
+        "--max-steps",
+        "100",
+    ]);
+    let output = run(&["tests/programs/hello_world.b", "--checkpoint", path]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr.contains("different program"), "{stderr}");
+    assert!(output.stdout.is_empty());
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn checkpoint_refuses_other_machines() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("machine.ckpt");
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+    let code = "++++++++[>++++++++<-]>+.+.+.+.+.";
+
+    let first = run(&[
+        "-e",
+        code,
+        "--memory-size",
+        "100",
+        "--checkpoint",
+        path,
+        "--checkpoint-every",
+        "5",
+        "--max-steps",
+        "20",
+    ]);
+    assert_eq!(first.status.code(), Some(124));
+    let output = run(&["-e", code, "--checkpoint", path]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr.contains("different program or machine"), "{stderr}");
+    assert!(output.stdout.is_empty());
+
+    let resumed = run(&["-e", code, "--memory-size", "100", "--checkpoint", path]);
+    assert_eq!(resumed.status.code(), Some(0));
+    assert_eq!(resumed.stdout, b"ABCDE");
+}
+
+#[test]
+fn checkpoint_with_a_lost_cursor_is_an_error() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cursor.ckpt");
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+    let args = [
+        "tests/fixtures/counting.b",
+        "--memory-size",
+        "16",
+        "--checkpoint",
+        path,
+    ];
+
+    run(&[&args[..], &["--max-steps", "100"]].concat());
+    let saved = fs::read_to_string(path).unwrap();
+    let cursor = saved.find("\"cursor\":").unwrap() + "\"cursor\":".len();
+    let end = cursor + saved[cursor..].find(|c: char| !c.is_ascii_digit()).unwrap();
+    fs::write(path, format!("{}16{}", &saved[..cursor], &saved[end..])).unwrap();
+    let output = run(&args);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr.contains("cursor at cell 16 of 16"), "{stderr}");
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn replayed_input_reproduces_the_run() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    for (name, args, stdin) in [
+        ("cat", &["tests/programs/cat.b"][..], &b"hello\n"[..]),
+        (
+            "eof",
+            &["tests/fixtures/read_past_eof.b", "--eof-mode", "zero"][..],
+            &b"ab"[..],
+        ),
+    ] {
+        let record = dir.join(format!("{name}.input"));
+        let record = record.to_str().unwrap();
+
+        let recorded = run_with_stdin(&[args, &["--record-input", record]].concat(), stdin);
+        assert_eq!(recorded.status.code(), Some(0));
+        assert_eq!(fs::read(record).unwrap(), stdin);
+
+        let replayed = run_with_stdin(&[args, &["--replay-input", record]].concat(), b"ignored");
+        assert_eq!(replayed.status.code(), Some(0));
+        assert_eq!(replayed.stdout, recorded.stdout);
+        fs::remove_file(record).unwrap();
+    }
+}
+
+#[test]
+fn input_str_feeds_inline_code() {
+    let output = run_with_stdin(&["-e", ",[.,]", "--input-str", "hi\\n"], b"ignored");
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"hi\n");
+}
+
+#[test]
+fn delay_slows_output_down() {
+    let start = Instant::now();
+    let output = run(&["-e", ",[.,]", "--input-str", "abc", "--delay", "10"]);
+    assert!(start.elapsed() >= Duration::from_millis(30));
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"abc");
+
+    let output = run(&["-e", ",[.,]", "--input-str", "a\nb", "--delay", "0"]);
+    assert_eq!(output.stdout, b"a\nb");
+
+    let output = run(&["-e", ".", "--delay-per-line"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(
+        stderr.contains("--delay-per-line requires --delay"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn raw_input_without_a_terminal_warns() {
+    let output = run_with_stdin(
+        &["-e", ",[.,]", "--eof-mode", "zero", "--raw-input"],
+        b"keys",
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"keys");
+    assert!(stderr.contains("--raw-input is ignored"), "{stderr}");
+}
+
+#[test]
+fn progress_stays_off_the_output() {
+    let output = run(&["tests/programs/hello_world.b", "--progress"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"Hello World!\n");
+    // Stderr is no terminal here, so there is no status line to draw.
+    assert!(output.stderr.is_empty());
+
+    let output = run(&["tests/programs/hello_world.b", "--progress", "--break", "0"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr.contains("--progress cannot be combined"), "{stderr}");
+}
+
+#[test]
+fn input_str_rejects_bad_escapes_and_input_files() {
+    let output = run(&["-e", ",.", "--input-str", "\\q"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr.contains("unknown escape \\q"), "{stderr}");
+
+    let output = run(&[
+        "-e",
+        ",.",
+        "--input-str",
+        "a",
+        "--input",
+        "tests/programs/cat.in",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn input_newlines_translate_line_endings() {
+    for (mode, expected) in [
+        ("none", &b"a\r\nb"[..]),
+        ("crlf-to-lf", b"a\nb"),
+        ("lf-to-cr", b"a\r\rb"),
+    ] {
+        let output = run(&[
+            "-e",
+            ",[.,]",
+            "--input-str",
+            "a\\r\\nb",
+            "--input-newlines",
+            mode,
+        ]);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stdout, expected, "{mode}");
+    }
+}
+
+#[test]
+fn tee_copies_output_to_a_file() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello_world.out");
+    let path = path.to_str().unwrap();
+
+    let output = run(&["tests/programs/hello_world.b", "--tee", path]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(fs::read(path).unwrap(), output.stdout);
+    assert_eq!(
+        output.stdout,
+        fs::read("tests/programs/hello_world.out").unwrap()
+    );
+    fs::remove_file(path).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn tee_failure_aborts_the_run() {
+    let output = run(&["tests/programs/hello_world.b", "--tee", "/dev/full"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(3));
+    assert!(stderr.contains("/dev/full"), "{stderr}");
+}
+
+#[test]
+fn output_newlines_translate_before_formatting() {
+    let output = run(&["-e", "++++++++++.+++.", "--output-newlines", "lf-to-crlf"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"\r\n\r");
+
+    let output = run(&[
+        "-e",
+        "++++++++++.+++.",
+        "--output-newlines",
+        "cr-to-lf",
+        "--output-format",
+        "hex",
+    ]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout.starts_with("00000000: 0a0a "), "{stdout}");
+}
+
+#[test]
+fn hex_output_format() {
+    let output = run(&["tests/programs/ascii_table.b", "--output-format", "hex"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let rows: Vec<_> = stdout.lines().take(2).collect();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        rows,
+        [
+            "00000000: 0001 0203 0405 0607 0809 0a0b 0c0d 0e0f  ................",
+            "00000010: 1011 1213 1415 1617 1819 1a1b 1c1d 1e1f  ................",
+        ]
+    );
+    assert_eq!(stdout.lines().count(), 8);
+}
+
+#[test]
+fn escaped_output_format() {
+    let output = run(&["tests/programs/ascii_table.b", "--output-format", "escaped"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stdout.starts_with("\\x00\\x01"), "{stdout}");
+    assert!(stdout.contains("@ABC"), "{stdout}");
+    assert!(stdout.ends_with("}~\\x7f"), "{stdout}");
+}
+
+#[test]
+fn seeded_random_bytes() {
+    let seeded = |seed| run(&["tests/fixtures/random.b", "--seed", seed]);
+    let first = seeded("7");
+
+    assert_eq!(first.status.code(), Some(0));
+    assert_eq!(first.stdout.len(), 2);
+    assert_eq!(seeded("7").stdout, first.stdout);
+    assert_ne!(seeded("8").stdout, first.stdout);
+}
+
+#[test]
+fn random_is_opt_in() {
+    let output = run(&["tests/fixtures/random.b"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, [0, 0]);
+}
+
+#[test]
+fn empty_loops_warn_but_run() {
+    let output = run(&["tests/fixtures/empty_loop.b"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"A");
+    assert!(
+        stderr.contains(
+            "warning[BF003]: empty loop either does nothing or never terminates at line 2, col 1"
+        ),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn deny_empty_loops_refuses_to_run() {
+    let output = run(&["tests/fixtures/empty_loop.b", "--deny-empty-loops"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(stderr.contains("Refusing to run"), "{stderr}");
+
+    let output = run(&["tests/programs/hello_world.b", "--deny-empty-loops"]);
+    assert_eq!(output.status.code(), Some(0));
+}
+
+#[test]
+fn pipe_stages() {
+    let output = run(&[
+        "tests/fixtures/emit_abc.b",
+        "--pipe",
+        "tests/fixtures/bang_input.b",
+        "--eof-mode",
+        "zero",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"abc");
+}
+
+#[test]
+fn pipe_reports_the_failing_stage() {
+    let output = run(&[
+        "tests/fixtures/emit_abc.b",
+        "--pipe",
+        "tests/fixtures/read_past_eof.b",
+        "--pipe",
+        "tests/fixtures/bang_input.b",
+    ]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        stderr.contains("in stage 2 (tests/fixtures/read_past_eof.b)"),
+        "{stderr}"
+    );
+    assert!(stderr.contains("due to the end of input"), "{stderr}");
+}
+
+#[test]
+fn coverage_lists_commands_that_never_ran() {
+    let output = run(&["tests/fixtures/skipped_loop.b", "--coverage"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(
+        stderr,
+        "coverage: 6 of 8 commands ran\n\
+         never ran: '.' at line 2, col 7\n\
+         never ran: ']' at line 2, col 8\n"
+    );
+
+    let output = run(&["tests/programs/hello_world.b", "--coverage"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("never ran"), "{stderr}");
+}
+
+#[test]
+fn profile_folded_writes_loop_stacks() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("nested_loops.folded");
+    let output = run(&[
+        "tests/fixtures/nested_loops.b",
+        "--profile-folded",
+        path.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    // `++` runs as one instruction, so the outer loop takes 6 steps a round.
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "loop@2 12\nloop@2;loop@6 12\n"
+    );
+}
+
+#[test]
+fn highlight_shades_a_profiled_run() {
+    let counts = Path::new(env!("CARGO_TARGET_TMPDIR")).join("nested_loops.counts");
+    let html = Path::new(env!("CARGO_TARGET_TMPDIR")).join("nested_loops.html");
+    let output = run(&[
+        "tests/fixtures/nested_loops.b",
+        "--profile",
+        counts.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&[
+        "highlight",
+        "tests/fixtures/nested_loops.b",
+        "--standalone",
+        "--profile",
+        counts.to_str().unwrap(),
+        "--html",
+        html.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let html = fs::read_to_string(&html).unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>tests/fixtures/nested_loops.b</title>"));
+    // The inner loop's `-` runs 4 times, more than anything else.
+    assert!(html.contains("<span class=\"bf-arith bf-heat-4\" title=\"4\">-</span>"));
+}
+
+#[test]
+fn init_tape_preloads_cells() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hi.bin");
+    fs::write(&path, b"HI").unwrap();
+    let data = path.to_str().unwrap();
+
+    let output = run(&[
+        "tests/fixtures/staged_cells.b",
+        "--init-tape",
+        &format!("{data}:10"),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"HI");
+
+    let output = run(&[
+        "tests/fixtures/staged_cells.b",
+        "--init-tape",
+        &format!("{data}:10"),
+        "--init-tape",
+        &format!("{data}:11"),
+    ]);
+    assert_eq!(output.stdout, b"HH");
+
+    let output = run(&[
+        "tests/fixtures/staged_cells.b",
+        "--init-tape",
+        &format!("{data}:29999"),
+    ]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr.contains("the tape has only 30000 cells"), "{stderr}");
+}
+
+#[test]
+fn stats_count_loop_iterations() {
+    let output = run(&["tests/fixtures/multiply.b", "--stats"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    let rows: Vec<Vec<_>> = stderr
+        .lines()
+        .skip(2)
+        .map(|line| line.split_whitespace().collect())
+        .collect();
+    assert_eq!(rows, [["2:4", "1", "3", "18"], ["2:9", "3", "6", "18"]]);
+}
+
+#[test]
+fn timings_extend_stats() {
+    let output = run(&["tests/programs/hello_world.b", "--stats", "--timings"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(stderr.contains("est. time"), "{stderr}");
+
+    let output = run(&["tests/programs/hello_world.b", "--timings"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+fn json_error(args: &[&str]) -> (Option<i32>, serde_json::Value) {
+    let output = run(&[args, &["--error-format", "json"]].concat());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let error =
+        serde_json::from_str(stderr.trim_end()).unwrap_or_else(|err| panic!("{err} in {stderr:?}"));
+    (output.status.code(), error)
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let output = run(&["tests/programs/hello_world.b", "--max-steps", "none"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    let (code, error) = json_error(&["tests/programs/hello_world.b", "--max-steps", "none"]);
+    assert_eq!(code, Some(2));
+    assert_eq!(error["kind"], "usage");
+    assert_eq!(error["message"], "--max-steps requires a positive number");
+}
+
+#[test]
+fn missing_files_exit_with_3() {
+    let output = run(&["tests/fixtures/missing.b"]);
+    assert_eq!(output.status.code(), Some(3));
+
+    let (code, error) = json_error(&["tests/fixtures/missing.b"]);
+    assert_eq!(code, Some(3));
+    assert_eq!(error["kind"], "io");
+    assert!(error["message"]
+        .as_str()
+        .unwrap()
+        .starts_with("tests/fixtures/missing.b: "));
+}
+
+#[test]
+fn parse_errors_exit_with_4() {
+    let (code, error) = json_error(&["tests/fixtures/unclosed_loop.b"]);
+
+    assert_eq!(code, Some(4));
+    assert_eq!(error["kind"], "parse");
+    assert_eq!(error["file"], "tests/fixtures/unclosed_loop.b");
+    assert_eq!((&error["line"], &error["column"]), (&2.into(), &2.into()));
+    assert!(error.get("pc").is_none());
+}
+
+#[test]
+fn runtime_errors_exit_with_5() {
+    let (code, error) = json_error(&["tests/fixtures/read_past_eof.b"]);
+
+    assert_eq!(code, Some(5));
+    assert_eq!(error["kind"], "runtime");
+    assert_eq!(error["file"], "tests/fixtures/read_past_eof.b");
+    assert_eq!((&error["line"], &error["column"]), (&2.into(), &1.into()));
+    assert_eq!(error["pc"], 0);
+}
+
+#[test]
+fn runtime_errors_trace_their_loops() {
+    let output = run_with_stdin(&["tests/fixtures/nested_eof.b"], b"abcd");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        stderr.ends_with(
+            "  in the loop at 3:3 (instruction 4), iteration 2\n  \
+             in the loop at 2:3 (instruction 1), iteration 2\n"
+        ),
+        "{stderr}"
+    );
+
+    let (_, error) = json_error(&["tests/fixtures/nested_eof.b"]);
+    assert_eq!(error["loops"][0]["line"], 3);
+    assert_eq!(error["loops"][1]["iteration"], 1);
+}
+
+#[test]
+fn step_limit_exits_with_124() {
+    let (code, error) = json_error(&["tests/fixtures/counting.b", "--max-steps", "10"]);
+
+    assert_eq!(code, Some(124));
+    assert_eq!(error["kind"], "step_limit");
+    assert!(error["pc"].is_u64());
+}
+
+#[test]
+fn max_output_cuts_the_run_short() {
+    let output = run(&["tests/programs/hello_world.b", "--max-output", "5"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(output.stdout, b"Hello");
+    assert!(stderr.contains("output limit of 5 bytes"), "{stderr}");
+}
+
+#[test]
+fn max_memory_stops_a_growing_tape() {
+    let args = [
+        "tests/fixtures/runaway.b",
+        "--tape",
+        "grow",
+        "--max-memory",
+        "10000",
+    ];
+    let output = run(&args);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(
+        stderr.contains("memory limit: 10001 cells needed, 10000 allowed"),
+        "{stderr}"
+    );
+
+    let output = run(&[
+        "tests/programs/hello_world.b",
+        "--tape",
+        "grow",
+        "--max-memory",
+        "100",
+    ]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"Hello World!\n");
+}
+
+#[test]
+fn growing_tapes_underflow_at_every_level() {
+    // `--coverage` runs the program unoptimized.
+    for code in ["<>+.", "+.<"] {
+        for extra in [&[][..], &["--coverage"]] {
+            let mut args = vec!["-e", code, "--tape", "grow"];
+            args.extend_from_slice(extra);
+            let output = run(&args);
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert_eq!(output.status.code(), Some(5), "{args:?}");
+            assert!(stderr.contains("left of cell 0"), "{args:?}: {stderr}");
+        }
+    }
+}
+
+#[test]
+fn expect_matching_output_is_silent() {
+    let args = [
+        "tests/programs/cat.b",
+        "--input",
+        "tests/programs/cat.in",
+        "--expect",
+        "tests/programs/cat.out",
+    ];
+    let output = run(&args);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn expect_reports_the_first_difference() {
+    let expected = Path::new(env!("CARGO_TARGET_TMPDIR")).join("expect_mismatch.out");
+    fs::write(&expected, b"Hello World?\x00\n").unwrap();
+    let args = [
+        "tests/programs/hello_world.b",
+        "--expect",
+        expected.to_str().unwrap(),
+    ];
+    let output = run(&args);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert!(
+        stderr.contains("first difference at byte 11: expected 0x3f '?', got 0x21 '!'"),
+        "{stderr}"
+    );
+    assert!(
+        stderr.contains(r#"expected: "Hello World?\x00\n""#),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn expect_fails_on_a_hang() {
+    let args = [
+        "tests/fixtures/runaway.b",
+        "--expect",
+        "tests/programs/hello_world.out",
+        "--max-steps",
+        "1000",
+    ];
+    let output = run(&args);
+
+    assert_eq!(output.status.code(), Some(124));
+}
+
+#[test]
+fn test_command_summarizes_a_directory() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("suite");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir(&dir).unwrap();
+    fs::copy("tests/programs/cat.b", dir.join("echo.b")).unwrap();
+    fs::copy("tests/programs/cat.in", dir.join("echo.in")).unwrap();
+    fs::copy("tests/programs/cat.out", dir.join("echo.out")).unwrap();
+    fs::copy("tests/fixtures/emit_abc.b", dir.join("letters.b")).unwrap();
+    fs::write(dir.join("letters.out"), b"abd").unwrap();
+    let dir = dir.to_str().unwrap();
+
+    let output = run(&["test", dir]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.contains("echo     pass     pass"), "{stdout}");
+    assert!(stdout.contains("letters  fail     fail"), "{stdout}");
+    assert!(
+        stdout.contains("letters (plain): first difference at byte 2"),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains("2 case(s): 1 passed, 1 failed, 0 timed out"),
+        "{stdout}"
+    );
+
+    let output = run(&["test", dir, "--filter", "echo"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&["test", dir, "--update"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert!(
+        stdout.contains("2 passed, 0 failed, 0 timed out, 1 updated"),
+        "{stdout}"
+    );
+    assert_eq!(
+        fs::read(Path::new(dir).join("letters.out")).unwrap(),
+        b"abc"
+    );
+}
+
+#[test]
+fn visualize_refuses_without_a_terminal() {
+    let output = run(&["tests/programs/hello_world.b", "--visualize"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    assert!(stderr.contains("stdout is not one"), "{stderr}");
+}
+
+#[test]
+fn output_goes_to_a_file() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello_world.txt");
+    let output = run(&[
+        "tests/programs/hello_world.b",
+        "--output",
+        path.to_str().unwrap(),
+    ]);
+
+    assert!(output.status.success());
+    assert!(output.stdout.is_empty());
+    assert_eq!(fs::read(&path).unwrap(), b"Hello World!\n");
+}
+
+#[test]
+fn trace_json_is_capped() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit_abc.jsonl");
+    let args = [
+        "tests/fixtures/emit_abc.b",
+        "--trace-json",
+        path.to_str().unwrap(),
+        "--trace-json-limit",
+        "3",
+    ];
+    let output = run(&args);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"abc");
+
+    let trace = fs::read_to_string(&path).unwrap();
+    let records: Vec<serde_json::Value> = trace
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(records.len(), 3);
+    // The loop is folded into a single +96 on cell 1, one right of the cursor.
+    assert_eq!(
+        records[1]["token"]["IncrementAt"],
+        serde_json::json!([1, 96])
+    );
+    assert_eq!(records[1]["cursor"], 0);
+}
+
+#[test]
+fn break_prints_the_machine_at_each_breakpoint() {
+    // Offset 56 is the second `.`, the 28th byte of the line after the comment.
+    let output = run(&["tests/fixtures/emit_abc.b", "--break", "5,@56"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"abc");
+    assert_eq!(
+        stderr,
+        "Breakpoint at pc 5: cursor 1, cells 0..5: 0 [97] 0 0 0, 0 bytes read, 0 written\n\
+         Breakpoint at pc 7: cursor 1, cells 0..5: 0 [98] 0 0 0, 0 bytes read, 1 written\n"
+    );
+
+    let output = run(&["tests/fixtures/emit_abc.b", "--break", "@500"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn stats_counts_commands() {
+    let output = run(&["stats", "--format", "json", "tests/programs/hello_world.b"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let stats: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(stats["commands"]["."], 13);
+    assert_eq!(stats["commands"]["+"], 65);
+    assert_eq!(stats["max_depth"], 1);
+    assert_eq!(stats["loops"], 1);
+    assert_eq!(stats["tokens"]["O0"], 111);
+
+    let output = run(&["stats", "tests/programs/pi.b"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("max depth             11\n"), "{stdout}");
+}
+
+#[test]
+fn cache_dir_skips_parsing_on_the_second_run() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("program_cache");
+    let _ = fs::remove_dir_all(&dir);
+    let args = [
+        "tests/programs/hello_world.b",
+        "--cache-dir",
+        dir.to_str().unwrap(),
+        "-v",
+    ];
+
+    let first = run(&args);
+    let stderr = String::from_utf8(first.stderr).unwrap();
+    assert!(stderr.contains("in the program cache"), "{stderr}");
+    assert!(stderr.contains("pass remove_not_command"), "{stderr}");
+
+    let second = run(&args);
+    let stderr = String::from_utf8(second.stderr).unwrap();
+    assert!(stderr.contains("from the program cache"), "{stderr}");
+    assert!(!stderr.contains("pass remove_not_command"), "{stderr}");
+    assert_eq!(second.stdout, first.stdout);
+    assert_eq!(second.status.code(), Some(0));
+}
+
+#[test]
+fn cache_dir_recovers_from_corrupted_entries() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("corrupted_cache");
+    let _ = fs::remove_dir_all(&dir);
+    let args = [
+        "tests/programs/hello_world.b",
+        "--cache-dir",
+        dir.to_str().unwrap(),
+        "-v",
+    ];
+    let expected = run(&args).stdout;
+
+    for entry in fs::read_dir(&dir).unwrap() {
+        let path = entry.unwrap().path();
+        let mut bytes = fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        fs::write(&path, bytes).unwrap();
+    }
+    let output = run(&args);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("ignoring cache entry"), "{stderr}");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, expected);
+
+    let output = run(&[
+        "tests/programs/hello_world.b",
+        "--cache-dir",
+        dir.to_str().unwrap(),
+        "--cache-clear",
+        "-v",
+    ]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("stored"), "{stderr}");
+}
+
+#[test]
+fn shebang_line_is_skipped() {
+    let output = run(&["tests/fixtures/shebang.b"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"A");
+
+    let output = run(&["tests/fixtures/shebang_error.b"]);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(" --> 2:2\n"), "{stderr}");
+}
+
+#[test]
+fn header_directives_configure_the_machine() {
+    // On a 100-cell tape, 100 steps right come back to the same cell.
+    let output = run(&["tests/fixtures/directives.b"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"A");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("unknown directive `speed` on line 1"),
+        "{stderr}"
+    );
+
+    let output = run(&["tests/fixtures/directives.b", "--memory-size", "30"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"\0");
+
+    let output = run(&["tests/fixtures/directive_error.b"]);
+    assert_ne!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("directive on line 2: eof requires"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn brainloller_images_run() {
+    let output = run(&["tests/fixtures/brainloller.png"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, [1]);
+
+    // Right along the top, then turned down the right edge.
+    let output = run(&["tests/fixtures/brainloller_l.png"]);
+    assert_eq!(output.stdout, [3]);
+
+    let output = run(&["tests/fixtures/emit_abc.b", "--brainloller"]);
+    assert_ne!(output.status.code(), Some(0));
+}
+
+#[test]
+fn emitted_json_runs() {
+    let output = run(&["tests/programs/hello_world.b", "--emit-json"]);
+    assert_eq!(output.status.code(), Some(0));
+    let json = String::from_utf8(output.stdout).unwrap();
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello_world.json");
+    fs::write(&path, &json).unwrap();
+
+    let output = run(&[path.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"Hello World!\n");
+
+    // A jump past the end is refused, not followed.
+    let output = run(&["tests/programs/cat.b", "--emit-json"]);
+    let mut program: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    program["jump_table"][2] = 9999.into();
+    fs::write(&path, program.to_string()).unwrap();
+    let output = run(&[path.to_str().unwrap()]);
+    assert_ne!(output.status.code(), Some(0));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("invalid program"), "{stderr}");
+}
+
+#[test]
+fn emitted_js_prints_hello_world() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello_world.js");
+    let output = run(&[
+        "tests/programs/hello_world.b",
+        "--emit-js",
+        path.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+
+    let js = fs::read_to_string(&path).unwrap();
+    assert!(
+        js.starts_with("function bf(output, input, extension) {"),
+        "{js}"
+    );
+    let driver = format!(
+        "{js}\nconst out = [];\nbf((byte) => out.push(byte), () => null);\n\
+         process.stdout.write(Buffer.from(out));\n"
+    );
+    fs::write(&path, driver).unwrap();
+    // Node is not needed to build the crate, so without it only the file is
+    // checked.
+    if let Ok(output) = Command::new("node").arg(&path).output() {
+        assert_eq!(output.stdout, b"Hello World!\n");
+    }
+
+    let output = run(&["compile", "--target", "js", "tests/programs/hello_world.b"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), js);
+}
+
+#[test]
+fn emitted_llvm_matches_compile() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit_abc.ll");
+    let output = run(&[
+        "tests/fixtures/emit_abc.b",
+        "--emit-llvm",
+        path.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let ir = fs::read_to_string(&path).unwrap();
+    assert!(ir.contains("define i32 @main() {"), "{ir}");
+
+    let output = run(&["compile", "--target", "llvm", "tests/fixtures/emit_abc.b"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), ir);
+}
+
+#[test]
+fn explain_prints_pseudocode() {
+    let output = run(&["explain", "--offsets", "tests/programs/hello_world.b"]);
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("p[0] += 10;  // @0\np[1] += p[0] * 7;  // @10\n"),
+        "{stdout}"
+    );
+    assert!(!stdout.contains("while"), "{stdout}");
+}
+
+#[test]
+fn transpiles_to_ook_and_back() {
+    let ook = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello_world.ook");
+    let output = run(&[
+        "transpile",
+        "--to",
+        "ook",
+        "--output",
+        ook.to_str().unwrap(),
+        "tests/programs/hello_world.b",
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(fs::read_to_string(&ook).unwrap().starts_with("Ook. Ook."));
+
+    let output = run(&["transpile", "--to", "bf", ook.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let bf = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello_world_from_ook.b");
+    fs::write(&bf, &output.stdout).unwrap();
+    let output = run(&[bf.to_str().unwrap()]);
+    assert_eq!(output.stdout, b"Hello World!\n");
+
+    let output = run(&["transpile", "--to", "bf", "tests/programs/hello_world.b"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("'.ook' extension"), "{stderr}");
+}
+
+#[test]
+fn bench_opt_compares_levels() {
+    let output = run(&["bench-opt", "tests/programs/hello_world.b", "--runs", "2"]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert!(lines[0].starts_with("level"), "{stdout}");
+    for (line, level) in lines[1..4].iter().zip(["O0", "O1", "O2"]) {
+        assert!(line.starts_with(level), "{stdout}");
+    }
+    assert!(lines[1].ends_with("1.00x"), "{stdout}");
+    assert_eq!(lines[4], "outputs equal at every level (13 bytes)");
+}
+
+#[test]
+fn a_file_first_runs_it() {
+    for args in [
+        &["tests/programs/hello_world.b"][..],
+        &["run", "tests/programs/hello_world.b"],
+    ] {
+        let output = run(args);
+        assert_eq!(output.status.code(), Some(0));
+        assert_eq!(output.stdout, b"Hello World!\n");
+    }
+
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("hello_world.txt");
+    fs::copy("tests/programs/hello_world.b", &path).unwrap();
+    let output = run(&[path.to_str().unwrap()]);
+    assert_ne!(output.status.code(), Some(0));
+    let output = run(&[path.to_str().unwrap(), "--force-run"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"Hello World!\n");
+}
+
+#[test]
+fn subcommand_flags_stay_with_their_subcommand() {
+    let output = run(&["stats", "--runs", "2", "tests/programs/hello_world.b"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Unknown argument: --runs"), "{stderr}");
+
+    let output = run(&[
+        "bench-opt",
+        "--format",
+        "json",
+        "tests/programs/hello_world.b",
+    ]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Unknown argument: --format"), "{stderr}");
+}
+
+#[test]
+fn compile_matches_emit_json() {
+    let output = run(&["compile", "tests/fixtures/emit_abc.b"]);
+    assert_eq!(output.status.code(), Some(0));
+    let emitted = run(&["tests/fixtures/emit_abc.b", "--emit-json"]);
+    assert_eq!(output.stdout, emitted.stdout);
+
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("emit_abc.bfc");
+    let args = ["compile", "--target", "bytecode", "--output"];
+    let output = run(&[
+        &args[..],
+        &[path.to_str().unwrap(), "tests/fixtures/emit_abc.b"],
+    ]
+    .concat());
+    assert_eq!(output.status.code(), Some(0));
+    assert!(fs::read(&path).unwrap().starts_with(b"BFC"));
+}
+
+#[test]
+fn repl_runs_lines_as_their_loops_close() {
+    let output = run_with_stdin(&["repl"], b"++++++++[\n>++++++++<-]>+.\n+.\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"AB");
+
+    let output = run_with_stdin(&["repl"], b"+.\n]\n+.\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(output.stdout, [1, 2]);
+}
+
+#[test]
+fn debug_steps_back() {
+    let commands = b"s\ns\nrs\nrs\nrs\nf\nq\n";
+    let output = run_with_stdin(&["debug", "tests/programs/hello_world.b"], commands);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"Hello World!\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let reports: Vec<_> = stderr.lines().collect();
+    assert!(reports[..2].iter().all(|line| line.starts_with("step ")));
+    assert!(reports[2].starts_with("step 1, pc 1:"), "{stderr}");
+    assert!(reports[3].starts_with("step 0, pc 0:"), "{stderr}");
+    assert_eq!(reports[4], "error: already at the first recorded step");
+    assert!(reports[5].ends_with(", halted"), "{stderr}");
+}
+
+#[test]
+fn serve_runs_each_connection() {
+    let mut server = Command::new(env!("CARGO_BIN_EXE_bf-rust"))
+        .args(["serve", "tests/programs/cat.b", "--listen", "127.0.0.1:0"])
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut banner = String::new();
+    BufReader::new(server.stderr.take().unwrap())
+        .read_line(&mut banner)
+        .unwrap();
+    let addr = banner.trim().rsplit(' ').next().unwrap().to_string();
+
+    for message in ["first", "second"] {
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.write_all(message.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut echoed = String::new();
+        stream.read_to_string(&mut echoed).unwrap();
+        assert_eq!(echoed, message);
+    }
+    server.kill().unwrap();
+    server.wait().unwrap();
+}
+
+#[test]
+fn multi_tape_dumps_both_tapes() {
+    let dump = Path::new(env!("CARGO_TARGET_TMPDIR")).join("two_tapes.core");
+    let _ = fs::remove_file(&dump);
+    let output = run(&[
+        "tests/fixtures/two_tapes.b",
+        "--multi-tape",
+        "--core-dump",
+        dump.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(5));
+    assert_eq!(output.stdout, b"\x05");
+
+    let output = run(&["inspect-core", dump.to_str().unwrap()]);
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        report.contains("tape 0 (active): cursor 0, cells 0..9: [5] 0 0"),
+        "{report}"
+    );
+    assert!(
+        report.contains("tape 1: cursor 0, cells 0..9: [7] 0 0"),
+        "{report}"
+    );
+
+    // Without the flag `^` is a comment, so all twelve go to one cell.
+    let output = run(&["tests/fixtures/two_tapes.b", "--eof-mode", "zero"]);
+    assert_eq!(output.stdout, b"\x0c");
+}
+
+#[test]
+fn brainfork_threads_share_output() {
+    let output = run(&["tests/fixtures/parent_and_child.b", "--brainfork"]);
+    assert_eq!(output.status.code(), Some(0));
+    // The child runs first after a fork.
+    assert_eq!(output.stdout, b"cp");
+
+    // Without the flag `Y` is a comment and only the parent is left.
+    let output = run(&["tests/fixtures/parent_and_child.b"]);
+    assert_eq!(output.stdout, b"p");
+
+    let output = run(&[
+        "tests/fixtures/parent_and_child.b",
+        "--brainfork",
+        "--progress",
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn int_cells_end_input_at_minus_one() {
+    let input = b"a\xffb";
+    let output = run_with_stdin(
+        &[
+            "tests/fixtures/cat_minus_one.b",
+            "--cell-size",
+            "i32",
+            "--eof-mode",
+            "minus-one",
+        ],
+        input,
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, input);
+
+    // The same, declared in the program's header.
+    let output = run_with_stdin(&["tests/fixtures/int_cells.b"], input);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, input);
+    let output = run(&["compile", "--target", "js", "tests/fixtures/int_cells.b"]);
+    assert_ne!(output.status.code(), Some(0));
+
+    // In byte cells the 255 ends the copy early.
+    let output = run_with_stdin(
+        &["tests/fixtures/cat_minus_one.b", "--eof-mode", "minus-one"],
+        input,
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"a");
+
+    // And with an end of 0 it never ends, printing zeros.
+    let output = run_with_stdin(
+        &[
+            "tests/fixtures/cat_minus_one.b",
+            "--eof-mode",
+            "zero",
+            "--max-steps",
+            "100",
+        ],
+        b"ab",
+    );
+    assert_eq!(output.status.code(), Some(124));
+    assert!(output.stdout.starts_with(b"ab\0\0"));
+}
+
+#[test]
+fn int_cells_take_the_run_flags() {
+    // Prints `A` only if a cell holds 256, which a byte cannot.
+    let code = "++++++++[>++++++++<-]>[<++++>-]<[[-]>++++++++[<++++++++>-]<+.>]";
+    assert!(run(&["-e", code]).stdout.is_empty());
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR"));
+    let cat = dir.join("int_cells_cat.b");
+    fs::write(&cat, ",[.,]").unwrap();
+    let cat = cat.to_str().unwrap();
+    let trace = dir.join("int_cells.jsonl");
+    let trace = trace.to_str().unwrap();
+    for flags in [
+        &["--tape", "grow"][..],
+        &["--coverage"],
+        &["--stats"],
+        &["--random"],
+        &["--brainfork"],
+        &["--pipe", cat, "--eof-mode", "zero"],
+        &["--trace-json", trace],
+    ] {
+        let output = run(&[&["-e", code, "--cell-size", "i32"], flags].concat());
+        assert_eq!(output.status.code(), Some(0), "{flags:?}");
+        assert_eq!(output.stdout, b"A", "{flags:?}");
+    }
+
+    let path = dir.join("int_cells.ckpt");
+    let path = path.to_str().unwrap();
+    let _ = fs::remove_file(path);
+    let args = ["-e", code, "--cell-size", "i32", "--checkpoint", path];
+    let first = run(&[
+        &args[..],
+        &["--checkpoint-every", "50", "--max-steps", "100"],
+    ]
+    .concat());
+    assert_eq!(first.status.code(), Some(124));
+    // A checkpoint of `i32` cells is not one of bytes.
+    let output = run(&["-e", code, "--checkpoint", path]);
+    assert_eq!(output.status.code(), Some(3));
+    let second = run(&args);
+    assert_eq!(second.status.code(), Some(0));
+    assert_eq!(second.stdout, b"A");
+
+    let output = run(&["-e", code, "--cell-size", "i32", "--emit-js", "out.js"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn core_dumps_can_be_inspected() {
+    let dump = Path::new(env!("CARGO_TARGET_TMPDIR")).join("nested_eof.core");
+    let _ = fs::remove_file(&dump);
+    let output = run_with_stdin(
+        &[
+            "tests/fixtures/nested_eof.b",
+            "--core-dump",
+            dump.to_str().unwrap(),
+        ],
+        b"abcd",
+    );
+    assert_eq!(output.status.code(), Some(5));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Core dumped to "), "{stderr}");
+
+    let output = run(&["inspect-core", dump.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+    let report = String::from_utf8(output.stdout).unwrap();
+    assert!(report.starts_with("unexpected_eof at pc "), "{report}");
+    assert!(
+        report.contains("cursor 2, cells 0..11: 1 2 [100] 0"),
+        "{report}"
+    );
+    assert!(report.contains("pc 1      LoopStart"), "{report}");
+
+    let output = run(&[
+        "tests/programs/hello_world.b",
+        "--core-dump",
+        dump.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+}